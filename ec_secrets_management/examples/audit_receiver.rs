@@ -0,0 +1,155 @@
+/*---------------------------------------------------------------
+A local receiver for trying out the audit sinks. It accepts the
+webhook on http://127.0.0.1:9000/audit and syslog on UDP and TCP
+port 5514, and prints every entry it gets:
+
+    ECS_AUDIT_WEBHOOK_SECRET=s3cret cargo run --example audit_receiver
+
+With the secret set, webhook deliveries with a missing or wrong
+signature, or a timestamp more than five minutes off, get a 401.
+Pass other addresses as `--http <addr>` and `--syslog <addr>`.
+----------------------------------------------------------------*/
+use chrono::Utc;
+use ec_secrets_shared_library::utils::audit_sinks::verify_webhook_signature;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+const MAX_CLOCK_SKEW: i64 = 300;
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let mut http_address = "127.0.0.1:9000".to_string();
+    let mut syslog_address = "127.0.0.1:5514".to_string();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--http", Some(address)) => http_address = address,
+            ("--syslog", Some(address)) => syslog_address = address,
+            _ => {
+                eprintln!("usage: audit_receiver [--http <addr>] [--syslog <addr>]");
+                std::process::exit(2);
+            }
+        }
+    }
+    let secret = std::env::var("ECS_AUDIT_WEBHOOK_SECRET").ok();
+
+    let http = TcpListener::bind(&http_address).await?;
+    let syslog_tcp = TcpListener::bind(&syslog_address).await?;
+    let syslog_udp = UdpSocket::bind(&syslog_address).await?;
+    println!("Webhook: http://{}/audit", http_address);
+    println!("Syslog:  udp://{0} tcp://{0}", syslog_address);
+
+    tokio::spawn(async move {
+        let mut buffer = vec![0; 64 * 1024];
+        while let Ok((length, peer)) = syslog_udp.recv_from(&mut buffer).await {
+            println!(
+                "[syslog/udp {}] {}",
+                peer,
+                String::from_utf8_lossy(&buffer[..length])
+            );
+        }
+    });
+
+    tokio::spawn(async move {
+        while let Ok((stream, peer)) = syslog_tcp.accept().await {
+            tokio::spawn(async move {
+                let mut reader = BufReader::new(stream);
+                loop {
+                    // Octet counting: "<length> <message>"
+                    let mut length = Vec::new();
+                    match reader.read_until(b' ', &mut length).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => {}
+                    }
+                    let Ok(length) = String::from_utf8_lossy(&length).trim().parse::<usize>()
+                    else {
+                        eprintln!("[syslog/tcp {}] bad frame", peer);
+                        break;
+                    };
+                    let mut message = vec![0; length];
+                    if reader.read_exact(&mut message).await.is_err() {
+                        break;
+                    }
+                    println!(
+                        "[syslog/tcp {}] {}",
+                        peer,
+                        String::from_utf8_lossy(&message)
+                    );
+                }
+            });
+        }
+    });
+
+    loop {
+        let (stream, _) = http.accept().await?;
+        let secret = secret.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_webhook(stream, secret.as_deref()).await {
+                eprintln!("[webhook] {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_webhook(stream: TcpStream, secret: Option<&str>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+
+    let mut content_length = 0;
+    let mut timestamp = None;
+    let mut signature = None;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim().to_string();
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.parse().unwrap_or(0),
+                "x-locksmith-timestamp" => timestamp = value.parse::<i64>().ok(),
+                "x-locksmith-signature" => signature = Some(value),
+                _ => {}
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+
+    let verified = match (secret, timestamp, &signature) {
+        (None, _, _) => true,
+        (Some(secret), Some(timestamp), Some(signature)) => {
+            (Utc::now().timestamp() - timestamp).abs() <= MAX_CLOCK_SKEW
+                && verify_webhook_signature(secret.as_bytes(), timestamp, &body, signature)
+        }
+        _ => false,
+    };
+
+    let status = if !request_line.starts_with("POST ") {
+        "405 Method Not Allowed"
+    } else if verified {
+        println!(
+            "[webhook{}] {}",
+            if secret.is_some() { ", signed" } else { "" },
+            String::from_utf8_lossy(&body)
+        );
+        "200 OK"
+    } else {
+        eprintln!("[webhook] rejected a delivery with a bad signature");
+        "401 Unauthorized"
+    };
+
+    reader
+        .into_inner()
+        .write_all(
+            format!(
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .as_bytes(),
+        )
+        .await
+}
@@ -5,11 +5,17 @@ createApp({
         return {
             email: "",
             password: "",
+            code: "",
+            challengeToken: null,
             loading: false
         };
     },
     methods: {
         async handleLogin() {
+            if (this.challengeToken) {
+                return this.handleTotp();
+            }
+
             if (!this.email || !this.password) {
                 this.displayToaster("Please enter email and password", "red");
                 return;
@@ -33,14 +39,14 @@ createApp({
                     return;
                 }
 
-                // Store token and redirect
-                localStorage.setItem("authToken", data.token);
-                localStorage.setItem("ecId", this.email);
-                this.displayToaster("Login successful, redirecting...");
+                // Two-factor accounts complete the login with a code
+                if (data.mfa_required) {
+                    this.challengeToken = data.challenge_token;
+                    this.displayToaster("Enter the code from your authenticator app");
+                    return;
+                }
 
-                setTimeout(() => {
-                    window.location.href = "./console.html";
-                }, 1500);
+                this.completeLogin(data);
 
             } catch (error) {
                 this.displayToaster(error.message || "Something went wrong, please try again", "red");
@@ -49,6 +55,97 @@ createApp({
                 this.loading = false;
             }
         },
+        async handleTotp() {
+            if (!this.code) {
+                this.displayToaster("Please enter your two-factor code", "red");
+                return;
+            }
+
+            if (this.loading) return;
+            this.loading = true;
+
+            try {
+                const response = await fetch(`${API_BASE_URL}/login/totp`, {
+                    method: "POST",
+                    headers: { "Content-Type": "application/json" },
+                    body: JSON.stringify({ challenge_token: this.challengeToken, code: this.code }),
+                });
+
+                const data = await response.json();
+
+                if (data.status !== 200) {
+                    this.displayToaster(data.message || "Invalid code", "red");
+                    // The challenge may be exhausted or expired; start over
+                    this.challengeToken = null;
+                    this.code = "";
+                    return;
+                }
+
+                this.completeLogin(data);
+
+            } catch (error) {
+                this.displayToaster(error.message || "Something went wrong, please try again", "red");
+                console.error("[Error]::[Auth] -> ", error);
+            } finally {
+                this.loading = false;
+            }
+        },
+        async handlePasskeyLogin() {
+            if (!passkeys.supported()) {
+                this.displayToaster("Passkeys are not supported by this browser", "red");
+                return;
+            }
+
+            if (this.loading) return;
+            this.loading = true;
+
+            try {
+                // An email narrows the prompt to that account's passkeys
+                const start = await fetch(`${API_BASE_URL}/webauthn/login/start`, {
+                    method: "POST",
+                    headers: { "Content-Type": "application/json" },
+                    body: JSON.stringify({ email: this.email || null }),
+                });
+                const options = await start.json();
+
+                if (options.status !== 200) {
+                    this.displayToaster(options.message || "Passkey login failed", "red");
+                    return;
+                }
+
+                const assertion = await passkeys.get(options.public_key);
+                const response = await fetch(`${API_BASE_URL}/webauthn/login/finish`, {
+                    method: "POST",
+                    headers: { "Content-Type": "application/json" },
+                    body: JSON.stringify(assertion),
+                });
+                const data = await response.json();
+
+                if (data.status !== 200) {
+                    this.displayToaster(data.message || "Passkey login failed", "red");
+                    return;
+                }
+
+                this.completeLogin(data);
+
+            } catch (error) {
+                this.displayToaster(error.message || "Passkey login was cancelled", "red");
+                console.error("[Error]::[Auth] -> ", error);
+            } finally {
+                this.loading = false;
+            }
+        },
+        completeLogin(data) {
+            // Store token and redirect
+            localStorage.setItem("authToken", data.token);
+            localStorage.setItem("refreshToken", data.refresh_token);
+            localStorage.setItem("ecId", this.email);
+            this.displayToaster("Login successful, redirecting...");
+
+            setTimeout(() => {
+                window.location.href = "./console.html";
+            }, 1500);
+        },
         displayToaster(message, backgroundColor = "#ffa07a") {
             Toastify({
                 text: message,
@@ -0,0 +1,64 @@
+// Passkey helpers: the server exchanges binary WebAuthn fields as base64url strings.
+const base64url = {
+    encode(buffer) {
+        const bytes = new Uint8Array(buffer);
+        let binary = "";
+        bytes.forEach((byte) => (binary += String.fromCharCode(byte)));
+        return btoa(binary).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+    },
+    decode(value) {
+        const base64 = value.replace(/-/g, "+").replace(/_/g, "/");
+        const binary = atob(base64.padEnd(base64.length + (4 - (base64.length % 4)) % 4, "="));
+        return Uint8Array.from(binary, (char) => char.charCodeAt(0));
+    }
+};
+
+const passkeys = {
+    supported() {
+        return !!window.PublicKeyCredential;
+    },
+
+    // Runs navigator.credentials.create() with options from /webauthn/register/start.
+    async create(options, name) {
+        const publicKey = {
+            ...options,
+            challenge: base64url.decode(options.challenge),
+            user: { ...options.user, id: base64url.decode(options.user.id) },
+            excludeCredentials: (options.excludeCredentials || []).map((credential) => ({
+                ...credential,
+                id: base64url.decode(credential.id)
+            }))
+        };
+
+        const credential = await navigator.credentials.create({ publicKey });
+        return {
+            id: credential.id,
+            client_data_json: base64url.encode(credential.response.clientDataJSON),
+            attestation_object: base64url.encode(credential.response.attestationObject),
+            name
+        };
+    },
+
+    // Runs navigator.credentials.get() with options from /webauthn/login/start.
+    async get(options) {
+        const publicKey = {
+            ...options,
+            challenge: base64url.decode(options.challenge),
+            allowCredentials: (options.allowCredentials || []).map((credential) => ({
+                ...credential,
+                id: base64url.decode(credential.id)
+            }))
+        };
+
+        const credential = await navigator.credentials.get({ publicKey });
+        return {
+            id: credential.id,
+            client_data_json: base64url.encode(credential.response.clientDataJSON),
+            authenticator_data: base64url.encode(credential.response.authenticatorData),
+            signature: base64url.encode(credential.response.signature),
+            user_handle: credential.response.userHandle
+                ? base64url.encode(credential.response.userHandle)
+                : null
+        };
+    }
+};
@@ -67,7 +67,7 @@ pub async fn bad_gateway() -> &'static str {
 
 #[catch(503)]
 pub async fn service_unavailable() -> &'static str {
-    "Service Unavailable."
+    "Service Unavailable. Locksmith may be sealed; see /sys/seal-status."
 }
 
 #[catch(504)]
@@ -6,19 +6,53 @@ use std::sync::Arc;
 /*-------------
 Custom modules
 ---------------*/
-use ec_secrets_shared_library::repositories::{
-    keys::KeyRepository, users::UserRepository, vault::VaultRepository,
-};
+use ec_secrets_shared_library::db::Repositories;
 
 pub fn init() -> AdHoc {
     AdHoc::on_ignite(
         "Establish connection with Database cluster",
         |rocket| async {
             match connect().await {
-                Ok((user_repository, vault_repository, key_repository)) => rocket
-                    .manage(Arc::new(user_repository))
-                    .manage(Arc::new(vault_repository))
-                    .manage(Arc::new(key_repository)),
+                Ok(Repositories {
+                    users,
+                    vault,
+                    keys,
+                    app_roles,
+                    refresh_tokens,
+                    revocations,
+                    totp,
+                    webauthn,
+                    oidc,
+                    login_attempts,
+                    account_tokens,
+                    offboarding,
+                    audit,
+                    access_requests,
+                    break_glass,
+                    seal,
+                    snapshots,
+                    journal,
+                    keyring,
+                }) => rocket
+                    .manage(Arc::new(users))
+                    .manage(Arc::new(vault))
+                    .manage(Arc::new(keys))
+                    .manage(Arc::new(app_roles))
+                    .manage(Arc::new(refresh_tokens))
+                    .manage(Arc::new(revocations))
+                    .manage(Arc::new(totp))
+                    .manage(Arc::new(webauthn))
+                    .manage(Arc::new(oidc))
+                    .manage(Arc::new(login_attempts))
+                    .manage(Arc::new(account_tokens))
+                    .manage(Arc::new(offboarding))
+                    .manage(Arc::new(audit))
+                    .manage(Arc::new(access_requests))
+                    .manage(Arc::new(break_glass))
+                    .manage(Arc::new(seal))
+                    .manage(Arc::new(snapshots))
+                    .manage(Arc::new(journal))
+                    .manage(keyring),
                 Err(error) => {
                     panic!("Cannot connect to instance:: {:?}", error)
                 }
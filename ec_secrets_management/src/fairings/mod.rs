@@ -1,10 +1,32 @@
+/*--------------------
+Custom modules
+---------------------*/
+use ec_secrets_shared_library::repositories::{journal::JournalRepository, seal::SealRepository};
+use ec_secrets_shared_library::utils::{
+    approval,
+    audit_sinks::{self, AuditExportConfig, AuditExporter},
+    auth::AuthConfig,
+    journal::{self, Journal, JournalConfig, JournalError},
+    kms::{self, KeyManager, SealConfig},
+    mail,
+    oidc::OidcClient,
+    seal::Keyring,
+};
+
 /*--------------------
 Rocket modules
 ---------------------*/
-use rocket::fairing::{Fairing, Info, Kind};
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
 use rocket::http::Header;
 use rocket::{Request, Response};
 
+/*--------------------
+stdlib modules
+---------------------*/
+use std::sync::Arc;
+use std::time::Duration;
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct CORS;
 
 #[rocket::async_trait]
@@ -30,3 +52,190 @@ impl Fairing for CORS {
         response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
     }
 }
+
+/*---------------------------------------------------------------
+Read token lifetimes from the `auth` table of the Rocket config,
+falling back to the library defaults when it is absent. The single
+sign-on client, the mail transport and the access request notifier
+are built from the same settings.
+----------------------------------------------------------------*/
+pub fn auth_config() -> AdHoc {
+    AdHoc::try_on_ignite("Load authentication settings", |rocket| async {
+        let config = match rocket.figment().extract_inner::<AuthConfig>("auth") {
+            Ok(config) => config,
+            Err(error) if error.missing() => AuthConfig::default(),
+            Err(error) => {
+                log::error!("Invalid [auth] configuration: {}", error);
+                return Err(rocket);
+            }
+        };
+
+        if let Err(error) = config.validate_lifetimes() {
+            log::error!("Invalid [auth] configuration: {}", error);
+            return Err(rocket);
+        }
+
+        let mailer = match mail::transport(&config.mail) {
+            Ok(mailer) => mailer,
+            Err(error) => {
+                log::error!("Invalid [auth.mail] configuration: {}", error);
+                return Err(rocket);
+            }
+        };
+
+        let notifier = approval::notifier(&config.approvals, &config.mail, Arc::clone(&mailer));
+        let oidc = OidcClient::new(config.oidc.clone());
+        Ok(rocket
+            .manage(config)
+            .manage(oidc)
+            .manage(mailer)
+            .manage(notifier))
+    })
+}
+
+/*---------------------------------------------------------------
+Start the audit sinks configured in the `audit` table of the
+Rocket config. Without it, entries stay in the audit log only.
+----------------------------------------------------------------*/
+pub fn audit_export() -> AdHoc {
+    AdHoc::try_on_ignite("Start audit sinks", |rocket| async {
+        let config = match rocket.figment().extract_inner::<AuditExportConfig>("audit") {
+            Ok(config) => config,
+            Err(error) if error.missing() => AuditExportConfig::default(),
+            Err(error) => {
+                log::error!("Invalid [audit] configuration: {}", error);
+                return Err(rocket);
+            }
+        };
+
+        let sinks = match audit_sinks::sinks(&config) {
+            Ok(sinks) => sinks,
+            Err(error) => {
+                log::error!("Invalid [audit] sink configuration: {}", error);
+                return Err(rocket);
+            }
+        };
+        for sink in &sinks {
+            log::info!("Exporting audit events to the {} sink", sink.name());
+        }
+
+        let exporter = AuditExporter::start(sinks, config.queue_size);
+        Ok(rocket.manage(Arc::new(exporter)))
+    })
+}
+
+/*---------------------------------------------------------------
+Build the key manager configured in the `seal` table of the Rocket
+config and unseal with it. Attached after the database, whose seal
+holds the wrapped key. When the key manager cannot unwrap it, the
+server starts sealed and unseal shares still work. With `mlock` set,
+the master key is locked into RAM however it gets unsealed.
+----------------------------------------------------------------*/
+pub fn auto_unseal() -> AdHoc {
+    AdHoc::try_on_ignite("Auto-unseal", |rocket| async {
+        let config = match rocket.figment().extract_inner::<SealConfig>("seal") {
+            Ok(config) => config,
+            Err(error) if error.missing() => SealConfig::default(),
+            Err(error) => {
+                log::error!("Invalid [seal] configuration: {}", error);
+                return Err(rocket);
+            }
+        };
+
+        let manager = match kms::key_manager(&config) {
+            Ok(manager) => manager,
+            Err(error) => {
+                log::error!("Invalid [seal] key manager: {}", error);
+                return Err(rocket);
+            }
+        };
+
+        if config.mlock {
+            if let Some(keyring) = rocket.state::<Arc<Keyring>>() {
+                match keyring.lock_memory() {
+                    Ok(()) => log::info!("Locking the master key into memory"),
+                    Err(error) => log::warn!("Cannot lock the master key into memory: {}", error),
+                }
+            }
+        }
+
+        if let (Some(manager), Some(seal_repo), Some(keyring)) = (
+            &manager,
+            rocket.state::<Arc<SealRepository>>(),
+            rocket.state::<Arc<Keyring>>(),
+        ) {
+            match seal_repo.get().await {
+                Ok(Some(seal)) if keyring.is_sealed() => {
+                    match keyring.auto_unseal(&seal, manager.as_ref()).await {
+                        Ok(true) => log::info!("Unsealed with the {} key manager", manager.name()),
+                        Ok(false) => log::warn!(
+                            "No master key wrapped by the {} key manager yet; unseal with shares once to wrap it",
+                            manager.name()
+                        ),
+                        Err(error) => log::error!("Auto-unseal failed, staying sealed: {}", error),
+                    }
+                }
+                Ok(_) => {}
+                Err(error) => log::error!("Failed to read the seal configuration: {:?}", error),
+            }
+        }
+
+        Ok(rocket.manage::<Option<Arc<dyn KeyManager>>>(manager))
+    })
+}
+
+/*---------------------------------------------------------------
+Follow the database into the change journal configured in the
+`journal` table of the Rocket config. Attached after the database.
+The server does not start on a journal it cannot verify, so no
+change goes unrecorded.
+----------------------------------------------------------------*/
+pub fn change_journal() -> AdHoc {
+    AdHoc::try_on_ignite("Start the change journal", |rocket| async {
+        let config = match rocket.figment().extract_inner::<JournalConfig>("journal") {
+            Ok(config) => config,
+            Err(error) if error.missing() => JournalConfig::default(),
+            Err(error) => {
+                log::error!("Invalid [journal] configuration: {}", error);
+                return Err(rocket);
+            }
+        };
+        if !config.enabled {
+            return Ok(rocket);
+        }
+
+        let opened = journal::journal_key()
+            .ok_or(JournalError::MissingKey)
+            .and_then(|key| Journal::open(&config.path, &key));
+        let journal = match opened {
+            Ok(journal) => journal,
+            Err(error) => {
+                log::error!(
+                    "Cannot open the change journal at {}: {}",
+                    config.path.display(),
+                    error
+                );
+                return Err(rocket);
+            }
+        };
+
+        let Some(repository) = rocket.state::<Arc<JournalRepository>>() else {
+            log::error!("The change journal needs the database; attach it first");
+            return Err(rocket);
+        };
+        if let Err(error) = repository.enable_post_images().await {
+            log::error!(
+                "Cannot keep the post-images the change journal needs (MongoDB 6.0 or later): {}",
+                error
+            );
+            return Err(rocket);
+        }
+        log::info!("Journaling changes to {}", config.path.display());
+        journal::follow(
+            Arc::clone(repository),
+            journal,
+            Duration::from_secs(config.retry_delay.max(1)),
+        );
+        Ok(rocket)
+    })
+}
@@ -17,6 +17,15 @@ mod request_guards;
 mod routes;
 
 use custom_catchers::*;
+use routes::access_requests::access_request_routes;
+use routes::account::account_routes;
+use routes::approle::approle_routes;
+use routes::audit::audit_routes;
+use routes::break_glass::break_glass_routes;
+use routes::oidc::oidc_routes;
+use routes::sys::sys_routes;
+use routes::totp::totp_routes;
+use routes::transit::transit_routes;
 use routes::users::user_routes;
 use routes::vault::vault_routes;
 
@@ -40,9 +49,22 @@ fn rocket() -> _ {
     rocket::build()
         .attach(db::init())
         .attach(fairings::CORS)
+        .attach(fairings::auth_config())
+        .attach(fairings::audit_export())
+        .attach(fairings::auto_unseal())
+        .attach(fairings::change_journal())
         .mount("/", routes![health_check, _options])
         .mount("/", user_routes())
         .mount("/", vault_routes())
+        .mount("/", approle_routes())
+        .mount("/", totp_routes())
+        .mount("/", oidc_routes())
+        .mount("/", account_routes())
+        .mount("/", audit_routes())
+        .mount("/", access_request_routes())
+        .mount("/", break_glass_routes())
+        .mount("/", sys_routes())
+        .mount("/", transit_routes())
         .mount("/", FileServer::from(public_path))
         .register(
             "/",
@@ -1,3 +1,4 @@
+use ec_secrets_shared_library::utils::secret::SecretValue;
 use rocket::response::Responder;
 use serde::{Deserialize, Serialize};
 
@@ -16,6 +17,12 @@ pub struct DeleteSecretResponse {
     pub message: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProtectionResponse {
+    pub status: u16,
+    pub message: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ErrorResponse {
     pub status: u16,
@@ -37,6 +44,36 @@ pub struct SetupResponse {
 pub struct LoginResponse {
     pub status: u16,
     pub token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+/// Returned by `/login` instead of a token when the account has two-factor
+/// authentication enabled; complete it through `/login/totp`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MfaChallengeResponse {
+    pub status: u16,
+    pub mfa_required: bool,
+    pub challenge_token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum LoginOutcome {
+    Token(LoginResponse),
+    Challenge(MfaChallengeResponse),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LogoutResponse {
+    pub status: u16,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AccountResponse {
+    pub status: u16,
+    pub message: String,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -44,3 +81,64 @@ pub struct DeleteUserResponse {
     pub status: u16,
     pub message: String,
 }
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AppRoleResponse {
+    pub status: u16,
+    pub role_name: String,
+    pub role_id: String,
+    pub policies: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SecretIdResponse {
+    pub status: u16,
+    pub secret_id: String,
+    pub expires_at: String,
+    pub uses_remaining: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TotpEnrollmentResponse {
+    pub status: u16,
+    pub secret: SecretValue,
+    pub provisioning_uri: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RecoveryCodesResponse {
+    pub status: u16,
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DisableTotpResponse {
+    pub status: u16,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WebAuthnOptionsResponse {
+    pub status: u16,
+    /// Options for `navigator.credentials.create()` or `.get()`, with binary
+    /// fields base64url encoded.
+    pub public_key: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WebAuthnCredentialResponse {
+    pub status: u16,
+    pub credential_id: String,
+    pub name: String,
+}
+
+/*----------
+ Requests
+----------*/
+/// Query parameters the identity provider redirects back with.
+#[derive(Debug, rocket::FromForm)]
+pub struct OidcCallback<'r> {
+    pub code: Option<&'r str>,
+    pub state: Option<&'r str>,
+    pub error: Option<&'r str>,
+}
@@ -1,63 +1,459 @@
-use ec_secrets_shared_library::utils::auth::decode_keys;
-use pasetors::{
-    claims::{Claims, ClaimsValidationRules},
-    public,
-    token::UntrustedToken,
-    version4::V4,
-    Public,
+use crate::models::ErrorResponse;
+use chrono::{DateTime, Utc};
+use ec_secrets_shared_library::utils::{
+    audit_sinks::AuditExporter,
+    auth::{decode_keys, AuthConfig, TokenValidator},
+    mtls::certificate_claims,
+    policy::is_permitted,
+    seal::Keyring,
 };
+use log::{error, info, warn};
+use pasetors::claims::Claims;
 use rocket::async_trait;
+use rocket::mtls::{x509::GeneralName, Certificate};
+use rocket::serde::json::Json;
 use rocket::{
     http::Status,
     request::{FromRequest, Outcome},
     Request, State,
 };
-use std::sync::Arc;
+use std::{future::Future, net::IpAddr, sync::Arc};
 
-use ec_secrets_shared_library::repositories::keys::KeyRepository;
+use ec_secrets_shared_library::{
+    models::{AuditEvent, AuditOutcome, ADMIN_ROLE, APPROVER_ROLE, BREAK_GLASS_ROLE},
+    repositories::{
+        audit::AuditRepository, keys::KeyRepository, login_attempts::LoginAttemptRepository,
+        revocations::RevocationRepository,
+    },
+};
 
 pub struct TokenGuard(pub Claims);
 
+impl TokenGuard {
+    /// Policies carried by machine tokens; `None` for interactive user tokens,
+    /// which are not restricted beyond secret ownership.
+    pub fn policies(&self) -> Option<Vec<String>> {
+        self.0
+            .get_claim("policies")
+            .and_then(|policies| serde_json::from_value(policies.clone()).ok())
+    }
+
+    /// The subject (user email) the token was issued to.
+    pub fn subject(&self) -> Option<&str> {
+        self.0.get_claim("sub").and_then(|subject| subject.as_str())
+    }
+
+    /// The unique identifier of this token.
+    pub fn token_id(&self) -> Option<&str> {
+        self.0.get_claim("jti").and_then(|jti| jti.as_str())
+    }
+
+    /// When this token stops being valid.
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        claim_timestamp(&self.0, "exp")
+    }
+
+    /// Roles granted to the user when the token was issued.
+    pub fn roles(&self) -> Vec<String> {
+        self.0
+            .get_claim("roles")
+            .and_then(|roles| serde_json::from_value(roles.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    /// Whether this is a user token holding the `admin` role.
+    pub fn is_admin(&self) -> bool {
+        self.policies().is_none() && self.roles().iter().any(|role| role == ADMIN_ROLE)
+    }
+
+    /// Whether this token may read or change the account registered under
+    /// `email`: the user's own token, or an administrator's.
+    pub fn may_manage_account(&self, email: &str) -> bool {
+        self.is_admin() || (self.policies().is_none() && self.subject() == Some(email))
+    }
+
+    /// Whether this is a user token holding the `approver` or `admin` role,
+    /// which may decide on access requests for protected secrets.
+    pub fn can_approve(&self) -> bool {
+        self.policies().is_none()
+            && self
+                .roles()
+                .iter()
+                .any(|role| role == ADMIN_ROLE || role == APPROVER_ROLE)
+    }
+
+    /// Whether this is a user token holding the `breakglass` role, which may
+    /// open emergency access sessions.
+    pub fn can_break_glass(&self) -> bool {
+        self.policies().is_none() && self.roles().iter().any(|role| role == BREAK_GLASS_ROLE)
+    }
+
+    /// Whether this token may encrypt and decrypt with the transit key `name`:
+    /// machine tokens need a policy matching `transit/<name>`, user tokens
+    /// the `admin` role.
+    pub fn can_use_transit(&self, name: &str) -> bool {
+        match self.policies() {
+            Some(policies) => is_permitted(&policies, &format!("transit/{name}")),
+            None => self.roles().iter().any(|role| role == ADMIN_ROLE),
+        }
+    }
+
+    /// Returns `true` when this token may access the secret stored under `key`.
+    pub fn permits(&self, key: &str) -> bool {
+        match self.policies() {
+            Some(policies) => is_permitted(&policies, key),
+            None => true,
+        }
+    }
+}
+
 #[async_trait]
 impl<'r> FromRequest<'r> for TokenGuard {
     type Error = Status;
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let key_repo = match request.guard::<&State<Arc<KeyRepository>>>().await {
+        let config = match request.guard::<&State<AuthConfig>>().await {
             Outcome::Success(state) => state,
-            _ => return Outcome::Forward(Status::InternalServerError),
+            _ => return Outcome::Error((Status::InternalServerError, Status::InternalServerError)),
+        };
+
+        let claims = match request.headers().get_one("Authorization") {
+            Some(header) if header.starts_with("Bearer ") => {
+                let token = header.trim_start_matches("Bearer ").trim();
+                match bearer_claims(request, config, token).await {
+                    Ok(claims) => claims,
+                    Err(status) => return Outcome::Error((status, status)),
+                }
+            }
+            // Services may present a client certificate instead of a token.
+            _ => match client_certificate_claims(request, config).await {
+                Some(claims) => claims,
+                None => return Outcome::Error((Status::Unauthorized, Status::Unauthorized)),
+            },
+        };
+
+        match is_revoked(request, &claims).await {
+            Ok(false) => Outcome::Success(TokenGuard(claims)),
+            Ok(true) => Outcome::Error((Status::Unauthorized, Status::Unauthorized)),
+            Err(status) => Outcome::Error((status, status)),
+        }
+    }
+}
+
+async fn bearer_claims(
+    request: &Request<'_>,
+    config: &AuthConfig,
+    token: &str,
+) -> Result<Claims, Status> {
+    let key_repo = match request.guard::<&State<Arc<KeyRepository>>>().await {
+        Outcome::Success(state) => state,
+        _ => return Err(Status::InternalServerError),
+    };
+    let Ok((_, public_key)) = decode_keys(key_repo).await else {
+        return Err(Status::InternalServerError);
+    };
+
+    TokenValidator::from_env(config)
+        .validate(token, &public_key)
+        .map_err(|e| {
+            warn!("Rejected bearer token: {}", e);
+            Status::Unauthorized
+        })
+}
+
+/*---------------------------------------------------------------
+Map a client certificate, already verified against the configured
+CA bundle during the TLS handshake, to a service identity.
+----------------------------------------------------------------*/
+async fn client_certificate_claims(request: &Request<'_>, config: &AuthConfig) -> Option<Claims> {
+    if !config.mtls.enabled {
+        return None;
+    }
+    let certificate = request.guard::<Certificate<'_>>().await.succeeded()?;
+
+    let common_names: Vec<&str> = certificate.subject().common_names().collect();
+    let alt_names = subject_alt_names(&certificate);
+    let Some(identity) = config.mtls.resolve(&common_names, &alt_names) else {
+        warn!(
+            "No service identity for client certificate {}",
+            certificate.subject()
+        );
+        return None;
+    };
+
+    let validity = certificate.validity();
+    let not_before = DateTime::from_timestamp(validity.not_before.timestamp(), 0)?;
+    let not_after = DateTime::from_timestamp(validity.not_after.timestamp(), 0)?;
+    certificate_claims(identity, not_before, not_after)
+        .map_err(|e| warn!("Failed to build claims for {}: {}", identity.name, e))
+        .ok()
+}
+
+/// DNS, URI and email subject alternative names of a certificate.
+fn subject_alt_names<'a>(certificate: &'a Certificate<'a>) -> Vec<&'a str> {
+    let Ok(Some(extension)) = certificate.subject_alternative_name() else {
+        return vec![];
+    };
+    extension
+        .value
+        .general_names
+        .iter()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(name) | GeneralName::URI(name) | GeneralName::RFC822Name(name) => {
+                Some(*name)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn claim_timestamp(claims: &Claims, claim: &str) -> Option<DateTime<Utc>> {
+    claims
+        .get_claim(claim)
+        .and_then(|value| value.as_str())
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .map(|value| value.with_timezone(&Utc))
+}
+
+/*---------------------------------------------------------------
+Check a verified token against the revocation list, which is
+served from memory and only periodically reloaded from MongoDB.
+----------------------------------------------------------------*/
+async fn is_revoked(request: &Request<'_>, claims: &Claims) -> Result<bool, Status> {
+    let revocations = match request.guard::<&State<Arc<RevocationRepository>>>().await {
+        Outcome::Success(state) => state,
+        _ => return Err(Status::InternalServerError),
+    };
+
+    let subject = claims
+        .get_claim("sub")
+        .and_then(|subject| subject.as_str())
+        .ok_or(Status::Unauthorized)?;
+    let issued_at = claim_timestamp(claims, "iat").ok_or(Status::Unauthorized)?;
+    let jti = claims.get_claim("jti").and_then(|jti| jti.as_str());
+
+    revocations
+        .is_revoked(jti, subject, issued_at)
+        .await
+        .map_err(|_| Status::InternalServerError)
+}
+
+/*---------------------------------------------------------------
+A user token carrying the admin role. Machine tokens never qualify.
+----------------------------------------------------------------*/
+pub struct AdminGuard(pub TokenGuard);
+
+#[async_trait]
+impl<'r> FromRequest<'r> for AdminGuard {
+    type Error = Status;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = match TokenGuard::from_request(request).await {
+            Outcome::Success(token) => token,
+            Outcome::Error(error) => return Outcome::Error(error),
+            Outcome::Forward(status) => return Outcome::Forward(status),
         };
 
-        let auth_header = request.headers().get_one("Authorization");
-
-        match auth_header {
-            Some(token) if token.starts_with("Bearer ") => {
-                let token = token.trim_start_matches("Bearer ").trim();
-                let validation_rules = ClaimsValidationRules::new();
-                if let Ok(untrusted_token) = UntrustedToken::<Public, V4>::try_from(token) {
-                    if let Ok(kp) = decode_keys(key_repo).await {
-                        if let Ok(trusted_token) =
-                            public::verify(&kp.1, &untrusted_token, &validation_rules, None, None)
-                        {
-                            if let Some(claims) = trusted_token.payload_claims() {
-                                Outcome::Success(TokenGuard(claims.clone()))
-                            } else {
-                                Outcome::Error((Status::Unauthorized, Status::Unauthorized))
-                            }
-                        } else {
-                            Outcome::Error((
-                                Status::InternalServerError,
-                                Status::InternalServerError,
-                            ))
-                        }
-                    } else {
-                        Outcome::Error((Status::InternalServerError, Status::InternalServerError))
-                    }
-                } else {
-                    Outcome::Error((Status::InternalServerError, Status::InternalServerError))
+        if token.is_admin() {
+            Outcome::Success(AdminGuard(token))
+        } else {
+            Outcome::Error((Status::Forbidden, Status::Forbidden))
+        }
+    }
+}
+
+/*---------------------------------------------------------------
+Present while the master key is loaded. Routes that encrypt or
+decrypt take it first, so a sealed server answers them with
+503 Service Unavailable.
+----------------------------------------------------------------*/
+pub struct Unsealed;
+
+#[async_trait]
+impl<'r> FromRequest<'r> for Unsealed {
+    type Error = Status;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.guard::<&State<Arc<Keyring>>>().await {
+            Outcome::Success(keyring) if !keyring.is_sealed() => Outcome::Success(Unsealed),
+            Outcome::Success(_) => {
+                Outcome::Error((Status::ServiceUnavailable, Status::ServiceUnavailable))
+            }
+            _ => Outcome::Error((Status::InternalServerError, Status::InternalServerError)),
+        }
+    }
+}
+
+/*---------------------------------------------------------------
+Failed-login bookkeeping for a password login, together with the
+client address it comes from. The address honours the `ip_header`
+set in the Rocket configuration when the server sits behind a proxy.
+----------------------------------------------------------------*/
+pub struct LoginThrottle {
+    pub attempts: Arc<LoginAttemptRepository>,
+    pub client_address: Option<IpAddr>,
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for LoginThrottle {
+    type Error = Status;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.guard::<&State<Arc<LoginAttemptRepository>>>().await {
+            Outcome::Success(attempts) => Outcome::Success(LoginThrottle {
+                attempts: Arc::clone(attempts),
+                client_address: request.client_ip(),
+            }),
+            _ => Outcome::Error((Status::InternalServerError, Status::InternalServerError)),
+        }
+    }
+}
+
+/*---------------------------------------------------------------
+The audit log, together with the client address and user agent of
+the request being audited.
+----------------------------------------------------------------*/
+pub struct AuditTrail {
+    pub log: Arc<AuditRepository>,
+    pub exporter: Option<Arc<AuditExporter>>,
+    pub source_ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+impl AuditTrail {
+    /// Appends an event to the audit log. A failed write is reported but
+    /// never fails the request being audited.
+    pub async fn record(
+        &self,
+        actor: &str,
+        action: &str,
+        target: Option<&str>,
+        outcome: AuditOutcome,
+        detail: Option<String>,
+    ) {
+        let event = AuditEvent {
+            actor: actor.to_string(),
+            action: action.to_string(),
+            target: target.map(str::to_string),
+            outcome,
+            source_ip: self.source_ip.map(|address| address.to_string()),
+            user_agent: self.user_agent.clone(),
+            detail,
+        };
+        info!(
+            target: "audit",
+            "{} {} {} ({}){}",
+            event.actor,
+            event.action,
+            event.target.as_deref().unwrap_or("-"),
+            event.outcome,
+            event
+                .detail
+                .as_deref()
+                .map(|detail| format!(": {}", detail))
+                .unwrap_or_default()
+        );
+        match self.log.append(event).await {
+            Ok(entry) => {
+                if let Some(exporter) = &self.exporter {
+                    exporter.export(entry.into());
                 }
             }
-            _ => Outcome::Error((Status::Unauthorized, Status::Unauthorized)),
+            Err(e) => error!("Failed to write the audit log: {:?}", e),
+        }
+    }
+
+    /// Records the outcome of a route: refusals (401, 403, 429) are `denied`,
+    /// other errors `failure` with the error message as detail.
+    pub async fn record_result<T>(
+        &self,
+        actor: &str,
+        action: &str,
+        target: Option<&str>,
+        result: &Result<T, Json<ErrorResponse>>,
+    ) {
+        self.record_result_with(actor, action, target, result, |_| None)
+            .await
+    }
+
+    /// Like `record_result`, with a detail describing a successful outcome.
+    pub async fn record_result_with<T>(
+        &self,
+        actor: &str,
+        action: &str,
+        target: Option<&str>,
+        result: &Result<T, Json<ErrorResponse>>,
+        detail: impl FnOnce(&T) -> Option<String>,
+    ) {
+        let (outcome, detail) = match result {
+            Ok(value) => (AuditOutcome::Success, detail(value)),
+            Err(error) if matches!(error.status, 401 | 403 | 429) => {
+                (AuditOutcome::Denied, Some(error.message.clone()))
+            }
+            Err(error) => (AuditOutcome::Failure, Some(error.message.clone())),
+        };
+        self.record(actor, action, target, outcome, detail).await;
+    }
+
+    /// Runs the work of a route and records its outcome as `record_result`
+    /// does.
+    pub async fn audited<T>(
+        &self,
+        actor: &str,
+        action: &str,
+        target: Option<&str>,
+        work: impl Future<Output = Result<T, Json<ErrorResponse>>>,
+    ) -> Result<T, Json<ErrorResponse>> {
+        self.audited_with(actor, action, target, work, |_| None)
+            .await
+    }
+
+    /// Like `audited`, with a detail describing a successful outcome.
+    pub async fn audited_with<T>(
+        &self,
+        actor: &str,
+        action: &str,
+        target: Option<&str>,
+        work: impl Future<Output = Result<T, Json<ErrorResponse>>>,
+        detail: impl FnOnce(&T) -> Option<String>,
+    ) -> Result<T, Json<ErrorResponse>> {
+        let result = work.await;
+        self.record_result_with(actor, action, target, &result, detail)
+            .await;
+        result
+    }
+
+    /// Like `audited`, for routes that learn who the actor is from their own
+    /// work, such as logins; the work yields the actor with its response, and
+    /// failures are recorded as `anonymous`.
+    pub async fn audited_as<T>(
+        &self,
+        action: &str,
+        target: Option<&str>,
+        work: impl Future<Output = Result<(String, T), Json<ErrorResponse>>>,
+    ) -> Result<Json<T>, Json<ErrorResponse>> {
+        let (actor, result) = match work.await {
+            Ok((actor, response)) => (actor, Ok(Json(response))),
+            Err(error) => ("anonymous".to_string(), Err(error)),
+        };
+        self.record_result(&actor, action, target, &result).await;
+        result
+    }
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for AuditTrail {
+    type Error = Status;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.guard::<&State<Arc<AuditRepository>>>().await {
+            Outcome::Success(log) => Outcome::Success(AuditTrail {
+                log: Arc::clone(log),
+                exporter: request.rocket().state::<Arc<AuditExporter>>().cloned(),
+                source_ip: request.client_ip(),
+                user_agent: request.headers().get_one("User-Agent").map(str::to_string),
+            }),
+            _ => Outcome::Error((Status::InternalServerError, Status::InternalServerError)),
         }
     }
 }
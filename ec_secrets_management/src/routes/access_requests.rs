@@ -0,0 +1,575 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::ErrorResponse;
+use crate::request_guards::{AuditTrail, TokenGuard, Unsealed};
+use ec_secrets_shared_library::{
+    models::{
+        AccessDecision, AccessRequest, AccessRequestCreate, AccessRequestDocument,
+        AccessRequestStatus, ReleasedSecret, UserStatus, ADMIN_ROLE, APPROVER_ROLE,
+    },
+    repositories::{
+        access_requests::AccessRequestRepository, users::UserRepository, vault::VaultRepository,
+    },
+    utils::{
+        approval::{ApprovalEvent, ApprovalNotice, ApprovalNotifier},
+        auth::AuthConfig,
+    },
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use chrono::Utc;
+use log::{error, info};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, post, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+fn error_response(status: Status, message: &str) -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        status: status.code,
+        message: message.to_string(),
+    })
+}
+
+fn internal_error() -> Json<ErrorResponse> {
+    error_response(Status::InternalServerError, "Internal server error")
+}
+
+fn request_not_found() -> Json<ErrorResponse> {
+    error_response(Status::NotFound, "Access request not found")
+}
+
+async fn owner_of(users: &UserRepository, subject: &str) -> Result<String, Json<ErrorResponse>> {
+    users.owner_id(subject).await.map_err(|e| {
+        error!("Failed to resolve the owner {}: {:?}", subject, e);
+        internal_error()
+    })
+}
+
+/*----------------------------------------------------------------
+ Notify in the background; a slow or failing channel must not hold
+ up or fail the request
+-----------------------------------------------------------------*/
+fn notify(notifier: &Arc<dyn ApprovalNotifier>, notice: ApprovalNotice) {
+    if notice.recipients.is_empty() {
+        return;
+    }
+    let notifier = Arc::clone(notifier);
+    rocket::tokio::spawn(async move {
+        if let Err(e) = notifier.notify(&notice).await {
+            error!(
+                "Failed to send notice about access request {}: {}",
+                notice.request.id, e
+            );
+        }
+    });
+}
+
+/// Active users other than the requester who may approve a request.
+async fn approvers_for(
+    users: &UserRepository,
+    request: &AccessRequestDocument,
+) -> Result<Vec<String>, Json<ErrorResponse>> {
+    let all_users = users.list_users().await.map_err(|e| {
+        error!("Failed to list approvers: {:?}", e);
+        internal_error()
+    })?;
+    Ok(all_users
+        .into_iter()
+        .filter(|user| {
+            user.status == UserStatus::Active
+                && user.email != request.requester
+                && user.id.to_hex() != request.owner_id
+                && user
+                    .roles
+                    .iter()
+                    .any(|role| role == ADMIN_ROLE || role == APPROVER_ROLE)
+        })
+        .map(|user| user.email)
+        .collect())
+}
+
+/*----------------------------------------------------------------
+ Ask to read a protected secret, stating why. Secrets that need no
+ approval are released right away for the access window
+-----------------------------------------------------------------*/
+#[post("/vault/entries/<id>/access", data = "<request>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn request_access(
+    _unsealed: Unsealed,
+    repo: &State<Arc<AccessRequestRepository>>,
+    vault: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    notifier: &State<Arc<dyn ApprovalNotifier>>,
+    config: &State<AuthConfig>,
+    id: &str,
+    request: Json<AccessRequestCreate>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<AccessRequest>, Json<ErrorResponse>> {
+    let subject = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited_with(
+            &subject,
+            "secret.access.request",
+            Some(id),
+            request_access_inner(
+                repo, vault, users, notifier, config, id, request, token, &subject,
+            ),
+            |created| {
+                Some(format!(
+                    "request {} ({}): {}",
+                    created.id, created.status, created.justification
+                ))
+            },
+        )
+        .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn request_access_inner(
+    repo: &State<Arc<AccessRequestRepository>>,
+    vault: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    notifier: &State<Arc<dyn ApprovalNotifier>>,
+    config: &State<AuthConfig>,
+    id: &str,
+    request: Json<AccessRequestCreate>,
+    token: TokenGuard,
+    subject: &str,
+) -> Result<Json<AccessRequest>, Json<ErrorResponse>> {
+    if subject.is_empty() {
+        return Err(error_response(
+            Status::Unauthorized,
+            "Insufficient Permissions",
+        ));
+    }
+    let justification = config
+        .approvals
+        .justification(&request.justification)
+        .map_err(|message| error_response(Status::BadRequest, &message))?;
+
+    let owner_id = owner_of(users, subject).await?;
+    let entry = match vault.get_entry(id).await {
+        Ok(Some(entry)) if entry.owner_id == owner_id && entry.archived_at.is_none() => entry,
+        Ok(_) => return Err(error_response(Status::NotFound, "Vault entry not found.")),
+        Err(e) => {
+            error!("Failed to resolve vault entry {}: {:?}", id, e);
+            return Err(internal_error());
+        }
+    };
+    if !token.permits(&entry.key) {
+        return Err(error_response(
+            Status::Forbidden,
+            "Insufficient Permissions",
+        ));
+    }
+    if entry.protection.is_none() {
+        return Err(error_response(
+            Status::BadRequest,
+            "Vault entry is not protected; read it directly",
+        ));
+    }
+
+    let created = repo
+        .create(
+            &entry,
+            subject,
+            &justification,
+            config.approvals.request_lifetime(),
+            config.approvals.access_window(),
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to create access request for {}: {:?}", id, e);
+            internal_error()
+        })?;
+
+    if created.status == AccessRequestStatus::Pending {
+        let recipients = approvers_for(users, &created).await?;
+        info!(
+            "Access to {} requested by {}; {} approvers notified.",
+            created.secret_key,
+            subject,
+            recipients.len()
+        );
+        notify(
+            notifier,
+            ApprovalNotice {
+                event: ApprovalEvent::Requested,
+                request: AccessRequest::from(created.clone()),
+                recipients,
+            },
+        );
+    }
+    Ok(Json(AccessRequest::from(created)))
+}
+
+/*----------------------------------------------------------------
+ List access requests, newest first. Approvers see every request,
+ everyone else only their own
+-----------------------------------------------------------------*/
+#[get("/access-requests?<status>&<limit>")]
+pub async fn list_requests(
+    repo: &State<Arc<AccessRequestRepository>>,
+    status: Option<&str>,
+    limit: Option<i64>,
+    token: TokenGuard,
+) -> Result<Json<Vec<AccessRequest>>, Json<ErrorResponse>> {
+    let Some(subject) = token.subject() else {
+        return Err(error_response(
+            Status::Unauthorized,
+            "Insufficient Permissions",
+        ));
+    };
+    let status = status
+        .map(str::parse::<AccessRequestStatus>)
+        .transpose()
+        .map_err(|message| error_response(Status::BadRequest, &message))?;
+    let requester = (!token.can_approve()).then_some(subject);
+
+    match repo
+        .list(
+            requester,
+            status,
+            limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+        )
+        .await
+    {
+        Ok(requests) => Ok(Json(
+            requests.into_iter().map(AccessRequest::from).collect(),
+        )),
+        Err(e) => {
+            error!("Failed to list access requests: {:?}", e);
+            Err(internal_error())
+        }
+    }
+}
+
+/*---------------------------------------
+ Retrieve an access request by id
+----------------------------------------*/
+#[get("/access-requests/<request_id>")]
+pub async fn get_request(
+    repo: &State<Arc<AccessRequestRepository>>,
+    request_id: &str,
+    token: TokenGuard,
+) -> Result<Json<AccessRequest>, Json<ErrorResponse>> {
+    match repo.get(request_id).await {
+        Ok(Some(request))
+            if token.can_approve() || token.subject() == Some(request.requester.as_str()) =>
+        {
+            Ok(Json(AccessRequest::from(request)))
+        }
+        Ok(_) => Err(request_not_found()),
+        Err(e) => {
+            error!("Failed to retrieve access request {}: {:?}", request_id, e);
+            Err(internal_error())
+        }
+    }
+}
+
+/*----------------------------------------------------------------
+ Approve or deny a pending request. The deciding user must hold
+ the approver or admin role and may not decide on their own request
+-----------------------------------------------------------------*/
+#[allow(clippy::too_many_arguments)]
+async fn decide(
+    repo: &AccessRequestRepository,
+    users: &UserRepository,
+    notifier: &Arc<dyn ApprovalNotifier>,
+    config: &AuthConfig,
+    request_id: &str,
+    decision: Option<AccessDecision>,
+    approve: bool,
+    token: &TokenGuard,
+    audit: &AuditTrail,
+) -> Result<Json<AccessRequest>, Json<ErrorResponse>> {
+    let subject = token.subject().unwrap_or_default().to_string();
+    let action = if approve {
+        "secret.access.approve"
+    } else {
+        "secret.access.deny"
+    };
+    let comment = decision
+        .and_then(|decision| decision.comment)
+        .map(|comment| comment.trim().to_string())
+        .filter(|comment| !comment.is_empty());
+
+    audit
+        .audited_with(
+            &subject,
+            action,
+            Some(request_id),
+            decide_inner(
+                repo, users, notifier, config, request_id, approve, token, &subject, &comment,
+            ),
+            |decided| {
+                Some(match &comment {
+                    Some(comment) => format!(
+                        "{} for {}: {}",
+                        decided.secret_key, decided.requester, comment
+                    ),
+                    None => format!("{} for {}", decided.secret_key, decided.requester),
+                })
+            },
+        )
+        .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn decide_inner(
+    repo: &AccessRequestRepository,
+    users: &UserRepository,
+    notifier: &Arc<dyn ApprovalNotifier>,
+    config: &AuthConfig,
+    request_id: &str,
+    approve: bool,
+    token: &TokenGuard,
+    subject: &str,
+    comment: &Option<String>,
+) -> Result<Json<AccessRequest>, Json<ErrorResponse>> {
+    if !token.can_approve() {
+        return Err(error_response(
+            Status::Forbidden,
+            "Only approvers can decide on access requests",
+        ));
+    }
+    let decider_id = owner_of(users, subject).await?;
+
+    let decided = repo
+        .decide(
+            request_id,
+            subject,
+            &decider_id,
+            approve,
+            comment.as_deref(),
+            config.approvals.access_window(),
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to decide on access request {}: {:?}", request_id, e);
+            internal_error()
+        })?;
+
+    let Some(decided) = decided else {
+        // Work out why the request could not be decided on.
+        return Err(match repo.get(request_id).await {
+            Ok(Some(request)) if request.requester == subject || request.owner_id == decider_id => {
+                error_response(
+                    Status::Forbidden,
+                    "You cannot decide on your own access request",
+                )
+            }
+            Ok(Some(_)) => error_response(Status::Conflict, "Access request is no longer pending"),
+            Ok(None) => request_not_found(),
+            Err(e) => {
+                error!("Failed to retrieve access request {}: {:?}", request_id, e);
+                internal_error()
+            }
+        });
+    };
+
+    info!(
+        "Access request {} for {} {} by {}.",
+        request_id,
+        decided.secret_key,
+        if approve { "approved" } else { "denied" },
+        subject
+    );
+    let decided = AccessRequest::from(decided);
+    notify(
+        notifier,
+        ApprovalNotice {
+            event: if approve {
+                ApprovalEvent::Approved
+            } else {
+                ApprovalEvent::Denied
+            },
+            request: decided.clone(),
+            recipients: vec![decided.requester.clone()],
+        },
+    );
+    Ok(Json(decided))
+}
+
+#[post("/access-requests/<request_id>/approve", data = "<decision>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn approve_request(
+    repo: &State<Arc<AccessRequestRepository>>,
+    users: &State<Arc<UserRepository>>,
+    notifier: &State<Arc<dyn ApprovalNotifier>>,
+    config: &State<AuthConfig>,
+    request_id: &str,
+    decision: Option<Json<AccessDecision>>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<AccessRequest>, Json<ErrorResponse>> {
+    decide(
+        repo,
+        users,
+        notifier,
+        config,
+        request_id,
+        decision.map(Json::into_inner),
+        true,
+        &token,
+        &audit,
+    )
+    .await
+}
+
+#[post("/access-requests/<request_id>/deny", data = "<decision>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn deny_request(
+    repo: &State<Arc<AccessRequestRepository>>,
+    users: &State<Arc<UserRepository>>,
+    notifier: &State<Arc<dyn ApprovalNotifier>>,
+    config: &State<AuthConfig>,
+    request_id: &str,
+    decision: Option<Json<AccessDecision>>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<AccessRequest>, Json<ErrorResponse>> {
+    decide(
+        repo,
+        users,
+        notifier,
+        config,
+        request_id,
+        decision.map(Json::into_inner),
+        false,
+        &token,
+        &audit,
+    )
+    .await
+}
+
+/*----------------------------------------------------------------
+ Read the secret behind an approved request while its access window
+ is open
+-----------------------------------------------------------------*/
+#[get("/access-requests/<request_id>/secret")]
+pub async fn release_secret(
+    _unsealed: Unsealed,
+    repo: &State<Arc<AccessRequestRepository>>,
+    vault: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    request_id: &str,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<ReleasedSecret>, Json<ErrorResponse>> {
+    let subject = token.subject().unwrap_or_default().to_string();
+    let mut secret_id = None;
+
+    let result = release_secret_inner(
+        repo,
+        vault,
+        users,
+        request_id,
+        token,
+        &subject,
+        &mut secret_id,
+    )
+    .await;
+
+    audit
+        .record_result_with(
+            &subject,
+            "secret.reveal",
+            Some(secret_id.as_deref().unwrap_or(request_id)),
+            &result,
+            |_| Some(format!("access request {}", request_id)),
+        )
+        .await;
+    result
+}
+
+async fn release_secret_inner(
+    repo: &State<Arc<AccessRequestRepository>>,
+    vault: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    request_id: &str,
+    token: TokenGuard,
+    subject: &str,
+    secret_id: &mut Option<String>,
+) -> Result<Json<ReleasedSecret>, Json<ErrorResponse>> {
+    let request = match repo.get(request_id).await {
+        Ok(Some(request)) if request.requester == subject => request,
+        Ok(_) => return Err(request_not_found()),
+        Err(e) => {
+            error!("Failed to retrieve access request {}: {:?}", request_id, e);
+            return Err(internal_error());
+        }
+    };
+    *secret_id = Some(request.secret_id.clone());
+
+    if owner_of(users, subject).await? != request.owner_id {
+        return Err(request_not_found());
+    }
+    let Some(access_until) = request
+        .access_until
+        .filter(|_| request.grants_access(Utc::now()))
+    else {
+        return Err(error_response(
+            Status::Forbidden,
+            "Access request is not approved or its access window has closed",
+        ));
+    };
+    if !token.permits(&request.secret_key) {
+        return Err(error_response(
+            Status::Forbidden,
+            "Insufficient Permissions",
+        ));
+    }
+
+    match vault
+        .get_protected_secret(&request.secret_id, &request.owner_id)
+        .await
+    {
+        Ok(Some(entry)) => {
+            info!(
+                "Released vault entry {} for access request {}.",
+                request.secret_id, request_id
+            );
+            Ok(Json(ReleasedSecret {
+                id: entry.id.to_hex(),
+                key: entry.key,
+                value: entry.value,
+                access_until: access_until.to_rfc3339(),
+            }))
+        }
+        Ok(None) => Err(error_response(
+            Status::NotFound,
+            "Vault entry not found or no longer protected.",
+        )),
+        Err(e) => {
+            error!(
+                "Failed to release vault entry {}: {:?}",
+                request.secret_id, e
+            );
+            Err(internal_error())
+        }
+    }
+}
+
+pub fn access_request_routes() -> Vec<rocket::Route> {
+    routes![
+        request_access,
+        list_requests,
+        get_request,
+        approve_request,
+        deny_request,
+        release_secret
+    ]
+}
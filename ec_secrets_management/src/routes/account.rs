@@ -0,0 +1,357 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::{AccountResponse, ErrorResponse};
+use crate::request_guards::{AuditTrail, LoginThrottle, TokenGuard};
+use crate::routes::totp::account_owner;
+use crate::routes::users::{revoke_all_sessions, validate_credentials};
+use ec_secrets_shared_library::{
+    models::{PasswordForgotRequest, PasswordResetRequest, UserCredentials},
+    repositories::{
+        account_tokens::{AccountTokenRepository, EMAIL_VERIFICATION, PASSWORD_RESET},
+        refresh_tokens::RefreshTokenRepository,
+        revocations::RevocationRepository,
+        users::UserRepository,
+    },
+    utils::{
+        auth::{hash_password, AuthConfig},
+        mail::{password_reset_message, verification_message, MailTransport},
+    },
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use log::{error, info, warn};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, post, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+fn internal_error() -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        status: Status::InternalServerError.code,
+        message: "Internal server error".to_string(),
+    })
+}
+
+fn invalid_token() -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        status: Status::BadRequest.code,
+        message: "Invalid or expired token".to_string(),
+    })
+}
+
+/*---------------------------------------------------------------
+ Mail a fresh verification link, replacing any earlier one
+----------------------------------------------------------------*/
+pub(crate) async fn send_verification(
+    email: &str,
+    token_repo: &AccountTokenRepository,
+    mailer: &dyn MailTransport,
+    config: &AuthConfig,
+) -> Result<(), String> {
+    let lifetime = config.email_verification_lifetime();
+    let token = token_repo
+        .issue(email, EMAIL_VERIFICATION, lifetime)
+        .await
+        .map_err(|e| e.to_string())?;
+    mailer
+        .send(&verification_message(&config.mail, email, &token, lifetime))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/*--------------------------------------------------------
+ Send the signed-in user a new email verification link
+---------------------------------------------------------*/
+#[post("/email/verification")]
+pub async fn request_verification(
+    repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<AccountTokenRepository>>,
+    mailer: &State<Arc<dyn MailTransport>>,
+    config: &State<AuthConfig>,
+    token: TokenGuard,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    let subject = account_owner(&token)?;
+
+    let user = match repo.get_user_by_email(subject).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return Err(Json(ErrorResponse {
+                status: Status::NotFound.code,
+                message: "User not found".to_string(),
+            }))
+        }
+        Err(e) => {
+            error!("Failed to look up {}: {:?}", subject, e);
+            return Err(internal_error());
+        }
+    };
+    if user.email_verified {
+        return Err(Json(ErrorResponse {
+            status: Status::Conflict.code,
+            message: "Email address is already verified".to_string(),
+        }));
+    }
+
+    if let Err(e) = send_verification(&user.email, token_repo, mailer.as_ref(), config).await {
+        error!("Failed to send verification mail to {}: {}", user.email, e);
+        return Err(internal_error());
+    }
+
+    Ok(Json(AccountResponse {
+        status: Status::Ok.code,
+        message: "Verification email sent".to_string(),
+    }))
+}
+
+/*----------------------------------------------
+ Confirm an email address from a mailed link
+-----------------------------------------------*/
+#[get("/email/verify?<token>")]
+pub async fn verify_email(
+    repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<AccountTokenRepository>>,
+    token: &str,
+    audit: AuditTrail,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    audit
+        .audited_as(
+            "user.email_verify",
+            None,
+            verify_email_inner(repo, token_repo, token),
+        )
+        .await
+}
+
+async fn verify_email_inner(
+    repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<AccountTokenRepository>>,
+    token: &str,
+) -> Result<(String, AccountResponse), Json<ErrorResponse>> {
+    let verification = match token_repo.consume(token, EMAIL_VERIFICATION).await {
+        Ok(Some(verification)) => verification,
+        Ok(None) => return Err(invalid_token()),
+        Err(e) => {
+            error!("Failed to load email verification: {:?}", e);
+            return Err(internal_error());
+        }
+    };
+
+    match repo.mark_email_verified(&verification.email).await {
+        Ok(true) => {
+            info!("{} verified their email address.", verification.email);
+            Ok((
+                verification.email,
+                AccountResponse {
+                    status: Status::Ok.code,
+                    message: "Email address verified".to_string(),
+                },
+            ))
+        }
+        // The address changed (or the account went away) since the mail was sent.
+        Ok(false) => Err(invalid_token()),
+        Err(e) => {
+            error!("Failed to verify {}: {:?}", verification.email, e);
+            Err(internal_error())
+        }
+    }
+}
+
+/*---------------------------------------------------------------
+ Mail a password reset link. The answer is the same whether or
+ not the account exists, so it cannot be used to probe for users
+----------------------------------------------------------------*/
+#[post("/password/forgot", data = "<request>")]
+pub async fn forgot_password(
+    repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<AccountTokenRepository>>,
+    mailer: &State<Arc<dyn MailTransport>>,
+    config: &State<AuthConfig>,
+    request: Json<PasswordForgotRequest>,
+    audit: AuditTrail,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    let actor = request.email.clone();
+    audit
+        .audited(
+            &actor,
+            "user.password_forgot",
+            None,
+            forgot_password_inner(repo, token_repo, mailer, config, request),
+        )
+        .await
+}
+
+async fn forgot_password_inner(
+    repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<AccountTokenRepository>>,
+    mailer: &State<Arc<dyn MailTransport>>,
+    config: &State<AuthConfig>,
+    request: Json<PasswordForgotRequest>,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    match repo.get_user_by_email(&request.email).await {
+        Ok(Some(user)) => {
+            let lifetime = config.password_reset_lifetime();
+            let token = match token_repo
+                .issue(&user.email, PASSWORD_RESET, lifetime)
+                .await
+            {
+                Ok(token) => token,
+                Err(e) => {
+                    error!("Failed to issue password reset for {}: {:?}", user.email, e);
+                    return Err(internal_error());
+                }
+            };
+            let message = password_reset_message(&config.mail, &user.email, &token, lifetime);
+
+            // Deliver in the background so response times don't reveal the account.
+            let mailer = Arc::clone(mailer);
+            rocket::tokio::spawn(async move {
+                match mailer.send(&message).await {
+                    Ok(()) => info!("Password reset requested for {}.", message.to),
+                    Err(e) => error!("Failed to send password reset to {}: {}", message.to, e),
+                }
+            });
+        }
+        Ok(None) => warn!(
+            "Password reset requested for unknown account {}.",
+            request.email
+        ),
+        Err(e) => {
+            error!("Failed to look up {}: {:?}", request.email, e);
+            return Err(internal_error());
+        }
+    }
+
+    Ok(Json(AccountResponse {
+        status: Status::Ok.code,
+        message: "If the account exists, a password reset link has been sent".to_string(),
+    }))
+}
+
+/*---------------------------------------------------------------
+ Set a new password with a mailed reset token and sign the user
+ out everywhere
+----------------------------------------------------------------*/
+#[post("/password/reset", data = "<request>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn reset_password(
+    repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<AccountTokenRepository>>,
+    revocations: &State<Arc<RevocationRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    config: &State<AuthConfig>,
+    throttle: LoginThrottle,
+    request: Json<PasswordResetRequest>,
+    audit: AuditTrail,
+) -> Result<Json<AccountResponse>, Json<ErrorResponse>> {
+    audit
+        .audited_as(
+            "user.password_reset",
+            None,
+            reset_password_inner(
+                repo,
+                token_repo,
+                revocations,
+                refresh_repo,
+                config,
+                throttle,
+                request,
+            ),
+        )
+        .await
+}
+
+async fn reset_password_inner(
+    repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<AccountTokenRepository>>,
+    revocations: &State<Arc<RevocationRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    config: &State<AuthConfig>,
+    throttle: LoginThrottle,
+    request: Json<PasswordResetRequest>,
+) -> Result<(String, AccountResponse), Json<ErrorResponse>> {
+    let user = match token_repo.find(&request.token, PASSWORD_RESET).await {
+        Ok(Some(reset)) => match repo.get_user_by_email(&reset.email).await {
+            Ok(Some(user)) => user,
+            Ok(None) => return Err(invalid_token()),
+            Err(e) => {
+                error!("Failed to look up {}: {:?}", reset.email, e);
+                return Err(internal_error());
+            }
+        },
+        Ok(None) => return Err(invalid_token()),
+        Err(e) => {
+            error!("Failed to load password reset: {:?}", e);
+            return Err(internal_error());
+        }
+    };
+
+    // Validate before using the token up, so a rejected password can be retried.
+    let credentials = UserCredentials {
+        email: user.email.clone(),
+        password: request.password.clone(),
+    };
+    validate_credentials(&credentials, &config.password_policy, Some(&user))?;
+
+    match token_repo.consume(&request.token, PASSWORD_RESET).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(invalid_token()),
+        Err(e) => {
+            error!("Failed to consume password reset: {:?}", e);
+            return Err(internal_error());
+        }
+    }
+
+    let hashed_password =
+        hash_password(&request.password, &config.password_hashing).map_err(|e| {
+            error!("Failed to hash password: {}", e);
+            internal_error()
+        })?;
+    let id = user.id.to_string();
+    if let Err(e) = repo
+        .update_user(
+            &id,
+            None,
+            Some(&hashed_password),
+            config.password_policy.history,
+        )
+        .await
+    {
+        error!("Failed to reset the password of {}: {:?}", user.email, e);
+        return Err(internal_error());
+    }
+
+    if let Err(e) = revoke_all_sessions(revocations, refresh_repo, &user.email).await {
+        error!("Failed to revoke sessions for {}: {:?}", user.email, e);
+    }
+    // The reset proves control of the mailbox and ends any lockout.
+    if let Err(e) = repo.mark_email_verified(&user.email).await {
+        error!("Failed to verify {}: {:?}", user.email, e);
+    }
+    if let Err(e) = throttle.attempts.clear_account(&user.email).await {
+        error!("Failed to clear failed logins of {}: {:?}", user.email, e);
+    }
+
+    Ok((
+        user.email,
+        AccountResponse {
+            status: Status::Ok.code,
+            message: "Password reset; sign in with the new password".to_string(),
+        },
+    ))
+}
+
+pub fn account_routes() -> Vec<rocket::Route> {
+    routes![
+        request_verification,
+        verify_email,
+        forgot_password,
+        reset_password
+    ]
+}
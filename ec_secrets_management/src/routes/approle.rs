@@ -0,0 +1,310 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::{AppRoleResponse, ErrorResponse, LoginResponse, SecretIdResponse};
+use crate::request_guards::{AuditTrail, TokenGuard};
+use ec_secrets_shared_library::{
+    models::{AppRole, AppRoleCredentials, SecretIdRequest},
+    repositories::{
+        app_roles::{validate_role, AppRoleRepository},
+        keys::KeyRepository,
+    },
+    utils::auth::{issue_token, AuthConfig},
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use chrono::Duration;
+use log::{error, info, warn};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, post, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::net::IpAddr;
+use std::sync::Arc;
+
+fn insufficient_permissions() -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        status: Status::Forbidden.code,
+        message: "Insufficient Permissions".to_string(),
+    })
+}
+
+/// Roles may only be managed by interactive users, never by machine tokens.
+fn role_owner(token: &TokenGuard) -> Result<&str, Json<ErrorResponse>> {
+    if token.policies().is_some() {
+        return Err(insufficient_permissions());
+    }
+    token
+        .0
+        .get_claim("sub")
+        .and_then(|subject| subject.as_str())
+        .ok_or_else(insufficient_permissions)
+}
+
+/*------------------
+ Create an AppRole
+-------------------*/
+#[post("/approle/role", data = "<role>")]
+pub async fn create_role(
+    repo: &State<Arc<AppRoleRepository>>,
+    role: Json<AppRole>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<AppRoleResponse>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    let target = role.role_name.clone();
+    audit
+        .audited(
+            &actor,
+            "approle.create",
+            Some(&target),
+            create_role_inner(repo, role, token),
+        )
+        .await
+}
+
+async fn create_role_inner(
+    repo: &State<Arc<AppRoleRepository>>,
+    role: Json<AppRole>,
+    token: TokenGuard,
+) -> Result<Json<AppRoleResponse>, Json<ErrorResponse>> {
+    let created_by = role_owner(&token)?;
+
+    if let Err(message) = validate_role(&role) {
+        return Err(Json(ErrorResponse {
+            status: Status::BadRequest.code,
+            message,
+        }));
+    }
+
+    match repo.create_role(&role, created_by).await {
+        Ok(role) => {
+            info!("AppRole '{}' created successfully.", role.role_name);
+            Ok(Json(AppRoleResponse {
+                status: Status::Ok.code,
+                role_name: role.role_name,
+                role_id: role.role_id,
+                policies: role.policies,
+            }))
+        }
+        Err(e) => {
+            error!("Failed to create AppRole: {:?}", e);
+            Err(Json(ErrorResponse {
+                status: Status::Conflict.code,
+                message: "Failed to create role".to_string(),
+            }))
+        }
+    }
+}
+
+/*------------------
+ Delete an AppRole
+-------------------*/
+#[delete("/approle/role/<role_name>")]
+pub async fn delete_role(
+    repo: &State<Arc<AppRoleRepository>>,
+    role_name: &str,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<AppRoleResponse>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited(
+            &actor,
+            "approle.delete",
+            Some(role_name),
+            delete_role_inner(repo, role_name, token),
+        )
+        .await
+}
+
+async fn delete_role_inner(
+    repo: &State<Arc<AppRoleRepository>>,
+    role_name: &str,
+    token: TokenGuard,
+) -> Result<Json<AppRoleResponse>, Json<ErrorResponse>> {
+    let created_by = role_owner(&token)?;
+
+    match repo.delete_role(role_name, created_by).await {
+        Ok(Some(role)) => {
+            info!("AppRole '{}' deleted successfully.", role.role_name);
+            Ok(Json(AppRoleResponse {
+                status: Status::Ok.code,
+                role_name: role.role_name,
+                role_id: role.role_id,
+                policies: role.policies,
+            }))
+        }
+        Ok(None) => Err(Json(ErrorResponse {
+            status: Status::NotFound.code,
+            message: "Role not found.".to_string(),
+        })),
+        Err(e) => {
+            error!("Failed to delete AppRole '{}': {:?}", role_name, e);
+            Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Failed to delete role.".to_string(),
+            }))
+        }
+    }
+}
+
+/*--------------------------------
+ Generate a secret id for a role
+---------------------------------*/
+#[post("/approle/role/<role_name>/secret-id", data = "<request>")]
+pub async fn generate_secret_id(
+    repo: &State<Arc<AppRoleRepository>>,
+    role_name: &str,
+    request: Option<Json<SecretIdRequest>>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<SecretIdResponse>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited(
+            &actor,
+            "approle.secret_id",
+            Some(role_name),
+            generate_secret_id_inner(repo, role_name, request, token),
+        )
+        .await
+}
+
+async fn generate_secret_id_inner(
+    repo: &State<Arc<AppRoleRepository>>,
+    role_name: &str,
+    request: Option<Json<SecretIdRequest>>,
+    token: TokenGuard,
+) -> Result<Json<SecretIdResponse>, Json<ErrorResponse>> {
+    let created_by = role_owner(&token)?;
+    let request = request
+        .map(|request| request.into_inner())
+        .unwrap_or_default();
+
+    let role = match repo.get_role_by_name(role_name, created_by).await {
+        Ok(Some(role)) => role,
+        Ok(None) => {
+            return Err(Json(ErrorResponse {
+                status: Status::NotFound.code,
+                message: "Role not found.".to_string(),
+            }))
+        }
+        Err(e) => {
+            error!("Failed to retrieve AppRole '{}': {:?}", role_name, e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Failed to generate secret id.".to_string(),
+            }));
+        }
+    };
+
+    match repo.generate_secret_id(&role, &request.cidr_list).await {
+        Ok((secret_id, document)) => {
+            info!("Secret id generated for AppRole '{}'.", role.role_name);
+            Ok(Json(SecretIdResponse {
+                status: Status::Ok.code,
+                secret_id,
+                expires_at: document.expires_at.to_rfc3339(),
+                uses_remaining: document.uses_remaining,
+            }))
+        }
+        Err(e) => {
+            error!("Failed to generate secret id: {:?}", e);
+            Err(Json(ErrorResponse {
+                status: Status::BadRequest.code,
+                message: "Failed to generate secret id.".to_string(),
+            }))
+        }
+    }
+}
+
+/*-------------------------------------------------
+ Exchange a role id and secret id for a token
+--------------------------------------------------*/
+#[post("/approle/login", data = "<credentials>")]
+pub async fn login(
+    repo: &State<Arc<AppRoleRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    config: &State<AuthConfig>,
+    credentials: Json<AppRoleCredentials>,
+    client_ip: Option<IpAddr>,
+    audit: AuditTrail,
+) -> Result<Json<LoginResponse>, Json<ErrorResponse>> {
+    let target = credentials.role_id.clone();
+    audit
+        .audited_as(
+            "auth.login.approle",
+            Some(&target),
+            login_inner(repo, key_repo, config, credentials, client_ip),
+        )
+        .await
+}
+
+async fn login_inner(
+    repo: &State<Arc<AppRoleRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    config: &State<AuthConfig>,
+    credentials: Json<AppRoleCredentials>,
+    client_ip: Option<IpAddr>,
+) -> Result<(String, LoginResponse), Json<ErrorResponse>> {
+    let role = match repo
+        .consume_secret_id(&credentials.role_id, &credentials.secret_id, client_ip)
+        .await
+    {
+        Ok(Some(role)) => role,
+        Ok(None) => {
+            warn!("Rejected AppRole login from {:?}.", client_ip);
+            return Err(Json(ErrorResponse {
+                status: Status::Unauthorized.code,
+                message: "Invalid role id or secret id".to_string(),
+            }));
+        }
+        Err(e) => {
+            error!("Failed to verify AppRole credentials: {:?}", e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }));
+        }
+    };
+
+    let token = match issue_token(
+        &role.created_by,
+        &[],
+        Some(&role.policies),
+        Duration::seconds(role.token_ttl),
+        config,
+        key_repo,
+    )
+    .await
+    {
+        Ok(token) => token,
+        Err(e) => {
+            error!("Failed to issue AppRole token: {}", e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }));
+        }
+    };
+
+    info!("AppRole '{}' logged in.", role.role_name);
+    Ok((
+        role.role_name,
+        LoginResponse {
+            status: Status::Ok.code,
+            token,
+            refresh_token: None,
+        },
+    ))
+}
+
+pub fn approle_routes() -> Vec<rocket::Route> {
+    routes![create_role, delete_role, generate_secret_id, login]
+}
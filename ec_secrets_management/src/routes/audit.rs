@@ -0,0 +1,119 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::ErrorResponse;
+use crate::request_guards::AdminGuard;
+use ec_secrets_shared_library::{
+    models::{AuditEntry, AuditOutcome, AuditQuery, ChainVerification},
+    repositories::audit::AuditRepository,
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use chrono::{DateTime, Utc};
+use log::{error, warn};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+fn bad_request(message: String) -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        status: Status::BadRequest.code,
+        message,
+    })
+}
+
+fn parse_time(
+    name: &str,
+    value: Option<&str>,
+) -> Result<Option<DateTime<Utc>>, Json<ErrorResponse>> {
+    value
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|time| time.with_timezone(&Utc))
+                .map_err(|_| bad_request(format!("'{}' must be an RFC 3339 timestamp", name)))
+        })
+        .transpose()
+}
+
+/*---------------------------------------------------------------
+ Search the audit log, newest entries first (administrative action)
+----------------------------------------------------------------*/
+#[allow(clippy::too_many_arguments)]
+#[get("/audit?<actor>&<action>&<target>&<outcome>&<since>&<until>&<limit>")]
+pub async fn list_events(
+    audit_repo: &State<Arc<AuditRepository>>,
+    actor: Option<String>,
+    action: Option<String>,
+    target: Option<String>,
+    outcome: Option<&str>,
+    since: Option<&str>,
+    until: Option<&str>,
+    limit: Option<i64>,
+    _admin: AdminGuard,
+) -> Result<Json<Vec<AuditEntry>>, Json<ErrorResponse>> {
+    let query = AuditQuery {
+        actor,
+        action,
+        target,
+        outcome: outcome
+            .map(str::parse::<AuditOutcome>)
+            .transpose()
+            .map_err(bad_request)?,
+        since: parse_time("since", since)?,
+        until: parse_time("until", until)?,
+        limit: limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+    };
+
+    match audit_repo.query(&query).await {
+        Ok(entries) => Ok(Json(entries.into_iter().map(AuditEntry::from).collect())),
+        Err(e) => {
+            error!("Failed to query the audit log: {:?}", e);
+            Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }))
+        }
+    }
+}
+
+/*---------------------------------------------------------------
+ Walk the hash chain and report the first tampered or missing entry
+----------------------------------------------------------------*/
+#[get("/audit/verify")]
+pub async fn verify_chain(
+    audit_repo: &State<Arc<AuditRepository>>,
+    _admin: AdminGuard,
+) -> Result<Json<ChainVerification>, Json<ErrorResponse>> {
+    match audit_repo.verify().await {
+        Ok(verification) => {
+            if let Some(broken_at) = &verification.broken_at {
+                warn!(
+                    "Audit chain broken at entry {}: {}",
+                    broken_at.sequence, broken_at.reason
+                );
+            }
+            Ok(Json(verification))
+        }
+        Err(e) => {
+            error!("Failed to verify the audit log: {:?}", e);
+            Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }))
+        }
+    }
+}
+
+pub fn audit_routes() -> Vec<rocket::Route> {
+    routes![list_events, verify_chain]
+}
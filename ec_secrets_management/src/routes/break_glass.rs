@@ -0,0 +1,482 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::ErrorResponse;
+use crate::request_guards::{AuditTrail, TokenGuard, Unsealed};
+use ec_secrets_shared_library::{
+    models::{
+        AuditEntry, AuditQuery, BreakGlassDocument, BreakGlassEntry, BreakGlassReport,
+        BreakGlassRequest, BreakGlassSession, ReleasedSecret, UserStatus, ADMIN_ROLE,
+        APPROVER_ROLE,
+    },
+    repositories::{
+        audit::AuditRepository, break_glass::BreakGlassRepository, users::UserRepository,
+        vault::VaultRepository,
+    },
+    utils::{
+        approval::ApprovalNotifier,
+        auth::AuthConfig,
+        break_glass::{covers, BreakGlassEvent, BreakGlassNotice},
+    },
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use chrono::Utc;
+use log::{error, warn};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, post, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+const REPORT_LIMIT: i64 = 10_000;
+
+fn error_response(status: Status, message: &str) -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        status: status.code,
+        message: message.to_string(),
+    })
+}
+
+fn internal_error() -> Json<ErrorResponse> {
+    error_response(Status::InternalServerError, "Internal server error")
+}
+
+fn session_not_found() -> Json<ErrorResponse> {
+    error_response(Status::NotFound, "Break-glass session not found")
+}
+
+/*----------------------------------------------------------------
+ A session visible to the caller: their own, or any for admins
+-----------------------------------------------------------------*/
+async fn visible_session(
+    repo: &BreakGlassRepository,
+    id: &str,
+    token: &TokenGuard,
+) -> Result<BreakGlassDocument, Json<ErrorResponse>> {
+    match repo.get(id).await {
+        Ok(Some(session)) if token.is_admin() || token.subject() == Some(session.user.as_str()) => {
+            Ok(session)
+        }
+        Ok(_) => Err(session_not_found()),
+        Err(e) => {
+            error!("Failed to retrieve break-glass session {}: {:?}", id, e);
+            Err(internal_error())
+        }
+    }
+}
+
+/*----------------------------------------------------------------
+ The caller's own session, while it still grants access
+-----------------------------------------------------------------*/
+async fn active_session(
+    repo: &BreakGlassRepository,
+    id: &str,
+    token: &TokenGuard,
+) -> Result<BreakGlassDocument, Json<ErrorResponse>> {
+    let session = match repo.get(id).await {
+        Ok(Some(session)) if token.subject() == Some(session.user.as_str()) => session,
+        Ok(_) => return Err(session_not_found()),
+        Err(e) => {
+            error!("Failed to retrieve break-glass session {}: {:?}", id, e);
+            return Err(internal_error());
+        }
+    };
+    if !token.can_break_glass() || !session.is_active(Utc::now()) {
+        return Err(error_response(
+            Status::Forbidden,
+            "Break-glass session has ended or expired",
+        ));
+    }
+    Ok(session)
+}
+
+/*----------------------------------------------------------------
+ Tell the approvers, the administrators and the configured
+ addresses, in the background so a slow channel cannot hold up
+ the emergency
+-----------------------------------------------------------------*/
+async fn alert(
+    users: &UserRepository,
+    notifier: &Arc<dyn ApprovalNotifier>,
+    config: &AuthConfig,
+    event: BreakGlassEvent,
+    session: BreakGlassSession,
+) {
+    let mut recipients = match users.list_users().await {
+        Ok(all_users) => all_users
+            .into_iter()
+            .filter(|user| {
+                user.status == UserStatus::Active
+                    && user.email != session.user
+                    && user
+                        .roles
+                        .iter()
+                        .any(|role| role == ADMIN_ROLE || role == APPROVER_ROLE)
+            })
+            .map(|user| user.email)
+            .collect(),
+        Err(e) => {
+            error!("Failed to list break-glass recipients: {:?}", e);
+            Vec::new()
+        }
+    };
+    for address in &config.break_glass.notify {
+        if !recipients.contains(address) {
+            recipients.push(address.clone());
+        }
+    }
+
+    let notice = BreakGlassNotice {
+        event,
+        session,
+        recipients,
+    };
+    warn!("{}", notice.subject());
+    let notifier = Arc::clone(notifier);
+    rocket::tokio::spawn(async move {
+        if let Err(e) = notifier.notify_break_glass(&notice).await {
+            error!(
+                "Failed to send notice about break-glass session {}: {}",
+                notice.session.id, e
+            );
+        }
+    });
+}
+
+/*----------------------------------------------------------------
+ Break the glass: open an emergency session on a configured path.
+ Requires the breakglass role and a reason
+-----------------------------------------------------------------*/
+#[post("/break-glass", data = "<request>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn open_session(
+    repo: &State<Arc<BreakGlassRepository>>,
+    users: &State<Arc<UserRepository>>,
+    notifier: &State<Arc<dyn ApprovalNotifier>>,
+    config: &State<AuthConfig>,
+    request: Json<BreakGlassRequest>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<BreakGlassSession>, Json<ErrorResponse>> {
+    let subject = token.subject().unwrap_or_default().to_string();
+    let result = open_session_inner(repo, users, notifier, config, &request, token, &subject).await;
+    let target = result.as_ref().ok().map(|session| session.id.clone());
+    audit
+        .record_result_with(
+            &subject,
+            "breakglass.activate",
+            target.as_deref(),
+            &result,
+            |session| {
+                Some(format!(
+                    "{} until {}: {}",
+                    session.path, session.expires_at, session.reason
+                ))
+            },
+        )
+        .await;
+    result
+}
+
+async fn open_session_inner(
+    repo: &State<Arc<BreakGlassRepository>>,
+    users: &State<Arc<UserRepository>>,
+    notifier: &State<Arc<dyn ApprovalNotifier>>,
+    config: &State<AuthConfig>,
+    request: &BreakGlassRequest,
+    token: TokenGuard,
+    subject: &str,
+) -> Result<Json<BreakGlassSession>, Json<ErrorResponse>> {
+    if !config.break_glass.enabled() {
+        return Err(error_response(
+            Status::Forbidden,
+            "Break-glass access is not configured",
+        ));
+    }
+    if subject.is_empty() || !token.can_break_glass() {
+        return Err(error_response(
+            Status::Forbidden,
+            "Insufficient Permissions",
+        ));
+    }
+    let (reason, duration) = config
+        .break_glass
+        .validate(&request.reason, &request.path, request.duration)
+        .map_err(|message| error_response(Status::BadRequest, &message))?;
+
+    let session = repo
+        .create(subject, &reason, &request.path, duration)
+        .await
+        .map_err(|e| {
+            error!("Failed to open break-glass session: {:?}", e);
+            internal_error()
+        })?;
+    let session = BreakGlassSession::from(session);
+
+    alert(
+        users,
+        notifier,
+        config,
+        BreakGlassEvent::Opened,
+        session.clone(),
+    )
+    .await;
+    Ok(Json(session))
+}
+
+/*----------------------------------------------------------------
+ List break-glass sessions, newest first. Admins see every session,
+ everyone else only their own
+-----------------------------------------------------------------*/
+#[get("/break-glass?<limit>")]
+pub async fn list_sessions(
+    repo: &State<Arc<BreakGlassRepository>>,
+    limit: Option<i64>,
+    token: TokenGuard,
+) -> Result<Json<Vec<BreakGlassSession>>, Json<ErrorResponse>> {
+    let Some(subject) = token.subject() else {
+        return Err(error_response(
+            Status::Unauthorized,
+            "Insufficient Permissions",
+        ));
+    };
+    let user = (!token.is_admin()).then_some(subject);
+
+    match repo
+        .list(user, limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT))
+        .await
+    {
+        Ok(sessions) => Ok(Json(
+            sessions.into_iter().map(BreakGlassSession::from).collect(),
+        )),
+        Err(e) => {
+            error!("Failed to list break-glass sessions: {:?}", e);
+            Err(internal_error())
+        }
+    }
+}
+
+/*---------------------------------------
+ Retrieve a break-glass session by id
+----------------------------------------*/
+#[get("/break-glass/<id>")]
+pub async fn get_session(
+    repo: &State<Arc<BreakGlassRepository>>,
+    id: &str,
+    token: TokenGuard,
+) -> Result<Json<BreakGlassSession>, Json<ErrorResponse>> {
+    visible_session(repo, id, &token)
+        .await
+        .map(|session| Json(BreakGlassSession::from(session)))
+}
+
+/*----------------------------------------------------------------
+ List the secrets an active session reaches, without their values
+-----------------------------------------------------------------*/
+#[get("/break-glass/<id>/secrets")]
+pub async fn list_secrets(
+    _unsealed: Unsealed,
+    repo: &State<Arc<BreakGlassRepository>>,
+    vault: &State<Arc<VaultRepository>>,
+    id: &str,
+    token: TokenGuard,
+) -> Result<Json<Vec<BreakGlassEntry>>, Json<ErrorResponse>> {
+    let session = active_session(repo, id, &token).await?;
+
+    match vault.list_entries().await {
+        Ok(entries) => Ok(Json(
+            entries
+                .into_iter()
+                .filter(|entry| covers(&session.path, &entry.key))
+                .map(|entry| BreakGlassEntry {
+                    id: entry.id.to_hex(),
+                    key: entry.key,
+                    owner_id: entry.owner_id,
+                    protected: entry.protection.is_some(),
+                })
+                .collect(),
+        )),
+        Err(e) => {
+            error!("Failed to list vault entries: {:?}", e);
+            Err(internal_error())
+        }
+    }
+}
+
+/*----------------------------------------------------------------
+ Read a secret under the session's path while the session is active
+-----------------------------------------------------------------*/
+#[get("/break-glass/<id>/secrets/<secret_id>")]
+pub async fn reveal_secret(
+    _unsealed: Unsealed,
+    repo: &State<Arc<BreakGlassRepository>>,
+    vault: &State<Arc<VaultRepository>>,
+    id: &str,
+    secret_id: &str,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<ReleasedSecret>, Json<ErrorResponse>> {
+    let subject = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited_with(
+            &subject,
+            "breakglass.reveal",
+            Some(secret_id),
+            reveal_secret_inner(repo, vault, id, secret_id, token, &subject),
+            |secret| Some(format!("break-glass session {}: {}", id, secret.key)),
+        )
+        .await
+}
+
+async fn reveal_secret_inner(
+    repo: &State<Arc<BreakGlassRepository>>,
+    vault: &State<Arc<VaultRepository>>,
+    id: &str,
+    secret_id: &str,
+    token: TokenGuard,
+    subject: &str,
+) -> Result<Json<ReleasedSecret>, Json<ErrorResponse>> {
+    let session = active_session(repo, id, &token).await?;
+
+    match vault.reveal_entry(secret_id).await {
+        Ok(Some(entry)) if covers(&session.path, &entry.key) => {
+            warn!(
+                "Break-glass session {} of {} read vault entry {} ({}).",
+                id, subject, secret_id, entry.key
+            );
+            Ok(Json(ReleasedSecret {
+                id: entry.id.to_hex(),
+                key: entry.key,
+                value: entry.value,
+                access_until: session.expires_at.to_rfc3339(),
+            }))
+        }
+        Ok(_) => Err(error_response(Status::NotFound, "Vault entry not found.")),
+        Err(e) => {
+            error!("Failed to reveal vault entry {}: {:?}", secret_id, e);
+            Err(internal_error())
+        }
+    }
+}
+
+/*----------------------------------------------------------------
+ End a session before it expires, by its user or an admin
+-----------------------------------------------------------------*/
+#[post("/break-glass/<id>/end")]
+#[allow(clippy::too_many_arguments)]
+pub async fn end_session(
+    repo: &State<Arc<BreakGlassRepository>>,
+    users: &State<Arc<UserRepository>>,
+    notifier: &State<Arc<dyn ApprovalNotifier>>,
+    config: &State<AuthConfig>,
+    id: &str,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<BreakGlassSession>, Json<ErrorResponse>> {
+    let subject = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited(
+            &subject,
+            "breakglass.end",
+            Some(id),
+            end_session_inner(repo, users, notifier, config, id, token, &subject),
+        )
+        .await
+}
+
+async fn end_session_inner(
+    repo: &State<Arc<BreakGlassRepository>>,
+    users: &State<Arc<UserRepository>>,
+    notifier: &State<Arc<dyn ApprovalNotifier>>,
+    config: &State<AuthConfig>,
+    id: &str,
+    token: TokenGuard,
+    subject: &str,
+) -> Result<Json<BreakGlassSession>, Json<ErrorResponse>> {
+    visible_session(repo, id, &token).await?;
+
+    let Some(ended) = repo.end(id, subject).await.map_err(|e| {
+        error!("Failed to end break-glass session {}: {:?}", id, e);
+        internal_error()
+    })?
+    else {
+        return Err(error_response(
+            Status::Conflict,
+            "Break-glass session has already ended or expired",
+        ));
+    };
+    let ended = BreakGlassSession::from(ended);
+
+    alert(
+        users,
+        notifier,
+        config,
+        BreakGlassEvent::Ended,
+        ended.clone(),
+    )
+    .await;
+    Ok(Json(ended))
+}
+
+/*----------------------------------------------------------------
+ Post-incident report: everything the user did during the session
+ and the secrets they read, with the session's own events
+-----------------------------------------------------------------*/
+#[get("/break-glass/<id>/report")]
+pub async fn session_report(
+    repo: &State<Arc<BreakGlassRepository>>,
+    audit_repo: &State<Arc<AuditRepository>>,
+    id: &str,
+    token: TokenGuard,
+) -> Result<Json<BreakGlassReport>, Json<ErrorResponse>> {
+    let session = visible_session(repo, id, &token).await?;
+
+    let during_session = AuditQuery {
+        actor: Some(session.user.clone()),
+        since: Some(session.started_at),
+        until: Some(session.closes_at()),
+        limit: REPORT_LIMIT,
+        ..AuditQuery::default()
+    };
+    let about_session = AuditQuery {
+        target: Some(id.to_string()),
+        limit: REPORT_LIMIT,
+        ..AuditQuery::default()
+    };
+
+    let mut events = Vec::new();
+    for query in [during_session, about_session] {
+        match audit_repo.query(&query).await {
+            Ok(entries) => events.extend(entries.into_iter().map(AuditEntry::from)),
+            Err(e) => {
+                error!("Failed to query the audit log for session {}: {:?}", id, e);
+                return Err(internal_error());
+            }
+        }
+    }
+
+    Ok(Json(BreakGlassReport::compile(
+        BreakGlassSession::from(session),
+        events,
+    )))
+}
+
+pub fn break_glass_routes() -> Vec<rocket::Route> {
+    routes![
+        open_session,
+        list_sessions,
+        get_session,
+        list_secrets,
+        reveal_secret,
+        end_session,
+        session_report
+    ]
+}
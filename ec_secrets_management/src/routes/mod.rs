@@ -1,2 +1,13 @@
+pub mod access_requests;
+pub mod account;
+pub mod approle;
+pub mod audit;
+pub mod break_glass;
+pub mod oidc;
+pub mod sys;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod totp;
+pub mod transit;
 pub mod users;
 pub mod vault;
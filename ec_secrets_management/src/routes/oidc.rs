@@ -0,0 +1,266 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::{ErrorResponse, LoginResponse, OidcCallback};
+use crate::request_guards::AuditTrail;
+use crate::routes::users::start_session;
+use ec_secrets_shared_library::{
+    models::{AuditOutcome, UserDocument},
+    repositories::{
+        keys::KeyRepository, oidc::OidcRepository, refresh_tokens::RefreshTokenRepository,
+        users::UserRepository,
+    },
+    utils::{
+        auth::{generate_identifier, hash_password, AuthConfig},
+        oidc::{OidcClient, OidcIdentity},
+    },
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use chrono::Duration;
+use log::{error, warn};
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::serde::json::Json;
+use rocket::{get, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+fn internal_error() -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        status: Status::InternalServerError.code,
+        message: "Internal server error".to_string(),
+    })
+}
+
+fn sso_failed() -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        status: Status::Unauthorized.code,
+        message: "Single sign-on failed".to_string(),
+    })
+}
+
+/*----------------------------------------------------------
+ Redirect the browser to the identity provider to sign in
+-----------------------------------------------------------*/
+#[get("/oidc/login")]
+pub async fn login(
+    oidc: &State<OidcClient>,
+    oidc_repo: &State<Arc<OidcRepository>>,
+) -> Result<Redirect, Json<ErrorResponse>> {
+    if !oidc.config().enabled {
+        return Err(Json(ErrorResponse {
+            status: Status::NotFound.code,
+            message: "Single sign-on is not enabled".to_string(),
+        }));
+    }
+
+    let lifetime = Duration::seconds(oidc.config().state_ttl);
+    let (state, pending) = match oidc_repo.begin_login(lifetime).await {
+        Ok(login) => login,
+        Err(e) => {
+            error!("Failed to store single sign-on login: {:?}", e);
+            return Err(internal_error());
+        }
+    };
+
+    match oidc
+        .authorization_url(&state, &pending.nonce, &pending.code_verifier)
+        .await
+    {
+        Ok(url) => Ok(Redirect::to(url)),
+        Err(e) => {
+            error!("Failed to reach the identity provider: {}", e);
+            Err(Json(ErrorResponse {
+                status: Status::BadGateway.code,
+                message: "Identity provider is unavailable".to_string(),
+            }))
+        }
+    }
+}
+
+/*-------------------------------------------------------------------
+ Complete the sign-in: exchange the code, validate the ID token and
+ issue a Locksmith session for the matching (or new) account
+--------------------------------------------------------------------*/
+#[get("/oidc/callback?<params..>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn callback(
+    params: OidcCallback<'_>,
+    repo: &State<Arc<UserRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    oidc_repo: &State<Arc<OidcRepository>>,
+    oidc: &State<OidcClient>,
+    config: &State<AuthConfig>,
+    audit: AuditTrail,
+) -> Result<Json<LoginResponse>, Json<ErrorResponse>> {
+    audit
+        .audited_as(
+            "auth.login.oidc",
+            None,
+            callback_inner(
+                params,
+                repo,
+                key_repo,
+                refresh_repo,
+                oidc_repo,
+                oidc,
+                config,
+                &audit,
+            ),
+        )
+        .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn callback_inner(
+    params: OidcCallback<'_>,
+    repo: &State<Arc<UserRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    oidc_repo: &State<Arc<OidcRepository>>,
+    oidc: &State<OidcClient>,
+    config: &State<AuthConfig>,
+    audit: &AuditTrail,
+) -> Result<(String, LoginResponse), Json<ErrorResponse>> {
+    if let Some(error) = params.error {
+        warn!("Identity provider refused the sign-in: {}", error);
+        return Err(sso_failed());
+    }
+    let (Some(code), Some(state)) = (params.code, params.state) else {
+        return Err(Json(ErrorResponse {
+            status: Status::BadRequest.code,
+            message: "Missing code or state".to_string(),
+        }));
+    };
+
+    let pending = match oidc_repo.consume_login(state).await {
+        Ok(Some(pending)) => pending,
+        Ok(None) => {
+            warn!("Rejected single sign-on callback with an unknown or expired state.");
+            return Err(Json(ErrorResponse {
+                status: Status::Unauthorized.code,
+                message: "Invalid or expired login request".to_string(),
+            }));
+        }
+        Err(e) => {
+            error!("Failed to load single sign-on login: {:?}", e);
+            return Err(internal_error());
+        }
+    };
+
+    let identity = match oidc
+        .authenticate(code, &pending.code_verifier, &pending.nonce)
+        .await
+    {
+        Ok(identity) => identity,
+        Err(e) => {
+            warn!("Single sign-on failed: {}", e);
+            return Err(sso_failed());
+        }
+    };
+
+    let user = provision_user(repo, oidc, config, &identity, audit).await?;
+
+    // The identity provider enforces its own second factor, like passkeys do.
+    start_session(
+        &user.email,
+        &user.roles,
+        repo,
+        key_repo,
+        refresh_repo,
+        config,
+    )
+    .await
+    .map(|session| (user.email, session))
+}
+
+/// Find the account for a provider identity, creating it when allowed, and
+/// apply the roles its groups map to.
+async fn provision_user(
+    repo: &UserRepository,
+    oidc: &OidcClient,
+    config: &AuthConfig,
+    identity: &OidcIdentity,
+    audit: &AuditTrail,
+) -> Result<UserDocument, Json<ErrorResponse>> {
+    let sync_roles = !oidc.config().group_roles.is_empty();
+
+    match repo.get_user_by_email(&identity.email).await {
+        Ok(Some(mut user)) => {
+            if sync_roles && user.roles != identity.roles {
+                if let Err(e) = repo.set_roles(&user.email, &identity.roles).await {
+                    error!("Failed to update roles of {}: {:?}", user.email, e);
+                    return Err(internal_error());
+                }
+                audit
+                    .record(
+                        "oidc",
+                        "user.roles",
+                        Some(&user.email),
+                        AuditOutcome::Success,
+                        Some(format!(
+                            "{} -> {}",
+                            user.roles.join(","),
+                            identity.roles.join(",")
+                        )),
+                    )
+                    .await;
+                user.roles = identity.roles.clone();
+            }
+            Ok(user)
+        }
+        Ok(None) if oidc.config().auto_provision => {
+            // SSO accounts get a random password nobody knows, so they can
+            // only sign in through the identity provider or a passkey.
+            let password = hash_password(&generate_identifier(), &config.password_hashing)
+                .map_err(|e| {
+                    error!("Failed to hash password: {}", e);
+                    internal_error()
+                })?;
+
+            match repo
+                .create_user(&identity.email, &password, &identity.roles)
+                .await
+            {
+                Ok(user) => {
+                    audit
+                        .record(
+                            "oidc",
+                            "user.create",
+                            Some(&user.email),
+                            AuditOutcome::Success,
+                            Some(format!("single sign-on subject {}", identity.subject)),
+                        )
+                        .await;
+                    Ok(user)
+                }
+                Err(e) => {
+                    error!("Failed to provision {}: {:?}", identity.email, e);
+                    Err(internal_error())
+                }
+            }
+        }
+        Ok(None) => {
+            warn!("No account for single sign-on user {}.", identity.email);
+            Err(Json(ErrorResponse {
+                status: Status::Forbidden.code,
+                message: "No account exists for this user".to_string(),
+            }))
+        }
+        Err(e) => {
+            error!("Failed to look up single sign-on user: {:?}", e);
+            Err(internal_error())
+        }
+    }
+}
+
+pub fn oidc_routes() -> Vec<rocket::Route> {
+    routes![login, callback]
+}
@@ -0,0 +1,424 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::ErrorResponse;
+use crate::request_guards::{AdminGuard, AuditTrail, Unsealed};
+use ec_secrets_shared_library::{
+    models::{
+        RestoreReport, RestoreRequest, SealDocument, SealInitRequest, SealInitResponse, SealStatus,
+        SnapshotRequest, SnapshotResponse, UnsealRequest,
+    },
+    repositories::{seal::SealRepository, snapshots::SnapshotRepository, vault::VaultRepository},
+    utils::{
+        kms::KeyManager,
+        seal::{Keyring, SealError},
+        snapshot::{Snapshot, SnapshotError},
+    },
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::{error, info, warn};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, post, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+fn error_response(status: Status, message: &str) -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        status: status.code,
+        message: message.to_string(),
+    })
+}
+
+fn internal_error() -> Json<ErrorResponse> {
+    error_response(Status::InternalServerError, "Internal server error")
+}
+
+async fn seal_config(
+    seal_repo: &SealRepository,
+) -> Result<Option<SealDocument>, Json<ErrorResponse>> {
+    seal_repo.get().await.map_err(|e| {
+        error!("Failed to read the seal configuration: {:?}", e);
+        internal_error()
+    })
+}
+
+/// Wraps the key just unsealed with shares when the configured key
+/// manager has no wrapped copy yet, so the next boot unseals itself.
+async fn store_wrapped_key(
+    seal_repo: &SealRepository,
+    keyring: &Keyring,
+    manager: &dyn KeyManager,
+    seal: &SealDocument,
+) {
+    if seal
+        .wrapped_key
+        .as_ref()
+        .is_some_and(|wrapped_key| wrapped_key.manager == manager.name())
+    {
+        return;
+    }
+    let stored = match keyring.wrap(manager).await {
+        Ok(wrapped_key) => seal_repo
+            .set_wrapped_key(&wrapped_key)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    match stored {
+        Ok(()) => info!(
+            "Master key wrapped with the {} key manager.",
+            manager.name()
+        ),
+        Err(e) => error!("Failed to wrap the master key for auto-unseal: {}", e),
+    }
+}
+
+/*----------------------------------------------------------------
+ Whether the server is initialized and sealed, and how many shares
+ were submitted so far. Open to everyone, like the health check
+-----------------------------------------------------------------*/
+#[get("/sys/seal-status")]
+pub async fn seal_status(
+    seal_repo: &State<Arc<SealRepository>>,
+    keyring: &State<Arc<Keyring>>,
+) -> Result<Json<SealStatus>, Json<ErrorResponse>> {
+    let seal = seal_config(seal_repo).await?;
+    Ok(Json(keyring.status(seal.as_ref())))
+}
+
+/*----------------------------------------------------------------
+ Split the master key into Shamir shares (administrative action).
+ A server still running on ECS_ENCRYPTION_KEY splits that key, so
+ existing secrets stay readable; otherwise a new key is generated.
+ The shares are returned once and never stored. With auto-unseal
+ configured the key is also wrapped by the key manager
+-----------------------------------------------------------------*/
+#[post("/sys/init", data = "<request>")]
+pub async fn initialize(
+    seal_repo: &State<Arc<SealRepository>>,
+    vault: &State<Arc<VaultRepository>>,
+    keyring: &State<Arc<Keyring>>,
+    key_manager: &State<Option<Arc<dyn KeyManager>>>,
+    request: Json<SealInitRequest>,
+    admin: AdminGuard,
+    audit: AuditTrail,
+) -> Result<Json<SealInitResponse>, Json<ErrorResponse>> {
+    let actor = admin.0.subject().unwrap_or_default().to_string();
+    audit
+        .audited_with(
+            &actor,
+            "sys.init",
+            None,
+            initialize_inner(seal_repo, vault, keyring, key_manager, &request),
+            |_| {
+                Some(format!(
+                    "{} shares, threshold {}",
+                    request.shares, request.threshold
+                ))
+            },
+        )
+        .await
+}
+
+async fn initialize_inner(
+    seal_repo: &State<Arc<SealRepository>>,
+    vault: &State<Arc<VaultRepository>>,
+    keyring: &State<Arc<Keyring>>,
+    key_manager: &State<Option<Arc<dyn KeyManager>>>,
+    request: &SealInitRequest,
+) -> Result<Json<SealInitResponse>, Json<ErrorResponse>> {
+    if seal_config(seal_repo).await?.is_some() {
+        return Err(error_response(
+            Status::Conflict,
+            &SealError::AlreadyInitialized.to_string(),
+        ));
+    }
+    if keyring.is_sealed() {
+        let stored = vault.count_all().await.map_err(|e| {
+            error!("Failed to count vault entries: {:?}", e);
+            internal_error()
+        })?;
+        if stored > 0 {
+            return Err(error_response(
+                Status::Conflict,
+                "Existing secrets are encrypted with ECS_ENCRYPTION_KEY; start the server with it set to initialize",
+            ));
+        }
+    }
+
+    let mut initialization = keyring
+        .initialize(request.shares, request.threshold)
+        .map_err(|e| error_response(Status::BadRequest, &e.to_string()))?;
+    if let Some(manager) = key_manager.inner() {
+        initialization.wrap(manager.as_ref()).await.map_err(|e| {
+            error!("Failed to wrap the master key: {}", e);
+            error_response(Status::BadGateway, &e.to_string())
+        })?;
+    }
+    let stored = seal_repo
+        .initialize(&initialization.seal)
+        .await
+        .map_err(|e| {
+            error!("Failed to store the seal configuration: {:?}", e);
+            internal_error()
+        })?;
+    if !stored {
+        return Err(error_response(
+            Status::Conflict,
+            &SealError::AlreadyInitialized.to_string(),
+        ));
+    }
+
+    let response = SealInitResponse {
+        shares: initialization.shares.clone(),
+        threshold: initialization.seal.threshold,
+    };
+    keyring.complete(initialization);
+    info!(
+        "Sealing initialized with {} shares and a threshold of {}.",
+        request.shares, request.threshold
+    );
+    Ok(Json(response))
+}
+
+/*----------------------------------------------------------------
+ Submit one unseal share, or discard those submitted so far with
+ `reset`. The shares themselves are the credential
+-----------------------------------------------------------------*/
+#[post("/sys/unseal", data = "<request>")]
+pub async fn unseal(
+    seal_repo: &State<Arc<SealRepository>>,
+    keyring: &State<Arc<Keyring>>,
+    key_manager: &State<Option<Arc<dyn KeyManager>>>,
+    request: Json<UnsealRequest>,
+    audit: AuditTrail,
+) -> Result<Json<SealStatus>, Json<ErrorResponse>> {
+    audit
+        .audited_with(
+            "anonymous",
+            "sys.unseal",
+            None,
+            unseal_inner(seal_repo, keyring, key_manager, request),
+            |status| {
+                Some(if status.sealed {
+                    format!(
+                        "{} of {} shares",
+                        status.progress,
+                        status.threshold.unwrap_or_default()
+                    )
+                } else {
+                    "unsealed".to_string()
+                })
+            },
+        )
+        .await
+}
+
+async fn unseal_inner(
+    seal_repo: &State<Arc<SealRepository>>,
+    keyring: &State<Arc<Keyring>>,
+    key_manager: &State<Option<Arc<dyn KeyManager>>>,
+    request: Json<UnsealRequest>,
+) -> Result<Json<SealStatus>, Json<ErrorResponse>> {
+    let Some(seal) = seal_config(seal_repo).await? else {
+        return Err(error_response(
+            Status::BadRequest,
+            &SealError::NotInitialized.to_string(),
+        ));
+    };
+    if request.reset {
+        keyring.reset();
+        return Ok(Json(keyring.status(Some(&seal))));
+    }
+    let Some(share) = request.share.as_deref() else {
+        return Err(error_response(
+            Status::BadRequest,
+            "Provide a share, or reset",
+        ));
+    };
+
+    match keyring.submit(&seal, share) {
+        Ok(true) => {
+            info!("Locksmith unsealed.");
+            if let Some(manager) = key_manager.inner() {
+                store_wrapped_key(seal_repo, keyring, manager.as_ref(), &seal).await;
+            }
+        }
+        Ok(false) => {}
+        Err(e @ SealError::WrongShares) => {
+            warn!("Unsealing failed: {}", e);
+            return Err(error_response(Status::Forbidden, &e.to_string()));
+        }
+        Err(e) => return Err(error_response(Status::BadRequest, &e.to_string())),
+    }
+    Ok(Json(keyring.status(Some(&seal))))
+}
+
+/*----------------------------------------------------------------
+ Wipe the master key from memory (administrative action). Secrets
+ stay unreadable until the server is unsealed again
+-----------------------------------------------------------------*/
+#[post("/sys/seal")]
+pub async fn seal(
+    seal_repo: &State<Arc<SealRepository>>,
+    keyring: &State<Arc<Keyring>>,
+    admin: AdminGuard,
+    audit: AuditTrail,
+) -> Result<Json<SealStatus>, Json<ErrorResponse>> {
+    let actor = admin.0.subject().unwrap_or_default().to_string();
+    audit
+        .audited(
+            &actor,
+            "sys.seal",
+            None,
+            seal_inner(seal_repo, keyring, &actor),
+        )
+        .await
+}
+
+async fn seal_inner(
+    seal_repo: &State<Arc<SealRepository>>,
+    keyring: &State<Arc<Keyring>>,
+    actor: &str,
+) -> Result<Json<SealStatus>, Json<ErrorResponse>> {
+    let Some(seal) = seal_config(seal_repo).await? else {
+        // Without shares there would be no way back in.
+        return Err(error_response(
+            Status::BadRequest,
+            &SealError::NotInitialized.to_string(),
+        ));
+    };
+    keyring.seal();
+    warn!("Locksmith sealed by {}.", actor);
+    Ok(Json(keyring.status(Some(&seal))))
+}
+
+/*----------------------------------------------------------------
+ Export users, secrets, TOTP enrollments, the signing key and
+ AppRoles into one archive encrypted with the given passphrase
+ (administrative action). Secret values are inside in plaintext,
+ so the snapshot can be restored under another master key
+-----------------------------------------------------------------*/
+#[post("/sys/snapshot", data = "<request>")]
+pub async fn snapshot(
+    _unsealed: Unsealed,
+    snapshots: &State<Arc<SnapshotRepository>>,
+    request: Json<SnapshotRequest>,
+    admin: AdminGuard,
+    audit: AuditTrail,
+) -> Result<Json<SnapshotResponse>, Json<ErrorResponse>> {
+    let actor = admin.0.subject().unwrap_or_default().to_string();
+    audit
+        .audited_with(
+            &actor,
+            "sys.snapshot",
+            None,
+            snapshot_inner(snapshots, request, &actor),
+            |response| {
+                let counts = response.manifest.counts;
+                Some(format!(
+                    "{} users, {} secrets, {} AppRoles",
+                    counts.users, counts.secrets, counts.app_roles
+                ))
+            },
+        )
+        .await
+}
+
+async fn snapshot_inner(
+    snapshots: &State<Arc<SnapshotRepository>>,
+    request: Json<SnapshotRequest>,
+    actor: &str,
+) -> Result<Json<SnapshotResponse>, Json<ErrorResponse>> {
+    let snapshot = snapshots.export().await.map_err(|e| {
+        error!("Failed to export a snapshot: {:?}", e);
+        internal_error()
+    })?;
+    let (manifest, archive) = snapshot
+        .pack(actor, &request.passphrase)
+        .map_err(|e| match e {
+            SnapshotError::WeakPassphrase => error_response(Status::BadRequest, &e.to_string()),
+            e => {
+                error!("Failed to pack a snapshot: {}", e);
+                internal_error()
+            }
+        })?;
+    info!("Snapshot taken by {}.", actor);
+    Ok(Json(SnapshotResponse {
+        manifest,
+        snapshot: STANDARD.encode(archive),
+    }))
+}
+
+/*----------------------------------------------------------------
+ Validate a snapshot and restore it, merging into this deployment
+ or replacing what it holds (administrative action). A dry run
+ reports what would change without writing anything
+-----------------------------------------------------------------*/
+#[post("/sys/restore", data = "<request>")]
+pub async fn restore(
+    _unsealed: Unsealed,
+    snapshots: &State<Arc<SnapshotRepository>>,
+    request: Json<RestoreRequest>,
+    admin: AdminGuard,
+    audit: AuditTrail,
+) -> Result<Json<RestoreReport>, Json<ErrorResponse>> {
+    let actor = admin.0.subject().unwrap_or_default().to_string();
+    audit
+        .audited_with(
+            &actor,
+            "sys.restore",
+            None,
+            restore_inner(snapshots, request, &actor),
+            |report| {
+                Some(format!(
+                    "{} mode{}, snapshot of {}: {} users, {} secrets restored",
+                    report.mode,
+                    if report.dry_run { " (dry run)" } else { "" },
+                    report.manifest.created_at,
+                    report.restored.users,
+                    report.restored.secrets
+                ))
+            },
+        )
+        .await
+}
+
+async fn restore_inner(
+    snapshots: &State<Arc<SnapshotRepository>>,
+    request: Json<RestoreRequest>,
+    actor: &str,
+) -> Result<Json<RestoreReport>, Json<ErrorResponse>> {
+    let archive = STANDARD
+        .decode(&request.snapshot)
+        .map_err(|_| error_response(Status::BadRequest, "The snapshot must be base64"))?;
+    let (manifest, snapshot) = Snapshot::unpack(&archive, &request.passphrase)
+        .map_err(|e| error_response(Status::BadRequest, &e.to_string()))?;
+
+    let report = snapshots
+        .restore(manifest, &snapshot, request.mode, request.dry_run)
+        .await
+        .map_err(|e| {
+            error!("Failed to restore a snapshot: {:?}", e);
+            internal_error()
+        })?;
+    if !report.dry_run {
+        warn!(
+            "Snapshot taken {} restored by {} in {} mode.",
+            report.manifest.created_at, actor, report.mode
+        );
+    }
+    Ok(Json(report))
+}
+
+pub fn sys_routes() -> Vec<rocket::Route> {
+    routes![seal_status, initialize, unseal, seal, snapshot, restore]
+}
@@ -0,0 +1,92 @@
+/*---------------------------------------------------------------
+ Route tests. The repositories share a MongoDB client that never
+ reaches a server, so only requests refused before any query (by
+ a request guard or an authorization check) can be exercised. The
+ keyring is unsealed so that the guards behind `Unsealed` run.
+----------------------------------------------------------------*/
+use crate::request_guards::TokenGuard;
+use chrono::Duration;
+use ec_secrets_shared_library::{
+    repositories::{
+        audit::AuditRepository, keys::KeyRepository, refresh_tokens::RefreshTokenRepository,
+        revocations::RevocationRepository, totp::TotpRepository, users::UserRepository,
+        vault::VaultRepository,
+    },
+    utils::{
+        auth::{build_claims, AuthConfig},
+        seal::Keyring,
+    },
+};
+use rocket::{local::asynchronous::Client, Route};
+use std::sync::Arc;
+
+const AUTHENTICATION_KEY: &str = "test-authentication-key";
+
+pub(crate) async fn client(routes: Vec<Route>) -> Client {
+    let mongo = mongodb::Client::with_uri_str("mongodb://127.0.0.1:9")
+        .await
+        .expect("Failed to build the MongoDB client");
+    let keyring = Arc::new(Keyring::unsealed(vec![7; 32]));
+    let db = "locksmith_test";
+
+    let rocket = rocket::build()
+        .manage(AuthConfig::default())
+        .manage(Arc::new(UserRepository::new(
+            &mongo,
+            db,
+            "users",
+            "bootstrap",
+        )))
+        .manage(Arc::new(VaultRepository::new(
+            &mongo,
+            db,
+            "vault",
+            Arc::clone(&keyring),
+        )))
+        .manage(Arc::new(KeyRepository::new(&mongo, db, "keys")))
+        .manage(Arc::new(RevocationRepository::new(
+            &mongo,
+            db,
+            "revocations",
+        )))
+        .manage(Arc::new(RefreshTokenRepository::new(
+            &mongo,
+            db,
+            "refresh_tokens",
+        )))
+        .manage(Arc::new(TotpRepository::new(
+            &mongo,
+            db,
+            "totp",
+            "mfa_challenges",
+            Arc::clone(&keyring),
+        )))
+        .manage(Arc::new(AuditRepository::new(
+            &mongo,
+            db,
+            "audit_log",
+            AUTHENTICATION_KEY.as_bytes(),
+        )))
+        .manage(keyring)
+        .mount("/", routes);
+    Client::untracked(rocket)
+        .await
+        .expect("Failed to build the test client")
+}
+
+/// A verified token for `subject`, as the token guard would yield it.
+pub(crate) fn token(subject: &str, roles: &[&str], policies: Option<&[&str]>) -> TokenGuard {
+    let roles: Vec<String> = roles.iter().map(|role| role.to_string()).collect();
+    let policies: Option<Vec<String>> =
+        policies.map(|policies| policies.iter().map(|policy| policy.to_string()).collect());
+    let claims = build_claims(
+        subject,
+        &roles,
+        policies.as_deref(),
+        Duration::minutes(5),
+        &AuthConfig::default(),
+        AUTHENTICATION_KEY,
+    )
+    .expect("Failed to build claims");
+    TokenGuard(claims)
+}
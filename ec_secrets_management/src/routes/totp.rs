@@ -0,0 +1,280 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::{
+    DisableTotpResponse, ErrorResponse, LoginResponse, RecoveryCodesResponse,
+    TotpEnrollmentResponse,
+};
+use crate::request_guards::{AuditTrail, TokenGuard, Unsealed};
+use crate::routes::users::start_session;
+use ec_secrets_shared_library::{
+    models::{TotpCode, TotpLoginRequest},
+    repositories::{
+        keys::KeyRepository, refresh_tokens::RefreshTokenRepository, totp::TotpRepository,
+        users::UserRepository,
+    },
+    utils::{auth::AuthConfig, totp::provisioning_uri},
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use log::{error, info, warn};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{post, routes, State};
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+/// Two-factor authentication belongs to user accounts, never to machine tokens.
+pub(crate) fn account_owner(token: &TokenGuard) -> Result<&str, Json<ErrorResponse>> {
+    match token.subject() {
+        Some(subject) if token.policies().is_none() => Ok(subject),
+        _ => Err(Json(ErrorResponse {
+            status: Status::Forbidden.code,
+            message: "Insufficient Permissions".to_string(),
+        })),
+    }
+}
+
+/*----------------------------------------------------
+ Start TOTP enrollment and share the secret to scan
+-----------------------------------------------------*/
+#[post("/totp/enroll")]
+pub async fn enroll(
+    _unsealed: Unsealed,
+    totp_repo: &State<Arc<TotpRepository>>,
+    token: TokenGuard,
+) -> Result<Json<TotpEnrollmentResponse>, Json<ErrorResponse>> {
+    let subject = account_owner(&token)?;
+
+    let secret = match totp_repo.begin_enrollment(subject).await {
+        Ok(secret) => secret,
+        Err(e) => {
+            error!("Failed to start TOTP enrollment for {}: {:?}", subject, e);
+            return Err(Json(ErrorResponse {
+                status: Status::Conflict.code,
+                message: "Two-factor authentication could not be enrolled".to_string(),
+            }));
+        }
+    };
+
+    match provisioning_uri(&secret, subject) {
+        Ok(provisioning_uri) => Ok(Json(TotpEnrollmentResponse {
+            status: Status::Ok.code,
+            secret,
+            provisioning_uri,
+        })),
+        Err(e) => {
+            error!("Failed to build TOTP provisioning URI: {}", e);
+            Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }))
+        }
+    }
+}
+
+/*--------------------------------------------------------------
+ Confirm enrollment with a first code and hand out recovery codes
+---------------------------------------------------------------*/
+#[post("/totp/confirm", data = "<request>")]
+pub async fn confirm(
+    _unsealed: Unsealed,
+    totp_repo: &State<Arc<TotpRepository>>,
+    request: Json<TotpCode>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<RecoveryCodesResponse>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited(
+            &actor,
+            "mfa.totp.enable",
+            None,
+            confirm_inner(totp_repo, request, token),
+        )
+        .await
+}
+
+async fn confirm_inner(
+    totp_repo: &State<Arc<TotpRepository>>,
+    request: Json<TotpCode>,
+    token: TokenGuard,
+) -> Result<Json<RecoveryCodesResponse>, Json<ErrorResponse>> {
+    let subject = account_owner(&token)?;
+
+    match totp_repo.confirm_enrollment(subject, &request.code).await {
+        Ok(Some(recovery_codes)) => {
+            info!("Two-factor authentication enabled for {}.", subject);
+            Ok(Json(RecoveryCodesResponse {
+                status: Status::Ok.code,
+                recovery_codes,
+            }))
+        }
+        Ok(None) => Err(Json(ErrorResponse {
+            status: Status::BadRequest.code,
+            message: "Invalid code".to_string(),
+        })),
+        Err(e) => {
+            error!("Failed to confirm TOTP enrollment for {}: {:?}", subject, e);
+            Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }))
+        }
+    }
+}
+
+/*----------------------------------------------------------
+ Turn off two-factor authentication, proven by a current code
+-----------------------------------------------------------*/
+#[post("/totp/disable", data = "<request>")]
+pub async fn disable(
+    _unsealed: Unsealed,
+    totp_repo: &State<Arc<TotpRepository>>,
+    request: Json<TotpCode>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<DisableTotpResponse>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited(
+            &actor,
+            "mfa.totp.disable",
+            None,
+            disable_inner(totp_repo, request, token),
+        )
+        .await
+}
+
+async fn disable_inner(
+    totp_repo: &State<Arc<TotpRepository>>,
+    request: Json<TotpCode>,
+    token: TokenGuard,
+) -> Result<Json<DisableTotpResponse>, Json<ErrorResponse>> {
+    let subject = account_owner(&token)?;
+
+    match totp_repo.verify_code(subject, &request.code).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(Json(ErrorResponse {
+                status: Status::BadRequest.code,
+                message: "Invalid code".to_string(),
+            }))
+        }
+        Err(e) => {
+            error!("Failed to verify TOTP code for {}: {:?}", subject, e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }));
+        }
+    }
+
+    match totp_repo.disable(subject).await {
+        Ok(_) => {
+            info!("Two-factor authentication disabled for {}.", subject);
+            Ok(Json(DisableTotpResponse {
+                status: Status::Ok.code,
+                message: "Two-factor authentication disabled".to_string(),
+            }))
+        }
+        Err(e) => {
+            error!("Failed to disable TOTP for {}: {:?}", subject, e);
+            Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }))
+        }
+    }
+}
+
+/*-----------------------------------------------------------
+ Complete a login challenge with a TOTP or recovery code
+------------------------------------------------------------*/
+#[post("/login/totp", data = "<request>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn login(
+    _unsealed: Unsealed,
+    repo: &State<Arc<UserRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    totp_repo: &State<Arc<TotpRepository>>,
+    config: &State<AuthConfig>,
+    request: Json<TotpLoginRequest>,
+    audit: AuditTrail,
+) -> Result<Json<LoginResponse>, Json<ErrorResponse>> {
+    audit
+        .audited_as(
+            "auth.login.totp",
+            None,
+            login_inner(repo, key_repo, refresh_repo, totp_repo, config, request),
+        )
+        .await
+}
+
+async fn login_inner(
+    repo: &State<Arc<UserRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    totp_repo: &State<Arc<TotpRepository>>,
+    config: &State<AuthConfig>,
+    request: Json<TotpLoginRequest>,
+) -> Result<(String, LoginResponse), Json<ErrorResponse>> {
+    let subject = match totp_repo
+        .complete_challenge(&request.challenge_token, &request.code)
+        .await
+    {
+        Ok(Some(subject)) => subject,
+        Ok(None) => {
+            warn!("Rejected two-factor login attempt.");
+            return Err(Json(ErrorResponse {
+                status: Status::Unauthorized.code,
+                message: "Invalid code or expired challenge".to_string(),
+            }));
+        }
+        Err(e) => {
+            error!("Failed to complete login challenge: {:?}", e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }));
+        }
+    };
+
+    let user = match repo.get_user_by_email(&subject).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return Err(Json(ErrorResponse {
+                status: Status::Unauthorized.code,
+                message: "Invalid code or expired challenge".to_string(),
+            }))
+        }
+        Err(e) => {
+            error!("Failed to look up user for two-factor login: {:?}", e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }));
+        }
+    };
+
+    start_session(
+        &user.email,
+        &user.roles,
+        repo,
+        key_repo,
+        refresh_repo,
+        config,
+    )
+    .await
+    .map(|session| (user.email, session))
+}
+
+pub fn totp_routes() -> Vec<rocket::Route> {
+    routes![enroll, confirm, disable, login]
+}
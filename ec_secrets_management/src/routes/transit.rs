@@ -0,0 +1,141 @@
+/*-------------
+Custom modules
+--------------*/
+use crate::models::ErrorResponse;
+use crate::request_guards::{AuditTrail, TokenGuard, Unsealed};
+use ec_secrets_shared_library::{
+    models::{TransitRequest, TransitResponse},
+    utils::{
+        kms::{is_valid_transit_key, transit_decrypt, transit_encrypt},
+        seal::Keyring,
+    },
+};
+
+/*-------------
+3rd party modules
+--------------*/
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{post, routes, State};
+use zeroize::Zeroizing;
+
+/*-------------
+stdlib modules
+--------------*/
+use std::sync::Arc;
+
+fn error_response(status: Status, message: &str) -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        status: status.code,
+        message: message.to_string(),
+    })
+}
+
+#[derive(Clone, Copy)]
+enum Operation {
+    Encrypt,
+    Decrypt,
+}
+
+/// Checks access to the transit key `name` and runs `operation` on the
+/// request data under it, auditing the call.
+async fn transit(
+    operation: Operation,
+    name: &str,
+    keyring: &Keyring,
+    request: &TransitRequest,
+    token: &TokenGuard,
+    audit: &AuditTrail,
+) -> Result<Json<TransitResponse>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    let action = match operation {
+        Operation::Encrypt => "transit.encrypt",
+        Operation::Decrypt => "transit.decrypt",
+    };
+    audit
+        .audited(
+            &actor,
+            action,
+            Some(name),
+            transit_inner(operation, name, keyring, request, token),
+        )
+        .await
+}
+
+async fn transit_inner(
+    operation: Operation,
+    name: &str,
+    keyring: &Keyring,
+    request: &TransitRequest,
+    token: &TokenGuard,
+) -> Result<Json<TransitResponse>, Json<ErrorResponse>> {
+    if !is_valid_transit_key(name) {
+        return Err(error_response(
+            Status::BadRequest,
+            "Transit key names use letters, digits, '-' and '_'",
+        ));
+    }
+    if !token.can_use_transit(name) {
+        return Err(error_response(
+            Status::Forbidden,
+            "Insufficient Permissions",
+        ));
+    }
+    let data = Zeroizing::new(
+        STANDARD
+            .decode(&request.data)
+            .map_err(|_| error_response(Status::BadRequest, "Data must be base64"))?,
+    );
+
+    let output = keyring
+        .with_key(|master_key| match operation {
+            Operation::Encrypt => transit_encrypt(master_key, name, &data).map(Zeroizing::new),
+            Operation::Decrypt => transit_decrypt(master_key, name, &data),
+        })
+        .map_err(|e| error_response(Status::ServiceUnavailable, &e.to_string()))?;
+    let Some(output) = output else {
+        return Err(error_response(
+            Status::BadRequest,
+            "Data cannot be processed under this transit key",
+        ));
+    };
+    Ok(Json(TransitResponse {
+        data: STANDARD.encode(&*output),
+    }))
+}
+
+/*----------------------------------------------------------------
+ Encrypt data under a named transit key, for another Locksmith
+ wrapping its master key. Nothing is stored
+-----------------------------------------------------------------*/
+#[post("/transit/<name>/encrypt", data = "<request>")]
+pub async fn encrypt(
+    _unsealed: Unsealed,
+    name: &str,
+    keyring: &State<Arc<Keyring>>,
+    request: Json<TransitRequest>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<TransitResponse>, Json<ErrorResponse>> {
+    transit(Operation::Encrypt, name, keyring, &request, &token, &audit).await
+}
+
+/*----------------------------------------------------------------
+ Decrypt data encrypted under the same transit key
+-----------------------------------------------------------------*/
+#[post("/transit/<name>/decrypt", data = "<request>")]
+pub async fn decrypt(
+    _unsealed: Unsealed,
+    name: &str,
+    keyring: &State<Arc<Keyring>>,
+    request: Json<TransitRequest>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<TransitResponse>, Json<ErrorResponse>> {
+    transit(Operation::Decrypt, name, keyring, &request, &token, &audit).await
+}
+
+pub fn transit_routes() -> Vec<rocket::Route> {
+    routes![encrypt, decrypt]
+}
@@ -1,18 +1,48 @@
 /*-------------
 Custom modules
 --------------*/
-use crate::models::{DeleteUserResponse, ErrorResponse, LoginResponse, SetupResponse};
+use crate::models::{
+    DeleteUserResponse, ErrorResponse, LoginOutcome, LoginResponse, LogoutResponse,
+    MfaChallengeResponse, SetupResponse, WebAuthnCredentialResponse, WebAuthnOptionsResponse,
+};
+use crate::request_guards::{AdminGuard, AuditTrail, LoginThrottle, TokenGuard};
+use crate::routes::account::send_verification;
 use ec_secrets_shared_library::{
-    models::{User, UserCredentials, UserDocument},
-    repositories::{keys::KeyRepository, users::UserRepository},
-    utils::auth::{authorize_user, hash_password}
+    models::{
+        AuditOutcome, LogoutRequest, OffboardRequest, Offboarded, RefreshTokenRequest, User,
+        UserCredentials, UserDocument, UserStatus, WebAuthnAssertion, WebAuthnCredential,
+        WebAuthnLoginRequest, WebAuthnRegistration, ADMIN_ROLE,
+    },
+    repositories::{
+        account_tokens::AccountTokenRepository,
+        keys::KeyRepository,
+        offboarding::OffboardingRepository,
+        refresh_tokens::{RefreshOutcome, RefreshTokenRepository},
+        revocations::RevocationRepository,
+        totp::TotpRepository,
+        users::UserRepository,
+        vault::VaultRepository,
+        webauthn::{WebAuthnRepository, AUTHENTICATION_CEREMONY, REGISTRATION_CEREMONY},
+    },
+    utils::{
+        auth::{authenticate_password, hash_password, issue_token, AuthConfig, LoginError},
+        mail::MailTransport,
+        password_policy::{is_valid_email, PasswordPolicy, PolicyViolation},
+        webauthn::{
+            client_data_challenge, verify_assertion, verify_registration, WebAuthnConfig,
+            COSE_ALG_ES256,
+        },
+    },
 };
 
 /*-------------
 3rd party modules
 --------------*/
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{Duration, Utc};
+use log::{error, info, warn};
 use rocket::http::Status;
-use rocket::serde::json::Json;
+use rocket::serde::json::{json, Json};
 use rocket::{delete, get, post, put, routes, State};
 
 /*-------------
@@ -20,11 +50,88 @@ stdlib modules
 --------------*/
 use std::sync::Arc;
 
+/*---------------------------------------------------------------
+ Check new credentials against the email syntax and the password
+ policy; `current` is the account whose password is being replaced
+----------------------------------------------------------------*/
+pub(crate) fn validate_credentials(
+    credentials: &UserCredentials,
+    policy: &PasswordPolicy,
+    current: Option<&UserDocument>,
+) -> Result<(), Json<ErrorResponse>> {
+    if !is_valid_email(&credentials.email) {
+        return Err(Json(ErrorResponse {
+            status: Status::BadRequest.code,
+            message: "Invalid email address".to_string(),
+        }));
+    }
+
+    let mut violations = match policy.check(&credentials.password, &[&credentials.email]) {
+        Ok(violations) => violations,
+        Err(e) => {
+            error!("Failed to read the breached password list: {}", e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }));
+        }
+    };
+    if let Some(user) = current {
+        if policy.reuses(
+            &credentials.password,
+            &user.password,
+            &user.password_history,
+        ) {
+            violations.push(PolicyViolation::Reused);
+        }
+    }
+
+    if violations.is_empty() {
+        return Ok(());
+    }
+    let reasons: Vec<String> = violations.iter().map(ToString::to_string).collect();
+    Err(Json(ErrorResponse {
+        status: Status::BadRequest.code,
+        message: format!("Password {}", reasons.join("; ")),
+    }))
+}
+
+pub(crate) fn account_disabled() -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        status: Status::Forbidden.code,
+        message: "Account is disabled".to_string(),
+    })
+}
+
 #[post("/setup", data = "<credentials>")]
 pub async fn setup(
     repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<AccountTokenRepository>>,
+    mailer: &State<Arc<dyn MailTransport>>,
+    config: &State<AuthConfig>,
+    credentials: Json<UserCredentials>,
+    audit: AuditTrail,
+) -> Result<Json<SetupResponse>, Json<ErrorResponse>> {
+    let email = credentials.email.clone();
+    audit
+        .audited(
+            &email,
+            "user.create",
+            Some(&email),
+            setup_inner(repo, token_repo, mailer, config, credentials),
+        )
+        .await
+}
+
+async fn setup_inner(
+    repo: &State<Arc<UserRepository>>,
+    token_repo: &State<Arc<AccountTokenRepository>>,
+    mailer: &State<Arc<dyn MailTransport>>,
+    config: &State<AuthConfig>,
     credentials: Json<UserCredentials>,
 ) -> Result<Json<SetupResponse>, Json<ErrorResponse>> {
+    validate_credentials(&credentials, &config.password_policy, None)?;
+
     // Check if the user already exists
     if let Ok(Some(_)) = repo.get_user_by_email(&credentials.email).await {
         return Err(Json(ErrorResponse {
@@ -33,7 +140,7 @@ pub async fn setup(
         }));
     }
 
-    let hashed_password = match hash_password(credentials.password.clone()) {
+    let hashed_password = match hash_password(&credentials.password, &config.password_hashing) {
         Ok(hash) => hash,
         Err(_e) => {
             return Err(Json(ErrorResponse {
@@ -43,9 +150,33 @@ pub async fn setup(
         }
     };
 
-    let _ = match repo.create_user(&credentials.email, &hashed_password).await {
+    // The very first account bootstraps the deployment and administers it.
+    let bootstrap = match repo.claim_bootstrap().await {
+        Ok(bootstrap) => bootstrap,
+        Err(_) => {
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Failed to setup account".to_string(),
+            }));
+        }
+    };
+    let roles = if bootstrap {
+        vec![ADMIN_ROLE.to_string()]
+    } else {
+        vec![]
+    };
+
+    let user = match repo
+        .create_user(&credentials.email, &hashed_password, &roles)
+        .await
+    {
         Ok(user) => user,
         Err(_) => {
+            if bootstrap {
+                if let Err(e) = repo.release_bootstrap().await {
+                    error!("Failed to release the bootstrap claim: {:?}", e);
+                }
+            }
             return Err(Json(ErrorResponse {
                 status: Status::InternalServerError.code,
                 message: "Failed to setup account".to_string(),
@@ -53,6 +184,11 @@ pub async fn setup(
         }
     };
 
+    // The account works right away; the link can be requested again later.
+    if let Err(e) = send_verification(&user.email, token_repo, mailer.as_ref(), config).await {
+        error!("Failed to send verification mail to {}: {}", user.email, e);
+    }
+
     Ok(Json(SetupResponse {
         status: Status::Ok.code,
         message: "User registered successfully".to_string(),
@@ -60,56 +196,401 @@ pub async fn setup(
 }
 
 #[post("/login", data = "<credentials>")]
+#[allow(clippy::too_many_arguments)]
 pub async fn login(
     repo: &State<Arc<UserRepository>>,
     key_repo: &State<Arc<KeyRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    totp_repo: &State<Arc<TotpRepository>>,
+    config: &State<AuthConfig>,
+    throttle: LoginThrottle,
     credentials: Json<UserCredentials>,
-) -> Result<Json<LoginResponse>, Json<ErrorResponse>> {
-    let user_document = match repo.get_user_by_email(&credentials.email).await {
-        Ok(Some(user_document)) => user_document,
-        Ok(None) => {
+    audit: AuditTrail,
+) -> Result<Json<LoginOutcome>, Json<ErrorResponse>> {
+    let actor = credentials.email.clone();
+    audit
+        .audited(
+            &actor,
+            "auth.login",
+            None,
+            login_inner(
+                repo,
+                key_repo,
+                refresh_repo,
+                totp_repo,
+                config,
+                throttle,
+                credentials,
+                &audit,
+            ),
+        )
+        .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn login_inner(
+    repo: &State<Arc<UserRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    totp_repo: &State<Arc<TotpRepository>>,
+    config: &State<AuthConfig>,
+    throttle: LoginThrottle,
+    credentials: Json<UserCredentials>,
+    audit: &AuditTrail,
+) -> Result<Json<LoginOutcome>, Json<ErrorResponse>> {
+    let user = match authenticate_password(
+        repo,
+        &throttle.attempts,
+        &credentials,
+        throttle.client_address,
+        config,
+    )
+    .await
+    {
+        Ok(user) => user,
+        Err(LoginError::InvalidCredentials(lockouts)) => {
+            for lockout in lockouts {
+                audit
+                    .record(
+                        &credentials.email,
+                        lockout.action(),
+                        Some(&lockout.target()),
+                        AuditOutcome::Denied,
+                        Some(lockout.detail()),
+                    )
+                    .await;
+            }
             return Err(Json(ErrorResponse {
                 status: Status::Unauthorized.code,
                 message: "Invalid email or password".to_string(),
-            }))
+            }));
         }
-        Err(_) => {
+        Err(LoginError::Disabled) => {
+            warn!("Login attempt for disabled account {}.", credentials.email);
+            return Err(account_disabled());
+        }
+        Err(LoginError::Throttled(retry_after)) => {
+            warn!("Throttled login attempt for {}.", credentials.email);
+            return Err(Json(ErrorResponse {
+                status: Status::TooManyRequests.code,
+                message: format!(
+                    "Too many failed login attempts, retry in {} seconds",
+                    retry_after
+                ),
+            }));
+        }
+        Err(e) => {
+            error!("Failed to authenticate {}: {}", credentials.email, e);
             return Err(Json(ErrorResponse {
                 status: Status::InternalServerError.code,
                 message: "Internal server error".to_string(),
-            }))
+            }));
         }
     };
 
-    let user = User {
-        id: user_document.id.to_string(),
-        email: user_document.email.clone(),
-        password: user_document.password.clone(),
-        created_at: user_document.created_at.to_rfc3339(),
-    };
+    // With two-factor authentication enabled the password only earns a challenge.
+    match totp_repo.is_enrolled(&user.email).await {
+        Ok(true) => {
+            return match totp_repo.create_challenge(&user.email).await {
+                Ok(challenge_token) => Ok(Json(LoginOutcome::Challenge(MfaChallengeResponse {
+                    status: Status::Ok.code,
+                    mfa_required: true,
+                    challenge_token,
+                }))),
+                Err(e) => {
+                    error!("Failed to create login challenge: {:?}", e);
+                    Err(Json(ErrorResponse {
+                        status: Status::InternalServerError.code,
+                        message: "Internal server error".to_string(),
+                    }))
+                }
+            };
+        }
+        Ok(false) => {}
+        Err(e) => {
+            error!("Failed to look up two-factor enrollment: {:?}", e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }));
+        }
+    }
+
+    start_session(
+        &user.email,
+        &user.roles,
+        repo,
+        key_repo,
+        refresh_repo,
+        config,
+    )
+    .await
+    .map(|session| Json(LoginOutcome::Token(session)))
+}
 
-    let token = match authorize_user(&user, &credentials, key_repo).await {
+/*---------------------------------------------------------------
+ Issue the access and refresh token pair of a fully authenticated user
+----------------------------------------------------------------*/
+pub(crate) async fn start_session(
+    subject: &str,
+    roles: &[String],
+    repo: &UserRepository,
+    key_repo: &KeyRepository,
+    refresh_repo: &RefreshTokenRepository,
+    config: &AuthConfig,
+) -> Result<LoginResponse, Json<ErrorResponse>> {
+    // Every login method ends here, so this is where disabled accounts are turned away.
+    match repo.get_user_by_email(subject).await {
+        Ok(Some(user)) if user.status == UserStatus::Disabled => {
+            warn!("Refused a session for disabled account {}.", subject);
+            return Err(account_disabled());
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Failed to look up {}: {:?}", subject, e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }));
+        }
+    }
+
+    let token = match issue_token(
+        subject,
+        roles,
+        None,
+        config.access_token_lifetime(),
+        config,
+        key_repo,
+    )
+    .await
+    {
         Ok(token) => token,
-        Err(_) => {
+        Err(e) => {
+            error!("Failed to issue access token: {}", e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }));
+        }
+    };
+
+    let refresh_token = match refresh_repo
+        .issue(subject, None, config.refresh_token_lifetime())
+        .await
+    {
+        Ok(refresh_token) => refresh_token,
+        Err(e) => {
+            error!("Failed to issue refresh token: {:?}", e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }));
+        }
+    };
+
+    if let Err(e) = repo.record_login(subject).await {
+        error!("Failed to record the login of {}: {:?}", subject, e);
+    }
+
+    Ok(LoginResponse {
+        status: Status::Ok.code,
+        token,
+        refresh_token: Some(refresh_token),
+    })
+}
+
+/*---------------------------------------------------------------
+ Exchange a refresh token for a new access and refresh token pair
+----------------------------------------------------------------*/
+#[post("/token/refresh", data = "<request>")]
+pub async fn refresh_token(
+    repo: &State<Arc<UserRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    config: &State<AuthConfig>,
+    request: Json<RefreshTokenRequest>,
+) -> Result<Json<LoginResponse>, Json<ErrorResponse>> {
+    let (subject, refresh_token) = match refresh_repo
+        .rotate(&request.refresh_token, config.refresh_token_lifetime())
+        .await
+    {
+        Ok(RefreshOutcome::Rotated {
+            subject,
+            refresh_token,
+        }) => (subject, refresh_token),
+        Ok(RefreshOutcome::Reused { subject, family_id }) => {
+            warn!(
+                "Refresh token reuse detected for {}; revoked token family {}.",
+                subject, family_id
+            );
             return Err(Json(ErrorResponse {
                 status: Status::Unauthorized.code,
-                message: "Invalid email or password".to_string(),
+                message: "Invalid refresh token".to_string(),
+            }));
+        }
+        Ok(RefreshOutcome::Invalid) => {
+            return Err(Json(ErrorResponse {
+                status: Status::Unauthorized.code,
+                message: "Invalid refresh token".to_string(),
             }))
         }
+        Err(e) => {
+            error!("Failed to rotate refresh token: {:?}", e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }));
+        }
+    };
+
+    // The account may have been removed since the refresh token was issued.
+    let user = match repo.get_user_by_email(&subject).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            let _ = refresh_repo.revoke_subject(&subject).await;
+            return Err(Json(ErrorResponse {
+                status: Status::Unauthorized.code,
+                message: "Invalid refresh token".to_string(),
+            }));
+        }
+        Err(e) => {
+            error!("Failed to look up user for refresh: {:?}", e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }));
+        }
+    };
+    if user.status == UserStatus::Disabled {
+        let _ = refresh_repo.revoke_subject(&subject).await;
+        return Err(account_disabled());
+    }
+
+    let token = match issue_token(
+        &subject,
+        &user.roles,
+        None,
+        config.access_token_lifetime(),
+        config,
+        key_repo,
+    )
+    .await
+    {
+        Ok(token) => token,
+        Err(e) => {
+            error!("Failed to issue access token: {}", e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }));
+        }
     };
 
     Ok(Json(LoginResponse {
         status: Status::Ok.code,
         token,
+        refresh_token: Some(refresh_token),
     }))
 }
 
-#[get("/users")]
-pub async fn list_users(
+/*--------------------------------------------------------------
+ Revoke the presented token and, if given, its refresh token family
+---------------------------------------------------------------*/
+#[post("/logout", data = "<request>")]
+pub async fn logout(
+    revocations: &State<Arc<RevocationRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    request: Option<Json<LogoutRequest>>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<LogoutResponse>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited(
+            &actor,
+            "auth.logout",
+            None,
+            logout_inner(revocations, refresh_repo, request, token),
+        )
+        .await
+}
+
+async fn logout_inner(
+    revocations: &State<Arc<RevocationRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    request: Option<Json<LogoutRequest>>,
+    token: TokenGuard,
+) -> Result<Json<LogoutResponse>, Json<ErrorResponse>> {
+    let (Some(jti), Some(subject), Some(expires_at)) =
+        (token.token_id(), token.subject(), token.expires_at())
+    else {
+        return Err(Json(ErrorResponse {
+            status: Status::BadRequest.code,
+            message: "Token cannot be revoked".to_string(),
+        }));
+    };
+
+    if let Err(e) = revocations.revoke_token(jti, subject, expires_at).await {
+        error!("Failed to revoke token: {:?}", e);
+        return Err(Json(ErrorResponse {
+            status: Status::InternalServerError.code,
+            message: "Internal server error".to_string(),
+        }));
+    }
+
+    if let Some(refresh_token) = request.and_then(|request| request.into_inner().refresh_token) {
+        if let Err(e) = refresh_repo.revoke_family_of(&refresh_token).await {
+            error!("Failed to revoke refresh token family: {:?}", e);
+        }
+    }
+
+    info!("{} logged out.", subject);
+    Ok(Json(LogoutResponse {
+        status: Status::Ok.code,
+        message: "Logged out successfully".to_string(),
+    }))
+}
+
+/*--------------------------------------------------
+ Revoke every session of a user (administrative action)
+---------------------------------------------------*/
+#[post("/users/<id>/revoke-sessions")]
+pub async fn revoke_sessions(
     repo: &State<Arc<UserRepository>>,
-) -> Result<Json<Vec<UserDocument>>, Json<ErrorResponse>> {
-    let users = match repo.list_users().await {
-        Ok(users) => users,
+    revocations: &State<Arc<RevocationRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    id: String,
+    admin: AdminGuard,
+    audit: AuditTrail,
+) -> Result<Json<LogoutResponse>, Json<ErrorResponse>> {
+    let actor = admin.0.subject().unwrap_or_default().to_string();
+    let target = id.clone();
+    audit
+        .audited(
+            &actor,
+            "user.revoke_sessions",
+            Some(&target),
+            revoke_sessions_inner(repo, revocations, refresh_repo, id, admin),
+        )
+        .await
+}
+
+async fn revoke_sessions_inner(
+    repo: &State<Arc<UserRepository>>,
+    revocations: &State<Arc<RevocationRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    id: String,
+    admin: AdminGuard,
+) -> Result<Json<LogoutResponse>, Json<ErrorResponse>> {
+    let user = match repo.get_user_by_id(&id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return Err(Json(ErrorResponse {
+                status: Status::NotFound.code,
+                message: "User not found".to_string(),
+            }))
+        }
         Err(_) => {
             return Err(Json(ErrorResponse {
                 status: Status::InternalServerError.code,
@@ -118,14 +599,52 @@ pub async fn list_users(
         }
     };
 
-    Ok(Json(users))
+    if let Err(e) = revoke_all_sessions(revocations, refresh_repo, &user.email).await {
+        error!("Failed to revoke sessions for {}: {:?}", user.email, e);
+        return Err(Json(ErrorResponse {
+            status: Status::InternalServerError.code,
+            message: "Internal server error".to_string(),
+        }));
+    }
+
+    info!(
+        "{} revoked all sessions for {}.",
+        admin.0.subject().unwrap_or_default(),
+        user.email
+    );
+    Ok(Json(LogoutResponse {
+        status: Status::Ok.code,
+        message: "All sessions revoked".to_string(),
+    }))
 }
 
-#[get("/users/<id>")]
-pub async fn get_user(
+/*--------------------------------------------------
+ Lift a login lockout (administrative action)
+---------------------------------------------------*/
+#[post("/users/<id>/unlock")]
+pub async fn unlock_user(
+    repo: &State<Arc<UserRepository>>,
+    throttle: LoginThrottle,
+    id: String,
+    admin: AdminGuard,
+    audit: AuditTrail,
+) -> Result<Json<LogoutResponse>, Json<ErrorResponse>> {
+    let target = id.clone();
+    audit
+        .audited(
+            admin.0.subject().unwrap_or_default(),
+            "user.unlock",
+            Some(&target),
+            unlock_user_inner(repo, throttle, id),
+        )
+        .await
+}
+
+async fn unlock_user_inner(
     repo: &State<Arc<UserRepository>>,
+    throttle: LoginThrottle,
     id: String,
-) -> Result<Json<UserDocument>, Json<ErrorResponse>> {
+) -> Result<Json<LogoutResponse>, Json<ErrorResponse>> {
     let user = match repo.get_user_by_id(&id).await {
         Ok(Some(user)) => user,
         Ok(None) => {
@@ -142,15 +661,350 @@ pub async fn get_user(
         }
     };
 
-    Ok(Json(user))
+    if let Err(e) = throttle.attempts.clear_account(&user.email).await {
+        error!("Failed to unlock {}: {:?}", user.email, e);
+        return Err(Json(ErrorResponse {
+            status: Status::InternalServerError.code,
+            message: "Internal server error".to_string(),
+        }));
+    }
+
+    Ok(Json(LogoutResponse {
+        status: Status::Ok.code,
+        message: "Account unlocked".to_string(),
+    }))
+}
+
+/*---------------------------------------------------------------
+ Disable or re-enable an account (administrative action). Disabling
+ blocks every login method and revokes the user's tokens
+----------------------------------------------------------------*/
+async fn change_status(
+    repo: &UserRepository,
+    id: &str,
+    status: UserStatus,
+    admin: &AdminGuard,
+) -> Result<UserDocument, Json<ErrorResponse>> {
+    let user = match repo.get_user_by_id(id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return Err(Json(ErrorResponse {
+                status: Status::NotFound.code,
+                message: "User not found".to_string(),
+            }))
+        }
+        Err(_) => {
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }))
+        }
+    };
+    if admin.0.subject() == Some(user.email.as_str()) {
+        return Err(Json(ErrorResponse {
+            status: Status::BadRequest.code,
+            message: "You cannot change the status of your own account".to_string(),
+        }));
+    }
+
+    match repo.set_status(id, status).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(Json(ErrorResponse {
+            status: Status::NotFound.code,
+            message: "User not found".to_string(),
+        })),
+        Err(e) => {
+            error!("Failed to set the status of {}: {:?}", user.email, e);
+            Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }))
+        }
+    }
+}
+
+#[post("/users/<id>/disable")]
+pub async fn disable_user(
+    repo: &State<Arc<UserRepository>>,
+    revocations: &State<Arc<RevocationRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    id: String,
+    admin: AdminGuard,
+    audit: AuditTrail,
+) -> Result<Json<User>, Json<ErrorResponse>> {
+    let actor = admin.0.subject().unwrap_or_default().to_string();
+    let target = id.clone();
+    audit
+        .audited(
+            &actor,
+            "user.disable",
+            Some(&target),
+            disable_user_inner(repo, revocations, refresh_repo, id, admin),
+        )
+        .await
+}
+
+async fn disable_user_inner(
+    repo: &State<Arc<UserRepository>>,
+    revocations: &State<Arc<RevocationRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    id: String,
+    admin: AdminGuard,
+) -> Result<Json<User>, Json<ErrorResponse>> {
+    let user = change_status(repo, &id, UserStatus::Disabled, &admin).await?;
+
+    if let Err(e) = revoke_all_sessions(revocations, refresh_repo, &user.email).await {
+        error!("Failed to revoke sessions for {}: {:?}", user.email, e);
+        return Err(Json(ErrorResponse {
+            status: Status::InternalServerError.code,
+            message: "Internal server error".to_string(),
+        }));
+    }
+    Ok(Json(User::from(user)))
+}
+
+#[post("/users/<id>/enable")]
+pub async fn enable_user(
+    repo: &State<Arc<UserRepository>>,
+    id: String,
+    admin: AdminGuard,
+    audit: AuditTrail,
+) -> Result<Json<User>, Json<ErrorResponse>> {
+    let actor = admin.0.subject().unwrap_or_default().to_string();
+    let target = id.clone();
+    audit
+        .audited(
+            &actor,
+            "user.enable",
+            Some(&target),
+            enable_user_inner(repo, id, admin),
+        )
+        .await
+}
+
+async fn enable_user_inner(
+    repo: &State<Arc<UserRepository>>,
+    id: String,
+    admin: AdminGuard,
+) -> Result<Json<User>, Json<ErrorResponse>> {
+    let user = change_status(repo, &id, UserStatus::Active, &admin).await?;
+    Ok(Json(User::from(user)))
+}
+
+/*---------------------------------------------------------------
+ Offboard a user (administrative action): their secrets move to
+ `reassign_to` or into the archive, and the account is removed, all
+ in one transaction
+----------------------------------------------------------------*/
+#[post("/users/<id>/offboard", data = "<request>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn offboard_user(
+    offboarding: &State<Arc<OffboardingRepository>>,
+    revocations: &State<Arc<RevocationRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    totp_repo: &State<Arc<TotpRepository>>,
+    id: String,
+    request: Option<Json<OffboardRequest>>,
+    admin: AdminGuard,
+    audit: AuditTrail,
+) -> Result<Json<DeleteUserResponse>, Json<ErrorResponse>> {
+    let actor = admin.0.subject().unwrap_or_default().to_string();
+    let target = id.clone();
+    audit
+        .audited_with(
+            &actor,
+            "user.offboard",
+            Some(&target),
+            offboard_user_inner(
+                offboarding,
+                revocations,
+                refresh_repo,
+                totp_repo,
+                id,
+                request,
+            ),
+            |response| Some(response.message.clone()),
+        )
+        .await
+}
+
+async fn offboard_user_inner(
+    offboarding: &State<Arc<OffboardingRepository>>,
+    revocations: &State<Arc<RevocationRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    totp_repo: &State<Arc<TotpRepository>>,
+    id: String,
+    request: Option<Json<OffboardRequest>>,
+) -> Result<Json<DeleteUserResponse>, Json<ErrorResponse>> {
+    let request = request.map(Json::into_inner).unwrap_or_default();
+    if request.reassign_to.as_deref() == Some(id.as_str()) {
+        return Err(Json(ErrorResponse {
+            status: Status::BadRequest.code,
+            message: "A user cannot inherit their own secrets".to_string(),
+        }));
+    }
+
+    let (user, outcome) = match offboarding
+        .offboard(&id, request.reassign_to.as_deref())
+        .await
+    {
+        Ok(Some(offboarded)) => offboarded,
+        Ok(None) => {
+            return Err(Json(ErrorResponse {
+                status: Status::NotFound.code,
+                message: "User not found".to_string(),
+            }))
+        }
+        Err(e) => {
+            error!("Failed to offboard user {}: {:?}", id, e);
+            return Err(Json(ErrorResponse {
+                status: Status::Conflict.code,
+                message: "Failed to offboard user; nothing was changed".to_string(),
+            }));
+        }
+    };
+
+    if let Err(e) = revoke_all_sessions(revocations, refresh_repo, &user.email).await {
+        error!("Failed to revoke sessions for {}: {:?}", user.email, e);
+    }
+    if let Err(e) = totp_repo.disable(&user.email).await {
+        error!(
+            "Failed to remove TOTP enrollment of {}: {:?}",
+            user.email, e
+        );
+    }
+
+    let message = match outcome {
+        Offboarded::Reassigned { to, secrets } => {
+            format!("User offboarded; {} secrets reassigned to {}", secrets, to)
+        }
+        Offboarded::Archived { secrets } => {
+            format!("User offboarded; {} secrets archived", secrets)
+        }
+    };
+    Ok(Json(DeleteUserResponse {
+        status: Status::Ok.code,
+        message,
+    }))
+}
+
+pub(crate) async fn revoke_all_sessions(
+    revocations: &RevocationRepository,
+    refresh_repo: &RefreshTokenRepository,
+    subject: &str,
+) -> Result<(), String> {
+    revocations
+        .revoke_subject(subject)
+        .await
+        .map_err(|e| e.to_string())?;
+    refresh_repo
+        .revoke_subject(subject)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[get("/users")]
+pub async fn list_users(
+    repo: &State<Arc<UserRepository>>,
+) -> Result<Json<Vec<User>>, Json<ErrorResponse>> {
+    let users = match repo.list_users().await {
+        Ok(users) => users,
+        Err(_) => {
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }))
+        }
+    };
+
+    Ok(Json(users.into_iter().map(User::from).collect()))
+}
+
+/*---------------------------------------------------------------
+ The account `id`, if `token` may manage it: users reach their own
+ account, administrators any. Others get 403 whether or not the
+ account exists.
+----------------------------------------------------------------*/
+async fn managed_account(
+    repo: &UserRepository,
+    token: &TokenGuard,
+    id: &str,
+) -> Result<UserDocument, Json<ErrorResponse>> {
+    match repo.get_user_by_id(id).await {
+        Ok(Some(user)) if token.may_manage_account(&user.email) => Ok(user),
+        Ok(None) if token.is_admin() => Err(Json(ErrorResponse {
+            status: Status::NotFound.code,
+            message: "User not found".to_string(),
+        })),
+        Ok(_) => Err(Json(ErrorResponse {
+            status: Status::Forbidden.code,
+            message: "Insufficient Permissions".to_string(),
+        })),
+        Err(_) => Err(Json(ErrorResponse {
+            status: Status::InternalServerError.code,
+            message: "Internal server error".to_string(),
+        })),
+    }
+}
+
+#[get("/users/<id>")]
+pub async fn get_user(
+    repo: &State<Arc<UserRepository>>,
+    id: String,
+    token: TokenGuard,
+) -> Result<Json<User>, Json<ErrorResponse>> {
+    let user = managed_account(repo, &token, &id).await?;
+    Ok(Json(User::from(user)))
 }
 
 #[put("/update/<id>", data = "<credentials>")]
+#[allow(clippy::too_many_arguments)]
 pub async fn update_user(
     repo: &State<Arc<UserRepository>>,
+    revocations: &State<Arc<RevocationRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    totp_repo: &State<Arc<TotpRepository>>,
+    config: &State<AuthConfig>,
+    id: String,
+    credentials: Json<UserCredentials>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<User>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    let target = id.clone();
+    audit
+        .audited(
+            &actor,
+            "user.update",
+            Some(&target),
+            update_user_inner(
+                repo,
+                revocations,
+                refresh_repo,
+                totp_repo,
+                config,
+                id,
+                credentials,
+                token,
+            ),
+        )
+        .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn update_user_inner(
+    repo: &State<Arc<UserRepository>>,
+    revocations: &State<Arc<RevocationRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    totp_repo: &State<Arc<TotpRepository>>,
+    config: &State<AuthConfig>,
     id: String,
     credentials: Json<UserCredentials>,
-) -> Result<Json<UserDocument>, Json<ErrorResponse>> {
+    token: TokenGuard,
+) -> Result<Json<User>, Json<ErrorResponse>> {
+    let current = managed_account(repo, &token, &id).await?;
+
     // Check if the email is already in use by another user
     if let Ok(Some(existing_user)) = repo.get_user_by_email(&credentials.email).await {
         // If the email exists and it's not the user being updated
@@ -162,7 +1016,9 @@ pub async fn update_user(
         }
     }
 
-    let hashed_password = match hash_password(credentials.password.clone()) {
+    validate_credentials(&credentials, &config.password_policy, Some(&current))?;
+
+    let hashed_password = match hash_password(&credentials.password, &config.password_hashing) {
         Ok(hash) => hash,
         Err(_) => {
             return Err(Json(ErrorResponse {
@@ -173,7 +1029,12 @@ pub async fn update_user(
     };
 
     let user = match repo
-        .update_user(&id, Some(&credentials.email), Some(&hashed_password))
+        .update_user(
+            &id,
+            Some(&credentials.email),
+            Some(&hashed_password),
+            config.password_policy.history,
+        )
         .await
     {
         Ok(Some(user)) => user,
@@ -191,41 +1052,618 @@ pub async fn update_user(
         }
     };
 
-    Ok(Json(user))
+    // The password (and possibly the email) changed: tokens issued before must stop working.
+    if let Err(e) = revoke_all_sessions(revocations, refresh_repo, &user.email).await {
+        error!("Failed to revoke sessions for {}: {:?}", user.email, e);
+    }
+
+    // Enrollments are keyed by email, so the second factor must follow a rename.
+    if user.email != credentials.email {
+        if let Err(e) = totp_repo
+            .rename_subject(&user.email, &credentials.email)
+            .await
+        {
+            error!("Failed to move TOTP enrollment of {}: {:?}", user.email, e);
+        }
+    }
+
+    // `user` is the account as it was before the update.
+    match repo.get_user_by_id(&id).await {
+        Ok(Some(updated)) => Ok(Json(User::from(updated))),
+        Ok(None) => Err(Json(ErrorResponse {
+            status: Status::NotFound.code,
+            message: "User not found".to_string(),
+        })),
+        Err(_) => Err(Json(ErrorResponse {
+            status: Status::InternalServerError.code,
+            message: "Internal server error".to_string(),
+        })),
+    }
 }
 
 #[delete("/delete/user/<id>")]
+#[allow(clippy::too_many_arguments)]
 pub async fn delete_user(
     repo: &State<Arc<UserRepository>>,
+    vault_repo: &State<Arc<VaultRepository>>,
+    revocations: &State<Arc<RevocationRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    totp_repo: &State<Arc<TotpRepository>>,
     id: String,
+    admin: AdminGuard,
+    audit: AuditTrail,
 ) -> Result<Json<DeleteUserResponse>, Json<ErrorResponse>> {
-    match repo.delete_user(&id).await {
-        Ok(Some(_)) => Ok(Json(DeleteUserResponse {
-            status: Status::Ok.code,
-            message: "User deleted successfully".to_string(),
-        })),
-        Ok(None) => {
+    let target = id.clone();
+    audit
+        .audited(
+            admin.0.subject().unwrap_or_default(),
+            "user.delete",
+            Some(&target),
+            delete_user_inner(repo, vault_repo, revocations, refresh_repo, totp_repo, id),
+        )
+        .await
+}
+
+async fn delete_user_inner(
+    repo: &State<Arc<UserRepository>>,
+    vault_repo: &State<Arc<VaultRepository>>,
+    revocations: &State<Arc<RevocationRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    totp_repo: &State<Arc<TotpRepository>>,
+    id: String,
+) -> Result<Json<DeleteUserResponse>, Json<ErrorResponse>> {
+    // Deleting would orphan the user's secrets; those accounts go through offboarding.
+    match vault_repo.count_owned(&id).await {
+        Ok(0) => {}
+        Ok(_) => {
             return Err(Json(ErrorResponse {
-                status: Status::NotFound.code,
-                message: "User not found".to_string(),
+                status: Status::Conflict.code,
+                message: "User still owns secrets; offboard them instead".to_string(),
             }))
         }
-        Err(_) => {
+        Err(e) => {
+            error!("Failed to count the secrets of user {}: {:?}", id, e);
             return Err(Json(ErrorResponse {
                 status: Status::InternalServerError.code,
                 message: "Internal server error".to_string(),
+            }));
+        }
+    }
+
+    match repo.delete_user(&id).await {
+        Ok(Some(user)) => {
+            if let Err(e) = revoke_all_sessions(revocations, refresh_repo, &user.email).await {
+                error!("Failed to revoke sessions for {}: {:?}", user.email, e);
+            }
+            if let Err(e) = totp_repo.disable(&user.email).await {
+                error!(
+                    "Failed to remove TOTP enrollment of {}: {:?}",
+                    user.email, e
+                );
+            }
+            Ok(Json(DeleteUserResponse {
+                status: Status::Ok.code,
+                message: "User deleted successfully".to_string(),
             }))
         }
+        Ok(None) => Err(Json(ErrorResponse {
+            status: Status::NotFound.code,
+            message: "User not found".to_string(),
+        })),
+        Err(_) => Err(Json(ErrorResponse {
+            status: Status::InternalServerError.code,
+            message: "Internal server error".to_string(),
+        })),
     }
 }
 
+/*---------------------------------------------------------------
+ Passkeys (WebAuthn): registration and authentication ceremonies
+----------------------------------------------------------------*/
+fn webauthn_error(status: Status, message: &str) -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        status: status.code,
+        message: message.to_string(),
+    })
+}
+
+fn user_verification(config: &WebAuthnConfig) -> &'static str {
+    if config.require_user_verification {
+        "required"
+    } else {
+        "preferred"
+    }
+}
+
+fn credential_descriptors(user: &UserDocument) -> Vec<serde_json::Value> {
+    user.webauthn_credentials
+        .iter()
+        .map(|credential| json!({ "type": "public-key", "id": credential.credential_id }))
+        .collect()
+}
+
+#[post("/webauthn/register/start")]
+pub async fn webauthn_register_start(
+    repo: &State<Arc<UserRepository>>,
+    webauthn_repo: &State<Arc<WebAuthnRepository>>,
+    config: &State<AuthConfig>,
+    token: TokenGuard,
+) -> Result<Json<WebAuthnOptionsResponse>, Json<ErrorResponse>> {
+    let Some(subject) = token.subject().filter(|_| token.policies().is_none()) else {
+        return Err(webauthn_error(
+            Status::Forbidden,
+            "Insufficient Permissions",
+        ));
+    };
+
+    let user = match repo.get_user_by_email(subject).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(webauthn_error(Status::NotFound, "User not found")),
+        Err(e) => {
+            error!("Failed to look up user for passkey registration: {:?}", e);
+            return Err(webauthn_error(
+                Status::InternalServerError,
+                "Internal server error",
+            ));
+        }
+    };
+
+    let webauthn = &config.webauthn;
+    let challenge = match webauthn_repo
+        .create_challenge(
+            REGISTRATION_CEREMONY,
+            Some(subject),
+            Duration::seconds(webauthn.challenge_ttl),
+        )
+        .await
+    {
+        Ok(challenge) => challenge,
+        Err(e) => {
+            error!("Failed to create passkey registration challenge: {:?}", e);
+            return Err(webauthn_error(
+                Status::InternalServerError,
+                "Internal server error",
+            ));
+        }
+    };
+
+    Ok(Json(WebAuthnOptionsResponse {
+        status: Status::Ok.code,
+        public_key: json!({
+            "challenge": challenge,
+            "rp": { "id": webauthn.rp_id, "name": webauthn.rp_name },
+            "user": {
+                "id": URL_SAFE_NO_PAD.encode(user.id.to_string()),
+                "name": user.email,
+                "displayName": user.email,
+            },
+            "pubKeyCredParams": [{ "type": "public-key", "alg": COSE_ALG_ES256 }],
+            "timeout": webauthn.challenge_ttl * 1000,
+            "attestation": "none",
+            "excludeCredentials": credential_descriptors(&user),
+            "authenticatorSelection": {
+                "residentKey": "preferred",
+                "userVerification": user_verification(webauthn),
+            },
+        }),
+    }))
+}
+
+#[post("/webauthn/register/finish", data = "<registration>")]
+pub async fn webauthn_register_finish(
+    repo: &State<Arc<UserRepository>>,
+    webauthn_repo: &State<Arc<WebAuthnRepository>>,
+    config: &State<AuthConfig>,
+    registration: Json<WebAuthnRegistration>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<WebAuthnCredentialResponse>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited(
+            &actor,
+            "mfa.passkey.add",
+            None,
+            webauthn_register_finish_inner(repo, webauthn_repo, config, registration, token),
+        )
+        .await
+}
+
+async fn webauthn_register_finish_inner(
+    repo: &State<Arc<UserRepository>>,
+    webauthn_repo: &State<Arc<WebAuthnRepository>>,
+    config: &State<AuthConfig>,
+    registration: Json<WebAuthnRegistration>,
+    token: TokenGuard,
+) -> Result<Json<WebAuthnCredentialResponse>, Json<ErrorResponse>> {
+    let Some(subject) = token.subject().filter(|_| token.policies().is_none()) else {
+        return Err(webauthn_error(
+            Status::Forbidden,
+            "Insufficient Permissions",
+        ));
+    };
+
+    let Ok(challenge) = client_data_challenge(&registration.client_data_json) else {
+        return Err(webauthn_error(
+            Status::BadRequest,
+            "Invalid passkey registration",
+        ));
+    };
+    let challenge = match webauthn_repo
+        .consume_challenge(&challenge, REGISTRATION_CEREMONY)
+        .await
+    {
+        Ok(Some(challenge)) if challenge.subject.as_deref() == Some(subject) => challenge,
+        Ok(_) => {
+            return Err(webauthn_error(
+                Status::BadRequest,
+                "Unknown or expired registration",
+            ))
+        }
+        Err(e) => {
+            error!("Failed to load passkey registration challenge: {:?}", e);
+            return Err(webauthn_error(
+                Status::InternalServerError,
+                "Internal server error",
+            ));
+        }
+    };
+
+    let credential =
+        match verify_registration(&config.webauthn, &challenge.challenge, &registration) {
+            Ok(credential) => credential,
+            Err(e) => {
+                warn!("Rejected passkey registration for {}: {}", subject, e);
+                return Err(webauthn_error(
+                    Status::BadRequest,
+                    "Invalid passkey registration",
+                ));
+            }
+        };
+
+    let credential = WebAuthnCredential {
+        credential_id: credential.credential_id,
+        public_key: credential.public_key,
+        sign_count: credential.sign_count,
+        name: registration
+            .name
+            .clone()
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or_else(|| "Passkey".to_string()),
+        created_at: Utc::now(),
+    };
+
+    match repo.add_webauthn_credential(subject, &credential).await {
+        Ok(true) => {
+            info!("Passkey '{}' registered for {}.", credential.name, subject);
+            Ok(Json(WebAuthnCredentialResponse {
+                status: Status::Ok.code,
+                credential_id: credential.credential_id,
+                name: credential.name,
+            }))
+        }
+        Ok(false) => Err(webauthn_error(
+            Status::Conflict,
+            "Passkey is already registered",
+        )),
+        Err(e) => {
+            error!("Failed to store passkey for {}: {:?}", subject, e);
+            Err(webauthn_error(
+                Status::InternalServerError,
+                "Internal server error",
+            ))
+        }
+    }
+}
+
+#[delete("/webauthn/credentials/<credential_id>")]
+pub async fn webauthn_remove_credential(
+    repo: &State<Arc<UserRepository>>,
+    credential_id: &str,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<WebAuthnCredentialResponse>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited(
+            &actor,
+            "mfa.passkey.remove",
+            Some(credential_id),
+            webauthn_remove_credential_inner(repo, credential_id, token),
+        )
+        .await
+}
+
+async fn webauthn_remove_credential_inner(
+    repo: &State<Arc<UserRepository>>,
+    credential_id: &str,
+    token: TokenGuard,
+) -> Result<Json<WebAuthnCredentialResponse>, Json<ErrorResponse>> {
+    let Some(subject) = token.subject().filter(|_| token.policies().is_none()) else {
+        return Err(webauthn_error(
+            Status::Forbidden,
+            "Insufficient Permissions",
+        ));
+    };
+
+    match repo
+        .remove_webauthn_credential(subject, credential_id)
+        .await
+    {
+        Ok(true) => {
+            info!("Passkey removed for {}.", subject);
+            Ok(Json(WebAuthnCredentialResponse {
+                status: Status::Ok.code,
+                credential_id: credential_id.to_string(),
+                name: String::new(),
+            }))
+        }
+        Ok(false) => Err(webauthn_error(Status::NotFound, "Passkey not found")),
+        Err(e) => {
+            error!("Failed to remove passkey for {}: {:?}", subject, e);
+            Err(webauthn_error(
+                Status::InternalServerError,
+                "Internal server error",
+            ))
+        }
+    }
+}
+
+#[post("/webauthn/login/start", data = "<request>")]
+pub async fn webauthn_login_start(
+    repo: &State<Arc<UserRepository>>,
+    webauthn_repo: &State<Arc<WebAuthnRepository>>,
+    config: &State<AuthConfig>,
+    request: Option<Json<WebAuthnLoginRequest>>,
+) -> Result<Json<WebAuthnOptionsResponse>, Json<ErrorResponse>> {
+    let email = request.and_then(|request| request.into_inner().email);
+
+    // Unknown accounts get an empty list rather than an error, so the
+    // endpoint does not reveal which emails are registered.
+    let allow_credentials = match &email {
+        Some(email) => match repo.get_user_by_email(email).await {
+            Ok(user) => user
+                .as_ref()
+                .map(credential_descriptors)
+                .unwrap_or_default(),
+            Err(e) => {
+                error!("Failed to look up user for passkey login: {:?}", e);
+                return Err(webauthn_error(
+                    Status::InternalServerError,
+                    "Internal server error",
+                ));
+            }
+        },
+        None => vec![],
+    };
+
+    let webauthn = &config.webauthn;
+    let challenge = match webauthn_repo
+        .create_challenge(
+            AUTHENTICATION_CEREMONY,
+            email.as_deref(),
+            Duration::seconds(webauthn.challenge_ttl),
+        )
+        .await
+    {
+        Ok(challenge) => challenge,
+        Err(e) => {
+            error!("Failed to create passkey login challenge: {:?}", e);
+            return Err(webauthn_error(
+                Status::InternalServerError,
+                "Internal server error",
+            ));
+        }
+    };
+
+    Ok(Json(WebAuthnOptionsResponse {
+        status: Status::Ok.code,
+        public_key: json!({
+            "challenge": challenge,
+            "rpId": webauthn.rp_id,
+            "timeout": webauthn.challenge_ttl * 1000,
+            "userVerification": user_verification(webauthn),
+            "allowCredentials": allow_credentials,
+        }),
+    }))
+}
+
+#[post("/webauthn/login/finish", data = "<assertion>")]
+pub async fn webauthn_login_finish(
+    repo: &State<Arc<UserRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    webauthn_repo: &State<Arc<WebAuthnRepository>>,
+    config: &State<AuthConfig>,
+    assertion: Json<WebAuthnAssertion>,
+    audit: AuditTrail,
+) -> Result<Json<LoginResponse>, Json<ErrorResponse>> {
+    let target = assertion.id.clone();
+    audit
+        .audited_as(
+            "auth.login.passkey",
+            Some(&target),
+            webauthn_login_finish_inner(
+                repo,
+                key_repo,
+                refresh_repo,
+                webauthn_repo,
+                config,
+                assertion,
+            ),
+        )
+        .await
+}
+
+async fn webauthn_login_finish_inner(
+    repo: &State<Arc<UserRepository>>,
+    key_repo: &State<Arc<KeyRepository>>,
+    refresh_repo: &State<Arc<RefreshTokenRepository>>,
+    webauthn_repo: &State<Arc<WebAuthnRepository>>,
+    config: &State<AuthConfig>,
+    assertion: Json<WebAuthnAssertion>,
+) -> Result<(String, LoginResponse), Json<ErrorResponse>> {
+    let rejected = || webauthn_error(Status::Unauthorized, "Passkey login failed");
+
+    let Ok(challenge) = client_data_challenge(&assertion.client_data_json) else {
+        return Err(rejected());
+    };
+    let challenge = match webauthn_repo
+        .consume_challenge(&challenge, AUTHENTICATION_CEREMONY)
+        .await
+    {
+        Ok(Some(challenge)) => challenge,
+        Ok(None) => return Err(rejected()),
+        Err(e) => {
+            error!("Failed to load passkey login challenge: {:?}", e);
+            return Err(webauthn_error(
+                Status::InternalServerError,
+                "Internal server error",
+            ));
+        }
+    };
+
+    let user = match repo.get_user_by_credential_id(&assertion.id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(rejected()),
+        Err(e) => {
+            error!("Failed to look up passkey owner: {:?}", e);
+            return Err(webauthn_error(
+                Status::InternalServerError,
+                "Internal server error",
+            ));
+        }
+    };
+    if challenge
+        .subject
+        .as_ref()
+        .is_some_and(|subject| *subject != user.email)
+    {
+        return Err(rejected());
+    }
+
+    let Some(credential) = user
+        .webauthn_credentials
+        .iter()
+        .find(|credential| credential.credential_id == assertion.id)
+    else {
+        return Err(rejected());
+    };
+
+    let sign_count = match verify_assertion(
+        &config.webauthn,
+        &challenge.challenge,
+        &credential.public_key,
+        credential.sign_count,
+        &assertion,
+    ) {
+        Ok(sign_count) => sign_count,
+        Err(e) => {
+            warn!("Rejected passkey login for {}: {}", user.email, e);
+            return Err(rejected());
+        }
+    };
+
+    // A concurrent login with the same counter value means a cloned authenticator.
+    if sign_count > 0 {
+        match repo
+            .update_webauthn_sign_count(&credential.credential_id, sign_count)
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!("Passkey counter regression for {}.", user.email);
+                return Err(rejected());
+            }
+            Err(e) => {
+                error!("Failed to update passkey counter: {:?}", e);
+                return Err(webauthn_error(
+                    Status::InternalServerError,
+                    "Internal server error",
+                ));
+            }
+        }
+    }
+
+    info!("{} logged in with a passkey.", user.email);
+    start_session(
+        &user.email,
+        &user.roles,
+        repo,
+        key_repo,
+        refresh_repo,
+        config,
+    )
+    .await
+    .map(|session| (user.email, session))
+}
+
 pub fn user_routes() -> Vec<rocket::Route> {
     routes![
         setup,
         login,
+        refresh_token,
+        logout,
+        revoke_sessions,
+        unlock_user,
+        disable_user,
+        enable_user,
+        offboard_user,
         // list_users, - This endpoint will be used for administrative processes
         get_user,
         update_user,
-        delete_user
+        delete_user,
+        webauthn_register_start,
+        webauthn_register_finish,
+        webauthn_remove_credential,
+        webauthn_login_start,
+        webauthn_login_finish
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::testing::{client, token};
+    use rocket::http::ContentType;
+
+    const ID: &str = "67deab3abad6b6cc81b7d692";
+
+    #[rocket::async_test]
+    async fn accounts_require_a_token() {
+        let client = client(routes![get_user, update_user, delete_user]).await;
+
+        let response = client.get(format!("/users/{ID}")).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client
+            .put(format!("/update/{ID}"))
+            .header(ContentType::JSON)
+            .body(r#"{ "email": "admin@example.com", "password": "Tr0ub4dor&3-horse" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.delete(format!("/delete/user/{ID}")).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn accounts_are_managed_by_their_owner_or_an_admin() {
+        let owner = token("alice@example.com", &[], None);
+        assert!(owner.may_manage_account("alice@example.com"));
+        assert!(!owner.is_admin());
+
+        let other = token("bob@example.com", &[], None);
+        assert!(!other.may_manage_account("alice@example.com"));
+
+        let admin = token("root@example.com", &[ADMIN_ROLE], None);
+        assert!(admin.may_manage_account("alice@example.com"));
+        assert!(admin.is_admin());
+
+        // Machine tokens never manage accounts, even one named like the account.
+        let machine = token("alice@example.com", &[ADMIN_ROLE], Some(&["*"]));
+        assert!(!machine.may_manage_account("alice@example.com"));
+        assert!(!machine.is_admin());
+    }
+}
@@ -2,9 +2,17 @@
 Custom modules
 --------------*/
 use crate::models::*;
-use crate::request_guards::TokenGuard;
-use ec_secrets_shared_library::models::{Secret, VaultDocument};
-use ec_secrets_shared_library::repositories::vault::VaultRepository;
+use crate::request_guards::{AdminGuard, AuditTrail, TokenGuard, Unsealed};
+use ec_secrets_shared_library::models::{
+    loosens_protection, ImportAction, ImportReport, ImportRequest, ProtectionUpdate,
+    RestoreArchivedRequest, Secret, UserStatus, VaultDocument,
+};
+use ec_secrets_shared_library::repositories::{
+    users::UserRepository,
+    vault::{Restored, VaultRepository},
+};
+use ec_secrets_shared_library::utils::import;
+use ec_secrets_shared_library::utils::secret::SecretValue;
 
 /*-------------
 3rd party modules
@@ -12,26 +20,130 @@ use ec_secrets_shared_library::repositories::vault::VaultRepository;
 use log::{error, info};
 use rocket::http::Status;
 use rocket::serde::json::Json;
-use rocket::{delete, get, post, routes, State};
+use rocket::{delete, get, post, put, routes, State};
 
 /*-------------
 stdlib modules
 --------------*/
 use std::sync::Arc;
 
+/*----------------------------------------------------------------
+ Secrets belong to the user id behind a token subject, so they stay
+ with the account when its email address changes
+-----------------------------------------------------------------*/
+async fn owner_of(users: &UserRepository, subject: &str) -> Result<String, Json<ErrorResponse>> {
+    users.owner_id(subject).await.map_err(|e| {
+        error!("Failed to resolve the owner {}: {:?}", subject, e);
+        Json(ErrorResponse {
+            status: Status::InternalServerError.code,
+            message: "Internal server error".to_string(),
+        })
+    })
+}
+
+/*----------------------------------------------------------------
+ Machine tokens may only touch secrets matching one of their policies
+-----------------------------------------------------------------*/
+async fn ensure_permitted(
+    repo: &VaultRepository,
+    token: &TokenGuard,
+    id: &str,
+    owner_id: &str,
+) -> Result<(), Json<ErrorResponse>> {
+    if token.policies().is_none() {
+        return Ok(());
+    }
+
+    match repo.get_secret_key(id, owner_id).await {
+        Ok(Some(key)) if token.permits(&key) => Ok(()),
+        Ok(_) => Err(Json(ErrorResponse {
+            status: Status::Forbidden.code,
+            message: "Insufficient Permissions".to_string(),
+        })),
+        Err(e) => {
+            error!("Failed to resolve vault entry {}: {:?}", id, e);
+            Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Failed to retrieve vault entry.".to_string(),
+            }))
+        }
+    }
+}
+
+/*----------------------------------------------------------------
+ Protected entries are only released through an access request
+-----------------------------------------------------------------*/
+async fn ensure_unprotected(
+    repo: &VaultRepository,
+    id: &str,
+    owner_id: &str,
+) -> Result<(), Json<ErrorResponse>> {
+    match repo.get_entry(id).await {
+        Ok(Some(entry)) if entry.owner_id == owner_id && entry.protection.is_some() => {
+            Err(Json(ErrorResponse {
+                status: Status::Forbidden.code,
+                message: "Vault entry is protected; request access with a justification"
+                    .to_string(),
+            }))
+        }
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Failed to resolve vault entry {}: {:?}", id, e);
+            Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Failed to retrieve vault entry.".to_string(),
+            }))
+        }
+    }
+}
+
 /*---------------------
  Create a vault entry
 ---------------------*/
 #[post("/create/vault/entry", data = "<secret>")]
 pub async fn create_secret(
+    _unsealed: Unsealed,
     repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    secret: Json<Secret>,
+    claims: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<CreateSecretResponse>, Json<ErrorResponse>> {
+    let actor = claims.subject().unwrap_or_default().to_string();
+    let target = secret.key.clone();
+    audit
+        .audited(
+            &actor,
+            "secret.create",
+            Some(&target),
+            create_secret_inner(repo, users, secret, claims),
+        )
+        .await
+}
+
+async fn create_secret_inner(
+    repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
     secret: Json<Secret>,
     claims: TokenGuard,
 ) -> Result<Json<CreateSecretResponse>, Json<ErrorResponse>> {
     if let Some(created_by) = claims.0.get_claim("sub") {
         if let Some(created_by) = created_by.as_str() {
+            if !claims.permits(&secret.key) {
+                return Err(Json(ErrorResponse {
+                    status: Status::Forbidden.code,
+                    message: "Insufficient Permissions".to_string(),
+                }));
+            }
+            let owner_id = owner_of(users, created_by).await?;
             match repo
-                .create_secret(&secret.key, &secret.value, created_by)
+                .create_secret(
+                    &secret.key,
+                    &secret.value,
+                    created_by,
+                    &owner_id,
+                    secret.protection,
+                )
                 .await
             {
                 Ok(_) => {
@@ -68,13 +180,37 @@ pub async fn create_secret(
 ---------------------------*/
 #[get("/retrieve/vault/entries")]
 pub async fn list_entries(
+    _unsealed: Unsealed,
     repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<Vec<VaultDocument>>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited(
+            &actor,
+            "secret.list",
+            None,
+            list_entries_inner(repo, users, token),
+        )
+        .await
+}
+
+async fn list_entries_inner(
+    repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
     token: TokenGuard,
 ) -> Result<Json<Vec<VaultDocument>>, Json<ErrorResponse>> {
     if let Some(subject) = token.0.get_claim("sub") {
         if let Some(subject) = subject.as_str() {
-            match repo.list_secrets(subject).await {
+            let owner_id = owner_of(users, subject).await?;
+            match repo.list_secrets(&owner_id).await {
                 Ok(entries) => {
+                    let entries: Vec<VaultDocument> = entries
+                        .into_iter()
+                        .filter(|entry| token.permits(&entry.key))
+                        .collect();
                     info!("Successfully retrieved {} vault entries.", entries.len());
                     Ok(Json(entries)) // Always return an array, even if empty
                 }
@@ -105,10 +241,30 @@ pub async fn list_entries(
 ------------------------------*/
 #[get("/retrieve/vault/entries/<id>")]
 pub async fn get_entry(
+    _unsealed: Unsealed,
     repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
     id: &str,
     token: TokenGuard,
-) -> Result<Json<String>, Json<ErrorResponse>> {
+    audit: AuditTrail,
+) -> Result<Json<SecretValue>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited(
+            &actor,
+            "secret.reveal",
+            Some(id),
+            get_entry_inner(repo, users, id, token),
+        )
+        .await
+}
+
+async fn get_entry_inner(
+    repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    id: &str,
+    token: TokenGuard,
+) -> Result<Json<SecretValue>, Json<ErrorResponse>> {
     if id.trim().is_empty() {
         error!("Invalid request: Provided ID is empty.");
         return Err(Json(ErrorResponse {
@@ -118,7 +274,10 @@ pub async fn get_entry(
     }
     if let Some(subject) = token.0.get_claim("sub") {
         if let Some(subject) = subject.as_str() {
-            match repo.get_secret_by_id(&id, subject).await {
+            let owner_id = owner_of(users, subject).await?;
+            ensure_permitted(repo, &token, id, &owner_id).await?;
+            ensure_unprotected(repo, id, &owner_id).await?;
+            match repo.get_secret_by_id(id, &owner_id).await {
                 Ok(Some(entry)) => {
                     info!("Successfully retrieved vault entry with ID: {}", id);
                     Ok(Json(entry))
@@ -160,8 +319,29 @@ pub async fn get_entry(
 ----------------------------------*/
 #[get("/retrieve/vault/entry/<created_by>")]
 pub async fn get_entry_by_author(
+    _unsealed: Unsealed,
     repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
     created_by: &str,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<Vec<VaultDocument>>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited(
+            &actor,
+            "secret.list",
+            Some(created_by),
+            get_entry_by_author_inner(repo, users, created_by, token),
+        )
+        .await
+}
+
+async fn get_entry_by_author_inner(
+    repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    created_by: &str,
+    token: TokenGuard,
 ) -> Result<Json<Vec<VaultDocument>>, Json<ErrorResponse>> {
     if created_by.trim().is_empty() {
         error!("Invalid request: Provided author name is empty.");
@@ -170,9 +350,27 @@ pub async fn get_entry_by_author(
             message: "Invalid author name provided.".to_string(),
         }));
     }
+    let Some(subject) = token.subject() else {
+        return Err(Json(ErrorResponse {
+            status: Status::Unauthorized.code,
+            message: "Insufficient Permissions".to_string(),
+        }));
+    };
+    let owner_id = owner_of(users, subject).await?;
 
-    match repo.get_secret_by_author(created_by).await {
-        Ok(secrets) if !secrets.is_empty() => {
+    match repo.get_secret_by_author(created_by, &owner_id).await {
+        Ok(secrets) => {
+            let secrets: Vec<VaultDocument> = secrets
+                .into_iter()
+                .filter(|entry| token.permits(&entry.key))
+                .collect();
+            if secrets.is_empty() {
+                error!("No vault entries found for author: {}", created_by);
+                return Err(Json(ErrorResponse {
+                    status: Status::NotFound.code,
+                    message: "No vault entries found.".to_string(),
+                }));
+            }
             info!(
                 "Successfully retrieved {} vault entries for author: {}",
                 secrets.len(),
@@ -180,13 +378,6 @@ pub async fn get_entry_by_author(
             );
             Ok(Json(secrets))
         }
-        Ok(_) => {
-            error!("No vault entries found for author: {}", created_by);
-            Err(Json(ErrorResponse {
-                status: Status::NotFound.code,
-                message: "No vault entries found.".to_string(),
-            }))
-        }
         Err(e) => {
             error!(
                 "Failed to retrieve vault entries for author: {}. Error: {:?}",
@@ -205,7 +396,27 @@ pub async fn get_entry_by_author(
 ----------------------*/
 #[delete("/delete/<id>")]
 pub async fn delete_entry(
+    _unsealed: Unsealed,
+    repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    id: &str,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<DeleteSecretResponse>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited(
+            &actor,
+            "secret.delete",
+            Some(id),
+            delete_entry_inner(repo, users, id, token),
+        )
+        .await
+}
+
+async fn delete_entry_inner(
     repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
     id: &str,
     token: TokenGuard,
 ) -> Result<Json<DeleteSecretResponse>, Json<ErrorResponse>> {
@@ -219,7 +430,9 @@ pub async fn delete_entry(
 
     if let Some(subject) = token.0.get_claim("sub") {
         if let Some(subject) = subject.as_str() {
-            match repo.delete_secret(&id, subject).await {
+            let owner_id = owner_of(users, subject).await?;
+            ensure_permitted(repo, &token, id, &owner_id).await?;
+            match repo.delete_secret(id, &owner_id).await {
                 Ok(Some(_)) => {
                     info!("Successfully deleted vault entry with ID: {}", id);
                     Ok(Json(DeleteSecretResponse {
@@ -259,12 +472,328 @@ pub async fn delete_entry(
     }
 }
 
+/*----------------------------------------------------------------
+ Protect a vault entry, or change how it is protected. Owners may
+ add safeguards; only administrators may remove them
+-----------------------------------------------------------------*/
+#[put("/vault/entries/<id>/protection", data = "<update>")]
+pub async fn set_protection(
+    _unsealed: Unsealed,
+    repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    id: &str,
+    update: Json<ProtectionUpdate>,
+    token: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<ProtectionResponse>, Json<ErrorResponse>> {
+    let actor = token.subject().unwrap_or_default().to_string();
+    audit
+        .audited(
+            &actor,
+            "secret.protect",
+            Some(id),
+            set_protection_inner(repo, users, id, update, token),
+        )
+        .await
+}
+
+async fn set_protection_inner(
+    repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    id: &str,
+    update: Json<ProtectionUpdate>,
+    token: TokenGuard,
+) -> Result<Json<ProtectionResponse>, Json<ErrorResponse>> {
+    let (Some(subject), None) = (token.subject(), token.policies()) else {
+        return Err(Json(ErrorResponse {
+            status: Status::Forbidden.code,
+            message: "Insufficient Permissions".to_string(),
+        }));
+    };
+    let not_found = || {
+        Json(ErrorResponse {
+            status: Status::NotFound.code,
+            message: "Vault entry not found.".to_string(),
+        })
+    };
+
+    let entry = match repo.get_entry(id).await {
+        Ok(Some(entry)) if entry.archived_at.is_none() => entry,
+        Ok(_) => return Err(not_found()),
+        Err(e) => {
+            error!("Failed to resolve vault entry {}: {:?}", id, e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Failed to retrieve vault entry.".to_string(),
+            }));
+        }
+    };
+    let is_admin = token.is_admin();
+    if entry.owner_id != owner_of(users, subject).await? && !is_admin {
+        return Err(not_found());
+    }
+
+    let protection = update.protection();
+    if loosens_protection(entry.protection, protection) && !is_admin {
+        return Err(Json(ErrorResponse {
+            status: Status::Forbidden.code,
+            message: "Only an administrator can relax the protection of a secret".to_string(),
+        }));
+    }
+
+    match repo.set_protection(id, protection).await {
+        Ok(true) => {
+            info!("Protection of vault entry {} set to {:?}.", id, protection);
+            Ok(Json(ProtectionResponse {
+                status: Status::Ok.code,
+                message: match protection {
+                    Some(protection) if protection.require_approval => {
+                        "Vault entry protected; reads need a justification and approval"
+                    }
+                    Some(_) => "Vault entry protected; reads need a justification",
+                    None => "Vault entry is no longer protected",
+                }
+                .to_string(),
+            }))
+        }
+        Ok(false) => Err(not_found()),
+        Err(e) => {
+            error!("Failed to protect vault entry {}: {:?}", id, e);
+            Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Failed to update vault entry.".to_string(),
+            }))
+        }
+    }
+}
+
+/*----------------------------------------------------------------
+ List archived entries (administrative action): secrets of users
+ offboarded without a successor, without their values
+-----------------------------------------------------------------*/
+#[get("/vault/archive")]
+pub async fn list_archived(
+    repo: &State<Arc<VaultRepository>>,
+    admin: AdminGuard,
+    audit: AuditTrail,
+) -> Result<Json<Vec<VaultDocument>>, Json<ErrorResponse>> {
+    let actor = admin.0.subject().unwrap_or_default();
+    audit
+        .audited(actor, "secret.list_archived", None, async {
+            repo.list_archived().await.map(Json).map_err(|e| {
+                error!("Failed to retrieve archived vault entries: {:?}", e);
+                Json(ErrorResponse {
+                    status: Status::InternalServerError.code,
+                    message: "Failed to retrieve vault entries.".to_string(),
+                })
+            })
+        })
+        .await
+}
+
+/*----------------------------------------------------------------
+ Restore an archived entry to an active user (administrative action)
+-----------------------------------------------------------------*/
+#[post("/vault/archive/<id>/restore", data = "<request>")]
+pub async fn restore_archived(
+    repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    id: &str,
+    request: Json<RestoreArchivedRequest>,
+    admin: AdminGuard,
+    audit: AuditTrail,
+) -> Result<Json<VaultDocument>, Json<ErrorResponse>> {
+    audit
+        .audited(
+            admin.0.subject().unwrap_or_default(),
+            "secret.restore",
+            Some(id),
+            restore_archived_inner(repo, users, id, request),
+        )
+        .await
+}
+
+async fn restore_archived_inner(
+    repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    id: &str,
+    request: Json<RestoreArchivedRequest>,
+) -> Result<Json<VaultDocument>, Json<ErrorResponse>> {
+    let owner = match users.get_user_by_id(&request.owner_id).await {
+        Ok(Some(owner)) if owner.status != UserStatus::Disabled => owner,
+        Ok(_) => {
+            return Err(Json(ErrorResponse {
+                status: Status::BadRequest.code,
+                message: "The new owner must be an active user".to_string(),
+            }))
+        }
+        Err(e) => {
+            error!("Failed to resolve user {}: {:?}", request.owner_id, e);
+            return Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Internal server error".to_string(),
+            }));
+        }
+    };
+
+    match repo
+        .restore_archived(id, &owner.id.to_hex(), &owner.email)
+        .await
+    {
+        Ok(Restored::Entry(entry)) => {
+            info!("Archived vault entry {} restored to {}.", id, owner.email);
+            Ok(Json(entry))
+        }
+        Ok(Restored::NotFound) => Err(Json(ErrorResponse {
+            status: Status::NotFound.code,
+            message: "Archived vault entry not found.".to_string(),
+        })),
+        Ok(Restored::KeyTaken) => Err(Json(ErrorResponse {
+            status: Status::Conflict.code,
+            message: "The new owner already holds a secret with this key".to_string(),
+        })),
+        Err(e) => {
+            error!("Failed to restore vault entry {}: {:?}", id, e);
+            Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Failed to restore vault entry.".to_string(),
+            }))
+        }
+    }
+}
+
+/*----------------------------------------------------------------
+ Import a dotenv file, JSON or YAML document or Vault KV export as
+ vault entries, all or nothing. A dry run returns the same diff
+ without writing anything
+-----------------------------------------------------------------*/
+#[post("/vault/import", data = "<request>")]
+pub async fn import_entries(
+    _unsealed: Unsealed,
+    repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    request: Json<ImportRequest>,
+    claims: TokenGuard,
+    audit: AuditTrail,
+) -> Result<Json<ImportReport>, Json<ErrorResponse>> {
+    let Some(subject) = claims.subject().map(str::to_string) else {
+        return Err(Json(ErrorResponse {
+            status: Status::Unauthorized.code,
+            message: "Insufficient Permissions".to_string(),
+        }));
+    };
+    audit
+        .audited_with(
+            &subject,
+            "secret.import",
+            request.prefix.as_deref(),
+            import_entries_inner(repo, users, &request, &claims, &subject),
+            |report| {
+                Some(format!(
+                    "{} {}{}: {} created, {} overwritten, {} new versions, {} skipped",
+                    report.changes.len(),
+                    request.format,
+                    if report.dry_run { " (dry run)" } else { "" },
+                    report.count(ImportAction::Create),
+                    report.count(ImportAction::Overwrite),
+                    report.count(ImportAction::NewVersion),
+                    report.count(ImportAction::Skip),
+                ))
+            },
+        )
+        .await
+}
+
+async fn import_entries_inner(
+    repo: &State<Arc<VaultRepository>>,
+    users: &State<Arc<UserRepository>>,
+    request: &ImportRequest,
+    claims: &TokenGuard,
+    subject: &str,
+) -> Result<Json<ImportReport>, Json<ErrorResponse>> {
+    let entries = import::parse(request.format, &request.content, request.prefix.as_deref())
+        .map_err(|e| {
+            Json(ErrorResponse {
+                status: Status::BadRequest.code,
+                message: e.to_string(),
+            })
+        })?;
+    if let Some((key, _)) = entries.iter().find(|(key, _)| !claims.permits(key)) {
+        return Err(Json(ErrorResponse {
+            status: Status::Forbidden.code,
+            message: format!("Insufficient Permissions for {}", key),
+        }));
+    }
+
+    let owner_id = owner_of(users, subject).await?;
+    match repo
+        .import(
+            &entries,
+            subject,
+            &owner_id,
+            request.conflict,
+            request.dry_run,
+        )
+        .await
+    {
+        Ok(report) => {
+            info!("Imported {} vault entries.", report.changes.len());
+            Ok(Json(report))
+        }
+        Err(e) => {
+            error!("Failed to import vault entries: {:?}", e);
+            Err(Json(ErrorResponse {
+                status: Status::InternalServerError.code,
+                message: "Failed to import vault entries".to_string(),
+            }))
+        }
+    }
+}
+
 pub fn vault_routes() -> Vec<rocket::Route> {
     routes![
         create_secret,
         list_entries,
         get_entry,
         get_entry_by_author,
-        delete_entry
+        delete_entry,
+        set_protection,
+        import_entries,
+        list_archived,
+        restore_archived
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::testing::client;
+    use rocket::http::ContentType;
+
+    #[rocket::async_test]
+    async fn the_archive_requires_a_token() {
+        let client = client(routes![list_archived, restore_archived]).await;
+
+        let response = client.get("/vault/archive").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client
+            .post("/vault/archive/67deab3abad6b6cc81b7d692/restore")
+            .header(ContentType::JSON)
+            .body(r#"{ "owner_id": "67deab3abad6b6cc81b7d693" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn listing_by_author_requires_a_token() {
+        let client = client(routes![get_entry_by_author]).await;
+
+        let response = client
+            .get("/retrieve/vault/entry/user@example.com")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
@@ -1,6 +1,11 @@
+use chrono::{DateTime, Utc};
 use clap::{Arg, Command};
-use ec_secrets_manager_cli::models::{auth::Auth, session::Session};
-use ec_secrets_shared_library::models::{Secret, UserCredentials};
+use ec_secrets_manager_cli::models::{auth::Auth, operator::Operator, session::Session};
+use ec_secrets_shared_library::models::{
+    AuditQuery, ConflictStrategy, ImportFormat, RestoreMode, Secret, SecretProtection,
+    UserCredentials,
+};
+use std::path::Path;
 
 #[tokio::main]
 async fn main() {
@@ -29,6 +34,10 @@ async fn main() {
                         .help("The user's password"),
                 ),
         )
+        .subcommand(
+            Command::new("logout")
+                .about("revokes the current session and removes the stored token"),
+        )
         .subcommand(
             Command::new("users")
                 .about("allow users to execute user management capabilities of lock smith")
@@ -53,6 +62,19 @@ async fn main() {
                             .help("user account id"),
                     ),
                 )
+                .subcommand(
+                    Command::new("grant-admin")
+                        .about(
+                            "grant the admin role; without a session only while no admin exists",
+                        )
+                        .arg(
+                            Arg::new("email")
+                                .short('e')
+                                .long("email")
+                                .required(true)
+                                .help("user email address"),
+                        ),
+                )
                 .subcommand(
                     Command::new("create")
                         .about("create a new user account in lock smith")
@@ -92,6 +114,20 @@ async fn main() {
                                 .long("value")
                                 .required(true)
                                 .help("Seret Value"),
+                        )
+                        .arg(
+                            Arg::new("protected")
+                                .long("protected")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Require a justification to read the secret"),
+                        )
+                        .arg(
+                            Arg::new("require-approval")
+                                .long("require-approval")
+                                .action(clap::ArgAction::SetTrue)
+                                .help(
+                                    "Also require a second user's approval (implies --protected)",
+                                ),
                         ),
                 )
                 .subcommand(
@@ -105,6 +141,47 @@ async fn main() {
                                 .help("Secret Id"),
                         ),
                 )
+                .subcommand(
+                    Command::new("import")
+                        .about("import a dotenv, JSON or YAML file or a HashiCorp Vault KV export")
+                        .arg_required_else_help(true)
+                        .arg(
+                            Arg::new("file")
+                                .short('f')
+                                .long("file")
+                                .required(true)
+                                .help("The file to import"),
+                        )
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .required(false)
+                                .value_parser(["dotenv", "json", "yaml", "vault"])
+                                .help("Defaults to what the file name suggests"),
+                        )
+                        .arg(
+                            Arg::new("prefix")
+                                .short('p')
+                                .long("prefix")
+                                .required(false)
+                                .help("Prepended to every key, e.g. apps/billing"),
+                        )
+                        .arg(
+                            Arg::new("conflict")
+                                .short('c')
+                                .long("conflict")
+                                .required(false)
+                                .default_value("skip")
+                                .value_parser(["skip", "overwrite", "new-version"])
+                                .help("What to do with keys you already hold under another value"),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Show what would change without writing anything"),
+                        ),
+                )
                 .subcommand(
                     Command::new("delete")
                         .about("delete secret in lock smith")
@@ -117,6 +194,207 @@ async fn main() {
                         ),
                 ),
         )
+        .subcommand(
+            Command::new("audit")
+                .about("inspect the lock smith audit log (admin only)")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("list")
+                        .about("list audit events, newest first")
+                        .arg(
+                            Arg::new("actor")
+                                .short('a')
+                                .long("actor")
+                                .required(false)
+                                .help("Only events of this actor"),
+                        )
+                        .arg(
+                            Arg::new("action")
+                                .short('c')
+                                .long("action")
+                                .required(false)
+                                .help("Only events of this action, e.g. secret.reveal"),
+                        )
+                        .arg(
+                            Arg::new("limit")
+                                .short('l')
+                                .long("limit")
+                                .required(false)
+                                .default_value("50")
+                                .value_parser(clap::value_parser!(i64).range(1..=1000))
+                                .help("Maximum number of events"),
+                        ),
+                )
+                .subcommand(Command::new("verify").about("verify the hash chain of the audit log")),
+        )
+        .subcommand(
+            Command::new("journal")
+                .about("verify, compact and replay the change journal (admin only)")
+                .arg_required_else_help(true)
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .global(true)
+                        .required(false)
+                        .default_value("journal/locksmith.journal")
+                        .help("The journal file"),
+                )
+                .subcommand(Command::new("verify").about("verify the MAC chain of the journal"))
+                .subcommand(
+                    Command::new("compact")
+                        .about("keep only the last change to each document before a point in time")
+                        .arg_required_else_help(true)
+                        .arg(
+                            Arg::new("before")
+                                .short('b')
+                                .long("before")
+                                .required(true)
+                                .value_parser(parse_timestamp)
+                                .help("RFC 3339 timestamp; later changes are all kept"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("replay")
+                        .about("apply journaled changes to the database, e.g. after restoring a snapshot")
+                        .arg_required_else_help(true)
+                        .arg(
+                            Arg::new("until")
+                                .short('t')
+                                .long("until")
+                                .required(true)
+                                .value_parser(parse_timestamp)
+                                .help("RFC 3339 timestamp of the last change to replay"),
+                        )
+                        .arg(
+                            Arg::new("since")
+                                .short('s')
+                                .long("since")
+                                .required(false)
+                                .value_parser(parse_timestamp)
+                                .help("RFC 3339 timestamp; only changes after it, e.g. the snapshot's"),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Report what would change without writing anything"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("operator")
+                .about("seal, unseal and initialize a running lock smith server")
+                .arg_required_else_help(true)
+                .arg(
+                    Arg::new("url")
+                        .short('u')
+                        .long("url")
+                        .global(true)
+                        .required(false)
+                        .help(
+                            "The server's URL; defaults to ECS_SERVER_URL or http://localhost:8089",
+                        ),
+                )
+                .subcommand(Command::new("status").about("show whether the server is sealed"))
+                .subcommand(
+                    Command::new("init")
+                        .about("split the master key into unseal shares (admin only)")
+                        .arg(
+                            Arg::new("shares")
+                                .short('n')
+                                .long("shares")
+                                .required(false)
+                                .default_value("5")
+                                .value_parser(clap::value_parser!(u8).range(1..))
+                                .help("Number of shares to hand out"),
+                        )
+                        .arg(
+                            Arg::new("threshold")
+                                .short('t')
+                                .long("threshold")
+                                .required(false)
+                                .default_value("3")
+                                .value_parser(clap::value_parser!(u8).range(1..))
+                                .help("Number of shares needed to unseal"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("unseal")
+                        .about("submit one unseal share")
+                        .arg_required_else_help(true)
+                        .arg(
+                            Arg::new("share")
+                                .short('s')
+                                .long("share")
+                                .required(false)
+                                .help("An unseal share"),
+                        )
+                        .arg(
+                            Arg::new("reset")
+                                .long("reset")
+                                .action(clap::ArgAction::SetTrue)
+                                .conflicts_with("share")
+                                .help("Discard the shares submitted so far"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("seal")
+                        .about("wipe the master key from the server's memory (admin only)"),
+                )
+                .subcommand(
+                    Command::new("snapshot")
+                        .about("export an encrypted snapshot of users, secrets, keys and AppRoles (admin only)")
+                        .arg_required_else_help(true)
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .required(true)
+                                .help("File to write the snapshot to"),
+                        )
+                        .arg(
+                            Arg::new("passphrase")
+                                .short('p')
+                                .long("passphrase")
+                                .required(false)
+                                .help("Passphrase encrypting the snapshot; defaults to ECS_SNAPSHOT_PASSPHRASE"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("restore")
+                        .about("restore a snapshot (admin only)")
+                        .arg_required_else_help(true)
+                        .arg(
+                            Arg::new("input")
+                                .short('i')
+                                .long("input")
+                                .required(true)
+                                .help("The snapshot file"),
+                        )
+                        .arg(
+                            Arg::new("passphrase")
+                                .short('p')
+                                .long("passphrase")
+                                .required(false)
+                                .help("Passphrase of the snapshot; defaults to ECS_SNAPSHOT_PASSPHRASE"),
+                        )
+                        .arg(
+                            Arg::new("mode")
+                                .short('m')
+                                .long("mode")
+                                .required(false)
+                                .default_value("merge")
+                                .value_parser(["merge", "replace"])
+                                .help("merge adds what is missing; replace wipes users, secrets, keys and AppRoles first"),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Report what would change without writing anything"),
+                        ),
+                ),
+        )
         .get_matches();
 
     match matches.subcommand() {
@@ -126,50 +404,74 @@ async fn main() {
                 password: sub_matches
                     .get_one::<String>("password")
                     .unwrap()
-                    .to_string(),
+                    .as_str()
+                    .into(),
             };
             authed_user.login(creds).await.map_or_else(
                 |error| println!("\x1b[0;31m Login failed: {error} \x1b[0m"),
                 |_| println!("\x1b[0;32m Login successful \x1b[0m"),
             );
         }
-        Some(("users", submatches)) => match submatches.subcommand() {
-            Some(("list", submatches)) => {
-                let id: Option<&str> = submatches.get_one::<String>("id").map(|id| id.as_str());
-                session.get_users(id).await.map_or_else(
-                    |error| println!("\x1b[0;31m Error fetching users: {error} \x1b[0m"),
-                    |_| println!("\x1b[0;32m Fetch successful \x1b[0m"),
-                );
-            }
-            Some(("delete", submatches)) => {
-                let id: Option<&str> = submatches.get_one::<String>("id").map(|id| id.as_str());
-                session.delete_user(id).await.map_or_else(
-                    |error| println!("\x1b[0;31m Error deleting user: {error} \x1b[0m"),
-                    |_| println!("\x1b[0;32m Deleted user successfully '\x1b[0m"),
+        Some(("logout", _)) => {
+            session.logout().await.map_or_else(
+                |error| println!("\x1b[0;31m Logout failed: {error} \x1b[0m"),
+                |_| println!("\x1b[0;32m Logged out successfully \x1b[0m"),
+            );
+        }
+        Some(("users", submatches)) => {
+            match submatches.subcommand() {
+                Some(("list", submatches)) => {
+                    let id: Option<&str> = submatches.get_one::<String>("id").map(|id| id.as_str());
+                    session.get_users(id).await.map_or_else(
+                        |error| println!("\x1b[0;31m Error fetching users: {error} \x1b[0m"),
+                        |_| println!("\x1b[0;32m Fetch successful \x1b[0m"),
+                    );
+                }
+                Some(("delete", submatches)) => {
+                    let id: Option<&str> = submatches.get_one::<String>("id").map(|id| id.as_str());
+                    session.delete_user(id).await.map_or_else(
+                        |error| println!("\x1b[0;31m Error deleting user: {error} \x1b[0m"),
+                        |_| println!("\x1b[0;32m Deleted user successfully '\x1b[0m"),
+                    );
+                }
+                Some(("grant-admin", submatches)) => {
+                    let email = submatches.get_one::<String>("email").unwrap();
+                    session.grant_admin(email).await.map_or_else(
+                    |error| println!("\x1b[0;31m Error granting the admin role: {error} \x1b[0m"),
+                    |_| println!("\x1b[0;32m Admin role granted; it applies from the next login \x1b[0m"),
                 );
-            }
-            Some(("create", submatches)) => {
-                let creds = UserCredentials {
-                    email: submatches.get_one::<String>("email").unwrap().to_string(),
-                    password: submatches
-                        .get_one::<String>("password")
-                        .unwrap()
-                        .to_string(),
-                };
+                }
+                Some(("create", submatches)) => {
+                    let creds = UserCredentials {
+                        email: submatches.get_one::<String>("email").unwrap().to_string(),
+                        password: submatches
+                            .get_one::<String>("password")
+                            .unwrap()
+                            .as_str()
+                            .into(),
+                    };
 
-                session.create_user(creds).await.map_or_else(
-                    |error| println!("\x1b[0;31m Error creating user: {error} \x1b[0m"),
-                    |_| println!("\x1b[0;32m User created successfully \x1b[0m"),
-                );
+                    session.create_user(creds).await.map_or_else(
+                        |error| println!("\x1b[0;31m Error creating user: {error} \x1b[0m"),
+                        |_| println!("\x1b[0;32m User created successfully \x1b[0m"),
+                    );
+                }
+                _ => {}
             }
-            _ => {}
-        },
+        }
 
         Some(("secret", submatches)) => match submatches.subcommand() {
             Some(("create", submatches)) => {
+                let require_approval = submatches.get_flag("require-approval");
                 let secret = Secret {
                     key: submatches.get_one::<String>("key").unwrap().to_string(),
-                    value: submatches.get_one::<String>("value").unwrap().to_string(),
+                    value: submatches
+                        .get_one::<String>("value")
+                        .unwrap()
+                        .as_str()
+                        .into(),
+                    protection: (require_approval || submatches.get_flag("protected"))
+                        .then_some(SecretProtection { require_approval }),
                 };
                 session.create_secret(secret).await.map_or_else(
                     |error| println!("\x1b[0;31m Error creating secret: {error} \x1b[0m"),
@@ -185,6 +487,39 @@ async fn main() {
                 );
             }
 
+            Some(("import", submatches)) => {
+                let file = Path::new(submatches.get_one::<String>("file").unwrap());
+                let format =
+                    submatches
+                        .get_one::<String>("format")
+                        .map(|format| match format.as_str() {
+                            "dotenv" => ImportFormat::Dotenv,
+                            "yaml" => ImportFormat::Yaml,
+                            "vault" => ImportFormat::Vault,
+                            _ => ImportFormat::Json,
+                        });
+                let conflict = match submatches.get_one::<String>("conflict").unwrap().as_str() {
+                    "overwrite" => ConflictStrategy::Overwrite,
+                    "new-version" => ConflictStrategy::NewVersion,
+                    _ => ConflictStrategy::Skip,
+                };
+                let prefix = submatches
+                    .get_one::<String>("prefix")
+                    .map(|prefix| prefix.as_str());
+                let dry_run = submatches.get_flag("dry-run");
+                session
+                    .import_secrets(file, format, prefix, conflict, dry_run)
+                    .await
+                    .map_or_else(
+                        |error| println!("\x1b[0;31m Import failed: {error} \x1b[0m"),
+                        |_| {
+                            if !dry_run {
+                                println!("\x1b[0;32m Imported secrets successfully \x1b[0m")
+                            }
+                        },
+                    );
+            }
+
             Some(("delete", submatches)) => {
                 let id: &str = submatches.get_one::<String>("id").unwrap().as_str();
                 session.delete_secret(id).await.map_or_else(
@@ -194,6 +529,161 @@ async fn main() {
             }
             _ => {}
         },
+
+        Some(("audit", submatches)) => match submatches.subcommand() {
+            Some(("list", submatches)) => {
+                let query = AuditQuery {
+                    actor: submatches.get_one::<String>("actor").cloned(),
+                    action: submatches.get_one::<String>("action").cloned(),
+                    limit: *submatches.get_one::<i64>("limit").unwrap(),
+                    ..AuditQuery::default()
+                };
+                session.list_audit_events(query).await.map_or_else(
+                    |error| println!("\x1b[0;31m Error fetching audit events: {error} \x1b[0m"),
+                    |_| println!("\x1b[0;32m Fetched audit events successfully \x1b[0m"),
+                );
+            }
+
+            Some(("verify", _)) => {
+                session.verify_audit_log().await.map_or_else(
+                    |error| println!("\x1b[0;31m Audit log verification failed: {error} \x1b[0m"),
+                    |_| println!("\x1b[0;32m Audit log is intact \x1b[0m"),
+                );
+            }
+            _ => {}
+        },
+
+        Some(("journal", submatches)) => {
+            let path = Path::new(submatches.get_one::<String>("path").unwrap());
+            match submatches.subcommand() {
+                Some(("verify", _)) => {
+                    session.verify_journal(path).await.map_or_else(
+                        |error| println!("\x1b[0;31m Journal verification failed: {error} \x1b[0m"),
+                        |_| println!("\x1b[0;32m Journal is intact \x1b[0m"),
+                    );
+                }
+
+                Some(("compact", submatches)) => {
+                    let before = *submatches.get_one::<DateTime<Utc>>("before").unwrap();
+                    session.compact_journal(path, before).await.map_or_else(
+                        |error| println!("\x1b[0;31m Compaction failed: {error} \x1b[0m"),
+                        |_| println!("\x1b[0;32m Journal compacted \x1b[0m"),
+                    );
+                }
+
+                Some(("replay", submatches)) => {
+                    let until = *submatches.get_one::<DateTime<Utc>>("until").unwrap();
+                    let since = submatches.get_one::<DateTime<Utc>>("since").copied();
+                    let dry_run = submatches.get_flag("dry-run");
+                    session
+                        .replay_journal(path, since, until, dry_run)
+                        .await
+                        .map_or_else(
+                            |error| println!("\x1b[0;31m Replay failed: {error} \x1b[0m"),
+                            |_| {
+                                if !dry_run {
+                                    println!("\x1b[0;32m Journal replayed \x1b[0m")
+                                }
+                            },
+                        );
+                }
+                _ => {}
+            }
+        }
+
+        Some(("operator", submatches)) => {
+            let operator =
+                Operator::new(submatches.get_one::<String>("url").map(|url| url.as_str()));
+            match submatches.subcommand() {
+                Some(("status", _)) => {
+                    if let Err(error) = operator.status().await {
+                        println!("\x1b[0;31m Error fetching seal status: {error} \x1b[0m");
+                    }
+                }
+
+                Some(("init", submatches)) => {
+                    let shares = *submatches.get_one::<u8>("shares").unwrap();
+                    let threshold = *submatches.get_one::<u8>("threshold").unwrap();
+                    operator.init(shares, threshold).await.map_or_else(
+                        |error| println!("\x1b[0;31m Initialization failed: {error} \x1b[0m"),
+                        |_| println!("\x1b[0;32m Lock Smith initialized \x1b[0m"),
+                    );
+                }
+
+                Some(("unseal", submatches)) => {
+                    let share = submatches
+                        .get_one::<String>("share")
+                        .map(|share| share.as_str());
+                    if let Err(error) = operator.unseal(share, submatches.get_flag("reset")).await {
+                        println!("\x1b[0;31m Unsealing failed: {error} \x1b[0m");
+                    }
+                }
+
+                Some(("seal", _)) => {
+                    operator.seal().await.map_or_else(
+                        |error| println!("\x1b[0;31m Sealing failed: {error} \x1b[0m"),
+                        |_| println!("\x1b[0;32m Lock Smith sealed \x1b[0m"),
+                    );
+                }
+
+                Some(("snapshot", submatches)) => {
+                    let Some(passphrase) = snapshot_passphrase(submatches) else {
+                        println!("\x1b[0;31m A passphrase is required \x1b[0m");
+                        return;
+                    };
+                    let output = Path::new(submatches.get_one::<String>("output").unwrap());
+                    operator.snapshot(output, &passphrase).await.map_or_else(
+                        |error| println!("\x1b[0;31m Snapshot failed: {error} \x1b[0m"),
+                        |_| {
+                            println!(
+                                "\x1b[0;32m Snapshot written to {} \x1b[0m",
+                                output.display()
+                            )
+                        },
+                    );
+                }
+
+                Some(("restore", submatches)) => {
+                    let Some(passphrase) = snapshot_passphrase(submatches) else {
+                        println!("\x1b[0;31m A passphrase is required \x1b[0m");
+                        return;
+                    };
+                    let input = Path::new(submatches.get_one::<String>("input").unwrap());
+                    let mode = match submatches.get_one::<String>("mode").unwrap().as_str() {
+                        "replace" => RestoreMode::Replace,
+                        _ => RestoreMode::Merge,
+                    };
+                    let dry_run = submatches.get_flag("dry-run");
+                    operator
+                        .restore(input, &passphrase, mode, dry_run)
+                        .await
+                        .map_or_else(
+                            |error| println!("\x1b[0;31m Restore failed: {error} \x1b[0m"),
+                            |_| {
+                                if !dry_run {
+                                    println!("\x1b[0;32m Snapshot restored \x1b[0m")
+                                }
+                            },
+                        );
+                }
+                _ => {}
+            }
+        }
         _ => {}
     }
 }
+
+/// The `--passphrase` given, else `ECS_SNAPSHOT_PASSPHRASE`.
+fn snapshot_passphrase(matches: &clap::ArgMatches) -> Option<String> {
+    matches
+        .get_one::<String>("passphrase")
+        .cloned()
+        .or_else(|| std::env::var("ECS_SNAPSHOT_PASSPHRASE").ok())
+        .filter(|passphrase| !passphrase.is_empty())
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|error| error.to_string())
+}
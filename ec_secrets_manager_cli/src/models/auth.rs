@@ -1,46 +1,108 @@
 use home;
-use std::fs;
+use std::{
+    fs,
+    io::{self, Write},
+};
 
 use ec_secrets_shared_library::{
-    models::{User, UserCredentials},
-    utils::auth::authorize_user,
+    db::Repositories,
+    models::UserCredentials,
+    utils::auth::{AuthConfig, LoginError, authenticate_password, issue_token},
 };
 
-use super::get_repos;
+use super::{audit, audit_lockout, get_repos};
 
 pub struct Auth;
 
+impl Default for Auth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Auth {
     pub fn new() -> Self {
         Self
     }
 
     pub async fn login(&mut self, creds: UserCredentials) -> Result<(), String> {
-        let (user_repo, _, key_repo) = get_repos().await?;
-
-        let user_doc = user_repo
-            .get_user_by_email(&creds.email)
-            .await
-            .map_err(|error| error.to_string())?;
-
-        if let Some(user_doc) = user_doc {
-            let user = User {
-                id: user_doc.id.to_string(),
-                email: user_doc.email.clone(),
-                password: user_doc.password.clone(),
-                created_at: user_doc.created_at.to_rfc3339(),
-            };
+        let Repositories {
+            users: user_repo,
+            keys: key_repo,
+            totp: totp_repo,
+            login_attempts,
+            audit: audit_repo,
+            ..
+        } = get_repos().await?;
+
+        let result = async {
+            // The CLI talks to the database directly, so only per-account limits apply.
+            let config = AuthConfig::default();
+            let user =
+                match authenticate_password(&user_repo, &login_attempts, &creds, None, &config)
+                    .await
+                {
+                    Ok(user) => user,
+                    Err(LoginError::InvalidCredentials(lockouts)) => {
+                        for lockout in &lockouts {
+                            audit_lockout(&audit_repo, &creds.email, lockout).await;
+                        }
+                        return Err("Invalid login credentials".to_owned());
+                    }
+                    Err(error) => return Err(error.to_string()),
+                };
+
+            if totp_repo
+                .is_enrolled(&user.email)
+                .await
+                .map_err(|error| error.to_string())?
+            {
+                let code = prompt_code()?;
+                if !totp_repo
+                    .verify_code(&user.email, &code)
+                    .await
+                    .map_err(|error| error.to_string())?
+                {
+                    return Err("Invalid two-factor code".to_owned());
+                }
+            }
+
+            let token = issue_token(
+                &user.email,
+                &user.roles,
+                None,
+                config.access_token_lifetime(),
+                &config,
+                &key_repo,
+            )
+            .await?;
+            user_repo
+                .record_login(&user.email)
+                .await
+                .map_err(|error| error.to_string())?;
 
-            let token = authorize_user(&user, &creds, &key_repo).await?;
             let Some(home_dir) = home::home_dir() else {
                 return Err("Error acccessing the home directory".to_owned());
             };
             let token_file = home_dir.join(".lock_smith.config");
             fs::write(token_file, token).map_err(|error| error.to_string())?;
-        } else {
-            return Err("Invalid login credentials".to_owned());
-        }
 
-        Ok(())
+            Ok(())
+        }
+        .await;
+        audit(&audit_repo, &creds.email, "auth.login", None, &result).await;
+        result
     }
 }
+
+/// Reads a TOTP or recovery code from the terminal.
+fn prompt_code() -> Result<String, String> {
+    print!("Two-factor code: ");
+    io::stdout().flush().map_err(|error| error.to_string())?;
+
+    let mut code = String::new();
+    io::stdin()
+        .read_line(&mut code)
+        .map_err(|error| error.to_string())?;
+    Ok(code.trim().to_owned())
+}
@@ -1,12 +1,66 @@
 use ec_secrets_shared_library::{
-    db::connect,
-    repositories::{keys::KeyRepository, users::UserRepository, vault::VaultRepository},
+    db::{Repositories, connect},
+    models::{AuditEvent, AuditOutcome},
+    repositories::audit::AuditRepository,
+    utils::lockout::Lockout,
 };
 
 pub mod auth;
+pub mod operator;
 pub mod session;
 
-pub async fn get_repos() -> Result<(UserRepository, VaultRepository, KeyRepository), String> {
+pub async fn get_repos() -> Result<Repositories, String> {
     let repos = connect().await.map_err(|error| error.to_string())?;
     Ok(repos)
 }
+
+/// Records a CLI action in the audit log. The CLI has no client address;
+/// its events are told apart by their user agent.
+pub async fn audit<T>(
+    repo: &AuditRepository,
+    actor: &str,
+    action: &str,
+    target: Option<&str>,
+    result: &Result<T, String>,
+) {
+    let (outcome, detail) = match result {
+        Ok(_) => (AuditOutcome::Success, None),
+        Err(error) => (AuditOutcome::Failure, Some(error.clone())),
+    };
+    append(repo, actor, action, target, outcome, detail).await;
+}
+
+/// Records a lockout tripped by a failed login as a denied event.
+pub async fn audit_lockout(repo: &AuditRepository, actor: &str, lockout: &Lockout) {
+    append(
+        repo,
+        actor,
+        lockout.action(),
+        Some(&lockout.target()),
+        AuditOutcome::Denied,
+        Some(lockout.detail()),
+    )
+    .await;
+}
+
+async fn append(
+    repo: &AuditRepository,
+    actor: &str,
+    action: &str,
+    target: Option<&str>,
+    outcome: AuditOutcome,
+    detail: Option<String>,
+) {
+    let event = AuditEvent {
+        actor: actor.to_owned(),
+        action: action.to_owned(),
+        target: target.map(str::to_owned),
+        outcome,
+        source_ip: None,
+        user_agent: Some(format!("ec_lock_smith/{}", env!("CARGO_PKG_VERSION"))),
+        detail,
+    };
+    if let Err(error) = repo.append(event).await {
+        eprintln!("\x1b[0;33m Failed to write the audit log: {error} \x1b[0m");
+    }
+}
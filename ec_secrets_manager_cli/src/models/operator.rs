@@ -0,0 +1,233 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use home;
+use prettytable::{Cell, Row, Table};
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, de::DeserializeOwned};
+use std::{env, fs, path::Path};
+
+use ec_secrets_shared_library::{
+    models::{
+        RestoreMode, RestoreReport, RestoreRequest, SealInitRequest, SealInitResponse, SealStatus,
+        SnapshotCounts, SnapshotManifest, SnapshotRequest, SnapshotResponse, UnsealRequest,
+    },
+    utils::secret::SecretValue,
+};
+
+/*---------------------------------------------------------------
+Seal operations and snapshots. Unlike the other commands these go
+through the server's HTTP API: the master key lives in the
+server's memory, so that is where shares have to be submitted and
+secrets decrypted.
+----------------------------------------------------------------*/
+const DEFAULT_SERVER_URL: &str = "http://localhost:8089";
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: String,
+}
+
+pub struct Operator {
+    url: String,
+    client: Client,
+}
+
+impl Operator {
+    /// Targets `url`, else `ECS_SERVER_URL`, else a local server.
+    pub fn new(url: Option<&str>) -> Self {
+        let url = url
+            .map(str::to_owned)
+            .or_else(|| env::var("ECS_SERVER_URL").ok())
+            .unwrap_or_else(|| DEFAULT_SERVER_URL.to_owned());
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            client: Client::new(),
+        }
+    }
+
+    /// The session token stored by `login`, for admin-only operations.
+    fn authorized(request: RequestBuilder) -> Result<RequestBuilder, String> {
+        let Some(home_dir) = home::home_dir() else {
+            return Err("Error acccessing the home directory".to_owned());
+        };
+        let token = fs::read_to_string(home_dir.join(".lock_smith.config"))
+            .map_err(|_| "Session invalid. Please login.".to_owned())?;
+        Ok(request.bearer_auth(token.trim()))
+    }
+
+    async fn send<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, String> {
+        let response = request.send().await.map_err(|error| error.to_string())?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            // Error bodies are `ErrorResponse`s; catchers answer in plain text.
+            let message =
+                serde_json::from_str::<ErrorBody>(&body).map_or(body, |error| error.message);
+            return Err(format!("{status}: {message}"));
+        }
+        response.json().await.map_err(|error| error.to_string())
+    }
+
+    pub async fn status(&self) -> Result<(), String> {
+        let status: SealStatus =
+            Self::send(self.client.get(format!("{}/sys/seal-status", self.url))).await?;
+        print_status(&status);
+        Ok(())
+    }
+
+    pub async fn init(&self, shares: u8, threshold: u8) -> Result<(), String> {
+        let request = self
+            .client
+            .post(format!("{}/sys/init", self.url))
+            .json(&SealInitRequest { shares, threshold });
+        let response: SealInitResponse = Self::send(Self::authorized(request)?).await?;
+
+        let mut table = Table::new();
+        table.add_row(Row::new(vec![Cell::new("Share"), Cell::new("Key")]));
+        for (index, share) in response.shares.iter().enumerate() {
+            table.add_row(Row::new(vec![
+                Cell::new(&(index + 1).to_string()),
+                Cell::new(share),
+            ]));
+        }
+        table.printstd();
+        println!(
+            "Hand each share to a different operator; {} of them unseal Lock Smith. They are not shown again.",
+            response.threshold
+        );
+        Ok(())
+    }
+
+    pub async fn unseal(&self, share: Option<&str>, reset: bool) -> Result<(), String> {
+        let request = self
+            .client
+            .post(format!("{}/sys/unseal", self.url))
+            .json(&UnsealRequest {
+                share: share.map(SecretValue::from),
+                reset,
+            });
+        let status: SealStatus = Self::send(request).await?;
+        print_status(&status);
+        Ok(())
+    }
+
+    pub async fn seal(&self) -> Result<(), String> {
+        let request = self.client.post(format!("{}/sys/seal", self.url));
+        let status: SealStatus = Self::send(Self::authorized(request)?).await?;
+        print_status(&status);
+        Ok(())
+    }
+
+    pub async fn snapshot(&self, output: &Path, passphrase: &str) -> Result<(), String> {
+        let request =
+            self.client
+                .post(format!("{}/sys/snapshot", self.url))
+                .json(&SnapshotRequest {
+                    passphrase: passphrase.into(),
+                });
+        let response: SnapshotResponse = Self::send(Self::authorized(request)?).await?;
+        let archive = STANDARD
+            .decode(&response.snapshot)
+            .map_err(|error| error.to_string())?;
+        fs::write(output, archive).map_err(|error| error.to_string())?;
+        print_manifest(&response.manifest);
+        Ok(())
+    }
+
+    pub async fn restore(
+        &self,
+        input: &Path,
+        passphrase: &str,
+        mode: RestoreMode,
+        dry_run: bool,
+    ) -> Result<(), String> {
+        let archive = fs::read(input).map_err(|error| error.to_string())?;
+        let request = self
+            .client
+            .post(format!("{}/sys/restore", self.url))
+            .json(&RestoreRequest {
+                snapshot: STANDARD.encode(archive),
+                passphrase: passphrase.into(),
+                mode,
+                dry_run,
+            });
+        let report: RestoreReport = Self::send(Self::authorized(request)?).await?;
+        print_manifest(&report.manifest);
+        print_report(&report);
+        Ok(())
+    }
+}
+
+fn print_status(status: &SealStatus) {
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("Initialized"),
+        Cell::new("Sealed"),
+        Cell::new("Shares"),
+        Cell::new("Threshold"),
+        Cell::new("Progress"),
+    ]));
+    let count = |value: Option<u8>| value.map_or("-".to_owned(), |value| value.to_string());
+    table.add_row(Row::new(vec![
+        Cell::new(&status.initialized.to_string()),
+        Cell::new(&status.sealed.to_string()),
+        Cell::new(&count(status.shares)),
+        Cell::new(&count(status.threshold)),
+        Cell::new(&status.progress.to_string()),
+    ]));
+    table.printstd();
+}
+
+fn print_manifest(manifest: &SnapshotManifest) {
+    println!(
+        "Snapshot taken {} by {}",
+        manifest.created_at, manifest.created_by
+    );
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("Collection"),
+        Cell::new("Documents"),
+    ]));
+    for (collection, count) in collections(&manifest.counts) {
+        table.add_row(Row::new(vec![
+            Cell::new(collection),
+            Cell::new(&count.to_string()),
+        ]));
+    }
+    table.printstd();
+}
+
+fn print_report(report: &RestoreReport) {
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("Collection"),
+        Cell::new("Restored"),
+        Cell::new("Skipped"),
+        Cell::new("Removed"),
+    ]));
+    let rows = collections(&report.restored)
+        .into_iter()
+        .zip(collections(&report.skipped))
+        .zip(collections(&report.removed));
+    for (((collection, restored), (_, skipped)), (_, removed)) in rows {
+        table.add_row(Row::new(vec![
+            Cell::new(collection),
+            Cell::new(&restored.to_string()),
+            Cell::new(&skipped.to_string()),
+            Cell::new(&removed.to_string()),
+        ]));
+    }
+    table.printstd();
+    if report.dry_run {
+        println!("Dry run in {} mode; nothing was written.", report.mode);
+    }
+}
+
+fn collections(counts: &SnapshotCounts) -> [(&'static str, usize); 5] {
+    [
+        ("users", counts.users),
+        ("secrets", counts.secrets),
+        ("totp", counts.totp),
+        ("keys", counts.keys),
+        ("app_roles", counts.app_roles),
+    ]
+}
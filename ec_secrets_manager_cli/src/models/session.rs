@@ -1,26 +1,46 @@
+use chrono::{DateTime, Utc};
 use home;
 use prettytable::{Cell, Row, Table};
-use std::fs;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
 
 use ec_secrets_shared_library::{
-    models::{Secret, UserCredentials},
-    repositories::{users::UserRepository, vault::VaultRepository},
-    utils::auth::{decode_keys, hash_password},
-};
-use pasetors::{
-    Public,
-    claims::{Claims, ClaimsValidationRules},
-    public,
-    token::UntrustedToken,
-    version4::V4,
+    db::Repositories,
+    models::{
+        ADMIN_ROLE, AuditEntry, AuditQuery, ConflictStrategy, ImportAction, ImportFormat,
+        ImportReport, ReplayReport, Secret, User, UserCredentials,
+    },
+    repositories::{
+        audit::AuditRepository, journal::JournalRepository, revocations::RevocationRepository,
+        users::UserRepository, vault::VaultRepository,
+    },
+    utils::{
+        auth::{AuthConfig, TokenValidator, decode_keys, hash_password},
+        import,
+        journal::{self, JournalError},
+        password_policy::is_valid_email,
+    },
 };
+use pasetors::claims::Claims;
 
-use super::get_repos;
+use super::{audit, get_repos};
 
 pub struct Session {
     claims: Option<Claims>,
     user_repo: Option<UserRepository>,
     vault_repo: Option<VaultRepository>,
+    revocation_repo: Option<RevocationRepository>,
+    audit_repo: Option<AuditRepository>,
+    journal_repo: Option<JournalRepository>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Session {
@@ -29,6 +49,9 @@ impl Session {
             claims: None,
             user_repo: None,
             vault_repo: None,
+            revocation_repo: None,
+            audit_repo: None,
+            journal_repo: None,
         }
     }
 
@@ -40,27 +63,94 @@ impl Session {
 
         let token = fs::read_to_string(token_file).map_err(|error| error.to_string())?;
 
-        let untrusted_token = UntrustedToken::<Public, V4>::try_from(token.as_str())
+        let Repositories {
+            users: user_repo,
+            vault: vault_repo,
+            keys: key_repo,
+            revocations: revocation_repo,
+            audit: audit_repo,
+            journal: journal_repo,
+            ..
+        } = get_repos().await?;
+
+        let keys = decode_keys(&key_repo).await?;
+
+        let claims = TokenValidator::from_env(&AuthConfig::default())
+            .validate(token.trim(), &keys.1)
             .map_err(|error| error.to_string())?;
 
-        let (user_repo, vault_repo, key_repo) = get_repos().await?;
+        let subject = claims
+            .get_claim("sub")
+            .and_then(|subject| subject.as_str())
+            .ok_or("Session invalid. Please login.")?;
+        let issued_at = claims
+            .get_claim("iat")
+            .and_then(|iat| iat.as_str())
+            .and_then(|iat| DateTime::parse_from_rfc3339(iat).ok())
+            .ok_or("Session invalid. Please login.")?
+            .with_timezone(&Utc);
+        let jti = claims.get_claim("jti").and_then(|jti| jti.as_str());
+        if revocation_repo
+            .is_revoked(jti, subject, issued_at)
+            .await
+            .map_err(|error| error.to_string())?
+        {
+            return Err("Session revoked. Please login.".to_owned());
+        }
 
-        let keys = decode_keys(&key_repo).await?;
+        self.user_repo = Some(user_repo);
+        self.claims = Some(claims);
+        self.vault_repo = Some(vault_repo);
+        self.revocation_repo = Some(revocation_repo);
+        self.audit_repo = Some(audit_repo);
+        self.journal_repo = Some(journal_repo);
 
-        let validation_rules = ClaimsValidationRules::new();
+        Ok(())
+    }
 
-        let trusted_token =
-            public::verify(&keys.1, &untrusted_token, &validation_rules, None, None)
-                .map_err(|error| error.to_string())?;
+    /// Records an action of the signed-in user in the audit log.
+    async fn audit(&self, action: &str, target: Option<&str>, result: &Result<(), String>) {
+        let (Some(claims), Some(audit_repo)) = (&self.claims, &self.audit_repo) else {
+            return;
+        };
+        let actor = claims
+            .get_claim("sub")
+            .and_then(|subject| subject.as_str())
+            .unwrap_or_default();
+        audit(audit_repo, actor, action, target, result).await;
+    }
 
-        let Some(claims) = trusted_token.payload_claims() else {
-            return Err("Token has no payload".to_owned());
+    pub async fn logout(&mut self) -> Result<(), String> {
+        let _ = &self.validate_session().await?;
+
+        let (Some(claims), Some(revocation_repo)) = (&self.claims, &self.revocation_repo) else {
+            return Err("Session invalid. Please login.".to_owned());
         };
 
-        self.user_repo = Some(user_repo);
-        self.claims = Some(claims.clone());
-        self.vault_repo = Some(vault_repo);
+        let claim = |name: &str| {
+            claims
+                .get_claim(name)
+                .and_then(|value| value.as_str())
+                .map(str::to_owned)
+        };
+        let (Some(jti), Some(subject), Some(expiration)) =
+            (claim("jti"), claim("sub"), claim("exp"))
+        else {
+            return Err("Session token cannot be revoked".to_owned());
+        };
+        let expires_at = DateTime::parse_from_rfc3339(&expiration)
+            .map_err(|error| error.to_string())?
+            .with_timezone(&Utc);
+
+        revocation_repo
+            .revoke_token(&jti, &subject, expires_at)
+            .await
+            .map_err(|error| error.to_string())?;
 
+        let Some(home_dir) = home::home_dir() else {
+            return Err("Error acccessing the home directory".to_owned());
+        };
+        fs::remove_file(home_dir.join(".lock_smith.config")).map_err(|error| error.to_string())?;
         Ok(())
     }
 
@@ -71,9 +161,21 @@ impl Session {
         table.add_row(Row::new(vec![
             Cell::new("Id"),
             Cell::new("Email"),
+            Cell::new("Roles"),
+            Cell::new("Status"),
             Cell::new("CreatedAt"),
+            Cell::new("LastLogin"),
         ]));
-
+        let user_row = |user: User| {
+            Row::new(vec![
+                Cell::new(&user.id),
+                Cell::new(&user.email),
+                Cell::new(&user.roles.join(", ")),
+                Cell::new(&user.status.to_string()),
+                Cell::new(&user.created_at),
+                Cell::new(user.last_login.as_deref().unwrap_or("never")),
+            ])
+        };
 
         let Some(user_repo) = &self.user_repo else {
             return Err("failed to connect to the database".to_owned());
@@ -84,25 +186,15 @@ impl Session {
                 .get_user_by_id(id)
                 .await
                 .map_err(|error| error.to_string())?
-                .map(|user| {
-                    table.add_row(Row::new(vec![
-                        Cell::new(user.id.to_string().as_str()),
-                        Cell::new(user.email.as_str()),
-                        Cell::new(user.created_at.to_string().as_str()),
-                    ]));
-                });
+                .map(|user| table.add_row(user_row(User::from(user))));
         } else {
             let users = user_repo.list_users().await.map_err(|error| {
                 println!("Error: {error:?}");
                 error.to_string()
             })?;
 
-            users.iter().for_each(|user| {
-                table.add_row(Row::new(vec![
-                    Cell::new(user.id.to_string().as_str()),
-                    Cell::new(user.email.as_str()),
-                    Cell::new(user.created_at.to_string().as_str()),
-                ]));
+            users.into_iter().for_each(|user| {
+                table.add_row(user_row(User::from(user)));
             });
         }
         table.printstd();
@@ -112,130 +204,513 @@ impl Session {
     pub async fn delete_user(&mut self, id: Option<&str>) -> Result<(), String> {
         let _ = &self.validate_session().await?;
 
-        let Some(user_repo) = &self.user_repo else {
-            return Err("failed to connect to the database".to_owned());
-        };
+        let result = async {
+            let Some(user_repo) = &self.user_repo else {
+                return Err("failed to connect to the database".to_owned());
+            };
 
-        let Some(id) = id else {
-            return Err("Please provide an id for the account to delete".to_owned());
-        };
+            let Some(id) = id else {
+                return Err("Please provide an id for the account to delete".to_owned());
+            };
 
-        let _ = user_repo
-            .delete_user(id)
-            .await
-            .map_err(|error| error.to_string())?;
+            let Some(vault_repo) = &self.vault_repo else {
+                return Err("failed to connect to the database".to_owned());
+            };
+            if vault_repo
+                .count_owned(id)
+                .await
+                .map_err(|error| error.to_string())?
+                > 0
+            {
+                return Err(
+                    "User still owns secrets; offboard them through the API instead".to_owned(),
+                );
+            }
 
-        Ok(())
+            let _ = user_repo
+                .delete_user(id)
+                .await
+                .map_err(|error| error.to_string())?;
+
+            Ok(())
+        }
+        .await;
+        self.audit("user.delete", id, &result).await;
+        result
     }
 
     pub async fn create_user(&mut self, creds: UserCredentials) -> Result<(), String> {
         let _ = &self.validate_session().await?;
+        let email = creds.email.clone();
 
-        let Some(user_repo) = &self.user_repo else {
-            return Err("failed to connect to the database".to_owned());
-        };
+        let result = async {
+            let Some(user_repo) = &self.user_repo else {
+                return Err("failed to connect to the database".to_owned());
+            };
 
-        let hashed_pwd = hash_password(creds.password)?;
-        let _ = user_repo
-            .create_user(&creds.email, &hashed_pwd)
-            .await
-            .map_err(|error| error.to_string())?;
-        Ok(())
+            if !is_valid_email(&creds.email) {
+                return Err("Invalid email address".to_owned());
+            }
+            let config = AuthConfig::default();
+            let violations = config
+                .password_policy
+                .check(&creds.password, &[&creds.email])
+                .map_err(|error| error.to_string())?;
+            if !violations.is_empty() {
+                let reasons: Vec<String> = violations.iter().map(ToString::to_string).collect();
+                return Err(format!("Password {}", reasons.join("; ")));
+            }
+
+            let hashed_pwd = hash_password(&creds.password, &config.password_hashing)?;
+            let _ = user_repo
+                .create_user(&creds.email, &hashed_pwd, &[])
+                .await
+                .map_err(|error| error.to_string())?;
+            Ok(())
+        }
+        .await;
+        self.audit("user.create", Some(&email), &result).await;
+        result
     }
 
-    pub async fn create_secret(&mut self, secret: Secret) -> Result<(), String> {
-        let _ = &self.validate_session().await?;
+    /*---------------------------------------------------------------
+    Grant the admin role. Deployments set up before the first account
+    became their administrator have none: until one exists, whoever can
+    reach the database may grant it; afterwards only an admin can.
+    ----------------------------------------------------------------*/
+    pub async fn grant_admin(&mut self, email: &str) -> Result<(), String> {
+        let Repositories {
+            users: user_repo,
+            audit: audit_repo,
+            ..
+        } = get_repos().await?;
+
+        let admins = user_repo
+            .count_with_role(ADMIN_ROLE)
+            .await
+            .map_err(|error| error.to_string())?;
+        let actor = if admins == 0 {
+            "bootstrap".to_owned()
+        } else {
+            self.validate_session().await?;
+            self.require_admin()?;
+            self.claims
+                .as_ref()
+                .and_then(|claims| claims.get_claim("sub"))
+                .and_then(|subject| subject.as_str())
+                .unwrap_or_default()
+                .to_owned()
+        };
 
-        let Some(vault_repo) = &self.vault_repo else {
-            return Err("failed to connect to the database".to_owned());
+        let result = match user_repo.add_role(email, ADMIN_ROLE).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err("User not found".to_owned()),
+            Err(error) => Err(error.to_string()),
         };
+        audit(&audit_repo, &actor, "user.roles", Some(email), &result).await;
+        result
+    }
 
-        let Some(claims) = &self.claims else {
+    /// The signed-in subject and the owner id their secrets are stored under.
+    async fn owner(&self) -> Result<(String, String), String> {
+        let (Some(claims), Some(user_repo)) = (&self.claims, &self.user_repo) else {
             return Err("Session invalid. Please login.".to_owned());
         };
-
-        let Some(created_by) = claims.get_claim("sub") else {
-            return Err("".to_owned());
+        let Some(subject) = claims.get_claim("sub").and_then(|subject| subject.as_str()) else {
+            return Err("Session invalid. Please login.".to_owned());
         };
-
-        let _ = vault_repo
-            .create_secret(&secret.key, &secret.value, created_by.to_string().as_str())
+        let owner_id = user_repo
+            .owner_id(subject)
             .await
             .map_err(|error| error.to_string())?;
-        Ok(())
+        Ok((subject.to_owned(), owner_id))
     }
 
-    pub async fn list_secrets(&mut self, id: Option<&str>) -> Result<(), String> {
+    pub async fn create_secret(&mut self, secret: Secret) -> Result<(), String> {
         let _ = &self.validate_session().await?;
+        let key = secret.key.clone();
 
-        let Some(vault_repo) = &self.vault_repo else {
-            return Err("failed to connect to the database".to_owned());
-        };
+        let result = async {
+            let Some(vault_repo) = &self.vault_repo else {
+                return Err("failed to connect to the database".to_owned());
+            };
 
-        let Some(claims) = &self.claims else {
-            return Err("Session invalid. Please login.".to_owned());
-        };
+            let (created_by, owner_id) = self.owner().await?;
 
-        let mut table = Table::new();
+            let _ = vault_repo
+                .create_secret(
+                    &secret.key,
+                    &secret.value,
+                    &created_by,
+                    &owner_id,
+                    secret.protection,
+                )
+                .await
+                .map_err(|error| error.to_string())?;
+            Ok(())
+        }
+        .await;
+        self.audit("secret.create", Some(&key), &result).await;
+        result
+    }
 
-        let Some(created_by) = claims.get_claim("sub") else {
-            return Err("".to_owned());
-        };
+    /// Imports a file as secrets; `format` defaults to what the file name
+    /// suggests.
+    pub async fn import_secrets(
+        &mut self,
+        path: &Path,
+        format: Option<ImportFormat>,
+        prefix: Option<&str>,
+        conflict: ConflictStrategy,
+        dry_run: bool,
+    ) -> Result<(), String> {
+        let _ = &self.validate_session().await?;
 
-        if let Some(id) = id {
-            table.add_row(Row::new(vec![Cell::new("Id"), Cell::new("Secret")]));
-            let Some(secret) = vault_repo
-                .get_secret_by_id(id, created_by.to_string().as_str())
-                .await
-                .map_err(|error| error.to_string())?
-            else {
-                return Err("Invalid secret id".to_owned());
+        let result = async {
+            let Some(vault_repo) = &self.vault_repo else {
+                return Err("failed to connect to the database".to_owned());
             };
-            table.add_row(Row::new(vec![Cell::new(id), Cell::new(secret.as_str())]));
-        } else {
-            table.add_row(Row::new(vec![
-                Cell::new("Id"),
-                Cell::new("Key"),
-                Cell::new("Value"),
-            ]));
-            let secrets = vault_repo
-                .list_secrets(created_by.to_string().as_str())
+            let format = format
+                .or_else(|| import::detect_format(path))
+                .ok_or("Cannot tell the format from the file name; pass --format")?;
+            let content = fs::read_to_string(path).map_err(|error| error.to_string())?;
+            let entries =
+                import::parse(format, &content, prefix).map_err(|error| error.to_string())?;
+
+            let (created_by, owner_id) = self.owner().await?;
+            let report = vault_repo
+                .import(&entries, &created_by, &owner_id, conflict, dry_run)
                 .await
                 .map_err(|error| error.to_string())?;
-            if secrets.len() <= 0 {
-                return Err("No Secrets created yet".to_owned());
-            }
-            secrets.iter().for_each(|secret| {
+            print_import_report(&report);
+            Ok(())
+        }
+        .await;
+        if !dry_run {
+            self.audit("secret.import", prefix, &result).await;
+        }
+        result
+    }
+
+    pub async fn list_secrets(&mut self, id: Option<&str>) -> Result<(), String> {
+        let _ = &self.validate_session().await?;
+
+        let result = async {
+            let Some(vault_repo) = &self.vault_repo else {
+                return Err("failed to connect to the database".to_owned());
+            };
+
+            let (_, owner_id) = self.owner().await?;
+
+            let mut table = Table::new();
+
+            if let Some(id) = id {
+                table.add_row(Row::new(vec![Cell::new("Id"), Cell::new("Secret")]));
+                let protected = vault_repo
+                    .get_entry(id)
+                    .await
+                    .map_err(|error| error.to_string())?
+                    .is_some_and(|entry| entry.owner_id == owner_id && entry.protection.is_some());
+                if protected {
+                    return Err(
+                        "Secret is protected; request access with a justification through the API"
+                            .to_owned(),
+                    );
+                }
+                let Some(secret) = vault_repo
+                    .get_secret_by_id(id, &owner_id)
+                    .await
+                    .map_err(|error| error.to_string())?
+                else {
+                    return Err("Invalid secret id".to_owned());
+                };
+                table.add_row(Row::new(vec![Cell::new(id), Cell::new(secret.as_str())]));
+            } else {
                 table.add_row(Row::new(vec![
-                    Cell::new(secret.id.to_string().as_str()),
-                    Cell::new(secret.key.as_str()),
-                    Cell::new(secret.value.as_str()),
+                    Cell::new("Id"),
+                    Cell::new("Key"),
+                    Cell::new("Value"),
                 ]));
-            });
+                let secrets = vault_repo
+                    .list_secrets(&owner_id)
+                    .await
+                    .map_err(|error| error.to_string())?;
+                if secrets.is_empty() {
+                    return Err("No Secrets created yet".to_owned());
+                }
+                secrets.iter().for_each(|secret| {
+                    table.add_row(Row::new(vec![
+                        Cell::new(secret.id.to_string().as_str()),
+                        Cell::new(secret.key.as_str()),
+                        Cell::new(if secret.protection.is_some() {
+                            "(protected)"
+                        } else {
+                            secret.value.as_str()
+                        }),
+                    ]));
+                });
+            }
+            table.printstd();
+            Ok(())
         }
-        table.printstd();
-        Ok(())
+        .await;
+        let action = if id.is_some() {
+            "secret.reveal"
+        } else {
+            "secret.list"
+        };
+        self.audit(action, id, &result).await;
+        result
     }
 
     pub async fn delete_secret(&mut self, id: &str) -> Result<(), String> {
         let _ = &self.validate_session().await?;
 
-        let Some(vault_repo) = &self.vault_repo else {
-            return Err("failed to connect to the database".to_owned());
-        };
+        let result = async {
+            let Some(vault_repo) = &self.vault_repo else {
+                return Err("failed to connect to the database".to_owned());
+            };
 
-        let Some(claims) = &self.claims else {
+            let (_, owner_id) = self.owner().await?;
+
+            let _ = vault_repo
+                .delete_secret(id, &owner_id)
+                .await
+                .map_err(|error| error.to_string())?;
+            Ok(())
+        }
+        .await;
+        self.audit("secret.delete", Some(id), &result).await;
+        result
+    }
+
+    /// Fails unless the signed-in user holds the admin role.
+    fn require_admin(&self) -> Result<&AuditRepository, String> {
+        let (Some(claims), Some(audit_repo)) = (&self.claims, &self.audit_repo) else {
             return Err("Session invalid. Please login.".to_owned());
         };
+        let is_admin = claims
+            .get_claim("roles")
+            .and_then(|roles| roles.as_array())
+            .is_some_and(|roles| roles.iter().any(|role| role.as_str() == Some(ADMIN_ROLE)));
+        if !is_admin {
+            return Err("Insufficient Permissions".to_owned());
+        }
+        Ok(audit_repo)
+    }
 
-        let Some(created_by) = claims.get_claim("sub") else {
-            return Err("".to_owned());
-        };
+    pub async fn list_audit_events(&mut self, query: AuditQuery) -> Result<(), String> {
+        let _ = &self.validate_session().await?;
+        let audit_repo = self.require_admin()?;
 
-        let _ = vault_repo
-            .delete_secret(id, created_by.to_string().as_str())
+        let entries = audit_repo
+            .query(&query)
             .await
             .map_err(|error| error.to_string())?;
+
+        let mut table = Table::new();
+        table.add_row(Row::new(vec![
+            Cell::new("Seq"),
+            Cell::new("Timestamp"),
+            Cell::new("Actor"),
+            Cell::new("Action"),
+            Cell::new("Target"),
+            Cell::new("Outcome"),
+            Cell::new("Severity"),
+            Cell::new("Source"),
+            Cell::new("Detail"),
+        ]));
+        entries.into_iter().map(AuditEntry::from).for_each(|entry| {
+            table.add_row(Row::new(vec![
+                Cell::new(&entry.sequence.to_string()),
+                Cell::new(&entry.timestamp),
+                Cell::new(&entry.actor),
+                Cell::new(&entry.action),
+                Cell::new(entry.target.as_deref().unwrap_or("-")),
+                Cell::new(&entry.outcome.to_string()),
+                Cell::new(&entry.severity.to_string()),
+                Cell::new(entry.source_ip.as_deref().unwrap_or("cli")),
+                Cell::new(entry.detail.as_deref().unwrap_or("")),
+            ]));
+        });
+        table.printstd();
         Ok(())
     }
+
+    pub async fn verify_audit_log(&mut self) -> Result<(), String> {
+        let _ = &self.validate_session().await?;
+        let audit_repo = self.require_admin()?;
+
+        let verification = audit_repo
+            .verify()
+            .await
+            .map_err(|error| error.to_string())?;
+        println!(
+            "{} entries verified; head #{} {}",
+            verification.entries, verification.head_sequence, verification.head_hash
+        );
+        match verification.broken_at {
+            Some(broken_at) => Err(format!(
+                "chain broken at entry {}: {}",
+                broken_at.sequence, broken_at.reason
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn verify_journal(&mut self, path: &Path) -> Result<(), String> {
+        let _ = &self.validate_session().await?;
+        self.require_admin()?;
+
+        let key = journal::journal_key().ok_or(JournalError::MissingKey.to_string())?;
+        let verification = journal::read(path, &key)
+            .map_err(|error| error.to_string())?
+            .verification();
+        println!(
+            "{} entries verified; head #{} {}",
+            verification.entries, verification.head_sequence, verification.head_hash
+        );
+        match verification.broken_at {
+            Some(broken_at) => Err(format!(
+                "chain broken at entry {}: {}",
+                broken_at.sequence, broken_at.reason
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn compact_journal(
+        &mut self,
+        path: &Path,
+        before: DateTime<Utc>,
+    ) -> Result<(), String> {
+        let _ = &self.validate_session().await?;
+        self.require_admin()?;
+
+        let result = async {
+            let key = journal::journal_key().ok_or(JournalError::MissingKey.to_string())?;
+            let compaction =
+                journal::compact(path, &key, before).map_err(|error| error.to_string())?;
+            println!(
+                "{} entries compacted to {}",
+                compaction.before, compaction.after
+            );
+            Ok(())
+        }
+        .await;
+        self.audit("journal.compact", path.to_str(), &result).await;
+        result
+    }
+
+    /// Replays the journaled changes made after `since`, if given, up to and
+    /// including `until`.
+    pub async fn replay_journal(
+        &mut self,
+        path: &Path,
+        since: Option<DateTime<Utc>>,
+        until: DateTime<Utc>,
+        dry_run: bool,
+    ) -> Result<(), String> {
+        let _ = &self.validate_session().await?;
+        self.require_admin()?;
+        let Some(journal_repo) = &self.journal_repo else {
+            return Err("Session invalid. Please login.".to_owned());
+        };
+
+        let result = async {
+            let key = journal::journal_key().ok_or(JournalError::MissingKey.to_string())?;
+            let reading = journal::read(path, &key).map_err(|error| error.to_string())?;
+            if let Some(broken_at) = reading.broken_at {
+                return Err(format!(
+                    "chain broken at entry {}: {}",
+                    broken_at.sequence, broken_at.reason
+                ));
+            }
+            let changes: Vec<_> = reading
+                .changes
+                .into_iter()
+                .filter(|change| since.is_none_or(|since| change.timestamp > since))
+                .filter(|change| change.timestamp <= until)
+                .collect();
+
+            let report = journal_repo
+                .replay(&changes, dry_run)
+                .await
+                .map_err(|error| error.to_string())?;
+            print_replay_report(&report);
+            Ok(())
+        }
+        .await;
+        if !dry_run {
+            self.audit("journal.replay", Some(&until.to_rfc3339()), &result)
+                .await;
+        }
+        result
+    }
+}
+
+fn print_replay_report(report: &ReplayReport) {
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("Collection"),
+        Cell::new("Upserted"),
+        Cell::new("Deleted"),
+    ]));
+    let collections: BTreeSet<&String> = report
+        .upserted
+        .keys()
+        .chain(report.deleted.keys())
+        .collect();
+    for collection in collections {
+        let count = |counts: &BTreeMap<String, usize>| {
+            counts
+                .get(collection)
+                .copied()
+                .unwrap_or_default()
+                .to_string()
+        };
+        table.add_row(Row::new(vec![
+            Cell::new(collection),
+            Cell::new(&count(&report.upserted)),
+            Cell::new(&count(&report.deleted)),
+        ]));
+    }
+    table.printstd();
+    println!(
+        "{} changes replayed, {} skipped.",
+        report.changes - report.skipped,
+        report.skipped
+    );
+    if report.dry_run {
+        println!("Dry run; nothing was written.");
+    }
+}
+
+fn print_import_report(report: &ImportReport) {
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("Key"),
+        Cell::new("Action"),
+        Cell::new("Version"),
+    ]));
+    for change in &report.changes {
+        table.add_row(Row::new(vec![
+            Cell::new(&change.key),
+            Cell::new(&change.action.to_string()),
+            Cell::new(&change.version.to_string()),
+        ]));
+    }
+    table.printstd();
+    println!(
+        "{} created, {} overwritten, {} new versions, {} unchanged, {} skipped ({} on conflict).",
+        report.count(ImportAction::Create),
+        report.count(ImportAction::Overwrite),
+        report.count(ImportAction::NewVersion),
+        report.count(ImportAction::Unchanged),
+        report.count(ImportAction::Skip),
+        report.conflict
+    );
+    if report.dry_run {
+        println!("Dry run; nothing was written.");
+    }
 }
@@ -1,8 +1,48 @@
-use crate::repositories::{keys::KeyRepository, users::UserRepository, vault::VaultRepository};
+use crate::repositories::{
+    access_requests::AccessRequestRepository, account_tokens::AccountTokenRepository,
+    app_roles::AppRoleRepository, audit::AuditRepository, break_glass::BreakGlassRepository,
+    journal::JournalRepository, keys::KeyRepository, login_attempts::LoginAttemptRepository,
+    offboarding::OffboardingRepository, oidc::OidcRepository,
+    refresh_tokens::RefreshTokenRepository, revocations::RevocationRepository,
+    seal::SealRepository, snapshots::SnapshotRepository, totp::TotpRepository,
+    users::UserRepository, vault::VaultRepository, webauthn::WebAuthnRepository,
+};
+use crate::utils::auth::authentication_key;
+use crate::utils::seal::Keyring;
 use dotenvy::dotenv;
+use log::warn;
 use mongodb::{Client, options::ClientOptions};
+use std::sync::Arc;
 
-pub async fn connect() -> mongodb::error::Result<(UserRepository, VaultRepository, KeyRepository)> {
+/*---------------------------------------------------------------------------
+    Every repository backed by the Locksmith database, constructed from a
+    single client connection.
+---------------------------------------------------------------------------*/
+pub struct Repositories {
+    pub users: UserRepository,
+    pub vault: VaultRepository,
+    pub keys: KeyRepository,
+    pub app_roles: AppRoleRepository,
+    pub refresh_tokens: RefreshTokenRepository,
+    pub revocations: RevocationRepository,
+    pub totp: TotpRepository,
+    pub webauthn: WebAuthnRepository,
+    pub oidc: OidcRepository,
+    pub login_attempts: LoginAttemptRepository,
+    pub account_tokens: AccountTokenRepository,
+    pub offboarding: OffboardingRepository,
+    pub audit: AuditRepository,
+    pub access_requests: AccessRequestRepository,
+    pub break_glass: BreakGlassRepository,
+    pub seal: SealRepository,
+    pub snapshots: SnapshotRepository,
+    pub journal: JournalRepository,
+    /// Holds the master key; sealed until unsealed when sealing is
+    /// initialized, otherwise loaded from `ECS_ENCRYPTION_KEY`.
+    pub keyring: Arc<Keyring>,
+}
+
+pub async fn connect() -> mongodb::error::Result<Repositories> {
     dotenv().ok();
 
     let database_url = std::env::var_os("ECS_DATABASE_URL")
@@ -18,11 +58,108 @@ pub async fn connect() -> mongodb::error::Result<(UserRepository, VaultRepositor
     let client_options = ClientOptions::parse(database_url).await?;
     let client = Client::with_options(client_options)?;
 
-    let user_repo = UserRepository::new(&client, &database_name, "users");
+    let seal_repo = SealRepository::new(&client, &database_name, "seal");
+    let environment_key = std::env::var("ECS_ENCRYPTION_KEY").ok();
+    let keyring = Arc::new(match (seal_repo.get().await?, environment_key) {
+        (Some(_), Some(_)) => {
+            warn!("Sealing is initialized; ECS_ENCRYPTION_KEY is ignored and should be removed");
+            Keyring::sealed()
+        }
+        (Some(_), None) | (None, None) => Keyring::sealed(),
+        (None, Some(key)) => Keyring::unsealed(key.into_bytes()),
+    });
+
+    let user_repo = UserRepository::new(&client, &database_name, "users", "bootstrap");
 
-    let vault_repo = VaultRepository::new(&client, &database_name, "vault");
+    let vault_repo = VaultRepository::new(&client, &database_name, "vault", Arc::clone(&keyring));
+    vault_repo.assign_owners(&user_repo).await?;
 
     let keys_repo = KeyRepository::new(&client, &database_name, "keys");
 
-    Ok((user_repo, vault_repo, keys_repo))
+    let app_role_repo = AppRoleRepository::new(&client, &database_name, "app_roles", "secret_ids");
+    app_role_repo.create_indexes().await?;
+
+    let refresh_token_repo = RefreshTokenRepository::new(&client, &database_name, "refresh_tokens");
+    refresh_token_repo.create_indexes().await?;
+
+    let revocation_repo = RevocationRepository::new(&client, &database_name, "revocations");
+    revocation_repo.create_indexes().await?;
+
+    let totp_repo = TotpRepository::new(
+        &client,
+        &database_name,
+        "totp",
+        "mfa_challenges",
+        Arc::clone(&keyring),
+    );
+    totp_repo.create_indexes().await?;
+
+    let webauthn_repo = WebAuthnRepository::new(&client, &database_name, "webauthn_challenges");
+    webauthn_repo.create_indexes().await?;
+
+    let oidc_repo = OidcRepository::new(&client, &database_name, "oidc_logins");
+    oidc_repo.create_indexes().await?;
+
+    let login_attempt_repo = LoginAttemptRepository::new(&client, &database_name, "login_attempts");
+    login_attempt_repo.create_indexes().await?;
+
+    let account_token_repo = AccountTokenRepository::new(&client, &database_name, "account_tokens");
+    account_token_repo.create_indexes().await?;
+
+    let offboarding_repo = OffboardingRepository::new(&client, &database_name, "users", "vault");
+
+    let audit_repo = AuditRepository::new(
+        &client,
+        &database_name,
+        "audit_log",
+        authentication_key().as_bytes(),
+    );
+    audit_repo.create_indexes().await?;
+
+    let access_request_repo =
+        AccessRequestRepository::new(&client, &database_name, "access_requests");
+    access_request_repo.create_indexes().await?;
+
+    let break_glass_repo =
+        BreakGlassRepository::new(&client, &database_name, "break_glass_sessions");
+    break_glass_repo.create_indexes().await?;
+
+    let snapshot_repo = SnapshotRepository::new(
+        &client,
+        &database_name,
+        "users",
+        "vault",
+        "totp",
+        "keys",
+        "app_roles",
+        Arc::clone(&keyring),
+    );
+
+    let journal_repo = JournalRepository::new(
+        &client,
+        &database_name,
+        &["users", "vault", "totp", "keys", "app_roles"],
+    );
+
+    Ok(Repositories {
+        users: user_repo,
+        vault: vault_repo,
+        keys: keys_repo,
+        app_roles: app_role_repo,
+        refresh_tokens: refresh_token_repo,
+        revocations: revocation_repo,
+        totp: totp_repo,
+        webauthn: webauthn_repo,
+        oidc: oidc_repo,
+        login_attempts: login_attempt_repo,
+        account_tokens: account_token_repo,
+        offboarding: offboarding_repo,
+        audit: audit_repo,
+        access_requests: access_request_repo,
+        break_glass: break_glass_repo,
+        seal: seal_repo,
+        snapshots: snapshot_repo,
+        journal: journal_repo,
+        keyring,
+    })
 }
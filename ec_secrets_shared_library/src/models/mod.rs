@@ -2,6 +2,9 @@ use chrono::{DateTime, Utc};
 use mongodb::bson::oid::ObjectId;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::utils::secret::SecretValue;
 
 /*------------
  Encryption Keys models
@@ -20,33 +23,123 @@ pub struct KeyPairDocument {
 /*------------
  User models
 -------------*/
+pub const ADMIN_ROLE: &str = "admin";
+/// May approve access to protected secrets.
+pub const APPROVER_ROLE: &str = "approver";
+/// May open break-glass sessions in an emergency.
+pub const BREAK_GLASS_ROLE: &str = "breakglass";
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct UserDocument {
     #[serde(rename = "_id")]
     pub id: ObjectId,
     pub email: String,
     pub password: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Passkeys registered for console login.
+    #[serde(default)]
+    pub webauthn_credentials: Vec<WebAuthnCredential>,
+    /// Previous password hashes, most recent first, checked against reuse.
+    #[serde(default)]
+    pub password_history: Vec<String>,
+    /// Set once the user followed a verification (or password reset) link.
+    #[serde(default)]
+    pub email_verified: bool,
+    #[serde(default)]
+    pub status: UserStatus,
     #[serde(
         with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
         rename = "createdAt"
     )]
     pub created_at: DateTime<Utc>,
+    #[serde(
+        default,
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional",
+        rename = "lastLogin"
+    )]
+    pub last_login: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+    #[default]
+    Active,
+    Disabled,
+}
+
+impl std::fmt::Display for UserStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserStatus::Active => f.write_str("active"),
+            UserStatus::Disabled => f.write_str("disabled"),
+        }
+    }
+}
+
+/// A user as shown outside the database: never carries password hashes,
+/// passkey material or anything else only the server needs.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq)]
 pub struct User {
     #[serde(rename = "_id")]
     pub id: String,
     pub email: String,
-    pub password: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    pub email_verified: bool,
+    pub status: UserStatus,
     #[serde(rename = "createdAt")]
     pub created_at: String,
+    #[serde(rename = "lastLogin")]
+    pub last_login: Option<String>,
+}
+
+impl From<&UserDocument> for User {
+    fn from(user: &UserDocument) -> Self {
+        Self {
+            id: user.id.to_hex(),
+            email: user.email.clone(),
+            roles: user.roles.clone(),
+            email_verified: user.email_verified,
+            status: user.status,
+            created_at: user.created_at.to_rfc3339(),
+            last_login: user.last_login.map(|last_login| last_login.to_rfc3339()),
+        }
+    }
+}
+
+impl From<UserDocument> for User {
+    fn from(user: UserDocument) -> Self {
+        Self::from(&user)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct UserCredentials {
     pub email: String,
-    pub password: String,
+    pub password: SecretValue,
+}
+
+/// Where an offboarded user's secrets go: to another user, or into the
+/// archive when `reassign_to` is left out.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct OffboardRequest {
+    #[serde(default)]
+    pub reassign_to: Option<String>,
+}
+
+/// The user an archived secret is handed to when restored.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct RestoreArchivedRequest {
+    pub owner_id: String,
+}
+
+/// What happened to an offboarded user's secrets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Offboarded {
+    Reassigned { to: String, secrets: u64 },
+    Archived { secrets: u64 },
 }
 
 /*------------
@@ -57,13 +150,47 @@ pub struct VaultDocument {
     #[serde(rename = "_id")]
     pub id: ObjectId,
     pub key: String,
-    pub value: String,
+    pub value: SecretValue,
+    /// Email of whoever wrote the entry; informational only.
     pub created_by: String,
+    /// Id of the owning user, or the subject of a machine identity.
+    /// Stays put when the user changes their email address.
+    #[serde(default)]
+    pub owner_id: String,
     #[serde(
         with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
         rename = "createdAt"
     )]
     pub created_at: DateTime<Utc>,
+    /// Set when the owner was offboarded without a successor; archived
+    /// entries are kept but no longer reachable through the API.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional",
+        rename = "archivedAt"
+    )]
+    pub archived_at: Option<DateTime<Utc>>,
+    /// Set on protected entries, whose value is only released through an
+    /// access request (see `AccessRequestDocument`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protection: Option<SecretProtection>,
+    /// Counts up when an import adds a new version of a key; older
+    /// versions stay entries of their own.
+    #[serde(default = "first_version")]
+    pub version: u32,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+/// Reading a protected secret takes a justification and, when
+/// `require_approval` is set, the approval of a second user.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SecretProtection {
+    #[serde(default)]
+    pub require_approval: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
@@ -71,7 +198,7 @@ pub struct Vault {
     #[serde(rename = "_id")]
     pub id: String,
     pub key: String,
-    pub value: String,
+    pub value: SecretValue,
     pub created_by: String,
     #[serde(rename = "createdAt")]
     pub created_at: String,
@@ -80,5 +207,1342 @@ pub struct Vault {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Secret {
     pub key: String,
-    pub value: String,
+    pub value: SecretValue,
+    #[serde(default)]
+    pub protection: Option<SecretProtection>,
+}
+
+/// Body of `PUT /vault/entries/<id>/protection`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy)]
+pub struct ProtectionUpdate {
+    pub protected: bool,
+    #[serde(default)]
+    pub require_approval: bool,
+}
+
+impl ProtectionUpdate {
+    pub fn protection(&self) -> Option<SecretProtection> {
+        self.protected.then_some(SecretProtection {
+            require_approval: self.require_approval,
+        })
+    }
+}
+
+/// Whether moving from `current` to `requested` drops any safeguard:
+/// removing protection, or the approval step.
+pub fn loosens_protection(
+    current: Option<SecretProtection>,
+    requested: Option<SecretProtection>,
+) -> bool {
+    match (current, requested) {
+        (Some(_), None) => true,
+        (Some(current), Some(requested)) => current.require_approval && !requested.require_approval,
+        (None, _) => false,
+    }
+}
+
+/*------------
+ Access request models
+-------------*/
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessRequestStatus {
+    Pending,
+    Approved,
+    Denied,
+    /// Never stored: a pending request nobody decided on in time, or an
+    /// approval whose access window has closed.
+    Expired,
+}
+
+impl std::fmt::Display for AccessRequestStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccessRequestStatus::Pending => f.write_str("pending"),
+            AccessRequestStatus::Approved => f.write_str("approved"),
+            AccessRequestStatus::Denied => f.write_str("denied"),
+            AccessRequestStatus::Expired => f.write_str("expired"),
+        }
+    }
+}
+
+impl std::str::FromStr for AccessRequestStatus {
+    type Err = String;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "pending" => Ok(AccessRequestStatus::Pending),
+            "approved" => Ok(AccessRequestStatus::Approved),
+            "denied" => Ok(AccessRequestStatus::Denied),
+            "expired" => Ok(AccessRequestStatus::Expired),
+            other => Err(format!("unknown status '{}'", other)),
+        }
+    }
+}
+
+/// A request to read a protected secret. Requests that need no approval
+/// are approved when they are made; `decided_by` stays empty for them.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccessRequestDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub secret_id: String,
+    pub secret_key: String,
+    pub owner_id: String,
+    /// Token subject of whoever asked.
+    pub requester: String,
+    pub justification: String,
+    pub status: AccessRequestStatus,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+    /// When a pending request lapses.
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "expiresAt"
+    )]
+    pub expires_at: DateTime<Utc>,
+    #[serde(default)]
+    pub decided_by: Option<String>,
+    #[serde(
+        default,
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional",
+        rename = "decidedAt"
+    )]
+    pub decided_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub comment: Option<String>,
+    /// End of the window in which an approved request releases the value.
+    #[serde(
+        default,
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional",
+        rename = "accessUntil"
+    )]
+    pub access_until: Option<DateTime<Utc>>,
+}
+
+impl AccessRequestDocument {
+    /// The stored status, with lapsed requests and closed windows
+    /// reported as `expired`.
+    pub fn status_at(&self, now: DateTime<Utc>) -> AccessRequestStatus {
+        match self.status {
+            AccessRequestStatus::Pending if now >= self.expires_at => AccessRequestStatus::Expired,
+            AccessRequestStatus::Approved if self.access_until.is_none_or(|until| now >= until) => {
+                AccessRequestStatus::Expired
+            }
+            status => status,
+        }
+    }
+
+    pub fn grants_access(&self, now: DateTime<Utc>) -> bool {
+        self.status_at(now) == AccessRequestStatus::Approved
+    }
+}
+
+/// The public form of an access request.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AccessRequest {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub secret_id: String,
+    pub secret_key: String,
+    pub requester: String,
+    pub justification: String,
+    pub status: AccessRequestStatus,
+    pub created_at: String,
+    pub expires_at: String,
+    pub decided_by: Option<String>,
+    pub decided_at: Option<String>,
+    pub comment: Option<String>,
+    pub access_until: Option<String>,
+}
+
+impl From<AccessRequestDocument> for AccessRequest {
+    fn from(request: AccessRequestDocument) -> Self {
+        Self {
+            id: request.id.to_hex(),
+            status: request.status_at(Utc::now()),
+            secret_id: request.secret_id,
+            secret_key: request.secret_key,
+            requester: request.requester,
+            justification: request.justification,
+            created_at: request.created_at.to_rfc3339(),
+            expires_at: request.expires_at.to_rfc3339(),
+            decided_by: request.decided_by,
+            decided_at: request.decided_at.map(|at| at.to_rfc3339()),
+            comment: request.comment,
+            access_until: request.access_until.map(|until| until.to_rfc3339()),
+        }
+    }
+}
+
+/// Body of `POST /vault/entries/<id>/access`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AccessRequestCreate {
+    pub justification: String,
+}
+
+/// Body of the approve and deny endpoints.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct AccessDecision {
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+/// A protected secret released through an approved access request.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ReleasedSecret {
+    pub id: String,
+    pub key: String,
+    pub value: SecretValue,
+    pub access_until: String,
+}
+
+/*------------
+ AppRole models
+-------------*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AppRoleDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub role_name: String,
+    pub role_id: String,
+    pub policies: Vec<String>,
+    pub secret_id_ttl: i64,
+    pub secret_id_num_uses: i64,
+    pub token_ttl: i64,
+    pub created_by: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SecretIdDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub role_id: String,
+    pub secret_id_hash: String,
+    pub cidr_list: Vec<String>,
+    /// Remaining logins for this secret id; `None` means unlimited.
+    pub uses_remaining: Option<i64>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "expiresAt"
+    )]
+    pub expires_at: DateTime<Utc>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AppRole {
+    pub role_name: String,
+    pub policies: Vec<String>,
+    /// Lifetime of generated secret ids, in seconds.
+    pub secret_id_ttl: Option<i64>,
+    /// Number of logins a secret id allows.
+    pub secret_id_num_uses: Option<i64>,
+    /// Lifetime of tokens issued through this role, in seconds.
+    pub token_ttl: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct SecretIdRequest {
+    #[serde(default)]
+    pub cidr_list: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AppRoleCredentials {
+    pub role_id: String,
+    pub secret_id: SecretValue,
+}
+
+/*------------
+ Refresh token models
+-------------*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RefreshTokenDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// Every token produced by rotating the same login shares a family id.
+    pub family_id: String,
+    pub subject: String,
+    pub token_hash: String,
+    pub used: bool,
+    pub revoked: bool,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "expiresAt"
+    )]
+    pub expires_at: DateTime<Utc>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+/*------------
+ Token revocation models
+-------------*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RevocationDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// The revoked token id; `None` revokes every token of `subject`
+    /// issued before `revoked_at`.
+    pub jti: Option<String>,
+    pub subject: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "revokedAt"
+    )]
+    pub revoked_at: DateTime<Utc>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "expiresAt"
+    )]
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct LogoutRequest {
+    pub refresh_token: Option<String>,
+}
+
+/*------------
+ Login throttling models
+-------------*/
+/// Recent failed password logins of one account (`account:<email>`) or
+/// client address (`ip:<address>`).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoginAttemptDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub key: String,
+    pub failures: i64,
+    /// No password is checked for this key before this time.
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "blockedUntil"
+    )]
+    pub blocked_until: DateTime<Utc>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "expiresAt"
+    )]
+    pub expires_at: DateTime<Utc>,
+}
+
+/*------------
+ Account recovery models
+-------------*/
+/// A single-use email verification or password reset token, stored as a
+/// SHA-256 digest.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccountTokenDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub token_hash: String,
+    pub purpose: String,
+    pub email: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "expiresAt"
+    )]
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PasswordForgotRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PasswordResetRequest {
+    pub token: String,
+    pub password: SecretValue,
+}
+
+/*------------
+ Two-factor authentication models
+-------------*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TotpEnrollmentDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub subject: String,
+    /// The base32 TOTP secret, encrypted with the vault encryption key.
+    pub secret: String,
+    /// Enrollments only protect logins once a first code has been confirmed.
+    pub confirmed: bool,
+    /// SHA-256 digests of the unused recovery codes.
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
+    /// The time step of the last accepted code, so codes cannot be replayed.
+    pub last_used_step: Option<i64>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MfaChallengeDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub subject: String,
+    pub challenge_hash: String,
+    pub attempts_remaining: i64,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "expiresAt"
+    )]
+    pub expires_at: DateTime<Utc>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct TotpCode {
+    /// A current TOTP code or an unused recovery code.
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct TotpLoginRequest {
+    pub challenge_token: String,
+    pub code: String,
+}
+
+/*------------
+ WebAuthn models
+-------------*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebAuthnCredential {
+    /// Base64url credential id chosen by the authenticator.
+    pub credential_id: String,
+    /// Base64url SEC1 encoded P-256 public key.
+    pub public_key: String,
+    pub sign_count: u32,
+    pub name: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "createdAt"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebAuthnChallengeDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub challenge: String,
+    /// `registration` or `authentication`.
+    pub ceremony: String,
+    /// The registering user, or the user named at login; `None` for
+    /// discoverable-credential logins.
+    pub subject: Option<String>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "expiresAt"
+    )]
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A `PublicKeyCredential` returned by `navigator.credentials.create()`,
+/// with binary fields base64url encoded.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct WebAuthnRegistration {
+    pub id: String,
+    pub client_data_json: String,
+    pub attestation_object: String,
+    /// A label to tell passkeys apart, e.g. "work laptop".
+    pub name: Option<String>,
+}
+
+/// A `PublicKeyCredential` returned by `navigator.credentials.get()`,
+/// with binary fields base64url encoded.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct WebAuthnAssertion {
+    pub id: String,
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct WebAuthnLoginRequest {
+    /// Restricts the ceremony to this user's passkeys; omit to let the
+    /// authenticator offer any discoverable passkey.
+    pub email: Option<String>,
+}
+
+/*------------
+ Single sign-on models
+-------------*/
+/// An OIDC login in progress, from the redirect to the provider until its
+/// callback. Only the digest of the `state` parameter is stored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OidcLoginDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub state_hash: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "expiresAt"
+    )]
+    pub expires_at: DateTime<Utc>,
+}
+
+/*------------
+ Break-glass models
+-------------*/
+
+/// An emergency session that lets `user` read every secret whose key
+/// matches `path`, whoever owns it, until `expires_at`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BreakGlassDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// Token subject of whoever broke the glass.
+    pub user: String,
+    pub reason: String,
+    pub path: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "startedAt"
+    )]
+    pub started_at: DateTime<Utc>,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "expiresAt"
+    )]
+    pub expires_at: DateTime<Utc>,
+    /// Set when the session was closed before it expired.
+    #[serde(
+        default,
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional",
+        rename = "endedAt"
+    )]
+    pub ended_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub ended_by: Option<String>,
+}
+
+impl BreakGlassDocument {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.ended_at.is_none() && now < self.expires_at
+    }
+
+    /// When the session stopped granting access, or will.
+    pub fn closes_at(&self) -> DateTime<Utc> {
+        self.ended_at
+            .map_or(self.expires_at, |ended_at| ended_at.min(self.expires_at))
+    }
+}
+
+/// The public form of a break-glass session.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct BreakGlassSession {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub user: String,
+    pub reason: String,
+    pub path: String,
+    pub active: bool,
+    pub started_at: String,
+    pub expires_at: String,
+    pub ended_at: Option<String>,
+    pub ended_by: Option<String>,
+}
+
+impl From<BreakGlassDocument> for BreakGlassSession {
+    fn from(session: BreakGlassDocument) -> Self {
+        Self {
+            id: session.id.to_hex(),
+            active: session.is_active(Utc::now()),
+            user: session.user,
+            reason: session.reason,
+            path: session.path,
+            started_at: session.started_at.to_rfc3339(),
+            expires_at: session.expires_at.to_rfc3339(),
+            ended_at: session.ended_at.map(|at| at.to_rfc3339()),
+            ended_by: session.ended_by,
+        }
+    }
+}
+
+/// Body of `POST /break-glass`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct BreakGlassRequest {
+    pub reason: String,
+    pub path: String,
+    /// Seconds; defaults to, and is capped by, the configured durations.
+    #[serde(default)]
+    pub duration: Option<i64>,
+}
+
+/// A secret reachable through a break-glass session; values are only
+/// returned one at a time, each read being audited.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct BreakGlassEntry {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub key: String,
+    pub owner_id: String,
+    pub protected: bool,
+}
+
+/// What happened during a break-glass session, for the post-incident
+/// review: every audit entry of the user within the window, and the
+/// secrets they read.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct BreakGlassReport {
+    pub session: BreakGlassSession,
+    pub secrets_read: Vec<String>,
+    pub events: Vec<AuditEntry>,
+}
+
+impl BreakGlassReport {
+    /// Builds the report from audit entries found for the session, in any
+    /// order and possibly repeated; `secrets_read` lists the ids of the
+    /// secrets revealed, first read first.
+    pub fn compile(session: BreakGlassSession, mut events: Vec<AuditEntry>) -> Self {
+        events.sort_by_key(|event| event.sequence);
+        events.dedup_by_key(|event| event.sequence);
+
+        let mut secrets_read: Vec<String> = Vec::new();
+        for event in &events {
+            let revealed = matches!(event.action.as_str(), "breakglass.reveal" | "secret.reveal")
+                && event.outcome == AuditOutcome::Success;
+            if let Some(target) = event.target.as_ref().filter(|_| revealed)
+                && !secrets_read.contains(target)
+            {
+                secrets_read.push(target.clone());
+            }
+        }
+
+        Self {
+            session,
+            secrets_read,
+            events,
+        }
+    }
+}
+
+/*------------
+ Seal models
+-------------*/
+
+/// How the master key was split at initialization. There is at most one,
+/// stored under the id `seal`; the key itself and its shares are never
+/// stored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SealDocument {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub shares: u8,
+    pub threshold: u8,
+    /// A known value encrypted with the master key, to tell a rebuilt key
+    /// from a wrong one.
+    pub verifier: String,
+    #[serde(
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime",
+        rename = "initializedAt"
+    )]
+    pub initialized_at: DateTime<Utc>,
+    /// The master key wrapped by an external key manager, for unsealing
+    /// without shares on boot.
+    #[serde(
+        default,
+        rename = "wrappedKey",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub wrapped_key: Option<WrappedKey>,
+}
+
+/// The master key as wrapped by a key manager (see `utils::kms`).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct WrappedKey {
+    /// Name of the key manager that wrapped it, e.g. `pkcs11`.
+    pub manager: String,
+    /// The wrapped key, in base64.
+    pub ciphertext: String,
+}
+
+/// Body of `POST /sys/init`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy)]
+pub struct SealInitRequest {
+    pub shares: u8,
+    pub threshold: u8,
+}
+
+/// The unseal shares, handed out once at initialization.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct SealInitResponse {
+    pub shares: Vec<SecretValue>,
+    pub threshold: u8,
+}
+
+/// Body of `POST /sys/unseal`; `reset` discards the shares submitted so
+/// far instead.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct UnsealRequest {
+    #[serde(default)]
+    pub share: Option<SecretValue>,
+    #[serde(default)]
+    pub reset: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct SealStatus {
+    pub initialized: bool,
+    pub sealed: bool,
+    pub shares: Option<u8>,
+    pub threshold: Option<u8>,
+    /// Shares submitted towards the next unseal.
+    pub progress: usize,
+    /// The key manager able to unseal on boot, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_unseal: Option<String>,
+}
+
+/// Body of the transit encryption routes; values are in base64.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct TransitRequest {
+    pub data: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct TransitResponse {
+    pub data: String,
+}
+
+/*------------
+ Snapshot models
+-------------*/
+
+/// Describes a snapshot; stored in the clear inside its encrypted archive
+/// (see `utils::snapshot`).
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct SnapshotManifest {
+    /// Version of the archive layout.
+    pub format: u32,
+    pub created_at: String,
+    pub created_by: String,
+    pub counts: SnapshotCounts,
+    /// SHA-256 digest of every other file in the archive, by file name.
+    pub checksums: BTreeMap<String, String>,
+}
+
+/// Documents per collection.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SnapshotCounts {
+    pub users: usize,
+    pub secrets: usize,
+    pub totp: usize,
+    pub keys: usize,
+    pub app_roles: usize,
+}
+
+/// Body of `POST /sys/snapshot`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct SnapshotRequest {
+    pub passphrase: SecretValue,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct SnapshotResponse {
+    pub manifest: SnapshotManifest,
+    /// The encrypted archive, in base64.
+    pub snapshot: String,
+}
+
+/// `merge` only adds what the deployment lacks; `replace` wipes the
+/// snapshotted collections first.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreMode {
+    #[default]
+    Merge,
+    Replace,
+}
+
+impl std::fmt::Display for RestoreMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestoreMode::Merge => f.write_str("merge"),
+            RestoreMode::Replace => f.write_str("replace"),
+        }
+    }
+}
+
+/// Body of `POST /sys/restore`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct RestoreRequest {
+    /// The encrypted archive, in base64.
+    pub snapshot: String,
+    pub passphrase: SecretValue,
+    #[serde(default)]
+    pub mode: RestoreMode,
+    /// Validate and report without writing anything.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// What a restore changed, or would change on a dry run.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct RestoreReport {
+    pub manifest: SnapshotManifest,
+    pub mode: RestoreMode,
+    pub dry_run: bool,
+    pub restored: SnapshotCounts,
+    /// Already present, in `merge` mode.
+    pub skipped: SnapshotCounts,
+    /// Removed beforehand, in `replace` mode.
+    pub removed: SnapshotCounts,
+}
+
+/*------------
+ Journal models
+-------------*/
+
+/// What replaying the change journal wrote, or would write on a dry run,
+/// by collection (see `utils::journal`).
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub dry_run: bool,
+    /// Changes in the replayed window.
+    pub changes: usize,
+    pub upserted: BTreeMap<String, usize>,
+    pub deleted: BTreeMap<String, usize>,
+    /// Changes to collections that are not journaled.
+    pub skipped: usize,
+}
+
+/*------------
+ Import models
+-------------*/
+
+/// Layout of an import (see `utils::import`).
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// `KEY=value` lines.
+    Dotenv,
+    /// An object, nested objects flattened to `parent/child` keys.
+    Json,
+    /// As `json`.
+    Yaml,
+    /// HashiCorp Vault KV output: `vault kv get -format=json`, or an object
+    /// of such responses (or plain key/value maps) by secret path.
+    Vault,
+}
+
+impl std::fmt::Display for ImportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportFormat::Dotenv => f.write_str("dotenv"),
+            ImportFormat::Json => f.write_str("json"),
+            ImportFormat::Yaml => f.write_str("yaml"),
+            ImportFormat::Vault => f.write_str("vault"),
+        }
+    }
+}
+
+/// What an import does with a key the importer already holds.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictStrategy {
+    /// Keep the stored value.
+    #[default]
+    Skip,
+    /// Replace the value of the latest version in place.
+    Overwrite,
+    /// Add the value as a new version, keeping the others.
+    NewVersion,
+}
+
+impl std::fmt::Display for ConflictStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConflictStrategy::Skip => f.write_str("skip"),
+            ConflictStrategy::Overwrite => f.write_str("overwrite"),
+            ConflictStrategy::NewVersion => f.write_str("new_version"),
+        }
+    }
+}
+
+/// Body of `POST /vault/import`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ImportRequest {
+    pub format: ImportFormat,
+    /// The file to import, as text.
+    pub content: SecretValue,
+    /// Prepended to every key as `prefix/`.
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub conflict: ConflictStrategy,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportAction {
+    Create,
+    Overwrite,
+    NewVersion,
+    /// Held already with the same value.
+    Unchanged,
+    /// Held already with another value and the strategy is `skip`, or
+    /// held already and protected.
+    Skip,
+}
+
+impl std::fmt::Display for ImportAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportAction::Create => f.write_str("create"),
+            ImportAction::Overwrite => f.write_str("overwrite"),
+            ImportAction::NewVersion => f.write_str("new version"),
+            ImportAction::Unchanged => f.write_str("unchanged"),
+            ImportAction::Skip => f.write_str("skip"),
+        }
+    }
+}
+
+/// One line of the import diff; values are never included.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct ImportChange {
+    pub key: String,
+    pub action: ImportAction,
+    /// Version holding the imported value afterwards; the stored one for
+    /// skipped keys.
+    pub version: u32,
+}
+
+/// What an import wrote, or would write on a dry run.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub conflict: ConflictStrategy,
+    pub changes: Vec<ImportChange>,
+}
+
+impl ImportReport {
+    pub fn count(&self, action: ImportAction) -> usize {
+        self.changes
+            .iter()
+            .filter(|change| change.action == action)
+            .count()
+    }
+}
+
+/*------------
+ Audit models
+-------------*/
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+    Denied,
+}
+
+impl std::fmt::Display for AuditOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditOutcome::Success => f.write_str("success"),
+            AuditOutcome::Failure => f.write_str("failure"),
+            AuditOutcome::Denied => f.write_str("denied"),
+        }
+    }
+}
+
+impl std::str::FromStr for AuditOutcome {
+    type Err = String;
+
+    fn from_str(outcome: &str) -> Result<Self, Self::Err> {
+        match outcome {
+            "success" => Ok(AuditOutcome::Success),
+            "failure" => Ok(AuditOutcome::Failure),
+            "denied" => Ok(AuditOutcome::Denied),
+            other => Err(format!("unknown outcome '{}'", other)),
+        }
+    }
+}
+
+/// Something that happened, as recorded in the audit log. `action` is a
+/// dotted name such as `secret.reveal` or `user.disable`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    pub actor: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub outcome: AuditOutcome,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub detail: Option<String>,
+}
+
+/// Actions that always count as critical, whatever their outcome.
+pub const CRITICAL_AUDIT_PREFIX: &str = "breakglass.";
+
+impl AuditEvent {
+    /// Derived from the action and outcome rather than stored, so it is
+    /// covered by the chain hash without being part of it.
+    pub fn severity(&self) -> AuditSeverity {
+        if self.action.starts_with(CRITICAL_AUDIT_PREFIX) {
+            return AuditSeverity::Critical;
+        }
+        match self.outcome {
+            AuditOutcome::Success => AuditSeverity::Info,
+            AuditOutcome::Denied => AuditSeverity::Warning,
+            AuditOutcome::Failure => AuditSeverity::Error,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditSeverity {
+    #[default]
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+impl std::fmt::Display for AuditSeverity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditSeverity::Info => f.write_str("info"),
+            AuditSeverity::Warning => f.write_str("warning"),
+            AuditSeverity::Error => f.write_str("error"),
+            AuditSeverity::Critical => f.write_str("critical"),
+        }
+    }
+}
+
+/// A stored audit event, chained to its predecessor by `prev_hash`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEntryDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub sequence: i64,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+/// The public form of an audit entry.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AuditEntry {
+    pub sequence: i64,
+    pub timestamp: String,
+    pub actor: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub outcome: AuditOutcome,
+    #[serde(default)]
+    pub severity: AuditSeverity,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub detail: Option<String>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl From<AuditEntryDocument> for AuditEntry {
+    fn from(entry: AuditEntryDocument) -> Self {
+        Self {
+            sequence: entry.sequence,
+            timestamp: entry.timestamp.to_rfc3339(),
+            severity: entry.event.severity(),
+            actor: entry.event.actor,
+            action: entry.event.action,
+            target: entry.event.target,
+            outcome: entry.event.outcome,
+            source_ip: entry.event.source_ip,
+            user_agent: entry.event.user_agent,
+            detail: entry.event.detail,
+            prev_hash: entry.prev_hash,
+            hash: entry.hash,
+        }
+    }
+}
+
+/// Filters for reading the audit log; unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub outcome: Option<AuditOutcome>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: i64,
+}
+
+/// Where verification of the audit chain stopped.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct ChainBreak {
+    pub sequence: i64,
+    pub reason: String,
+}
+
+/// The result of walking the audit chain. `head_hash` is the hash of the
+/// last entry; record it elsewhere to also detect removal of the newest
+/// entries.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct ChainVerification {
+    pub valid: bool,
+    pub entries: u64,
+    pub head_sequence: i64,
+    pub head_hash: String,
+    pub broken_at: Option<ChainBreak>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$aGFzaGhhc2g";
+    const OLD_HASH: &str = "$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW";
+
+    fn document() -> UserDocument {
+        UserDocument {
+            id: ObjectId::new(),
+            email: "jane@example.com".to_string(),
+            password: HASH.to_string(),
+            roles: vec![ADMIN_ROLE.to_string()],
+            webauthn_credentials: vec![WebAuthnCredential {
+                credential_id: "credential".to_string(),
+                public_key: "public-key".to_string(),
+                sign_count: 1,
+                name: "laptop".to_string(),
+                created_at: Utc::now(),
+            }],
+            password_history: vec![OLD_HASH.to_string()],
+            email_verified: true,
+            status: UserStatus::Active,
+            created_at: Utc::now(),
+            last_login: Some(Utc::now()),
+        }
+    }
+
+    #[test]
+    fn users_never_serialize_password_hashes() {
+        let document = document();
+        let json = serde_json::to_string(&User::from(&document)).unwrap();
+
+        for secret in [HASH, OLD_HASH, "password", "public-key", "webauthn"] {
+            assert!(!json.contains(secret), "{secret} leaked into {json}");
+        }
+        let users = serde_json::to_string(&vec![User::from(document)]).unwrap();
+        assert!(!users.contains(HASH));
+    }
+
+    #[test]
+    fn users_keep_the_public_fields() {
+        let document = document();
+        let user = User::from(&document);
+
+        assert_eq!(user.id, document.id.to_hex());
+        assert_eq!(user.email, "jane@example.com");
+        assert_eq!(user.roles, vec![ADMIN_ROLE.to_string()]);
+        assert_eq!(user.status, UserStatus::Active);
+        assert!(user.last_login.is_some());
+
+        let json = serde_json::to_value(&user).unwrap();
+        assert_eq!(json["status"], "active");
+        assert_eq!(json["_id"], document.id.to_hex());
+    }
+
+    #[test]
+    fn legacy_vault_entries_load_without_an_owner() {
+        let legacy = bson::doc! {
+            "_id": ObjectId::new(),
+            "key": "db_password",
+            "value": "c2VjcmV0",
+            "created_by": "jane@example.com",
+            "createdAt": bson::DateTime::now(),
+        };
+        let entry: VaultDocument = bson::from_document(legacy).unwrap();
+
+        assert!(entry.owner_id.is_empty());
+        assert!(entry.archived_at.is_none());
+        let stored = bson::to_document(&entry).unwrap();
+        assert!(!stored.contains_key("archivedAt"));
+        assert_eq!(stored.get_str("owner_id").unwrap(), "");
+        assert!(entry.protection.is_none());
+        assert!(!stored.contains_key("protection"));
+    }
+
+    #[test]
+    fn only_dropping_a_safeguard_loosens_protection() {
+        let protected = Some(SecretProtection::default());
+        let approval = Some(SecretProtection {
+            require_approval: true,
+        });
+
+        assert!(!loosens_protection(None, protected));
+        assert!(!loosens_protection(protected, approval));
+        assert!(!loosens_protection(approval, approval));
+        assert!(loosens_protection(protected, None));
+        assert!(loosens_protection(approval, protected));
+    }
+
+    #[test]
+    fn break_glass_is_always_critical() {
+        let mut event = AuditEvent {
+            actor: "oncall@example.com".to_string(),
+            action: "secret.reveal".to_string(),
+            target: None,
+            outcome: AuditOutcome::Success,
+            source_ip: None,
+            user_agent: None,
+            detail: None,
+        };
+        assert_eq!(event.severity(), AuditSeverity::Info);
+        event.outcome = AuditOutcome::Denied;
+        assert_eq!(event.severity(), AuditSeverity::Warning);
+        event.action = "breakglass.reveal".to_string();
+        assert_eq!(event.severity(), AuditSeverity::Critical);
+    }
+
+    #[test]
+    fn break_glass_sessions_close() {
+        let now = Utc::now();
+        let mut session = BreakGlassDocument {
+            id: ObjectId::new(),
+            user: "oncall@example.com".to_string(),
+            reason: "Primary database down, approvers unreachable".to_string(),
+            path: "prod/*".to_string(),
+            started_at: now,
+            expires_at: now + chrono::Duration::minutes(30),
+            ended_at: None,
+            ended_by: None,
+        };
+        assert!(session.is_active(now));
+        assert!(!session.is_active(now + chrono::Duration::minutes(30)));
+        assert_eq!(session.closes_at(), session.expires_at);
+
+        session.ended_at = Some(now + chrono::Duration::minutes(5));
+        assert!(!session.is_active(now + chrono::Duration::minutes(6)));
+        assert_eq!(session.closes_at(), now + chrono::Duration::minutes(5));
+    }
+
+    #[test]
+    fn break_glass_reports_list_secrets_read() {
+        let event = |sequence: i64, action: &str, target: &str, outcome: AuditOutcome| AuditEntry {
+            sequence,
+            timestamp: Utc::now().to_rfc3339(),
+            actor: "oncall@example.com".to_string(),
+            action: action.to_string(),
+            target: Some(target.to_string()),
+            outcome,
+            severity: AuditSeverity::Critical,
+            source_ip: None,
+            user_agent: None,
+            detail: None,
+            prev_hash: String::new(),
+            hash: String::new(),
+        };
+        let session = BreakGlassSession::from(BreakGlassDocument {
+            id: ObjectId::new(),
+            user: "oncall@example.com".to_string(),
+            reason: "Primary database down, approvers unreachable".to_string(),
+            path: "prod/*".to_string(),
+            started_at: Utc::now(),
+            expires_at: Utc::now() + chrono::Duration::minutes(30),
+            ended_at: None,
+            ended_by: None,
+        });
+
+        let report = BreakGlassReport::compile(
+            session,
+            vec![
+                event(7, "breakglass.end", "s", AuditOutcome::Success),
+                event(5, "breakglass.reveal", "b", AuditOutcome::Success),
+                event(4, "breakglass.reveal", "a", AuditOutcome::Success),
+                event(6, "breakglass.reveal", "c", AuditOutcome::Denied),
+                event(5, "breakglass.reveal", "b", AuditOutcome::Success),
+                event(3, "breakglass.reveal", "b", AuditOutcome::Success),
+            ],
+        );
+        assert_eq!(report.secrets_read, ["b", "a"]);
+        assert_eq!(
+            report
+                .events
+                .iter()
+                .map(|event| event.sequence)
+                .collect::<Vec<_>>(),
+            [3, 4, 5, 6, 7]
+        );
+    }
+
+    #[test]
+    fn access_requests_expire() {
+        let now = Utc::now();
+        let mut request = AccessRequestDocument {
+            id: ObjectId::new(),
+            secret_id: ObjectId::new().to_hex(),
+            secret_key: "prod/db_password".to_string(),
+            owner_id: ObjectId::new().to_hex(),
+            requester: "jane@example.com".to_string(),
+            justification: "Rotating the replica credentials".to_string(),
+            status: AccessRequestStatus::Pending,
+            created_at: now,
+            expires_at: now + chrono::Duration::hours(1),
+            decided_by: None,
+            decided_at: None,
+            comment: None,
+            access_until: None,
+        };
+        assert_eq!(request.status_at(now), AccessRequestStatus::Pending);
+        assert!(!request.grants_access(now));
+        assert_eq!(
+            request.status_at(now + chrono::Duration::hours(2)),
+            AccessRequestStatus::Expired
+        );
+
+        request.status = AccessRequestStatus::Approved;
+        request.access_until = Some(now + chrono::Duration::minutes(15));
+        assert!(request.grants_access(now));
+        assert!(!request.grants_access(now + chrono::Duration::minutes(15)));
+
+        request.status = AccessRequestStatus::Denied;
+        assert_eq!(
+            request.status_at(now + chrono::Duration::days(1)),
+            AccessRequestStatus::Denied
+        );
+    }
 }
@@ -0,0 +1,176 @@
+use chrono::{Duration, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    Collection, IndexModel,
+    bson::{self, Document, doc, oid::ObjectId},
+    error::Result,
+    options::ReturnDocument,
+};
+
+use crate::models::{AccessRequestDocument, AccessRequestStatus, VaultDocument};
+
+/*---------------------------------------------------------------------------
+    The AccessRequestRepository keeps requests to read protected secrets and
+    their decisions. Requests are never deleted, so they double as a record
+    of who asked for what and who let them.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct AccessRequestRepository {
+    collection: Collection<AccessRequestDocument>,
+}
+
+impl AccessRequestRepository {
+    pub fn new(client: &mongodb::Client, db_name: &str, collection_name: &str) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<AccessRequestDocument>(collection_name);
+        Self { collection }
+    }
+
+    pub async fn create_indexes(&self) -> Result<()> {
+        let requester_index = IndexModel::builder()
+            .keys(doc! { "requester": 1, "createdAt": -1 })
+            .build();
+        let status_index = IndexModel::builder()
+            .keys(doc! { "status": 1, "createdAt": -1 })
+            .build();
+        self.collection
+            .create_indexes([requester_index, status_index])
+            .await?;
+        Ok(())
+    }
+
+    /*----------------------------------------------------------------
+    CREATE a request. Without an approval step it is approved at once
+    and opens the access window right away.
+    -----------------------------------------------------------------*/
+    pub async fn create(
+        &self,
+        secret: &VaultDocument,
+        requester: &str,
+        justification: &str,
+        request_lifetime: Duration,
+        access_window: Duration,
+    ) -> Result<AccessRequestDocument> {
+        let now = Utc::now();
+        let require_approval = secret
+            .protection
+            .is_some_and(|protection| protection.require_approval);
+        let request = AccessRequestDocument {
+            id: ObjectId::new(),
+            secret_id: secret.id.to_hex(),
+            secret_key: secret.key.clone(),
+            owner_id: secret.owner_id.clone(),
+            requester: requester.to_string(),
+            justification: justification.to_string(),
+            status: if require_approval {
+                AccessRequestStatus::Pending
+            } else {
+                AccessRequestStatus::Approved
+            },
+            created_at: now,
+            expires_at: now + request_lifetime,
+            decided_by: None,
+            decided_at: (!require_approval).then_some(now),
+            comment: None,
+            access_until: (!require_approval).then_some(now + access_window),
+        };
+
+        self.collection.insert_one(&request).await?;
+        Ok(request)
+    }
+
+    /*---------------
+    GET a request
+    ----------------*/
+    pub async fn get(&self, id: &str) -> Result<Option<AccessRequestDocument>> {
+        let Ok(object_id) = ObjectId::parse_str(id) else {
+            return Ok(None);
+        };
+        self.collection.find_one(doc! { "_id": object_id }).await
+    }
+
+    /*------------------------------------------------------------
+    LIST requests, newest first, optionally of a single requester
+    -------------------------------------------------------------*/
+    pub async fn list(
+        &self,
+        requester: Option<&str>,
+        status: Option<AccessRequestStatus>,
+        limit: i64,
+    ) -> Result<Vec<AccessRequestDocument>> {
+        let now = bson::DateTime::from_chrono(Utc::now());
+        let mut filter = match status {
+            None => Document::new(),
+            Some(AccessRequestStatus::Pending) => {
+                doc! { "status": "pending", "expiresAt": { "$gt": now } }
+            }
+            Some(AccessRequestStatus::Approved) => {
+                doc! { "status": "approved", "accessUntil": { "$gt": now } }
+            }
+            Some(AccessRequestStatus::Denied) => doc! { "status": "denied" },
+            Some(AccessRequestStatus::Expired) => doc! { "$or": [
+                { "status": "pending", "expiresAt": { "$lte": now } },
+                { "status": "approved", "accessUntil": { "$lte": now } },
+            ] },
+        };
+        if let Some(requester) = requester {
+            filter.insert("requester", requester);
+        }
+
+        self.collection
+            .find(filter)
+            .sort(doc! { "createdAt": -1 })
+            .limit(limit)
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /*-----------------------------------------------------------------
+    DECIDE on a pending request. Nobody decides on their own requests
+    or secrets: `None` means the request is missing, already decided,
+    lapsed, or was made by, or for a secret of, the deciding user
+    (`decided_by`, whose owner id is `decider_id`).
+    ------------------------------------------------------------------*/
+    pub async fn decide(
+        &self,
+        id: &str,
+        decided_by: &str,
+        decider_id: &str,
+        approve: bool,
+        comment: Option<&str>,
+        access_window: Duration,
+    ) -> Result<Option<AccessRequestDocument>> {
+        let Ok(object_id) = ObjectId::parse_str(id) else {
+            return Ok(None);
+        };
+        let now = Utc::now();
+        let mut decision = doc! {
+            "status": if approve { "approved" } else { "denied" },
+            "decided_by": decided_by,
+            "decidedAt": bson::DateTime::from_chrono(now),
+            "comment": comment,
+        };
+        if approve {
+            decision.insert(
+                "accessUntil",
+                bson::DateTime::from_chrono(now + access_window),
+            );
+        }
+
+        self.collection
+            .find_one_and_update(
+                doc! {
+                    "_id": object_id,
+                    "status": "pending",
+                    "expiresAt": { "$gt": bson::DateTime::from_chrono(now) },
+                    "requester": { "$ne": decided_by },
+                    "owner_id": { "$ne": decider_id },
+                },
+                doc! { "$set": decision },
+            )
+            .return_document(ReturnDocument::After)
+            .await
+    }
+}
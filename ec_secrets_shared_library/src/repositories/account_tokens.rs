@@ -0,0 +1,103 @@
+use chrono::{Duration, Utc};
+use mongodb::{
+    Client, Collection, IndexModel,
+    bson::{doc, oid::ObjectId},
+    error::Result,
+    options::IndexOptions,
+};
+
+use crate::{
+    models::AccountTokenDocument,
+    utils::auth::{generate_identifier, hash_identifier},
+};
+
+pub const EMAIL_VERIFICATION: &str = "email_verification";
+pub const PASSWORD_RESET: &str = "password_reset";
+
+/*---------------------------------------------------------------------------
+    The AccountTokenRepository stores the tokens mailed to users to verify
+    their address or reset their password. Tokens are kept as SHA-256
+    digests, expire quickly, can be used once, and issuing a new one
+    invalidates any earlier token of the same purpose.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct AccountTokenRepository {
+    collection: Collection<AccountTokenDocument>,
+}
+
+impl AccountTokenRepository {
+    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<AccountTokenDocument>(collection_name);
+        Self { collection }
+    }
+
+    /// Lets MongoDB purge tokens once they expire.
+    pub async fn create_indexes(&self) -> Result<()> {
+        let ttl_index = IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(std::time::Duration::from_secs(0))
+                    .build(),
+            )
+            .build();
+        let hash_index = IndexModel::builder()
+            .keys(doc! { "token_hash": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.collection
+            .create_indexes([ttl_index, hash_index])
+            .await?;
+        Ok(())
+    }
+
+    /*-----------------------------------------------------------
+    ISSUE a token for `email`, returning it in clear text once
+    ------------------------------------------------------------*/
+    pub async fn issue(&self, email: &str, purpose: &str, lifetime: Duration) -> Result<String> {
+        self.collection
+            .delete_many(doc! { "email": email, "purpose": purpose })
+            .await?;
+
+        let token = generate_identifier();
+        let document = AccountTokenDocument {
+            id: ObjectId::new(),
+            token_hash: hash_identifier(&token),
+            purpose: purpose.to_string(),
+            email: email.to_string(),
+            expires_at: Utc::now() + lifetime,
+        };
+        self.collection.insert_one(&document).await?;
+        Ok(token)
+    }
+
+    /*-------------------------------------------------
+    FIND a valid token without using it up
+    --------------------------------------------------*/
+    pub async fn find(&self, token: &str, purpose: &str) -> Result<Option<AccountTokenDocument>> {
+        self.collection.find_one(Self::filter(token, purpose)).await
+    }
+
+    /*-------------------------------------------------
+    CONSUME a valid token; each token works only once
+    --------------------------------------------------*/
+    pub async fn consume(
+        &self,
+        token: &str,
+        purpose: &str,
+    ) -> Result<Option<AccountTokenDocument>> {
+        self.collection
+            .find_one_and_delete(Self::filter(token, purpose))
+            .await
+    }
+
+    fn filter(token: &str, purpose: &str) -> mongodb::bson::Document {
+        doc! {
+            "token_hash": hash_identifier(token),
+            "purpose": purpose,
+            "expiresAt": { "$gt": Utc::now() },
+        }
+    }
+}
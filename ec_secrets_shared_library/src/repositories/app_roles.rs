@@ -0,0 +1,249 @@
+use std::net::IpAddr;
+
+use chrono::{Duration, Utc};
+use mongodb::{
+    Client, Collection, IndexModel,
+    bson::{doc, oid::ObjectId},
+    error::{Error, Result},
+    options::IndexOptions,
+};
+
+use crate::{
+    models::{AppRole, AppRoleDocument, SecretIdDocument},
+    utils::{
+        auth::{MAX_TOKEN_TTL, generate_identifier, hash_identifier},
+        policy::{ip_allowed, parse_cidr, validate_policy},
+    },
+};
+
+pub const DEFAULT_SECRET_ID_TTL: i64 = 600;
+pub const DEFAULT_SECRET_ID_NUM_USES: i64 = 1;
+pub const DEFAULT_TOKEN_TTL: i64 = 3600;
+
+/// Checks a role before it is created: it needs a name and at least one
+/// well-formed policy, and any lifetime or use count given must be positive;
+/// tokens may not outlive `MAX_TOKEN_TTL`.
+pub fn validate_role(role: &AppRole) -> std::result::Result<(), String> {
+    if role.role_name.trim().is_empty() {
+        return Err("A role requires a name.".to_string());
+    }
+    if role.policies.is_empty() {
+        return Err("A role requires at least one policy.".to_string());
+    }
+    for policy in &role.policies {
+        validate_policy(policy)?;
+    }
+    for (field, value) in [
+        ("secret_id_ttl", role.secret_id_ttl),
+        ("secret_id_num_uses", role.secret_id_num_uses),
+        ("token_ttl", role.token_ttl),
+    ] {
+        if value.is_some_and(|value| value <= 0) {
+            return Err(format!("{field} must be positive."));
+        }
+    }
+    if role.token_ttl.is_some_and(|ttl| ttl > MAX_TOKEN_TTL) {
+        return Err(format!("token_ttl may not exceed {MAX_TOKEN_TTL} seconds."));
+    }
+    Ok(())
+}
+
+/*---------------------------------------------------------------------------
+    The AppRoleRepository backs machine logins: a role carries a stable
+    role id and the policies its tokens are scoped to, while secret ids are
+    short-lived, usage-limited credentials issued against that role.
+
+    Secret ids are only ever stored as SHA-256 digests, and are purged
+    through a TTL index once they expire.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct AppRoleRepository {
+    roles: Collection<AppRoleDocument>,
+    secret_ids: Collection<SecretIdDocument>,
+}
+
+impl AppRoleRepository {
+    pub fn new(
+        client: &Client,
+        db_name: &str,
+        roles_collection: &str,
+        secret_ids_collection: &str,
+    ) -> Self {
+        let database = client.database(db_name);
+        Self {
+            roles: database.collection::<AppRoleDocument>(roles_collection),
+            secret_ids: database.collection::<SecretIdDocument>(secret_ids_collection),
+        }
+    }
+
+    /// Lets MongoDB purge secret ids once they expire.
+    pub async fn create_indexes(&self) -> Result<()> {
+        let ttl_index = IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(std::time::Duration::from_secs(0))
+                    .build(),
+            )
+            .build();
+        let role_index = IndexModel::builder()
+            .keys(doc! { "role_id": 1, "secret_id_hash": 1 })
+            .build();
+        self.secret_ids
+            .create_indexes([ttl_index, role_index])
+            .await?;
+        Ok(())
+    }
+
+    /*-----------------
+    CREATE a new role
+    --------------------*/
+    pub async fn create_role(&self, role: &AppRole, created_by: &str) -> Result<AppRoleDocument> {
+        validate_role(role)
+            .map_err(|e| Error::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, e)))?;
+
+        if self
+            .roles
+            .find_one(doc! { "role_name": &role.role_name, "created_by": created_by })
+            .await?
+            .is_some()
+        {
+            return Err(Error::from(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "A role with this name already exists.",
+            )));
+        }
+
+        let role = AppRoleDocument {
+            id: ObjectId::new(),
+            role_name: role.role_name.clone(),
+            role_id: generate_identifier(),
+            policies: role.policies.clone(),
+            secret_id_ttl: role.secret_id_ttl.unwrap_or(DEFAULT_SECRET_ID_TTL),
+            secret_id_num_uses: role
+                .secret_id_num_uses
+                .unwrap_or(DEFAULT_SECRET_ID_NUM_USES),
+            token_ttl: role.token_ttl.unwrap_or(DEFAULT_TOKEN_TTL),
+            created_by: created_by.to_string(),
+            created_at: Utc::now(),
+        };
+
+        self.roles.insert_one(&role).await?;
+        Ok(role)
+    }
+
+    /*-----------------
+    GET role by name
+    -------------------*/
+    pub async fn get_role_by_name(
+        &self,
+        role_name: &str,
+        created_by: &str,
+    ) -> Result<Option<AppRoleDocument>> {
+        let filter = doc! { "role_name": role_name, "created_by": created_by };
+        self.roles.find_one(filter).await
+    }
+
+    /*-------------
+    DELETE a role
+    ---------------*/
+    pub async fn delete_role(
+        &self,
+        role_name: &str,
+        created_by: &str,
+    ) -> Result<Option<AppRoleDocument>> {
+        let filter = doc! { "role_name": role_name, "created_by": created_by };
+        let role = self.roles.find_one_and_delete(filter).await?;
+        if let Some(role) = &role {
+            self.secret_ids
+                .delete_many(doc! { "role_id": &role.role_id })
+                .await?;
+        }
+        Ok(role)
+    }
+
+    /*-------------------------------------------------------
+    GENERATE a secret id, returning the clear-text value once
+    --------------------------------------------------------*/
+    pub async fn generate_secret_id(
+        &self,
+        role: &AppRoleDocument,
+        cidr_list: &[String],
+    ) -> Result<(String, SecretIdDocument)> {
+        for cidr in cidr_list {
+            parse_cidr(cidr).map_err(|e| {
+                Error::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+            })?;
+        }
+
+        let secret_id = generate_identifier();
+        let now = Utc::now();
+        let document = SecretIdDocument {
+            id: ObjectId::new(),
+            role_id: role.role_id.clone(),
+            secret_id_hash: hash_identifier(&secret_id),
+            cidr_list: cidr_list.to_vec(),
+            uses_remaining: (role.secret_id_num_uses > 0).then_some(role.secret_id_num_uses),
+            expires_at: now + Duration::seconds(role.secret_id_ttl),
+            created_at: now,
+        };
+
+        self.secret_ids.insert_one(&document).await?;
+        Ok((secret_id, document))
+    }
+
+    /*----------------------------------------------------------------
+    EXCHANGE a role id / secret id pair, consuming one use of the
+    secret id. Returns `None` when the pair is unknown, expired,
+    exhausted or presented from outside the allowed networks.
+    -----------------------------------------------------------------*/
+    pub async fn consume_secret_id(
+        &self,
+        role_id: &str,
+        secret_id: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<Option<AppRoleDocument>> {
+        let Some(role) = self.roles.find_one(doc! { "role_id": role_id }).await? else {
+            return Ok(None);
+        };
+
+        let now = Utc::now();
+        let filter = doc! {
+            "role_id": role_id,
+            "secret_id_hash": hash_identifier(secret_id),
+            "expiresAt": { "$gt": now },
+        };
+        let Some(document) = self.secret_ids.find_one(filter.clone()).await? else {
+            return Ok(None);
+        };
+
+        if !ip_allowed(&document.cidr_list, client_ip) {
+            return Ok(None);
+        }
+
+        if document.uses_remaining.is_none() {
+            return Ok(Some(role));
+        }
+
+        // Decrement atomically so concurrent logins cannot overspend a secret id.
+        let mut consume_filter = filter;
+        consume_filter.insert("_id", document.id);
+        consume_filter.insert("uses_remaining", doc! { "$gt": 0 });
+        let consumed = self
+            .secret_ids
+            .find_one_and_update(consume_filter, doc! { "$inc": { "uses_remaining": -1 } })
+            .await?;
+
+        let Some(consumed) = consumed else {
+            return Ok(None);
+        };
+
+        if consumed.uses_remaining == Some(1) {
+            self.secret_ids
+                .delete_one(doc! { "_id": consumed.id })
+                .await?;
+        }
+
+        Ok(Some(role))
+    }
+}
@@ -0,0 +1,162 @@
+use chrono::{DateTime, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    Client, Collection, IndexModel,
+    bson::{Document, doc, oid::ObjectId},
+    error::{ErrorKind, Result, WriteFailure},
+    options::IndexOptions,
+};
+use tokio::sync::Mutex;
+
+use crate::{
+    models::{AuditEntryDocument, AuditEvent, AuditQuery, ChainVerification},
+    utils::audit::{AuditKey, ChainVerifier, GENESIS_HASH},
+};
+
+/// Appends racing with another server instance retry this many times.
+const APPEND_ATTEMPTS: usize = 5;
+
+/*---------------------------------------------------------------------------
+    The AuditRepository keeps the append-only, hash-chained audit log (see
+    `utils::audit`). A unique index on the sequence number keeps the chain
+    linear when several server instances append at once.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct AuditRepository {
+    collection: Collection<AuditEntryDocument>,
+    key: AuditKey,
+    append_lock: Mutex<()>,
+}
+
+impl AuditRepository {
+    /// `key` is the server's authentication key; the chain is keyed
+    /// with an HMAC key derived from it.
+    pub fn new(client: &Client, db_name: &str, collection_name: &str, key: &[u8]) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<AuditEntryDocument>(collection_name);
+        Self {
+            collection,
+            key: AuditKey::derive(key),
+            append_lock: Mutex::new(()),
+        }
+    }
+
+    pub async fn create_indexes(&self) -> Result<()> {
+        let sequence_index = IndexModel::builder()
+            .keys(doc! { "sequence": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        let actor_index = IndexModel::builder()
+            .keys(doc! { "actor": 1, "timestamp": -1 })
+            .build();
+        self.collection
+            .create_indexes([sequence_index, actor_index])
+            .await?;
+        Ok(())
+    }
+
+    /*----------------------------------------
+    APPEND an event to the end of the chain
+    -----------------------------------------*/
+    pub async fn append(&self, event: AuditEvent) -> Result<AuditEntryDocument> {
+        let _guard = self.append_lock.lock().await;
+
+        let mut attempt = 1;
+        loop {
+            let (sequence, prev_hash) = match self.head().await? {
+                Some(head) => (head.sequence + 1, head.hash),
+                None => (1, GENESIS_HASH.to_string()),
+            };
+            // Stored at millisecond precision, so the returned entry matches the log.
+            let timestamp =
+                DateTime::from_timestamp_millis(Utc::now().timestamp_millis()).unwrap_or_default();
+            let entry = AuditEntryDocument {
+                id: ObjectId::new(),
+                sequence,
+                timestamp,
+                hash: self.key.entry_hash(sequence, timestamp, &event, &prev_hash),
+                event: event.clone(),
+                prev_hash,
+            };
+
+            match self.collection.insert_one(&entry).await {
+                Ok(_) => return Ok(entry),
+                Err(e) if is_duplicate_key(&e) && attempt < APPEND_ATTEMPTS => attempt += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /*------------------------------------
+    GET the newest entry of the chain
+    -------------------------------------*/
+    pub async fn head(&self) -> Result<Option<AuditEntryDocument>> {
+        self.collection
+            .find_one(doc! {})
+            .sort(doc! { "sequence": -1 })
+            .await
+    }
+
+    /*-------------------------------------
+    QUERY entries, newest first
+    --------------------------------------*/
+    pub async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntryDocument>> {
+        let mut filter = Document::new();
+        if let Some(actor) = &query.actor {
+            filter.insert("actor", actor);
+        }
+        if let Some(action) = &query.action {
+            filter.insert("action", action);
+        }
+        if let Some(target) = &query.target {
+            filter.insert("target", target);
+        }
+        if let Some(outcome) = query.outcome {
+            filter.insert("outcome", outcome.to_string());
+        }
+        let mut timestamp = Document::new();
+        if let Some(since) = query.since {
+            timestamp.insert("$gte", mongodb::bson::DateTime::from_chrono(since));
+        }
+        if let Some(until) = query.until {
+            timestamp.insert("$lt", mongodb::bson::DateTime::from_chrono(until));
+        }
+        if !timestamp.is_empty() {
+            filter.insert("timestamp", timestamp);
+        }
+
+        self.collection
+            .find(filter)
+            .sort(doc! { "sequence": -1 })
+            .limit(query.limit)
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /*-----------------------------------------------
+    VERIFY the whole chain, oldest entry first
+    ------------------------------------------------*/
+    pub async fn verify(&self) -> Result<ChainVerification> {
+        let mut cursor = self
+            .collection
+            .find(doc! {})
+            .sort(doc! { "sequence": 1 })
+            .await?;
+        let mut verifier = ChainVerifier::new(&self.key);
+        while let Some(entry) = cursor.try_next().await? {
+            if !verifier.push(&entry) {
+                break;
+            }
+        }
+        Ok(verifier.finish())
+    }
+}
+
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == 11000
+    )
+}
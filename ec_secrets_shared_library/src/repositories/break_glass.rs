@@ -0,0 +1,109 @@
+use chrono::{Duration, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    Collection, IndexModel,
+    bson::{self, Document, doc, oid::ObjectId},
+    error::Result,
+    options::ReturnDocument,
+};
+
+use crate::models::BreakGlassDocument;
+
+/*---------------------------------------------------------------------------
+    The BreakGlassRepository keeps emergency access sessions. Sessions are
+    never deleted; ended and expired ones stay for the post-incident review.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct BreakGlassRepository {
+    collection: Collection<BreakGlassDocument>,
+}
+
+impl BreakGlassRepository {
+    pub fn new(client: &mongodb::Client, db_name: &str, collection_name: &str) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<BreakGlassDocument>(collection_name);
+        Self { collection }
+    }
+
+    pub async fn create_indexes(&self) -> Result<()> {
+        let user_index = IndexModel::builder()
+            .keys(doc! { "user": 1, "startedAt": -1 })
+            .build();
+        self.collection.create_indexes([user_index]).await?;
+        Ok(())
+    }
+
+    /*-------------------------------------
+    OPEN a session for `duration` from now
+    --------------------------------------*/
+    pub async fn create(
+        &self,
+        user: &str,
+        reason: &str,
+        path: &str,
+        duration: Duration,
+    ) -> Result<BreakGlassDocument> {
+        let now = Utc::now();
+        let session = BreakGlassDocument {
+            id: ObjectId::new(),
+            user: user.to_string(),
+            reason: reason.to_string(),
+            path: path.to_string(),
+            started_at: now,
+            expires_at: now + duration,
+            ended_at: None,
+            ended_by: None,
+        };
+
+        self.collection.insert_one(&session).await?;
+        Ok(session)
+    }
+
+    /*---------------
+    GET a session
+    ----------------*/
+    pub async fn get(&self, id: &str) -> Result<Option<BreakGlassDocument>> {
+        let Ok(object_id) = ObjectId::parse_str(id) else {
+            return Ok(None);
+        };
+        self.collection.find_one(doc! { "_id": object_id }).await
+    }
+
+    /*------------------------------------------------------------
+    LIST sessions, newest first, optionally of a single user
+    -------------------------------------------------------------*/
+    pub async fn list(&self, user: Option<&str>, limit: i64) -> Result<Vec<BreakGlassDocument>> {
+        let mut filter = Document::new();
+        if let Some(user) = user {
+            filter.insert("user", user);
+        }
+
+        self.collection
+            .find(filter)
+            .sort(doc! { "startedAt": -1 })
+            .limit(limit)
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /*---------------------------------------------------------------
+    END an active session early. `None` means it is missing, already
+    ended or expired.
+    ----------------------------------------------------------------*/
+    pub async fn end(&self, id: &str, ended_by: &str) -> Result<Option<BreakGlassDocument>> {
+        let Ok(object_id) = ObjectId::parse_str(id) else {
+            return Ok(None);
+        };
+        let now = bson::DateTime::from_chrono(Utc::now());
+
+        self.collection
+            .find_one_and_update(
+                doc! { "_id": object_id, "endedAt": null, "expiresAt": { "$gt": now } },
+                doc! { "$set": { "endedAt": now, "ended_by": ended_by } },
+            )
+            .return_document(ReturnDocument::After)
+            .await
+    }
+}
@@ -0,0 +1,148 @@
+use mongodb::{
+    Client, ClientSession, Database,
+    bson::{Document, doc},
+    change_stream::{
+        ChangeStream,
+        event::{ChangeStreamEvent, ResumeToken},
+    },
+    error::{ErrorKind, Result},
+    options::FullDocumentType,
+};
+
+use crate::models::ReplayReport;
+use crate::utils::journal::{JournalChange, JournalOperation};
+
+/*---------------------------------------------------------------------------
+    The collections the change journal follows (see `utils::journal`):
+    the ones a snapshot holds. Replaying writes journaled documents back
+    exactly as they were stored, so values encrypted with the master key
+    only read back under the master key that wrote them.
+
+    A replay runs in a single transaction, like a restore; a dry run runs
+    the same transaction and aborts it.
+---------------------------------------------------------------------------*/
+pub struct JournalRepository {
+    client: Client,
+    database: Database,
+    collections: Vec<String>,
+}
+
+impl JournalRepository {
+    pub fn new(client: &Client, db_name: &str, collections: &[&str]) -> Self {
+        Self {
+            client: client.clone(),
+            database: client.database(db_name),
+            collections: collections.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    /// Has MongoDB (6.0 or later) keep the post-image of every change to
+    /// the journaled collections, creating those that do not exist yet,
+    /// so the journal records each document as that change left it.
+    pub async fn enable_post_images(&self) -> Result<()> {
+        let existing = self.database.list_collection_names().await?;
+        for name in &self.collections {
+            if !existing.contains(name) {
+                match self.database.create_collection(name).await {
+                    Ok(()) => {}
+                    Err(e) if is_namespace_exists(&e) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            self.database
+                .run_command(doc! {
+                    "collMod": name,
+                    "changeStreamPreAndPostImages": { "enabled": true },
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Streams changes to the journaled collections, after `resume_after`
+    /// when given.
+    pub async fn watch(
+        &self,
+        resume_after: Option<ResumeToken>,
+    ) -> Result<ChangeStream<ChangeStreamEvent<Document>>> {
+        self.database
+            .watch()
+            .pipeline([doc! { "$match": { "ns.coll": { "$in": &self.collections } } }])
+            .full_document(FullDocumentType::Required)
+            .resume_after(resume_after)
+            .await
+    }
+
+    /*---------------------------------------------------------------
+    REPLAY changes in journal order. Upserts replace the whole
+    document and deletes remove it, so replaying a change that is
+    already applied changes nothing.
+    ----------------------------------------------------------------*/
+    pub async fn replay(&self, changes: &[JournalChange], dry_run: bool) -> Result<ReplayReport> {
+        let mut session = self.client.start_session().await?;
+        session.start_transaction().await?;
+
+        match self.apply(&mut session, changes).await {
+            Ok(mut report) => {
+                if dry_run {
+                    session.abort_transaction().await?;
+                } else {
+                    session.commit_transaction().await?;
+                }
+                report.dry_run = dry_run;
+                Ok(report)
+            }
+            Err(e) => {
+                // The transaction is rolled back either way; report the original failure.
+                let _ = session.abort_transaction().await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn apply(
+        &self,
+        session: &mut ClientSession,
+        changes: &[JournalChange],
+    ) -> Result<ReplayReport> {
+        let mut report = ReplayReport {
+            changes: changes.len(),
+            ..ReplayReport::default()
+        };
+
+        for change in changes {
+            let record = &change.record;
+            if !self.collections.contains(&record.collection) {
+                report.skipped += 1;
+                continue;
+            }
+            let collection = self.database.collection::<Document>(&record.collection);
+            let filter = doc! { "_id": record.id.clone() };
+
+            match (record.operation, &record.document) {
+                (JournalOperation::Upsert, Some(document)) => {
+                    collection
+                        .replace_one(filter, document)
+                        .upsert(true)
+                        .session(&mut *session)
+                        .await?;
+                    *report
+                        .upserted
+                        .entry(record.collection.clone())
+                        .or_default() += 1;
+                }
+                (JournalOperation::Delete, _) => {
+                    collection.delete_one(filter).session(&mut *session).await?;
+                    *report.deleted.entry(record.collection.clone()).or_default() += 1;
+                }
+                (JournalOperation::Upsert, None) => report.skipped += 1,
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// NamespaceExists: another server instance created the collection first.
+fn is_namespace_exists(error: &mongodb::error::Error) -> bool {
+    matches!(error.kind.as_ref(), ErrorKind::Command(e) if e.code == 48)
+}
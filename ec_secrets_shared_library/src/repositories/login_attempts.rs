@@ -0,0 +1,175 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Duration, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    Client, Collection, IndexModel,
+    bson::doc,
+    error::Result,
+    options::{IndexOptions, ReturnDocument},
+};
+
+use crate::{models::LoginAttemptDocument, utils::lockout::LockoutConfig};
+
+fn account_key(email: &str) -> String {
+    // Case variations of an address must share one counter.
+    format!("account:{}", email.trim().to_lowercase())
+}
+
+fn address_key(address: &IpAddr) -> String {
+    format!("ip:{}", address)
+}
+
+/*---------------------------------------------------------------------------
+    The LoginAttemptRepository counts failed password logins per account
+    and per client address, and remembers until when each is blocked.
+
+    Counters are purged through a TTL index once they expire; expired
+    documents still waiting for the purge are ignored and replaced.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct LoginAttemptRepository {
+    collection: Collection<LoginAttemptDocument>,
+}
+
+impl LoginAttemptRepository {
+    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<LoginAttemptDocument>(collection_name);
+        Self { collection }
+    }
+
+    /// One counter per key; counters are purged once they expire.
+    pub async fn create_indexes(&self) -> Result<()> {
+        let ttl_index = IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(std::time::Duration::from_secs(0))
+                    .build(),
+            )
+            .build();
+        let key_index = IndexModel::builder()
+            .keys(doc! { "key": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.collection
+            .create_indexes([ttl_index, key_index])
+            .await?;
+        Ok(())
+    }
+
+    /*-------------------------------------------------------------
+    CHECK until when logins to an account, or from an address, are
+    blocked. `None` when a password may be tried right away.
+    --------------------------------------------------------------*/
+    pub async fn blocked_until(
+        &self,
+        email: &str,
+        address: Option<&IpAddr>,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let mut keys = vec![account_key(email)];
+        keys.extend(address.map(address_key));
+
+        let now = Utc::now();
+        let mut cursor = self
+            .collection
+            .find(doc! {
+                "key": { "$in": keys },
+                "expiresAt": { "$gt": now },
+                "blockedUntil": { "$gt": now },
+            })
+            .await?;
+
+        let mut blocked_until = None;
+        while let Some(attempts) = cursor.try_next().await? {
+            blocked_until = blocked_until.max(Some(attempts.blocked_until));
+        }
+        Ok(blocked_until)
+    }
+
+    /*-------------------------------------------------------------
+    RECORD a failed login for an account. Returns the number of
+    consecutive failures, including this one.
+    --------------------------------------------------------------*/
+    pub async fn record_account_failure(&self, email: &str, config: &LockoutConfig) -> Result<i64> {
+        let failures = self.increment(&account_key(email)).await?;
+
+        let now = Utc::now();
+        let blocked_until = now + config.account_delay(failures);
+        self.collection
+            .update_one(
+                doc! { "key": account_key(email) },
+                doc! {
+                    "$max": { "blockedUntil": blocked_until },
+                    "$set": { "expiresAt": blocked_until + Duration::seconds(config.reset_after) },
+                },
+            )
+            .await?;
+        Ok(failures)
+    }
+
+    /*-------------------------------------------------------------
+    RECORD a failed login from a client address. Failures count
+    within a fixed window that starts with the first of them.
+    --------------------------------------------------------------*/
+    pub async fn record_address_failure(
+        &self,
+        address: &IpAddr,
+        config: &LockoutConfig,
+    ) -> Result<i64> {
+        let key = address_key(address);
+        let failures = self.increment(&key).await?;
+
+        let now = Utc::now();
+        let window_end = now + Duration::seconds(config.ip_window);
+        let blocked_until = now + config.ip_delay(failures);
+        self.collection
+            .update_one(
+                doc! { "key": &key, "expiresAt": { "$lte": now } },
+                doc! { "$set": { "expiresAt": window_end } },
+            )
+            .await?;
+        self.collection
+            .update_one(
+                doc! { "key": &key },
+                doc! { "$max": { "blockedUntil": blocked_until, "expiresAt": blocked_until } },
+            )
+            .await?;
+        Ok(failures)
+    }
+
+    /*----------------------------------------------------
+    CLEAR an account's failures after a successful login or
+    when an administrator unlocks it
+    -----------------------------------------------------*/
+    pub async fn clear_account(&self, email: &str) -> Result<bool> {
+        let result = self
+            .collection
+            .delete_one(doc! { "key": account_key(email) })
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn increment(&self, key: &str) -> Result<i64> {
+        let now = Utc::now();
+        self.collection
+            .delete_one(doc! { "key": key, "expiresAt": { "$lte": now } })
+            .await?;
+
+        let attempts = self
+            .collection
+            .find_one_and_update(
+                doc! { "key": key },
+                doc! {
+                    "$inc": { "failures": 1 },
+                    "$setOnInsert": { "blockedUntil": now, "expiresAt": now },
+                },
+            )
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .await?;
+        Ok(attempts.map_or(1, |attempts| attempts.failures))
+    }
+}
@@ -1,3 +1,18 @@
+pub mod access_requests;
+pub mod account_tokens;
+pub mod app_roles;
+pub mod audit;
+pub mod break_glass;
+pub mod journal;
 pub mod keys;
+pub mod login_attempts;
+pub mod offboarding;
+pub mod oidc;
+pub mod refresh_tokens;
+pub mod revocations;
+pub mod seal;
+pub mod snapshots;
+pub mod totp;
 pub mod users;
 pub mod vault;
+pub mod webauthn;
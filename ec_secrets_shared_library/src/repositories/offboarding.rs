@@ -0,0 +1,139 @@
+use chrono::Utc;
+use mongodb::{
+    Client, ClientSession, Collection,
+    bson::{doc, oid::ObjectId},
+    error::{Error, Result},
+};
+
+use crate::models::{Offboarded, UserDocument, UserStatus, VaultDocument};
+
+/*---------------------------------------------------------------------------
+    Offboarding removes a user and hands their secrets to a successor, or
+    archives them, in a single transaction: either the account is gone and
+    every secret has moved, or nothing changed.
+
+    Transactions need MongoDB to run as a replica set.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct OffboardingRepository {
+    client: Client,
+    users: Collection<UserDocument>,
+    vault: Collection<VaultDocument>,
+}
+
+impl OffboardingRepository {
+    pub fn new(
+        client: &Client,
+        db_name: &str,
+        users_collection: &str,
+        vault_collection: &str,
+    ) -> Self {
+        let database = client.database(db_name);
+        Self {
+            client: client.clone(),
+            users: database.collection::<UserDocument>(users_collection),
+            vault: database.collection::<VaultDocument>(vault_collection),
+        }
+    }
+
+    /*---------------------------------------------------------------
+    OFFBOARD a user. Returns the removed account and what happened
+    to their secrets, or `None` when there is no such user.
+    ----------------------------------------------------------------*/
+    pub async fn offboard(
+        &self,
+        id: &str,
+        reassign_to: Option<&str>,
+    ) -> Result<Option<(UserDocument, Offboarded)>> {
+        let mut session = self.client.start_session().await?;
+        session.start_transaction().await?;
+
+        match self.apply(&mut session, id, reassign_to).await {
+            Ok(Some(outcome)) => {
+                session.commit_transaction().await?;
+                Ok(Some(outcome))
+            }
+            Ok(None) => {
+                session.abort_transaction().await?;
+                Ok(None)
+            }
+            Err(e) => {
+                // The transaction is rolled back either way; report the original failure.
+                let _ = session.abort_transaction().await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn apply(
+        &self,
+        session: &mut ClientSession,
+        id: &str,
+        reassign_to: Option<&str>,
+    ) -> Result<Option<(UserDocument, Offboarded)>> {
+        let object_id = ObjectId::parse_str(id).map_err(invalid)?;
+        let Some(user) = self
+            .users
+            .find_one(doc! { "_id": object_id })
+            .session(&mut *session)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let owned = doc! { "owner_id": user.id.to_hex() };
+
+        let outcome = match reassign_to {
+            Some(successor_id) => {
+                let successor_oid = ObjectId::parse_str(successor_id).map_err(invalid)?;
+                if successor_oid == user.id {
+                    return Err(invalid("a user cannot inherit their own secrets"));
+                }
+                let successor = self
+                    .users
+                    .find_one(doc! { "_id": successor_oid, "status": { "$ne": UserStatus::Disabled.to_string() } })
+                    .session(&mut *session)
+                    .await?
+                    .ok_or_else(|| invalid("the successor must be an active user"))?;
+
+                let result = self
+                    .vault
+                    .update_many(
+                        owned,
+                        doc! { "$set": { "owner_id": successor.id.to_hex(), "created_by": &successor.email } },
+                    )
+                    .session(&mut *session)
+                    .await?;
+                Offboarded::Reassigned {
+                    to: successor.email,
+                    secrets: result.modified_count,
+                }
+            }
+            None => {
+                let result = self
+                    .vault
+                    .update_many(
+                        owned,
+                        doc! { "$set": { "archivedAt": mongodb::bson::DateTime::from_chrono(Utc::now()) } },
+                    )
+                    .session(&mut *session)
+                    .await?;
+                Offboarded::Archived {
+                    secrets: result.modified_count,
+                }
+            }
+        };
+
+        self.users
+            .delete_one(doc! { "_id": user.id })
+            .session(&mut *session)
+            .await?;
+        Ok(Some((user, outcome)))
+    }
+}
+
+fn invalid<E: ToString>(reason: E) -> Error {
+    Error::from(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        reason.to_string(),
+    ))
+}
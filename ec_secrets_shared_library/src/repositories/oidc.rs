@@ -0,0 +1,78 @@
+use chrono::{Duration, Utc};
+use mongodb::{
+    Client, Collection, IndexModel,
+    bson::{doc, oid::ObjectId},
+    error::Result,
+    options::IndexOptions,
+};
+
+use crate::{
+    models::OidcLoginDocument,
+    utils::auth::{generate_identifier, hash_identifier},
+};
+
+/*---------------------------------------------------------------------------
+    The OidcRepository keeps single sign-on logins between the redirect to
+    the provider and its callback: the PKCE code verifier and the nonce the
+    ID token must carry, found again by the `state` parameter.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct OidcRepository {
+    logins: Collection<OidcLoginDocument>,
+}
+
+impl OidcRepository {
+    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
+        let logins = client
+            .database(db_name)
+            .collection::<OidcLoginDocument>(collection_name);
+        Self { logins }
+    }
+
+    /// Lets MongoDB purge logins that were never completed.
+    pub async fn create_indexes(&self) -> Result<()> {
+        let ttl_index = IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(std::time::Duration::from_secs(0))
+                    .build(),
+            )
+            .build();
+        let state_index = IndexModel::builder()
+            .keys(doc! { "state_hash": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.logins.create_indexes([ttl_index, state_index]).await?;
+        Ok(())
+    }
+
+    /*----------------------------------------------------------------
+    BEGIN a login, returning the clear-text `state` once along with
+    the stored nonce and code verifier.
+    -----------------------------------------------------------------*/
+    pub async fn begin_login(&self, lifetime: Duration) -> Result<(String, OidcLoginDocument)> {
+        let state = generate_identifier();
+        let login = OidcLoginDocument {
+            id: ObjectId::new(),
+            state_hash: hash_identifier(&state),
+            nonce: generate_identifier(),
+            code_verifier: generate_identifier(),
+            expires_at: Utc::now() + lifetime,
+        };
+
+        self.logins.insert_one(&login).await?;
+        Ok((state, login))
+    }
+
+    /*------------------------------------------------------
+    CONSUME a pending login; each callback can complete once
+    -------------------------------------------------------*/
+    pub async fn consume_login(&self, state: &str) -> Result<Option<OidcLoginDocument>> {
+        let filter = doc! {
+            "state_hash": hash_identifier(state),
+            "expiresAt": { "$gt": Utc::now() },
+        };
+        self.logins.find_one_and_delete(filter).await
+    }
+}
@@ -0,0 +1,178 @@
+use chrono::{Duration, Utc};
+use mongodb::{
+    Client, Collection, IndexModel,
+    bson::{doc, oid::ObjectId},
+    error::Result,
+    options::IndexOptions,
+};
+
+use crate::{
+    models::RefreshTokenDocument,
+    utils::auth::{generate_identifier, hash_identifier},
+};
+
+/// Result of presenting a refresh token for rotation.
+#[derive(Debug)]
+pub enum RefreshOutcome {
+    /// The token was valid and has been consumed; a successor has been issued.
+    Rotated {
+        subject: String,
+        refresh_token: String,
+    },
+    /// The token had already been used or revoked; its whole family is now revoked.
+    Reused { subject: String, family_id: String },
+    /// The token is unknown or expired.
+    Invalid,
+}
+
+/*---------------------------------------------------------------------------
+    The RefreshTokenRepository stores rotating refresh tokens as SHA-256
+    digests. Each successful refresh consumes the presented token and issues
+    a successor in the same family; presenting a consumed token again is
+    treated as theft and revokes the entire family.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct RefreshTokenRepository {
+    collection: Collection<RefreshTokenDocument>,
+}
+
+impl RefreshTokenRepository {
+    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<RefreshTokenDocument>(collection_name);
+        Self { collection }
+    }
+
+    /// Lets MongoDB purge refresh tokens once they expire.
+    pub async fn create_indexes(&self) -> Result<()> {
+        let ttl_index = IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(std::time::Duration::from_secs(0))
+                    .build(),
+            )
+            .build();
+        let hash_index = IndexModel::builder()
+            .keys(doc! { "token_hash": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.collection
+            .create_indexes([ttl_index, hash_index])
+            .await?;
+        Ok(())
+    }
+
+    /*--------------------------------------------------------------
+    ISSUE a refresh token, starting a new family unless one is given
+    ---------------------------------------------------------------*/
+    pub async fn issue(
+        &self,
+        subject: &str,
+        family_id: Option<&str>,
+        lifetime: Duration,
+    ) -> Result<String> {
+        let refresh_token = generate_identifier();
+        let now = Utc::now();
+        let document = RefreshTokenDocument {
+            id: ObjectId::new(),
+            family_id: family_id.map_or_else(generate_identifier, str::to_string),
+            subject: subject.to_string(),
+            token_hash: hash_identifier(&refresh_token),
+            used: false,
+            revoked: false,
+            expires_at: now + lifetime,
+            created_at: now,
+        };
+
+        self.collection.insert_one(&document).await?;
+        Ok(refresh_token)
+    }
+
+    /*----------------------------------------
+    ROTATE a refresh token with reuse detection
+    -----------------------------------------*/
+    pub async fn rotate(&self, refresh_token: &str, lifetime: Duration) -> Result<RefreshOutcome> {
+        let token_hash = hash_identifier(refresh_token);
+        let filter = doc! {
+            "token_hash": &token_hash,
+            "used": false,
+            "revoked": false,
+            "expiresAt": { "$gt": Utc::now() },
+        };
+
+        // Consuming atomically guarantees only one caller can rotate a given token.
+        if let Some(consumed) = self
+            .collection
+            .find_one_and_update(filter, doc! { "$set": { "used": true } })
+            .await?
+        {
+            let refresh_token = self
+                .issue(&consumed.subject, Some(&consumed.family_id), lifetime)
+                .await?;
+            return Ok(RefreshOutcome::Rotated {
+                subject: consumed.subject,
+                refresh_token,
+            });
+        }
+
+        match self
+            .collection
+            .find_one(doc! { "token_hash": &token_hash })
+            .await?
+        {
+            Some(document) if document.used || document.revoked => {
+                self.revoke_family(&document.family_id).await?;
+                Ok(RefreshOutcome::Reused {
+                    subject: document.subject,
+                    family_id: document.family_id,
+                })
+            }
+            _ => Ok(RefreshOutcome::Invalid),
+        }
+    }
+
+    /*---------------------------
+    REVOKE every token in a family
+    ----------------------------*/
+    pub async fn revoke_family(&self, family_id: &str) -> Result<u64> {
+        let result = self
+            .collection
+            .update_many(
+                doc! { "family_id": family_id },
+                doc! { "$set": { "revoked": true } },
+            )
+            .await?;
+        Ok(result.modified_count)
+    }
+
+    /*-------------------------------------------
+    REVOKE the family a presented token belongs to
+    --------------------------------------------*/
+    pub async fn revoke_family_of(&self, refresh_token: &str) -> Result<u64> {
+        let token_hash = hash_identifier(refresh_token);
+        match self
+            .collection
+            .find_one(doc! { "token_hash": &token_hash })
+            .await?
+        {
+            Some(document) => self.revoke_family(&document.family_id).await,
+            None => Ok(0),
+        }
+    }
+
+    /*-------------------------------------
+    REVOKE every refresh token of a subject
+    --------------------------------------*/
+    pub async fn revoke_subject(&self, subject: &str) -> Result<u64> {
+        let result = self
+            .collection
+            .update_many(
+                doc! { "subject": subject },
+                doc! { "$set": { "revoked": true } },
+            )
+            .await?;
+        Ok(result.modified_count)
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration as StdDuration, Instant},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    Client, Collection, IndexModel,
+    bson::{doc, oid::ObjectId},
+    error::Result,
+    options::{IndexOptions, UpdateOptions},
+};
+
+use crate::models::RevocationDocument;
+
+/// How long a "revoke all sessions" cutoff is kept. Token lifetimes are
+/// capped at this (`utils::auth::MAX_TOKEN_TTL`), so older cutoffs can no
+/// longer match anything.
+pub const SUBJECT_REVOCATION_RETENTION: i64 = 30 * 24 * 60 * 60;
+
+/// How often the in-memory revocation list is reloaded from the database.
+const CACHE_REFRESH_INTERVAL: StdDuration = StdDuration::from_secs(30);
+
+#[derive(Debug, Default)]
+struct RevocationCache {
+    tokens: HashMap<String, DateTime<Utc>>,
+    subjects: HashMap<String, DateTime<Utc>>,
+    synced_at: Option<Instant>,
+}
+
+impl RevocationCache {
+    fn is_stale(&self) -> bool {
+        self.synced_at
+            .is_none_or(|synced_at| synced_at.elapsed() >= CACHE_REFRESH_INTERVAL)
+    }
+
+    fn insert(&mut self, revocation: &RevocationDocument) {
+        match &revocation.jti {
+            Some(jti) => {
+                self.tokens.insert(jti.clone(), revocation.expires_at);
+            }
+            None => {
+                let cutoff = self
+                    .subjects
+                    .entry(revocation.subject.clone())
+                    .or_insert(revocation.revoked_at);
+                *cutoff = (*cutoff).max(revocation.revoked_at);
+            }
+        }
+    }
+
+    fn contains(&self, jti: Option<&str>, subject: &str, issued_at: DateTime<Utc>) -> bool {
+        let token_revoked = jti.is_some_and(|jti| self.tokens.contains_key(jti));
+        let subject_revoked = self
+            .subjects
+            .get(subject)
+            .is_some_and(|cutoff| issued_at <= *cutoff);
+        token_revoked || subject_revoked
+    }
+}
+
+/*---------------------------------------------------------------------------
+    The RevocationRepository records tokens invalidated before their expiry,
+    either individually by token id (`jti`) or for every token a subject
+    was issued up to a point in time.
+
+    Entries expire through a TTL index once the tokens they cover would have
+    expired anyway. Lookups are answered from an in-memory copy of the list
+    that is reloaded periodically, so request guards do not query MongoDB
+    on every request.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct RevocationRepository {
+    collection: Collection<RevocationDocument>,
+    cache: RwLock<RevocationCache>,
+}
+
+impl RevocationRepository {
+    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<RevocationDocument>(collection_name);
+        Self {
+            collection,
+            cache: RwLock::new(RevocationCache::default()),
+        }
+    }
+
+    /// Lets MongoDB purge revocations once the tokens they cover have expired.
+    pub async fn create_indexes(&self) -> Result<()> {
+        let ttl_index = IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(StdDuration::from_secs(0))
+                    .build(),
+            )
+            .build();
+        self.collection.create_index(ttl_index).await?;
+        Ok(())
+    }
+
+    /*---------------------------------
+    REVOKE a single token by its token id
+    ----------------------------------*/
+    pub async fn revoke_token(
+        &self,
+        jti: &str,
+        subject: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let revocation = RevocationDocument {
+            id: ObjectId::new(),
+            jti: Some(jti.to_string()),
+            subject: subject.to_string(),
+            revoked_at: Utc::now(),
+            expires_at,
+        };
+        self.collection.insert_one(&revocation).await?;
+        self.cache.write().unwrap().insert(&revocation);
+        Ok(())
+    }
+
+    /*------------------------------------------
+    REVOKE every token issued to a subject so far
+    -------------------------------------------*/
+    pub async fn revoke_subject(&self, subject: &str) -> Result<()> {
+        let now = Utc::now();
+        let revocation = RevocationDocument {
+            id: ObjectId::new(),
+            jti: None,
+            subject: subject.to_string(),
+            revoked_at: now,
+            expires_at: now + Duration::seconds(SUBJECT_REVOCATION_RETENTION),
+        };
+
+        // Only the latest cutoff matters, so keep a single entry per subject.
+        self.collection
+            .update_one(
+                doc! { "subject": subject, "jti": null },
+                doc! { "$set": {
+                    "revokedAt": revocation.revoked_at,
+                    "expiresAt": revocation.expires_at,
+                } },
+            )
+            .with_options(UpdateOptions::builder().upsert(true).build())
+            .await?;
+        self.cache.write().unwrap().insert(&revocation);
+        Ok(())
+    }
+
+    /*----------------------------------------------------------------
+    CHECK whether a token was revoked, reloading the list when stale
+    -----------------------------------------------------------------*/
+    pub async fn is_revoked(
+        &self,
+        jti: Option<&str>,
+        subject: &str,
+        issued_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        if self.cache.read().unwrap().is_stale() {
+            self.reload().await?;
+        }
+        Ok(self.cache.read().unwrap().contains(jti, subject, issued_at))
+    }
+
+    async fn reload(&self) -> Result<()> {
+        let mut cursor = self
+            .collection
+            .find(doc! { "expiresAt": { "$gt": Utc::now() } })
+            .await?;
+
+        let mut cache = RevocationCache::default();
+        while let Some(revocation) = cursor.try_next().await? {
+            cache.insert(&revocation);
+        }
+        cache.synced_at = Some(Instant::now());
+
+        *self.cache.write().unwrap() = cache;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn revocation(
+        jti: Option<&str>,
+        subject: &str,
+        revoked_at: DateTime<Utc>,
+    ) -> RevocationDocument {
+        RevocationDocument {
+            id: ObjectId::new(),
+            jti: jti.map(str::to_string),
+            subject: subject.to_string(),
+            revoked_at,
+            expires_at: revoked_at + Duration::hours(1),
+        }
+    }
+
+    #[test]
+    fn cache_lookups() {
+        let now = Utc::now();
+        let mut cache = RevocationCache::default();
+        assert!(cache.is_stale());
+
+        cache.insert(&revocation(Some("token-1"), "user@example.com", now));
+        cache.insert(&revocation(None, "admin@example.com", now));
+
+        assert!(cache.contains(Some("token-1"), "user@example.com", now));
+        assert!(!cache.contains(Some("token-2"), "user@example.com", now));
+        assert!(cache.contains(None, "admin@example.com", now - Duration::minutes(5)));
+        assert!(!cache.contains(None, "admin@example.com", now + Duration::minutes(5)));
+    }
+}
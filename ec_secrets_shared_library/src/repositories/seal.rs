@@ -0,0 +1,66 @@
+use mongodb::{
+    Collection,
+    bson::doc,
+    error::{ErrorKind, Result, WriteFailure},
+};
+
+use crate::models::{SealDocument, WrappedKey};
+use crate::utils::seal::SEAL_ID;
+
+/*---------------------------------------------------------------------------
+    The SealRepository keeps how the master key was split. Written once,
+    at initialization; neither the key nor its shares are ever stored,
+    only the key as wrapped by an external key manager.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct SealRepository {
+    collection: Collection<SealDocument>,
+}
+
+impl SealRepository {
+    pub fn new(client: &mongodb::Client, db_name: &str, collection_name: &str) -> Self {
+        let collection = client
+            .database(db_name)
+            .collection::<SealDocument>(collection_name);
+        Self { collection }
+    }
+
+    /*------------------------------------------
+    GET the seal configuration, if initialized
+    -------------------------------------------*/
+    pub async fn get(&self) -> Result<Option<SealDocument>> {
+        self.collection.find_one(doc! { "_id": SEAL_ID }).await
+    }
+
+    /*-------------------------------------------------------------
+    INITIALIZE once. `false` when another initialization came first
+    --------------------------------------------------------------*/
+    pub async fn initialize(&self, seal: &SealDocument) -> Result<bool> {
+        match self.collection.insert_one(seal).await {
+            Ok(_) => Ok(true),
+            Err(error) if is_duplicate_key(&error) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    /*---------------------------------------------------
+    SET the wrapped master key, replacing any earlier one
+    ----------------------------------------------------*/
+    pub async fn set_wrapped_key(&self, wrapped_key: &WrappedKey) -> Result<()> {
+        let wrapped_key = mongodb::bson::to_bson(wrapped_key)?;
+        self.collection
+            .update_one(
+                doc! { "_id": SEAL_ID },
+                doc! { "$set": { "wrappedKey": wrapped_key } },
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == 11000
+    )
+}
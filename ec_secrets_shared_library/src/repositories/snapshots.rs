@@ -0,0 +1,237 @@
+use futures::stream::TryStreamExt;
+use mongodb::{
+    Client, ClientSession, Collection,
+    bson::{Document, doc},
+    error::Result,
+};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::models::{
+    AppRoleDocument, KeyPairDocument, RestoreMode, RestoreReport, SnapshotCounts, SnapshotManifest,
+    TotpEnrollmentDocument, UserDocument, VaultDocument,
+};
+use crate::repositories::vault::{decrypt_value, encrypt_value};
+use crate::utils::seal::Keyring;
+use crate::utils::snapshot::Snapshot;
+
+/*---------------------------------------------------------------------------
+    Snapshots export the collections worth backing up and restore them
+    (see `utils::snapshot`). Values encrypted with the master key are
+    decrypted on export and encrypted again on restore, under the master
+    key of the deployment restored into.
+
+    A restore runs in a single transaction, so it applies completely or
+    not at all; a dry run runs the same transaction and aborts it.
+    Transactions need MongoDB to run as a replica set.
+---------------------------------------------------------------------------*/
+pub struct SnapshotRepository {
+    client: Client,
+    users: Collection<UserDocument>,
+    vault: Collection<VaultDocument>,
+    totp: Collection<TotpEnrollmentDocument>,
+    keys: Collection<KeyPairDocument>,
+    app_roles: Collection<AppRoleDocument>,
+    keyring: Arc<Keyring>,
+}
+
+/// Documents restored, skipped and removed by one restore.
+#[derive(Default)]
+struct Applied {
+    restored: SnapshotCounts,
+    skipped: SnapshotCounts,
+    removed: SnapshotCounts,
+}
+
+impl SnapshotRepository {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        client: &Client,
+        db_name: &str,
+        users_collection: &str,
+        vault_collection: &str,
+        totp_collection: &str,
+        keys_collection: &str,
+        app_roles_collection: &str,
+        keyring: Arc<Keyring>,
+    ) -> Self {
+        let database = client.database(db_name);
+        Self {
+            client: client.clone(),
+            users: database.collection::<UserDocument>(users_collection),
+            vault: database.collection::<VaultDocument>(vault_collection),
+            totp: database.collection::<TotpEnrollmentDocument>(totp_collection),
+            keys: database.collection::<KeyPairDocument>(keys_collection),
+            app_roles: database.collection::<AppRoleDocument>(app_roles_collection),
+            keyring,
+        }
+    }
+
+    /*---------------------------------------------------------------
+    EXPORT every user, secret (archived ones included), confirmed
+    TOTP enrollment, signing key and AppRole. Needs the keyring to be
+    unsealed.
+    ----------------------------------------------------------------*/
+    pub async fn export(&self) -> Result<Snapshot> {
+        let users = self.users.find(doc! {}).await?.try_collect().await?;
+
+        let mut secrets: Vec<VaultDocument> = self.vault.find(doc! {}).await?.try_collect().await?;
+        for secret in &mut secrets {
+            secret.value = decrypt_value(&self.keyring, &secret.value)?;
+        }
+
+        let mut totp: Vec<TotpEnrollmentDocument> = self
+            .totp
+            .find(doc! { "confirmed": true })
+            .await?
+            .try_collect()
+            .await?;
+        for enrollment in &mut totp {
+            enrollment.secret = decrypt_value(&self.keyring, &enrollment.secret)?.to_string();
+        }
+
+        let keys = self.keys.find(doc! {}).await?.try_collect().await?;
+        let app_roles = self.app_roles.find(doc! {}).await?.try_collect().await?;
+
+        Ok(Snapshot {
+            users,
+            secrets,
+            totp,
+            keys,
+            app_roles,
+        })
+    }
+
+    /*---------------------------------------------------------------
+    RESTORE a validated snapshot. `merge` keeps whatever the
+    deployment already has, matching users by id or email, AppRoles
+    by id or role id and TOTP enrollments by subject, and never
+    replaces the signing key; `replace` removes the snapshotted
+    collections first.
+    ----------------------------------------------------------------*/
+    pub async fn restore(
+        &self,
+        manifest: SnapshotManifest,
+        snapshot: &Snapshot,
+        mode: RestoreMode,
+        dry_run: bool,
+    ) -> Result<RestoreReport> {
+        // Encrypted under this deployment's master key before anything is written.
+        let mut snapshot = snapshot.clone();
+        for secret in &mut snapshot.secrets {
+            secret.value = encrypt_value(&self.keyring, &secret.value)?.into();
+        }
+        for enrollment in &mut snapshot.totp {
+            enrollment.secret = encrypt_value(&self.keyring, &enrollment.secret)?;
+        }
+
+        let mut session = self.client.start_session().await?;
+        session.start_transaction().await?;
+
+        match self.apply(&mut session, &snapshot, mode).await {
+            Ok(applied) => {
+                if dry_run {
+                    session.abort_transaction().await?;
+                } else {
+                    session.commit_transaction().await?;
+                }
+                Ok(RestoreReport {
+                    manifest,
+                    mode,
+                    dry_run,
+                    restored: applied.restored,
+                    skipped: applied.skipped,
+                    removed: applied.removed,
+                })
+            }
+            Err(e) => {
+                // The transaction is rolled back either way; report the original failure.
+                let _ = session.abort_transaction().await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn apply(
+        &self,
+        session: &mut ClientSession,
+        snapshot: &Snapshot,
+        mode: RestoreMode,
+    ) -> Result<Applied> {
+        let mut applied = Applied::default();
+
+        if mode == RestoreMode::Replace {
+            applied.removed = SnapshotCounts {
+                users: remove_all(&self.users, session).await?,
+                secrets: remove_all(&self.vault, session).await?,
+                totp: remove_all(&self.totp, session).await?,
+                keys: remove_all(&self.keys, session).await?,
+                app_roles: remove_all(&self.app_roles, session).await?,
+            };
+        }
+
+        (applied.restored.users, applied.skipped.users) =
+            restore_documents(&self.users, session, &snapshot.users, |user| {
+                doc! { "$or": [{ "_id": user.id }, { "email": &user.email }] }
+            })
+            .await?;
+        (applied.restored.secrets, applied.skipped.secrets) =
+            restore_documents(&self.vault, session, &snapshot.secrets, |secret| {
+                doc! { "_id": secret.id }
+            })
+            .await?;
+        (applied.restored.totp, applied.skipped.totp) =
+            restore_documents(&self.totp, session, &snapshot.totp, |enrollment| {
+                doc! { "subject": &enrollment.subject, "confirmed": true }
+            })
+            .await?;
+        (applied.restored.keys, applied.skipped.keys) =
+            restore_documents(&self.keys, session, &snapshot.keys, |_| doc! {}).await?;
+        (applied.restored.app_roles, applied.skipped.app_roles) =
+            restore_documents(&self.app_roles, session, &snapshot.app_roles, |role| {
+                doc! { "$or": [{ "_id": role.id }, { "role_id": &role.role_id }] }
+            })
+            .await?;
+
+        Ok(applied)
+    }
+}
+
+async fn remove_all<T: Send + Sync>(
+    collection: &Collection<T>,
+    session: &mut ClientSession,
+) -> Result<usize> {
+    let result = collection
+        .delete_many(doc! {})
+        .session(&mut *session)
+        .await?;
+    Ok(result.deleted_count as usize)
+}
+
+/// Inserts the documents that match nothing in `collection`; returns how
+/// many were inserted and how many skipped.
+async fn restore_documents<T: Serialize + Send + Sync>(
+    collection: &Collection<T>,
+    session: &mut ClientSession,
+    documents: &[T],
+    existing: impl Fn(&T) -> Document,
+) -> Result<(usize, usize)> {
+    let (mut restored, mut skipped) = (0, 0);
+    for document in documents {
+        let matches = collection
+            .count_documents(existing(document))
+            .limit(1)
+            .session(&mut *session)
+            .await?;
+        if matches > 0 {
+            skipped += 1;
+        } else {
+            collection
+                .insert_one(document)
+                .session(&mut *session)
+                .await?;
+            restored += 1;
+        }
+    }
+    Ok((restored, skipped))
+}
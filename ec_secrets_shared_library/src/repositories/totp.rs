@@ -0,0 +1,324 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::{Duration, Utc};
+use mongodb::{
+    Client, Collection, IndexModel,
+    bson::{doc, oid::ObjectId},
+    error::{Error, Result},
+    options::IndexOptions,
+};
+use std::sync::Arc;
+
+use crate::{
+    models::{MfaChallengeDocument, TotpEnrollmentDocument},
+    utils::{
+        auth::{generate_identifier, hash_identifier},
+        seal::Keyring,
+        secret::SecretValue,
+        totp::{
+            generate_recovery_codes, generate_totp_secret, normalize_recovery_code, verify_totp,
+        },
+        vault::{decrypt, encrypt},
+    },
+};
+
+/// Lifetime of the challenge token handed out after a correct password.
+pub const MFA_CHALLENGE_TTL: i64 = 300;
+/// Codes that may be tried against a single challenge before it is void.
+pub const MFA_CHALLENGE_ATTEMPTS: i64 = 5;
+
+fn invalid_data(message: impl ToString) -> Error {
+    Error::from(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        message.to_string(),
+    ))
+}
+
+/*---------------------------------------------------------------------------
+    The TotpRepository stores TOTP enrollments and the login challenges
+    that are completed with a code.
+
+    TOTP secrets are encrypted with the vault encryption key; recovery
+    codes and challenge tokens are only stored as SHA-256 digests.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct TotpRepository {
+    enrollments: Collection<TotpEnrollmentDocument>,
+    challenges: Collection<MfaChallengeDocument>,
+    keyring: Arc<Keyring>,
+}
+
+impl TotpRepository {
+    pub fn new(
+        client: &Client,
+        db_name: &str,
+        enrollments_collection: &str,
+        challenges_collection: &str,
+        keyring: Arc<Keyring>,
+    ) -> Self {
+        let database = client.database(db_name);
+
+        Self {
+            enrollments: database.collection::<TotpEnrollmentDocument>(enrollments_collection),
+            challenges: database.collection::<MfaChallengeDocument>(challenges_collection),
+            keyring,
+        }
+    }
+
+    /// One enrollment per subject; challenges are purged once they expire.
+    pub async fn create_indexes(&self) -> Result<()> {
+        let subject_index = IndexModel::builder()
+            .keys(doc! { "subject": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.enrollments.create_index(subject_index).await?;
+
+        let ttl_index = IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(std::time::Duration::from_secs(0))
+                    .build(),
+            )
+            .build();
+        let hash_index = IndexModel::builder()
+            .keys(doc! { "challenge_hash": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.challenges
+            .create_indexes([ttl_index, hash_index])
+            .await?;
+        Ok(())
+    }
+
+    /*----------------------------------------------------------------
+    BEGIN an enrollment, replacing any unconfirmed one. Returns the
+    clear-text secret to share with the authenticator app.
+    -----------------------------------------------------------------*/
+    pub async fn begin_enrollment(&self, subject: &str) -> Result<SecretValue> {
+        if self.is_enrolled(subject).await? {
+            return Err(Error::from(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "Two-factor authentication is already enabled.",
+            )));
+        }
+
+        let secret = SecretValue::new(generate_totp_secret());
+        let encrypted_secret = self
+            .keyring
+            .with_key(|key| encrypt(secret.as_bytes(), key))
+            .map_err(invalid_data)?
+            .map_err(invalid_data)?;
+
+        self.enrollments
+            .delete_many(doc! { "subject": subject, "confirmed": false })
+            .await?;
+        self.enrollments
+            .insert_one(&TotpEnrollmentDocument {
+                id: ObjectId::new(),
+                subject: subject.to_string(),
+                secret: STANDARD.encode(encrypted_secret),
+                confirmed: false,
+                recovery_codes: vec![],
+                last_used_step: None,
+                created_at: Utc::now(),
+            })
+            .await?;
+
+        Ok(secret)
+    }
+
+    /*----------------------------------------------------------------
+    CONFIRM a pending enrollment with a first code. Returns the
+    clear-text recovery codes, or `None` when the code is wrong.
+    -----------------------------------------------------------------*/
+    pub async fn confirm_enrollment(
+        &self,
+        subject: &str,
+        code: &str,
+    ) -> Result<Option<Vec<String>>> {
+        let Some(enrollment) = self
+            .enrollments
+            .find_one(doc! { "subject": subject, "confirmed": false })
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let secret = self.decrypt_secret(&enrollment)?;
+        let Some(step) = verify_totp(&secret, code, Utc::now().timestamp() as u64, None) else {
+            return Ok(None);
+        };
+
+        let recovery_codes = generate_recovery_codes();
+        let recovery_code_hashes: Vec<String> = recovery_codes
+            .iter()
+            .map(|code| hash_identifier(&normalize_recovery_code(code)))
+            .collect();
+
+        let result = self
+            .enrollments
+            .update_one(
+                doc! { "_id": enrollment.id, "confirmed": false },
+                doc! { "$set": {
+                    "confirmed": true,
+                    "recovery_codes": recovery_code_hashes,
+                    "last_used_step": step as i64,
+                } },
+            )
+            .await?;
+
+        Ok((result.modified_count == 1).then_some(recovery_codes))
+    }
+
+    /*--------------------------------------------------
+    CHECK whether a subject's logins require a TOTP code
+    ---------------------------------------------------*/
+    pub async fn is_enrolled(&self, subject: &str) -> Result<bool> {
+        let count = self
+            .enrollments
+            .count_documents(doc! { "subject": subject, "confirmed": true })
+            .await?;
+        Ok(count > 0)
+    }
+
+    /*----------------------------------------------------------------
+    VERIFY a TOTP code or consume a recovery code. A code is accepted
+    at most once.
+    -----------------------------------------------------------------*/
+    pub async fn verify_code(&self, subject: &str, code: &str) -> Result<bool> {
+        let Some(enrollment) = self
+            .enrollments
+            .find_one(doc! { "subject": subject, "confirmed": true })
+            .await?
+        else {
+            return Ok(false);
+        };
+
+        let secret = self.decrypt_secret(&enrollment)?;
+        let last_used_step = enrollment.last_used_step.map(|step| step as u64);
+
+        // Recording the step atomically stops two concurrent logins sharing a code.
+        if let Some(step) =
+            verify_totp(&secret, code, Utc::now().timestamp() as u64, last_used_step)
+        {
+            let result = self
+                .enrollments
+                .update_one(
+                    doc! {
+                        "_id": enrollment.id,
+                        "$or": [
+                            { "last_used_step": null },
+                            { "last_used_step": { "$lt": step as i64 } },
+                        ],
+                    },
+                    doc! { "$set": { "last_used_step": step as i64 } },
+                )
+                .await?;
+            return Ok(result.modified_count == 1);
+        }
+
+        let code_hash = hash_identifier(&normalize_recovery_code(code));
+        let result = self
+            .enrollments
+            .update_one(
+                doc! { "_id": enrollment.id, "recovery_codes": &code_hash },
+                doc! { "$pull": { "recovery_codes": &code_hash } },
+            )
+            .await?;
+        Ok(result.modified_count == 1)
+    }
+
+    /*-------------------------------------
+    DISABLE two-factor authentication
+    --------------------------------------*/
+    pub async fn disable(&self, subject: &str) -> Result<bool> {
+        self.challenges
+            .delete_many(doc! { "subject": subject })
+            .await?;
+        let result = self
+            .enrollments
+            .delete_many(doc! { "subject": subject })
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    /*----------------------------------------------
+    MOVE an enrollment when a user's email changes
+    -----------------------------------------------*/
+    pub async fn rename_subject(&self, subject: &str, new_subject: &str) -> Result<()> {
+        self.challenges
+            .delete_many(doc! { "subject": subject })
+            .await?;
+        self.enrollments
+            .update_many(
+                doc! { "subject": subject },
+                doc! { "$set": { "subject": new_subject } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /*---------------------------------------------------------
+    CREATE a login challenge, returning the clear-text token once
+    ----------------------------------------------------------*/
+    pub async fn create_challenge(&self, subject: &str) -> Result<String> {
+        let challenge_token = generate_identifier();
+        let now = Utc::now();
+        let challenge = MfaChallengeDocument {
+            id: ObjectId::new(),
+            subject: subject.to_string(),
+            challenge_hash: hash_identifier(&challenge_token),
+            attempts_remaining: MFA_CHALLENGE_ATTEMPTS,
+            expires_at: now + Duration::seconds(MFA_CHALLENGE_TTL),
+            created_at: now,
+        };
+
+        self.challenges.insert_one(&challenge).await?;
+        Ok(challenge_token)
+    }
+
+    /*----------------------------------------------------------------
+    COMPLETE a login challenge with a code. Returns the subject once
+    the code is accepted, consuming the challenge; every attempt uses
+    up one of the challenge's tries.
+    -----------------------------------------------------------------*/
+    pub async fn complete_challenge(
+        &self,
+        challenge_token: &str,
+        code: &str,
+    ) -> Result<Option<String>> {
+        let filter = doc! {
+            "challenge_hash": hash_identifier(challenge_token),
+            "expiresAt": { "$gt": Utc::now() },
+            "attempts_remaining": { "$gt": 0 },
+        };
+        let Some(challenge) = self
+            .challenges
+            .find_one_and_update(filter, doc! { "$inc": { "attempts_remaining": -1 } })
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        if !self.verify_code(&challenge.subject, code).await? {
+            return Ok(None);
+        }
+
+        self.challenges
+            .delete_one(doc! { "_id": challenge.id })
+            .await?;
+        Ok(Some(challenge.subject))
+    }
+
+    fn decrypt_secret(&self, enrollment: &TotpEnrollmentDocument) -> Result<SecretValue> {
+        let encrypted_secret = STANDARD.decode(&enrollment.secret).map_err(invalid_data)?;
+        let secret = self
+            .keyring
+            .with_key(|key| decrypt(&encrypted_secret, key))
+            .map_err(invalid_data)?
+            .map_err(invalid_data)?;
+        String::from_utf8(secret.to_vec())
+            .map(SecretValue::new)
+            .map_err(invalid_data)
+    }
+}
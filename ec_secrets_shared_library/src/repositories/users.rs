@@ -3,32 +3,82 @@ use chrono::{DateTime, Utc};
 use futures::stream::TryStreamExt;
 use mongodb::{
     Client, Collection,
-    bson::{doc, oid::ObjectId},
-    error::{Error, Result},
-    options::ClientOptions,
+    bson::{Document, doc, oid::ObjectId},
+    error::{Error, ErrorKind, Result, WriteFailure},
+    options::{ClientOptions, ReturnDocument},
 };
 use serde::{Deserialize, Serialize};
 
-use crate::{models::UserDocument, utils::auth::hash_password};
+use crate::{
+    models::{UserDocument, UserStatus, WebAuthnCredential},
+    utils::auth::hash_password,
+};
+
+/// Id of the marker written by the setup that creates the first account.
+const BOOTSTRAP_ID: &str = "admin";
 
 #[derive(Debug)]
 pub struct UserRepository {
     collection: Collection<UserDocument>,
+    bootstrap: Collection<Document>,
 }
 
 impl UserRepository {
-    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
-        let collection = client
-            .database(db_name)
-            .collection::<UserDocument>(collection_name);
-        Self { collection }
+    pub fn new(
+        client: &Client,
+        db_name: &str,
+        collection_name: &str,
+        bootstrap_collection: &str,
+    ) -> Self {
+        let database = client.database(db_name);
+        Self {
+            collection: database.collection::<UserDocument>(collection_name),
+            bootstrap: database.collection::<Document>(bootstrap_collection),
+        }
+    }
+
+    /*-----------------------------------------------------------------
+    CLAIM the bootstrap: `true` for the single setup allowed to make
+    the first account an administrator. Only possible while there are
+    no users; the marker's fixed id lets one concurrent setup win.
+    ------------------------------------------------------------------*/
+    pub async fn claim_bootstrap(&self) -> Result<bool> {
+        if self.count_users().await? > 0 {
+            return Ok(false);
+        }
+        let marker = doc! { "_id": BOOTSTRAP_ID, "claimedAt": Utc::now() };
+        match self.bootstrap.insert_one(marker).await {
+            Ok(_) => Ok(true),
+            Err(error) if is_duplicate_key(&error) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    /*----------------------------------------------------------
+    RELEASE a claimed bootstrap whose account was never created
+    -----------------------------------------------------------*/
+    pub async fn release_bootstrap(&self) -> Result<()> {
+        self.bootstrap
+            .delete_one(doc! { "_id": BOOTSTRAP_ID })
+            .await?;
+        Ok(())
     }
 
     /*-----------------
     CREATE a new user
     --------------------*/
-    pub async fn create_user(&self, email: &str, password: &str) -> Result<UserDocument> {
-        if let Some(_) = self.collection.find_one(doc! { "email": email }).await? {
+    pub async fn create_user(
+        &self,
+        email: &str,
+        password: &str,
+        roles: &[String],
+    ) -> Result<UserDocument> {
+        if self
+            .collection
+            .find_one(doc! { "email": email })
+            .await?
+            .is_some()
+        {
             return Err(Error::from(std::io::Error::new(
                 std::io::ErrorKind::AlreadyExists,
                 "A user with this email already exists.",
@@ -39,7 +89,13 @@ impl UserRepository {
             id: ObjectId::new(),
             email: email.to_string(),
             password: password.to_string(),
+            roles: roles.to_vec(),
+            webauthn_credentials: vec![],
+            password_history: vec![],
+            email_verified: false,
+            status: UserStatus::Active,
             created_at: Utc::now(),
+            last_login: None,
         };
 
         self.collection.insert_one(&user).await?;
@@ -51,7 +107,9 @@ impl UserRepository {
     GET user by id
     ---------------*/
     pub async fn get_user_by_id(&self, id: &str) -> Result<Option<UserDocument>> {
-        let object_id = ObjectId::parse_str(id).unwrap();
+        let Ok(object_id) = ObjectId::parse_str(id) else {
+            return Ok(None);
+        };
         let filter = doc! { "_id": object_id };
         let user = self.collection.find_one(filter).await?;
         Ok(user)
@@ -66,23 +124,43 @@ impl UserRepository {
         Ok(user)
     }
 
-    /*-------------
-    UPDATE a user
-    -------------*/
+    /*------------------------------------------------------------
+    UPDATE a user. A new password moves the current hash to the
+    front of the history, of which the `keep_history` most recent
+    entries are kept; this needs a pipeline update.
+    -------------------------------------------------------------*/
     pub async fn update_user(
         &self,
         id: &str,
         email: Option<&str>,
         password: Option<&str>,
+        keep_history: usize,
     ) -> Result<Option<UserDocument>> {
         let object_id = ObjectId::parse_str(id).unwrap();
         let mut update_doc = doc! {};
 
+        // Values are wrapped in `$literal`: password hashes start with `$`,
+        // which a pipeline would read as a field path.
         if let Some(email) = email {
-            update_doc.insert("email", email);
+            update_doc.insert("email", doc! { "$literal": email });
+            // A new address has to be verified again.
+            update_doc.insert(
+                "email_verified",
+                doc! { "$and": [
+                    { "$eq": ["$email", { "$literal": email }] },
+                    { "$ifNull": ["$email_verified", false] },
+                ] },
+            );
         }
         if let Some(password) = password {
-            update_doc.insert("password", password);
+            update_doc.insert("password", doc! { "$literal": password });
+            update_doc.insert(
+                "password_history",
+                doc! { "$slice": [
+                    { "$concatArrays": [["$password"], { "$ifNull": ["$password_history", []] }] },
+                    keep_history.max(1) as i64,
+                ] },
+            );
         }
 
         if update_doc.is_empty() {
@@ -90,12 +168,32 @@ impl UserRepository {
         }
 
         let filter = doc! { "_id": object_id };
-        let update = doc! { "$set": update_doc };
+        let update = vec![doc! { "$set": update_doc }];
 
         let user = self.collection.find_one_and_update(filter, update).await?;
         Ok(user)
     }
 
+    /*----------------------------------------------------------
+    REPLACE a password hash with an upgraded hash of the same
+    password, unless the password changed in the meantime
+    -----------------------------------------------------------*/
+    pub async fn rehash_password(
+        &self,
+        email: &str,
+        current: &str,
+        upgraded: &str,
+    ) -> Result<bool> {
+        let result = self
+            .collection
+            .update_one(
+                doc! { "email": email, "password": current },
+                doc! { "$set": { "password": upgraded } },
+            )
+            .await?;
+        Ok(result.modified_count == 1)
+    }
+
     /*-------------
     DELETE a user
     ---------------*/
@@ -106,6 +204,180 @@ impl UserRepository {
         Ok(user)
     }
 
+    /*------------------------------------
+    ADD a passkey to a user's credentials
+    -------------------------------------*/
+    pub async fn add_webauthn_credential(
+        &self,
+        email: &str,
+        credential: &WebAuthnCredential,
+    ) -> Result<bool> {
+        // Credential ids are globally unique: one passkey signs in one user.
+        if self
+            .get_user_by_credential_id(&credential.credential_id)
+            .await?
+            .is_some()
+        {
+            return Ok(false);
+        }
+
+        let credential = mongodb::bson::to_bson(credential)?;
+        let result = self
+            .collection
+            .update_one(
+                doc! { "email": email },
+                doc! { "$push": { "webauthn_credentials": credential } },
+            )
+            .await?;
+        Ok(result.modified_count == 1)
+    }
+
+    /*-------------------------------
+    GET user by passkey credential id
+    --------------------------------*/
+    pub async fn get_user_by_credential_id(
+        &self,
+        credential_id: &str,
+    ) -> Result<Option<UserDocument>> {
+        let filter = doc! { "webauthn_credentials.credential_id": credential_id };
+        self.collection.find_one(filter).await
+    }
+
+    /*--------------------------------------------------------------
+    RECORD a passkey's new signature counter. Fails when another
+    login already advanced the counter to or past `sign_count`.
+    ---------------------------------------------------------------*/
+    pub async fn update_webauthn_sign_count(
+        &self,
+        credential_id: &str,
+        sign_count: u32,
+    ) -> Result<bool> {
+        let filter = doc! {
+            "webauthn_credentials": { "$elemMatch": {
+                "credential_id": credential_id,
+                "sign_count": { "$lt": sign_count },
+            } },
+        };
+        let update = doc! { "$set": { "webauthn_credentials.$.sign_count": sign_count } };
+        let result = self.collection.update_one(filter, update).await?;
+        Ok(result.modified_count == 1)
+    }
+
+    /*-------------------------------------
+    REMOVE a passkey from a user's account
+    --------------------------------------*/
+    pub async fn remove_webauthn_credential(
+        &self,
+        email: &str,
+        credential_id: &str,
+    ) -> Result<bool> {
+        let result = self
+            .collection
+            .update_one(
+                doc! { "email": email },
+                doc! { "$pull": { "webauthn_credentials": { "credential_id": credential_id } } },
+            )
+            .await?;
+        Ok(result.modified_count == 1)
+    }
+
+    /*------------------------------------------
+    RECORD the time of a user's latest sign-in
+    -------------------------------------------*/
+    pub async fn record_login(&self, email: &str) -> Result<()> {
+        self.collection
+            .update_one(
+                doc! { "email": email },
+                doc! { "$set": { "lastLogin": mongodb::bson::DateTime::now() } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /*---------------------------------------------
+    MARK a user's email address as verified
+    ----------------------------------------------*/
+    pub async fn mark_email_verified(&self, email: &str) -> Result<bool> {
+        let result = self
+            .collection
+            .update_one(
+                doc! { "email": email },
+                doc! { "$set": { "email_verified": true } },
+            )
+            .await?;
+        Ok(result.matched_count == 1)
+    }
+
+    /*------------------------------------------------
+    SET a user's roles, e.g. from single sign-on groups
+    -------------------------------------------------*/
+    pub async fn set_roles(&self, email: &str, roles: &[String]) -> Result<bool> {
+        let result = self
+            .collection
+            .update_one(
+                doc! { "email": email },
+                doc! { "$set": { "roles": roles.to_vec() } },
+            )
+            .await?;
+        Ok(result.matched_count == 1)
+    }
+
+    /*---------------------------------------------
+    GRANT a role to a user, keeping the ones it has
+    ----------------------------------------------*/
+    pub async fn add_role(&self, email: &str, role: &str) -> Result<bool> {
+        let result = self
+            .collection
+            .update_one(
+                doc! { "email": email },
+                doc! { "$addToSet": { "roles": role } },
+            )
+            .await?;
+        Ok(result.matched_count == 1)
+    }
+
+    /*--------------------------------
+    COUNT the users holding a role
+    ---------------------------------*/
+    pub async fn count_with_role(&self, role: &str) -> Result<u64> {
+        self.collection
+            .count_documents(doc! { "roles": role })
+            .await
+    }
+
+    /*------------------------------------------------
+    SET a user's status, returning the updated account
+    -------------------------------------------------*/
+    pub async fn set_status(&self, id: &str, status: UserStatus) -> Result<Option<UserDocument>> {
+        let object_id = ObjectId::parse_str(id)
+            .map_err(|e| Error::from(std::io::Error::new(std::io::ErrorKind::InvalidInput, e)))?;
+        self.collection
+            .find_one_and_update(
+                doc! { "_id": object_id },
+                doc! { "$set": { "status": status.to_string() } },
+            )
+            .return_document(ReturnDocument::After)
+            .await
+    }
+
+    /*---------------------------------------------------------------
+    RESOLVE the owner id for a token subject: the user id for user
+    accounts, the subject itself for machine identities
+    ----------------------------------------------------------------*/
+    pub async fn owner_id(&self, subject: &str) -> Result<String> {
+        Ok(self
+            .get_user_by_email(subject)
+            .await?
+            .map_or_else(|| subject.to_string(), |user| user.id.to_hex()))
+    }
+
+    /*---------------
+    COUNT all users
+    ---------------*/
+    pub async fn count_users(&self) -> Result<u64> {
+        self.collection.count_documents(doc! {}).await
+    }
+
     /*-------------
     GET all users
     ---------------*/
@@ -121,3 +393,10 @@ impl UserRepository {
         Ok(users)
     }
 }
+
+fn is_duplicate_key(error: &Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == 11000
+    )
+}
@@ -5,38 +5,89 @@ use base64::{Engine, engine::general_purpose};
 use chrono::Utc;
 use futures::stream::TryStreamExt;
 use mongodb::{
-    Client, Collection,
+    Client, ClientSession, Collection,
     bson::{doc, oid::ObjectId},
-    error::Result,
+    error::{Error, Result},
+    options::ReturnDocument,
 };
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use crate::models::VaultDocument;
+use crate::models::{
+    ConflictStrategy, ImportAction, ImportChange, ImportReport, SecretProtection, VaultDocument,
+};
+use crate::repositories::users::UserRepository;
+use crate::utils::import::{ImportEntry, resolve_existing};
+use crate::utils::seal::{Keyring, SealError};
+use crate::utils::secret::SecretValue;
 use crate::utils::vault::{decrypt, encrypt};
 
+/// Result of restoring an archived entry.
+#[derive(Debug)]
+pub enum Restored {
+    /// The entry, now live and owned by the new owner; its value is left out.
+    Entry(VaultDocument),
+    /// No archived entry has that id.
+    NotFound,
+    /// The new owner already holds a live entry under the same key.
+    KeyTaken,
+}
+
+fn crypto_error(message: impl ToString) -> Error {
+    Error::from(std::io::Error::other(message.to_string()))
+}
+
+/// Encrypts a value with the master key, in base64 for safe string storage.
+pub(crate) fn encrypt_value(keyring: &Keyring, value: &str) -> Result<String> {
+    let encrypted_value = keyring
+        .with_key(|key| encrypt(value.as_bytes(), key))
+        .map_err(crypto_error)?
+        .map_err(crypto_error)?;
+    Ok(general_purpose::STANDARD.encode(encrypted_value))
+}
+
+/// Values that do not decrypt are returned as stored; only a sealed
+/// keyring is an error.
+pub(crate) fn decrypt_value(keyring: &Keyring, value: &str) -> Result<SecretValue> {
+    keyring
+        .with_key(|key| {
+            match BASE64_STANDARD
+                .decode(value)
+                .ok()
+                .and_then(|encoded| decrypt(&encoded, key).ok())
+            {
+                Some(decrypted) => String::from_utf8_lossy(&decrypted).into_owned().into(),
+                None => SecretValue::from(value),
+            }
+        })
+        .map_err(crypto_error)
+}
+
 #[derive(Debug)]
 pub struct VaultRepository {
+    client: Client,
     collection: Collection<VaultDocument>,
-    encryption_key: String,
+    keyring: Arc<Keyring>,
 }
 
 impl VaultRepository {
-    /// Create a new repository with a MongoDB collection and a shared SecretVault instance
-    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
+    /// Create a new repository with a MongoDB collection and the keyring
+    /// holding the master key; values can only be read or written unsealed.
+    pub fn new(
+        client: &Client,
+        db_name: &str,
+        collection_name: &str,
+        keyring: Arc<Keyring>,
+    ) -> Self {
         let collection = client
             .database(db_name)
             .collection::<VaultDocument>(collection_name);
 
-        let encryption_key = format!(
-            "{}",
-            std::env::var("ECS_ENCRYPTION_KEY").expect("ECS_ENCRYPTION_KEY must be set")
-        );
-
         Self {
+            client: client.clone(),
             collection,
-            encryption_key,
+            keyring,
         }
     }
 
@@ -48,51 +99,170 @@ impl VaultRepository {
         key: &str,
         value: &str,
         created_by: &str,
+        owner_id: &str,
+        protection: Option<SecretProtection>,
     ) -> Result<VaultDocument> {
-        let encrypted_value = encrypt(value.as_bytes(), self.encryption_key.as_bytes()).unwrap();
-
         let secret = VaultDocument {
             id: ObjectId::new(),
             key: key.to_string(),
-            value: general_purpose::STANDARD.encode(encrypted_value), // Use base64 for safe string storage
+            value: encrypt_value(&self.keyring, value)?.into(),
             created_by: created_by.to_string(),
+            owner_id: owner_id.to_string(),
             created_at: Utc::now(),
+            archived_at: None,
+            protection,
+            version: 1,
         };
 
         self.collection.insert_one(&secret).await?;
         Ok(secret)
     }
 
-    /*---------------
-    GET secret by id
-    ---------------*/
-    pub async fn get_secret_by_id(&self, id: &str, subject: &str) -> Result<Option<String>> {
+    /*---------------------------------------------------------
+    GET secret by id. Protected secrets are not returned here;
+    they are read through an access request.
+    ----------------------------------------------------------*/
+    pub async fn get_secret_by_id(&self, id: &str, owner_id: &str) -> Result<Option<SecretValue>> {
         let object_id = ObjectId::parse_str(id).unwrap();
-        let filter = doc! { "_id": object_id, "created_by": subject };
+        let filter = doc! { "_id": object_id, "owner_id": owner_id, "protection": null };
 
         if let Some(secret) = self.collection.find_one(filter).await? {
-            let encoded_value = BASE64_STANDARD.decode(&secret.value).unwrap();
-            let decrypted_value = decrypt(&encoded_value, &self.encryption_key.as_bytes()).unwrap();
-            return Ok(Some(String::from_utf8_lossy(&decrypted_value).to_string()));
+            return self.decrypt_value(&secret).map(Some);
         }
         Ok(None)
     }
 
-    /*-----------------
-    GET secret by author
-    -------------------*/
-    pub async fn get_secret_by_author(&self, created_by: &str) -> Result<Vec<VaultDocument>> {
-        let filter = doc! { "created_by": created_by };
+    /*-----------------------------------------------------------
+    GET a protected secret for an approved access request
+    ------------------------------------------------------------*/
+    pub async fn get_protected_secret(
+        &self,
+        id: &str,
+        owner_id: &str,
+    ) -> Result<Option<VaultDocument>> {
+        let Ok(object_id) = ObjectId::parse_str(id) else {
+            return Ok(None);
+        };
+        let filter = doc! { "_id": object_id, "owner_id": owner_id, "protection": { "$ne": null } };
+
+        let Some(mut secret) = self.collection.find_one(filter).await? else {
+            return Ok(None);
+        };
+        secret.value = self.decrypt_value(&secret)?;
+        Ok(Some(secret))
+    }
+
+    /*-------------------------------------------
+    GET an entry without decrypting its value
+    --------------------------------------------*/
+    pub async fn get_entry(&self, id: &str) -> Result<Option<VaultDocument>> {
+        let Ok(object_id) = ObjectId::parse_str(id) else {
+            return Ok(None);
+        };
+        self.collection.find_one(doc! { "_id": object_id }).await
+    }
+
+    /*--------------------------------------
+    SET or clear the protection of a secret
+    ---------------------------------------*/
+    pub async fn set_protection(
+        &self,
+        id: &str,
+        protection: Option<SecretProtection>,
+    ) -> Result<bool> {
+        let Ok(object_id) = ObjectId::parse_str(id) else {
+            return Ok(false);
+        };
+        let update = match protection {
+            Some(protection) => doc! { "$set": {
+                "protection": { "require_approval": protection.require_approval }
+            } },
+            None => doc! { "$unset": { "protection": "" } },
+        };
+        let result = self
+            .collection
+            .update_one(doc! { "_id": object_id }, update)
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    /*---------------------------------------------------------------
+    LIST the live entries of all owners, without values. Used to find
+    the secrets a break-glass session reaches.
+    ----------------------------------------------------------------*/
+    pub async fn list_entries(&self) -> Result<Vec<VaultDocument>> {
+        let mut cursor = self
+            .collection
+            .find(doc! { "archivedAt": null })
+            .sort(doc! { "key": 1 })
+            .await?;
+        let mut entries = Vec::new();
+
+        while let Some(mut entry) = cursor.try_next().await? {
+            entry.value = SecretValue::default();
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+
+    /*-----------------------------------------------------------------
+    REVEAL any entry, whoever owns it and however it is protected. Only
+    for break-glass sessions, which audit every read.
+    ------------------------------------------------------------------*/
+    pub async fn reveal_entry(&self, id: &str) -> Result<Option<VaultDocument>> {
+        let Ok(object_id) = ObjectId::parse_str(id) else {
+            return Ok(None);
+        };
+        let filter = doc! { "_id": object_id, "archivedAt": null };
+
+        let Some(mut secret) = self.collection.find_one(filter).await? else {
+            return Ok(None);
+        };
+        secret.value = self.decrypt_value(&secret)?;
+        Ok(Some(secret))
+    }
+
+    /// The plaintext of an entry. Protected entries read as empty, so
+    /// listings never carry their values.
+    fn listed_value(&self, secret: &VaultDocument) -> Result<SecretValue> {
+        if secret.protection.is_some() {
+            return Ok(SecretValue::default());
+        }
+        self.decrypt_value(secret)
+    }
+
+    fn decrypt_value(&self, secret: &VaultDocument) -> Result<SecretValue> {
+        decrypt_value(&self.keyring, &secret.value)
+    }
+
+    /*---------------------------------------------
+    GET the key of a secret without decrypting it
+    ---------------------------------------------*/
+    pub async fn get_secret_key(&self, id: &str, owner_id: &str) -> Result<Option<String>> {
+        let Ok(object_id) = ObjectId::parse_str(id) else {
+            return Ok(None);
+        };
+        let filter = doc! { "_id": object_id, "owner_id": owner_id };
+        let secret = self.collection.find_one(filter).await?;
+        Ok(secret.map(|secret| secret.key))
+    }
+
+    /*--------------------------------------------------------
+    GET an owner's live secrets by author; archived entries are
+    only reachable through the archive
+    ---------------------------------------------------------*/
+    pub async fn get_secret_by_author(
+        &self,
+        created_by: &str,
+        owner_id: &str,
+    ) -> Result<Vec<VaultDocument>> {
+        let filter = doc! { "created_by": created_by, "owner_id": owner_id, "archivedAt": null };
         let mut cursor = self.collection.find(filter).await?;
         let mut secrets = Vec::new();
 
         while let Some(mut secret) = cursor.try_next().await? {
-            if let Ok(encoded_value) = BASE64_STANDARD.decode(&secret.value) {
-                if let Ok(decrypted_value) = decrypt(&encoded_value, self.encryption_key.as_bytes())
-                {
-                    secret.value = String::from_utf8_lossy(&decrypted_value).to_string();
-                }
-            }
+            secret.value = self.listed_value(&secret)?;
             secrets.push(secret);
         }
 
@@ -102,14 +272,16 @@ impl VaultRepository {
     /*-------------
     DELETE a secret
     ---------------*/
-    pub async fn delete_secret(&self, id: &str, subject: &str) -> Result<Option<String>> {
+    pub async fn delete_secret(&self, id: &str, owner_id: &str) -> Result<Option<SecretValue>> {
         let object_id = ObjectId::parse_str(id).unwrap();
-        let filter = doc! { "_id": object_id, "created_by": subject };
+        let filter = doc! { "_id": object_id, "owner_id": owner_id };
 
+        // Refuse before deleting, so the removed value can be returned.
+        if self.keyring.is_sealed() {
+            return Err(crypto_error(SealError::Sealed));
+        }
         if let Some(secret) = self.collection.find_one_and_delete(filter).await? {
-            let encoded_value = BASE64_STANDARD.decode(&secret.value).unwrap();
-            let decrypted_value = decrypt(&encoded_value, &self.encryption_key.as_bytes()).unwrap();
-            return Ok(Some(String::from_utf8_lossy(&decrypted_value).to_string()));
+            return self.decrypt_value(&secret).map(Some);
         }
 
         Ok(None)
@@ -118,20 +290,294 @@ impl VaultRepository {
     /*-------------
     LIST all secrets
     ---------------*/
-    pub async fn list_secrets(&self, subject: &str) -> Result<Vec<VaultDocument>> {
-        let mut cursor = self.collection.find(doc! {"created_by": subject}).await?;
+    pub async fn list_secrets(&self, owner_id: &str) -> Result<Vec<VaultDocument>> {
+        let mut cursor = self
+            .collection
+            .find(doc! { "owner_id": owner_id, "archivedAt": null })
+            .await?;
         let mut secrets = Vec::new();
 
         while let Some(mut secret) = cursor.try_next().await? {
-            if let Ok(encoded_value) = BASE64_STANDARD.decode(&secret.value) {
-                if let Ok(decrypted_value) = decrypt(&encoded_value, self.encryption_key.as_bytes())
-                {
-                    secret.value = String::from_utf8_lossy(&decrypted_value).to_string();
-                }
-            }
+            secret.value = self.listed_value(&secret)?;
             secrets.push(secret);
         }
 
         Ok(secrets)
     }
+
+    /*---------------------------------------------------------------
+    LIST archived entries, without values: the secrets of offboarded
+    users that were not handed to a successor
+    ----------------------------------------------------------------*/
+    pub async fn list_archived(&self) -> Result<Vec<VaultDocument>> {
+        let mut cursor = self
+            .collection
+            .find(doc! { "archivedAt": { "$ne": null } })
+            .sort(doc! { "archivedAt": -1, "key": 1 })
+            .await?;
+        let mut entries = Vec::new();
+
+        while let Some(mut entry) = cursor.try_next().await? {
+            entry.value = SecretValue::default();
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+
+    /*---------------------------------------------------------------
+    RESTORE an archived entry to a new owner, protection unchanged.
+    Refused when the owner already holds a live entry under its key.
+    ----------------------------------------------------------------*/
+    pub async fn restore_archived(
+        &self,
+        id: &str,
+        owner_id: &str,
+        created_by: &str,
+    ) -> Result<Restored> {
+        let Ok(object_id) = ObjectId::parse_str(id) else {
+            return Ok(Restored::NotFound);
+        };
+        let archived = doc! { "_id": object_id, "archivedAt": { "$ne": null } };
+        let Some(entry) = self.collection.find_one(archived.clone()).await? else {
+            return Ok(Restored::NotFound);
+        };
+
+        let taken = doc! { "owner_id": owner_id, "key": &entry.key, "archivedAt": null };
+        if self.collection.find_one(taken).await?.is_some() {
+            return Ok(Restored::KeyTaken);
+        }
+
+        let restored = self
+            .collection
+            .find_one_and_update(
+                archived,
+                doc! {
+                    "$set": { "owner_id": owner_id, "created_by": created_by },
+                    "$unset": { "archivedAt": "" },
+                },
+            )
+            .return_document(ReturnDocument::After)
+            .await?;
+        Ok(match restored {
+            Some(mut entry) => {
+                entry.value = SecretValue::default();
+                Restored::Entry(entry)
+            }
+            None => Restored::NotFound,
+        })
+    }
+
+    /*-------------------------------------
+    COUNT the secrets an owner still holds
+    --------------------------------------*/
+    pub async fn count_owned(&self, owner_id: &str) -> Result<u64> {
+        self.collection
+            .count_documents(doc! { "owner_id": owner_id })
+            .await
+    }
+
+    /*------------------------
+    COUNT every stored entry
+    -------------------------*/
+    pub async fn count_all(&self) -> Result<u64> {
+        self.collection.count_documents(doc! {}).await
+    }
+
+    /*---------------------------------------------------------------
+    ASSIGN owners to entries written before ownership moved from the
+    author's email to their user id. Entries of machine identities,
+    and of authors that no longer exist, keep their subject.
+    ----------------------------------------------------------------*/
+    pub async fn assign_owners(&self, users: &UserRepository) -> Result<u64> {
+        let unowned = doc! { "owner_id": { "$exists": false } };
+        if self.collection.count_documents(unowned.clone()).await? == 0 {
+            return Ok(0);
+        }
+
+        let mut assigned = 0;
+        for user in users.list_users().await? {
+            let result = self
+                .collection
+                .update_many(
+                    doc! {
+                        // Older CLI builds stored the subject JSON-quoted.
+                        "created_by": { "$in": [&user.email, format!("\"{}\"", user.email)] },
+                        "owner_id": { "$exists": false },
+                    },
+                    doc! { "$set": { "owner_id": user.id.to_hex() } },
+                )
+                .await?;
+            assigned += result.modified_count;
+        }
+        let result = self
+            .collection
+            .update_many(
+                unowned,
+                vec![doc! { "$set": { "owner_id": "$created_by" } }],
+            )
+            .await?;
+        Ok(assigned + result.modified_count)
+    }
+
+    /*---------------------------------------------------------------
+    IMPORT entries for one owner in a single transaction, so an import
+    applies completely or not at all; a dry run runs the same
+    transaction and aborts it. A key the owner holds already is
+    compared against its latest version, and `conflict` decides what
+    happens when the value differs; a protected key is skipped without
+    being compared. Transactions need MongoDB to run
+    as a replica set.
+    ----------------------------------------------------------------*/
+    pub async fn import(
+        &self,
+        entries: &[ImportEntry],
+        created_by: &str,
+        owner_id: &str,
+        conflict: ConflictStrategy,
+        dry_run: bool,
+    ) -> Result<ImportReport> {
+        // Encrypted before the transaction starts, which keeps it short.
+        let encrypted = entries
+            .iter()
+            .map(|(_, value)| encrypt_value(&self.keyring, value))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut session = self.client.start_session().await?;
+        session.start_transaction().await?;
+
+        let mut changes = Vec::with_capacity(entries.len());
+        let mut applied = Ok(());
+        for ((key, value), encrypted) in entries.iter().zip(encrypted) {
+            let change = self
+                .import_entry(
+                    &mut session,
+                    key,
+                    value,
+                    encrypted,
+                    created_by,
+                    owner_id,
+                    conflict,
+                )
+                .await;
+            match change {
+                Ok(change) => changes.push(change),
+                Err(e) => {
+                    applied = Err(e);
+                    break;
+                }
+            }
+        }
+
+        match applied {
+            Ok(()) => {
+                if dry_run {
+                    session.abort_transaction().await?;
+                } else {
+                    session.commit_transaction().await?;
+                }
+                Ok(ImportReport {
+                    dry_run,
+                    conflict,
+                    changes,
+                })
+            }
+            Err(e) => {
+                // The transaction is rolled back either way; report the original failure.
+                let _ = session.abort_transaction().await;
+                Err(e)
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn import_entry(
+        &self,
+        session: &mut ClientSession,
+        key: &str,
+        value: &SecretValue,
+        encrypted: String,
+        created_by: &str,
+        owner_id: &str,
+        conflict: ConflictStrategy,
+    ) -> Result<ImportChange> {
+        let latest = self
+            .collection
+            .find_one(doc! { "owner_id": owner_id, "key": key, "archivedAt": null })
+            .sort(doc! { "version": -1, "createdAt": -1 })
+            .session(&mut *session)
+            .await?;
+
+        let change = |action, version| ImportChange {
+            key: key.to_string(),
+            action,
+            version,
+        };
+        let Some(latest) = latest else {
+            self.insert_version(session, key, encrypted, created_by, owner_id, None, 1)
+                .await?;
+            return Ok(change(ImportAction::Create, 1));
+        };
+
+        let action = resolve_existing(latest.protection.is_some(), conflict, || {
+            self.decrypt_value(&latest)
+                .map(|stored| stored.as_str() == value.as_str())
+        })?;
+        match action {
+            ImportAction::Overwrite => {
+                self.collection
+                    .update_one(
+                        doc! { "_id": latest.id },
+                        doc! { "$set": { "value": encrypted } },
+                    )
+                    .session(&mut *session)
+                    .await?;
+                Ok(change(action, latest.version))
+            }
+            ImportAction::NewVersion => {
+                let version = latest.version + 1;
+                self.insert_version(
+                    session,
+                    key,
+                    encrypted,
+                    created_by,
+                    owner_id,
+                    latest.protection,
+                    version,
+                )
+                .await?;
+                Ok(change(action, version))
+            }
+            _ => Ok(change(action, latest.version)),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn insert_version(
+        &self,
+        session: &mut ClientSession,
+        key: &str,
+        encrypted: String,
+        created_by: &str,
+        owner_id: &str,
+        protection: Option<SecretProtection>,
+        version: u32,
+    ) -> Result<()> {
+        let secret = VaultDocument {
+            id: ObjectId::new(),
+            key: key.to_string(),
+            value: encrypted.into(),
+            created_by: created_by.to_string(),
+            owner_id: owner_id.to_string(),
+            created_at: Utc::now(),
+            archived_at: None,
+            protection,
+            version,
+        };
+        self.collection
+            .insert_one(&secret)
+            .session(&mut *session)
+            .await?;
+        Ok(())
+    }
 }
@@ -0,0 +1,89 @@
+use chrono::{Duration, Utc};
+use mongodb::{
+    Client, Collection, IndexModel,
+    bson::{doc, oid::ObjectId},
+    error::Result,
+    options::IndexOptions,
+};
+
+use crate::{models::WebAuthnChallengeDocument, utils::auth::generate_identifier};
+
+pub const REGISTRATION_CEREMONY: &str = "registration";
+pub const AUTHENTICATION_CEREMONY: &str = "authentication";
+
+/*---------------------------------------------------------------------------
+    The WebAuthnRepository keeps the challenges of passkey ceremonies in
+    progress. A challenge is handed to the browser, signed by the
+    authenticator and consumed exactly once when the ceremony completes.
+---------------------------------------------------------------------------*/
+#[derive(Debug)]
+pub struct WebAuthnRepository {
+    challenges: Collection<WebAuthnChallengeDocument>,
+}
+
+impl WebAuthnRepository {
+    pub fn new(client: &Client, db_name: &str, collection_name: &str) -> Self {
+        let challenges = client
+            .database(db_name)
+            .collection::<WebAuthnChallengeDocument>(collection_name);
+        Self { challenges }
+    }
+
+    /// Lets MongoDB purge abandoned ceremonies once they expire.
+    pub async fn create_indexes(&self) -> Result<()> {
+        let ttl_index = IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(std::time::Duration::from_secs(0))
+                    .build(),
+            )
+            .build();
+        let challenge_index = IndexModel::builder()
+            .keys(doc! { "challenge": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.challenges
+            .create_indexes([ttl_index, challenge_index])
+            .await?;
+        Ok(())
+    }
+
+    /*-------------------------------
+    CREATE a challenge for a ceremony
+    --------------------------------*/
+    pub async fn create_challenge(
+        &self,
+        ceremony: &str,
+        subject: Option<&str>,
+        lifetime: Duration,
+    ) -> Result<String> {
+        let challenge = generate_identifier();
+        let document = WebAuthnChallengeDocument {
+            id: ObjectId::new(),
+            challenge: challenge.clone(),
+            ceremony: ceremony.to_string(),
+            subject: subject.map(str::to_string),
+            expires_at: Utc::now() + lifetime,
+        };
+
+        self.challenges.insert_one(&document).await?;
+        Ok(challenge)
+    }
+
+    /*-----------------------------------------------------
+    CONSUME a pending challenge; each can complete only once
+    ------------------------------------------------------*/
+    pub async fn consume_challenge(
+        &self,
+        challenge: &str,
+        ceremony: &str,
+    ) -> Result<Option<WebAuthnChallengeDocument>> {
+        let filter = doc! {
+            "challenge": challenge,
+            "ceremony": ceremony,
+            "expiresAt": { "$gt": Utc::now() },
+        };
+        self.challenges.find_one_and_delete(filter).await
+    }
+}
@@ -0,0 +1,317 @@
+use async_trait::async_trait;
+use chrono::Duration;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::models::AccessRequest;
+use crate::utils::break_glass::BreakGlassNotice;
+use crate::utils::mail::{MailConfig, MailError, MailMessage, MailTransport};
+
+/*---------------------------------------------------------------
+Access to protected secrets. A reader states a justification and,
+for secrets that require it, waits for a second user holding the
+`approver` or `admin` role to approve. Approved requests release
+the value for `access_window` seconds; undecided requests lapse
+after `request_ttl` seconds.
+
+Read from the `[default.auth.approvals]` table of the server's
+Rocket configuration.
+----------------------------------------------------------------*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApprovalConfig {
+    pub access_window: i64,
+    pub request_ttl: i64,
+    pub min_justification_length: usize,
+    pub notifier: NotifierKind,
+}
+
+impl Default for ApprovalConfig {
+    fn default() -> Self {
+        Self {
+            access_window: Duration::minutes(15).num_seconds(),
+            request_ttl: Duration::days(1).num_seconds(),
+            min_justification_length: 10,
+            notifier: NotifierKind::Log,
+        }
+    }
+}
+
+impl ApprovalConfig {
+    pub fn access_window(&self) -> Duration {
+        Duration::seconds(self.access_window)
+    }
+
+    pub fn request_lifetime(&self) -> Duration {
+        Duration::seconds(self.request_ttl)
+    }
+
+    /// The trimmed justification, or why it is not accepted.
+    pub fn justification(&self, text: &str) -> Result<String, String> {
+        let text = text.trim();
+        if text.chars().count() < self.min_justification_length {
+            return Err(format!(
+                "A justification of at least {} characters is required",
+                self.min_justification_length
+            ));
+        }
+        Ok(text.to_string())
+    }
+}
+
+/// `log` writes notices to the server log; `mail` sends them through the
+/// mail transport configured under `[default.auth.mail]`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifierKind {
+    Log,
+    Mail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApprovalEvent {
+    /// Sent to the users who may approve.
+    Requested,
+    /// Sent to the requester.
+    Approved,
+    /// Sent to the requester.
+    Denied,
+}
+
+#[derive(Debug, Clone)]
+pub struct ApprovalNotice {
+    pub event: ApprovalEvent,
+    pub request: AccessRequest,
+    pub recipients: Vec<String>,
+}
+
+impl ApprovalNotice {
+    pub fn subject(&self) -> String {
+        match self.event {
+            ApprovalEvent::Requested => {
+                format!("Access requested to {}", self.request.secret_key)
+            }
+            ApprovalEvent::Approved => {
+                format!("Access to {} approved", self.request.secret_key)
+            }
+            ApprovalEvent::Denied => format!("Access to {} denied", self.request.secret_key),
+        }
+    }
+
+    pub fn body(&self, public_url: &str) -> String {
+        let request = &self.request;
+        let comment = request
+            .comment
+            .as_deref()
+            .map(|comment| format!("\nComment: {}", comment))
+            .unwrap_or_default();
+        match self.event {
+            ApprovalEvent::Requested => format!(
+                "{} asked to read the protected secret {}.\n\nJustification: {}\n\nApprove or deny the request before {}:\n\n{}/access-requests/{}",
+                request.requester,
+                request.secret_key,
+                request.justification,
+                request.expires_at,
+                public_url.trim_end_matches('/'),
+                request.id
+            ),
+            ApprovalEvent::Approved => format!(
+                "{} approved your request to read {}. The value can be read until {}.{}",
+                request.decided_by.as_deref().unwrap_or("An approver"),
+                request.secret_key,
+                request.access_until.as_deref().unwrap_or("-"),
+                comment
+            ),
+            ApprovalEvent::Denied => format!(
+                "{} denied your request to read {}.{}",
+                request.decided_by.as_deref().unwrap_or("An approver"),
+                request.secret_key,
+                comment
+            ),
+        }
+    }
+}
+
+/// Tells people about access requests and break-glass sessions;
+/// implement it to plug in another channel such as chat.
+#[async_trait]
+pub trait ApprovalNotifier: Send + Sync {
+    async fn notify(&self, notice: &ApprovalNotice) -> Result<(), MailError>;
+    async fn notify_break_glass(&self, notice: &BreakGlassNotice) -> Result<(), MailError>;
+}
+
+/// Builds the notifier selected by the configuration.
+pub fn notifier(
+    config: &ApprovalConfig,
+    mail: &MailConfig,
+    mailer: Arc<dyn MailTransport>,
+) -> Arc<dyn ApprovalNotifier> {
+    match config.notifier {
+        NotifierKind::Log => Arc::new(LogNotifier),
+        NotifierKind::Mail => Arc::new(MailNotifier {
+            mailer,
+            public_url: mail.public_url.clone(),
+        }),
+    }
+}
+
+pub struct LogNotifier;
+
+#[async_trait]
+impl ApprovalNotifier for LogNotifier {
+    async fn notify(&self, notice: &ApprovalNotice) -> Result<(), MailError> {
+        info!(
+            target: "approvals",
+            "{} (request {}, to {})",
+            notice.subject(),
+            notice.request.id,
+            notice.recipients.join(", ")
+        );
+        Ok(())
+    }
+
+    async fn notify_break_glass(&self, notice: &BreakGlassNotice) -> Result<(), MailError> {
+        warn!(
+            target: "approvals",
+            "{}: {} (session {}, to {})",
+            notice.subject(),
+            notice.session.reason,
+            notice.session.id,
+            notice.recipients.join(", ")
+        );
+        Ok(())
+    }
+}
+
+pub struct MailNotifier {
+    pub mailer: Arc<dyn MailTransport>,
+    pub public_url: String,
+}
+
+#[async_trait]
+impl ApprovalNotifier for MailNotifier {
+    async fn notify(&self, notice: &ApprovalNotice) -> Result<(), MailError> {
+        self.send(
+            &notice.recipients,
+            notice.subject(),
+            notice.body(&self.public_url),
+        )
+        .await
+    }
+
+    async fn notify_break_glass(&self, notice: &BreakGlassNotice) -> Result<(), MailError> {
+        self.send(
+            &notice.recipients,
+            notice.subject(),
+            notice.body(&self.public_url),
+        )
+        .await
+    }
+}
+
+impl MailNotifier {
+    async fn send(
+        &self,
+        recipients: &[String],
+        subject: String,
+        body: String,
+    ) -> Result<(), MailError> {
+        // Machine identities have no mailbox.
+        for to in recipients.iter().filter(|to| to.contains('@')) {
+            self.mailer
+                .send(&MailMessage {
+                    to: to.clone(),
+                    subject: subject.clone(),
+                    body: body.clone(),
+                })
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AccessRequestStatus;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Outbox(Mutex<Vec<MailMessage>>);
+
+    #[async_trait]
+    impl MailTransport for Outbox {
+        async fn send(&self, message: &MailMessage) -> Result<(), MailError> {
+            self.0.lock().unwrap().push(message.clone());
+            Ok(())
+        }
+    }
+
+    fn request() -> AccessRequest {
+        AccessRequest {
+            id: "665f1c0ffee0000000000001".to_string(),
+            secret_id: "665f1c0ffee0000000000002".to_string(),
+            secret_key: "prod/db_password".to_string(),
+            requester: "jane@example.com".to_string(),
+            justification: "Rotating the replica credentials".to_string(),
+            status: AccessRequestStatus::Pending,
+            created_at: "2025-03-01T12:00:00+00:00".to_string(),
+            expires_at: "2025-03-02T12:00:00+00:00".to_string(),
+            decided_by: None,
+            decided_at: None,
+            comment: None,
+            access_until: None,
+        }
+    }
+
+    #[test]
+    fn justifications_need_substance() {
+        let config = ApprovalConfig::default();
+        assert!(config.justification("   debugging ").is_err());
+        assert_eq!(
+            config
+                .justification("  INC-4211: rotate credentials\n")
+                .unwrap(),
+            "INC-4211: rotate credentials"
+        );
+    }
+
+    #[tokio::test]
+    async fn mail_notifier_skips_machine_identities() {
+        let outbox = Arc::new(Outbox::default());
+        let config = ApprovalConfig {
+            notifier: NotifierKind::Mail,
+            ..ApprovalConfig::default()
+        };
+        let mail = MailConfig {
+            public_url: "https://locksmith.example.com/".to_string(),
+            ..MailConfig::default()
+        };
+        let notifier = notifier(&config, &mail, outbox.clone());
+
+        notifier
+            .notify(&ApprovalNotice {
+                event: ApprovalEvent::Requested,
+                request: request(),
+                recipients: vec!["ops@example.com".to_string(), "role:deployer".to_string()],
+            })
+            .await
+            .unwrap();
+
+        let sent = outbox.0.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "ops@example.com");
+        assert_eq!(sent[0].subject, "Access requested to prod/db_password");
+        assert!(
+            sent[0]
+                .body
+                .contains("Justification: Rotating the replica credentials")
+        );
+        assert!(
+            sent[0]
+                .body
+                .contains("https://locksmith.example.com/access-requests/665f1c0ffee0000000000001")
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::models::{AuditEntryDocument, AuditEvent, ChainBreak, ChainVerification};
+
+/*---------------------------------------------------------------
+The audit log is a hash chain: every entry carries an HMAC-SHA256
+of its own contents together with the hash of the entry before
+it, starting from `GENESIS_HASH`. Editing an entry changes its
+hash, and removing one leaves a gap in the sequence numbers and a
+`prev_hash` that no longer links up, so both show on verification.
+The HMAC key is derived from the server's authentication key, so
+whoever can write to the database but does not hold that key
+cannot forge a chain that verifies; rotating the key starts a log
+that verifies only with the new one.
+----------------------------------------------------------------*/
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// The key the chain hashes are computed with.
+#[derive(Clone)]
+pub struct AuditKey(Zeroizing<Vec<u8>>);
+
+impl AuditKey {
+    pub fn derive(key: &[u8]) -> Self {
+        let mut mac =
+            <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(b"locksmith-audit:mac");
+        Self(Zeroizing::new(mac.finalize().into_bytes().to_vec()))
+    }
+
+    /// The chain hash of an entry. Timestamps count at millisecond
+    /// precision, which is what MongoDB stores.
+    pub fn entry_hash(
+        &self,
+        sequence: i64,
+        timestamp: DateTime<Utc>,
+        event: &AuditEvent,
+        prev_hash: &str,
+    ) -> String {
+        let canonical = json!([
+            sequence,
+            timestamp.timestamp_millis(),
+            event.actor,
+            event.action,
+            event.target,
+            event.outcome,
+            event.source_ip,
+            event.user_agent,
+            event.detail,
+            prev_hash,
+        ]);
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.0)
+            .expect("HMAC accepts keys of any length");
+        mac.update(canonical.to_string().as_bytes());
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+}
+
+impl std::fmt::Debug for AuditKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AuditKey(..)")
+    }
+}
+
+/// Walks the chain in sequence order, stopping at the first break.
+#[derive(Debug)]
+pub struct ChainVerifier<'a> {
+    key: &'a AuditKey,
+    entries: u64,
+    head_sequence: i64,
+    head_hash: String,
+    broken_at: Option<ChainBreak>,
+}
+
+impl<'a> ChainVerifier<'a> {
+    pub fn new(key: &'a AuditKey) -> Self {
+        Self {
+            key,
+            entries: 0,
+            head_sequence: 0,
+            head_hash: GENESIS_HASH.to_string(),
+            broken_at: None,
+        }
+    }
+
+    /// Checks the next entry; returns `false` once the chain is broken.
+    pub fn push(&mut self, entry: &AuditEntryDocument) -> bool {
+        if self.broken_at.is_some() {
+            return false;
+        }
+
+        let expected = self.head_sequence + 1;
+        let reason = if entry.sequence != expected {
+            Some(format!("entry {} is missing", expected))
+        } else if entry.prev_hash != self.head_hash {
+            Some("does not link to the previous entry".to_string())
+        } else if entry.hash
+            != self.key.entry_hash(
+                entry.sequence,
+                entry.timestamp,
+                &entry.event,
+                &entry.prev_hash,
+            )
+        {
+            Some("contents were modified".to_string())
+        } else {
+            None
+        };
+
+        if let Some(reason) = reason {
+            self.broken_at = Some(ChainBreak {
+                sequence: entry.sequence,
+                reason,
+            });
+            return false;
+        }
+        self.entries += 1;
+        self.head_sequence = entry.sequence;
+        self.head_hash = entry.hash.clone();
+        true
+    }
+
+    pub fn finish(self) -> ChainVerification {
+        ChainVerification {
+            valid: self.broken_at.is_none(),
+            entries: self.entries,
+            head_sequence: self.head_sequence,
+            head_hash: self.head_hash,
+            broken_at: self.broken_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AuditOutcome;
+    use mongodb::bson::oid::ObjectId;
+
+    fn key() -> AuditKey {
+        AuditKey::derive(b"test-authentication-key")
+    }
+
+    fn chain(length: i64) -> Vec<AuditEntryDocument> {
+        let mut prev_hash = GENESIS_HASH.to_string();
+        (1..=length)
+            .map(|sequence| {
+                let event = AuditEvent {
+                    actor: "jane@example.com".to_string(),
+                    action: "secret.reveal".to_string(),
+                    target: Some(format!("secret-{}", sequence)),
+                    outcome: AuditOutcome::Success,
+                    source_ip: Some("192.0.2.10".to_string()),
+                    user_agent: None,
+                    detail: None,
+                };
+                let timestamp = Utc::now();
+                let hash = key().entry_hash(sequence, timestamp, &event, &prev_hash);
+                let entry = AuditEntryDocument {
+                    id: ObjectId::new(),
+                    sequence,
+                    timestamp,
+                    event,
+                    prev_hash: prev_hash.clone(),
+                    hash: hash.clone(),
+                };
+                prev_hash = hash;
+                entry
+            })
+            .collect()
+    }
+
+    fn verify(entries: &[AuditEntryDocument]) -> ChainVerification {
+        let key = key();
+        let mut verifier = ChainVerifier::new(&key);
+        entries.iter().all(|entry| verifier.push(entry));
+        verifier.finish()
+    }
+
+    #[test]
+    fn accepts_an_intact_chain() {
+        let entries = chain(3);
+        let verification = verify(&entries);
+
+        assert!(verification.valid);
+        assert_eq!(verification.entries, 3);
+        assert_eq!(verification.head_sequence, 3);
+        assert_eq!(verification.head_hash, entries[2].hash);
+        assert!(verify(&[]).valid);
+    }
+
+    #[test]
+    fn detects_edited_entries() {
+        let mut entries = chain(3);
+        entries[1].event.outcome = AuditOutcome::Denied;
+
+        let verification = verify(&entries);
+        assert!(!verification.valid);
+        assert_eq!(verification.entries, 1);
+        assert_eq!(
+            verification.broken_at,
+            Some(ChainBreak {
+                sequence: 2,
+                reason: "contents were modified".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn detects_removed_and_reforged_entries() {
+        let mut entries = chain(4);
+        entries.remove(1);
+        assert_eq!(
+            verify(&entries).broken_at.map(|at| at.reason),
+            Some("entry 2 is missing".to_string())
+        );
+
+        // Renumbering around the gap still leaves a broken link.
+        entries[1].sequence = 2;
+        entries[1].hash = key().entry_hash(
+            2,
+            entries[1].timestamp,
+            &entries[1].event,
+            &entries[1].prev_hash,
+        );
+        assert_eq!(
+            verify(&entries).broken_at.map(|at| at.reason),
+            Some("does not link to the previous entry".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_chains_hashed_without_the_key() {
+        // Rehashing an edited chain only helps with the server's key.
+        let forger = AuditKey::derive(b"guessed-key");
+        let mut entries = chain(2);
+        entries[1].event.outcome = AuditOutcome::Denied;
+        entries[1].hash = forger.entry_hash(
+            2,
+            entries[1].timestamp,
+            &entries[1].event,
+            &entries[1].prev_hash,
+        );
+        assert_eq!(
+            verify(&entries).broken_at.map(|at| at.reason),
+            Some("contents were modified".to_string())
+        );
+    }
+
+    #[test]
+    fn entries_survive_storage() {
+        // Stored timestamps lose sub-millisecond precision; hashes must not depend on it.
+        let stored: Vec<AuditEntryDocument> = chain(2)
+            .iter()
+            .map(|entry| {
+                let document = mongodb::bson::to_document(entry).unwrap();
+                assert_eq!(document.get_str("action").unwrap(), "secret.reveal");
+                mongodb::bson::from_document(document).unwrap()
+            })
+            .collect();
+
+        assert!(verify(&stored).valid);
+    }
+}
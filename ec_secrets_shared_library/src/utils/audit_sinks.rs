@@ -0,0 +1,783 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpStream, UdpSocket},
+    sync::mpsc::{self, error::TrySendError},
+};
+
+use crate::models::{AuditEntry, AuditSeverity};
+
+/*---------------------------------------------------------------
+Audit entries are copied to external sinks once they are in the
+log: a JSON-lines file that rotates by size, an RFC 5424 syslog
+collector over UDP or TCP, and an HTTP webhook signed with
+HMAC-SHA256.
+
+Each sink has its own bounded queue drained by a background task,
+so a slow or unreachable sink never holds up a request. When a
+queue is full the entry is dropped for that sink with a warning;
+the log itself stays complete, and receivers can spot the gap in
+the sequence numbers.
+
+Read from the `[default.audit]` table of the server's Rocket
+configuration.
+----------------------------------------------------------------*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditExportConfig {
+    /// Entries each sink may fall behind before new ones are dropped.
+    pub queue_size: usize,
+    pub file: FileSinkConfig,
+    pub syslog: SyslogSinkConfig,
+    pub webhook: WebhookSinkConfig,
+}
+
+impl Default for AuditExportConfig {
+    fn default() -> Self {
+        Self {
+            queue_size: 1024,
+            file: FileSinkConfig::default(),
+            syslog: SyslogSinkConfig::default(),
+            webhook: WebhookSinkConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileSinkConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    /// Size at which the file is rotated to `<path>.1`.
+    pub max_bytes: u64,
+    /// Rotated files kept next to the active one.
+    pub max_files: u32,
+}
+
+impl Default for FileSinkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("logs/audit.jsonl"),
+            max_bytes: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyslogSinkConfig {
+    pub enabled: bool,
+    pub address: String,
+    pub protocol: SyslogProtocol,
+    /// Syslog facility code, 0 to 23; 10 is `authpriv`.
+    pub facility: u8,
+    pub app_name: String,
+    /// Sent as HOSTNAME; defaults to the `HOSTNAME` environment variable.
+    pub hostname: Option<String>,
+}
+
+impl Default for SyslogSinkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1:514".to_string(),
+            protocol: SyslogProtocol::Udp,
+            facility: 10,
+            app_name: "locksmith".to_string(),
+            hostname: None,
+        }
+    }
+}
+
+/// `tcp` frames messages with octet counting (RFC 6587).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogProtocol {
+    Udp,
+    Tcp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookSinkConfig {
+    pub enabled: bool,
+    pub url: String,
+    /// HMAC key for the `X-Locksmith-Signature` header; may be supplied
+    /// through the `ECS_AUDIT_WEBHOOK_SECRET` environment variable.
+    pub secret: Option<String>,
+    pub max_retries: u32,
+    /// Seconds before the first retry; doubles with every attempt.
+    pub retry_delay: u64,
+    /// Seconds to wait for the receiver.
+    pub timeout: u64,
+}
+
+impl Default for WebhookSinkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: "http://127.0.0.1:9000/audit".to_string(),
+            secret: None,
+            max_retries: 5,
+            retry_delay: 1,
+            timeout: 10,
+        }
+    }
+}
+
+impl WebhookSinkConfig {
+    fn secret(&self) -> Option<String> {
+        std::env::var("ECS_AUDIT_WEBHOOK_SECRET")
+            .ok()
+            .or_else(|| self.secret.clone())
+            .filter(|secret| !secret.is_empty())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum AuditSinkError {
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    #[error("failed to serialize entry: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("receiver answered {0}")]
+    Rejected(u16),
+}
+
+/// Delivers audit entries; implement it to plug in another destination.
+#[async_trait]
+pub trait AuditSink: Send {
+    fn name(&self) -> &'static str;
+    async fn deliver(&mut self, entry: &AuditEntry) -> Result<(), AuditSinkError>;
+}
+
+/// Builds the sinks enabled in the configuration.
+pub fn sinks(config: &AuditExportConfig) -> Result<Vec<Box<dyn AuditSink>>, AuditSinkError> {
+    let mut sinks: Vec<Box<dyn AuditSink>> = Vec::new();
+    if config.file.enabled {
+        sinks.push(Box::new(FileSink::new(config.file.clone())));
+    }
+    if config.syslog.enabled {
+        sinks.push(Box::new(SyslogSink::new(config.syslog.clone())?));
+    }
+    if config.webhook.enabled {
+        sinks.push(Box::new(WebhookSink::new(&config.webhook)?));
+    }
+    Ok(sinks)
+}
+
+/*---------------------------------------------------------------
+The exporter fans entries out to one queue per sink.
+----------------------------------------------------------------*/
+pub struct AuditExporter {
+    queues: Vec<(&'static str, mpsc::Sender<Arc<AuditEntry>>)>,
+}
+
+impl AuditExporter {
+    /// Spawns a delivery task per sink; call from within a Tokio runtime.
+    pub fn start(sinks: Vec<Box<dyn AuditSink>>, queue_size: usize) -> Self {
+        let queues = sinks
+            .into_iter()
+            .map(|mut sink| {
+                let name = sink.name();
+                let (sender, mut receiver) = mpsc::channel::<Arc<AuditEntry>>(queue_size.max(1));
+                tokio::spawn(async move {
+                    while let Some(entry) = receiver.recv().await {
+                        if let Err(e) = sink.deliver(&entry).await {
+                            error!(
+                                "Audit sink '{}' failed to deliver entry {}: {}",
+                                name, entry.sequence, e
+                            );
+                        }
+                    }
+                });
+                (name, sender)
+            })
+            .collect();
+        Self { queues }
+    }
+
+    /// Queues an entry for every sink without waiting for delivery.
+    pub fn export(&self, entry: AuditEntry) {
+        let entry = Arc::new(entry);
+        for (name, sender) in &self.queues {
+            match sender.try_send(Arc::clone(&entry)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => warn!(
+                    "Audit sink '{}' is falling behind; dropped entry {}",
+                    name, entry.sequence
+                ),
+                Err(TrySendError::Closed(_)) => error!(
+                    "Audit sink '{}' has stopped; dropped entry {}",
+                    name, entry.sequence
+                ),
+            }
+        }
+    }
+}
+
+/*---------------------------------------------------------------
+JSON lines, one entry per line
+----------------------------------------------------------------*/
+pub struct FileSink {
+    config: FileSinkConfig,
+}
+
+impl FileSink {
+    pub fn new(config: FileSinkConfig) -> Self {
+        Self { config }
+    }
+
+    fn rotated(&self, index: u32) -> PathBuf {
+        let mut name = self.config.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    /// Shifts `<path>.N` to `<path>.N+1`, dropping the oldest, and moves
+    /// the active file to `<path>.1`.
+    fn rotate(&self) -> std::io::Result<()> {
+        if self.config.max_files == 0 {
+            return fs::remove_file(&self.config.path);
+        }
+        remove_if_exists(&self.rotated(self.config.max_files))?;
+        for index in (1..self.config.max_files).rev() {
+            let from = self.rotated(index);
+            if from.exists() {
+                fs::rename(from, self.rotated(index + 1))?;
+            }
+        }
+        fs::rename(&self.config.path, self.rotated(1))
+    }
+}
+
+#[async_trait]
+impl AuditSink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn deliver(&mut self, entry: &AuditEntry) -> Result<(), AuditSinkError> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        if let Some(directory) = self.config.path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let size = fs::metadata(&self.config.path)
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        if size > 0 && size + line.len() as u64 > self.config.max_bytes {
+            self.rotate()?;
+        }
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)?
+            .write_all(&line)?;
+        Ok(())
+    }
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/*---------------------------------------------------------------
+RFC 5424 syslog
+----------------------------------------------------------------*/
+
+/// Structured data ID; 32473 is the private enterprise number reserved
+/// for documentation.
+const SD_ID: &str = "audit@32473";
+
+pub struct SyslogSink {
+    config: SyslogSinkConfig,
+    hostname: String,
+    stream: Option<TcpStream>,
+}
+
+impl SyslogSink {
+    pub fn new(config: SyslogSinkConfig) -> Result<Self, AuditSinkError> {
+        if config.facility > 23 {
+            return Err(AuditSinkError::Config(format!(
+                "syslog facility {} is out of range (0-23)",
+                config.facility
+            )));
+        }
+        let hostname = config
+            .hostname
+            .clone()
+            .or_else(|| std::env::var("HOSTNAME").ok())
+            .unwrap_or_default();
+        Ok(Self {
+            config,
+            hostname,
+            stream: None,
+        })
+    }
+
+    async fn send_tcp(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        // A dropped connection is reopened once before giving up.
+        for _ in 0..2 {
+            let stream = match &mut self.stream {
+                Some(stream) => stream,
+                None => self
+                    .stream
+                    .insert(TcpStream::connect(&self.config.address).await?),
+            };
+            match stream.write_all(frame).await {
+                Ok(()) => return Ok(()),
+                Err(_) => self.stream = None,
+            }
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::BrokenPipe,
+            "syslog connection closed",
+        ))
+    }
+}
+
+#[async_trait]
+impl AuditSink for SyslogSink {
+    fn name(&self) -> &'static str {
+        "syslog"
+    }
+
+    async fn deliver(&mut self, entry: &AuditEntry) -> Result<(), AuditSinkError> {
+        let message = syslog_message(&self.config, &self.hostname, entry)?;
+        match self.config.protocol {
+            SyslogProtocol::Udp => {
+                let local = if self.config.address.starts_with('[') {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                };
+                let socket = UdpSocket::bind(local).await?;
+                socket.connect(&self.config.address).await?;
+                socket.send(message.as_bytes()).await?;
+            }
+            SyslogProtocol::Tcp => {
+                let frame = format!("{} {}", message.len(), message);
+                self.send_tcp(frame.as_bytes()).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Formats an entry as an RFC 5424 message: the entry's severity maps
+/// onto the syslog one, the key fields go into structured data and the whole
+/// entry follows as JSON.
+pub fn syslog_message(
+    config: &SyslogSinkConfig,
+    hostname: &str,
+    entry: &AuditEntry,
+) -> Result<String, AuditSinkError> {
+    let severity = match entry.severity {
+        AuditSeverity::Info => 6,
+        AuditSeverity::Warning => 4,
+        AuditSeverity::Error => 3,
+        AuditSeverity::Critical => 2,
+    };
+    let timestamp = DateTime::parse_from_rfc3339(&entry.timestamp)
+        .map(|time| {
+            time.with_timezone(&Utc)
+                .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+                .to_string()
+        })
+        .unwrap_or_else(|_| "-".to_string());
+
+    let mut data = format!(
+        "[{} sequence=\"{}\" actor=\"{}\" action=\"{}\" outcome=\"{}\"",
+        SD_ID,
+        entry.sequence,
+        escape_param(&entry.actor),
+        escape_param(&entry.action),
+        entry.outcome
+    );
+    if let Some(target) = &entry.target {
+        data.push_str(&format!(" target=\"{}\"", escape_param(target)));
+    }
+    if let Some(source_ip) = &entry.source_ip {
+        data.push_str(&format!(" source_ip=\"{}\"", escape_param(source_ip)));
+    }
+    data.push_str(&format!(" hash=\"{}\"]", entry.hash));
+
+    Ok(format!(
+        "<{}>1 {} {} {} {} {} {} {}",
+        config.facility as u16 * 8 + severity,
+        timestamp,
+        header_field(hostname, 255),
+        header_field(&config.app_name, 48),
+        std::process::id(),
+        header_field(&entry.action, 32),
+        data,
+        serde_json::to_string(entry)?
+    ))
+}
+
+/// Header fields are printable ASCII without spaces; `-` stands for none.
+fn header_field(value: &str, max_len: usize) -> String {
+    let field: String = value
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(max_len)
+        .collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+fn escape_param(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/*---------------------------------------------------------------
+HTTP webhook. Each entry is POSTed as JSON with
+`X-Locksmith-Timestamp` (Unix seconds) and, when a secret is set,
+`X-Locksmith-Signature: sha256=<hex>` over "<timestamp>.<body>".
+Connection errors, 408, 429 and 5xx answers are retried with
+exponential backoff; other answers are final.
+----------------------------------------------------------------*/
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<String>,
+    max_retries: u32,
+    retry_delay: Duration,
+}
+
+impl WebhookSink {
+    pub fn new(config: &WebhookSinkConfig) -> Result<Self, AuditSinkError> {
+        let url = reqwest::Url::parse(&config.url)
+            .map_err(|e| AuditSinkError::Config(format!("webhook url: {}", e)))?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()?;
+        Ok(Self {
+            client,
+            url: url.to_string(),
+            secret: config.secret(),
+            max_retries: config.max_retries,
+            retry_delay: Duration::from_secs(config.retry_delay),
+        })
+    }
+
+    async fn post(&self, body: &[u8]) -> Result<(), AuditSinkError> {
+        let timestamp = Utc::now().timestamp();
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .header("X-Locksmith-Timestamp", timestamp.to_string())
+            .body(body.to_vec());
+        if let Some(secret) = &self.secret {
+            request = request.header(
+                "X-Locksmith-Signature",
+                webhook_signature(secret.as_bytes(), timestamp, body),
+            );
+        }
+
+        let status = request.send().await?.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(AuditSinkError::Rejected(status.as_u16()))
+        }
+    }
+}
+
+#[async_trait]
+impl AuditSink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn deliver(&mut self, entry: &AuditEntry) -> Result<(), AuditSinkError> {
+        let body = serde_json::to_vec(entry)?;
+        let mut delay = self.retry_delay;
+        let mut attempt = 0;
+        loop {
+            match self.post(&body).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.max_retries && is_retryable(&e) => {
+                    warn!(
+                        "Audit webhook attempt {} for entry {} failed: {}; retrying in {}s",
+                        attempt + 1,
+                        entry.sequence,
+                        e,
+                        delay.as_secs()
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn is_retryable(error: &AuditSinkError) -> bool {
+    match error {
+        AuditSinkError::Http(_) => true,
+        AuditSinkError::Rejected(status) => matches!(status, 408 | 429 | 500..=599),
+        _ => false,
+    }
+}
+
+/// The `X-Locksmith-Signature` value for a webhook body.
+pub fn webhook_signature(secret: &[u8], timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+/// Checks a webhook signature in constant time; for receivers.
+pub fn verify_webhook_signature(
+    secret: &[u8],
+    timestamp: i64,
+    body: &[u8],
+    signature: &str,
+) -> bool {
+    let Some(expected) = signature.strip_prefix("sha256=").and_then(decode_hex) else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AuditOutcome;
+    use crate::utils::auth::generate_identifier;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn entry(sequence: i64, outcome: AuditOutcome) -> AuditEntry {
+        AuditEntry {
+            sequence,
+            timestamp: "2025-03-01T12:30:45.123+00:00".to_string(),
+            actor: "jane@example.com".to_string(),
+            action: "secret.reveal".to_string(),
+            target: Some("db \"prod\" [primary]".to_string()),
+            outcome,
+            severity: match outcome {
+                AuditOutcome::Success => AuditSeverity::Info,
+                AuditOutcome::Denied => AuditSeverity::Warning,
+                AuditOutcome::Failure => AuditSeverity::Error,
+            },
+            source_ip: Some("192.0.2.10".to_string()),
+            user_agent: None,
+            detail: None,
+            prev_hash: "0".repeat(64),
+            hash: "ab".repeat(32),
+        }
+    }
+
+    #[tokio::test]
+    async fn file_sink_rotates_by_size() {
+        let directory = std::env::temp_dir().join(format!("ec_audit_{}", generate_identifier()));
+        let path = directory.join("audit.jsonl");
+        let line_len = serde_json::to_vec(&entry(1, AuditOutcome::Success))
+            .unwrap()
+            .len() as u64
+            + 1;
+        let mut sink = FileSink::new(FileSinkConfig {
+            enabled: true,
+            path: path.clone(),
+            max_bytes: line_len * 2,
+            max_files: 2,
+        });
+
+        for sequence in 1..=7 {
+            sink.deliver(&entry(sequence, AuditOutcome::Success))
+                .await
+                .unwrap();
+        }
+
+        let sequences = |path: &Path| -> Vec<i64> {
+            fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<AuditEntry>(line).unwrap().sequence)
+                .collect()
+        };
+        assert_eq!(sequences(&path), vec![7]);
+        assert_eq!(sequences(&sink.rotated(1)), vec![5, 6]);
+        assert_eq!(sequences(&sink.rotated(2)), vec![3, 4]);
+        assert!(!sink.rotated(3).exists());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn syslog_messages_follow_rfc_5424() {
+        let config = SyslogSinkConfig::default();
+        let message =
+            syslog_message(&config, "vault 01", &entry(42, AuditOutcome::Denied)).unwrap();
+
+        // authpriv (10) * 8 + warning (4)
+        let expected_header = format!(
+            "<84>1 2025-03-01T12:30:45.123Z vault_01 locksmith {} secret.reveal ",
+            std::process::id()
+        );
+        assert!(message.starts_with(&expected_header), "{}", message);
+        assert!(message.contains(
+            "[audit@32473 sequence=\"42\" actor=\"jane@example.com\" action=\"secret.reveal\" \
+             outcome=\"denied\" target=\"db \\\"prod\\\" [primary\\]\" source_ip=\"192.0.2.10\""
+        ));
+
+        let json = &message[message.find("] {").unwrap() + 2..];
+        assert_eq!(
+            serde_json::from_str::<AuditEntry>(json).unwrap().sequence,
+            42
+        );
+
+        // authpriv (10) * 8 + critical (2)
+        let critical = AuditEntry {
+            action: "breakglass.reveal".to_string(),
+            severity: AuditSeverity::Critical,
+            ..entry(43, AuditOutcome::Success)
+        };
+        assert!(
+            syslog_message(&config, "vault", &critical)
+                .unwrap()
+                .starts_with("<82>1 ")
+        );
+        assert!(
+            SyslogSink::new(SyslogSinkConfig {
+                facility: 24,
+                ..config
+            })
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn syslog_sink_frames_tcp_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut sink = SyslogSink::new(SyslogSinkConfig {
+            enabled: true,
+            address: listener.local_addr().unwrap().to_string(),
+            protocol: SyslogProtocol::Tcp,
+            hostname: Some("vault".to_string()),
+            ..SyslogSinkConfig::default()
+        })
+        .unwrap();
+
+        sink.deliver(&entry(1, AuditOutcome::Success))
+            .await
+            .unwrap();
+        sink.deliver(&entry(2, AuditOutcome::Failure))
+            .await
+            .unwrap();
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut reader = BufReader::new(stream);
+        for priority in ["<86>", "<83>"] {
+            let mut length = Vec::new();
+            reader.read_until(b' ', &mut length).await.unwrap();
+            let length: usize = std::str::from_utf8(&length)
+                .unwrap()
+                .trim()
+                .parse()
+                .unwrap();
+            let mut message = vec![0; length];
+            reader.read_exact(&mut message).await.unwrap();
+            assert!(message.starts_with(priority.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn webhook_signatures_verify() {
+        let body = br#"{"sequence":1}"#;
+        let signature = webhook_signature(b"s3cret", 1_740_000_000, body);
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature.len(), 7 + 64);
+
+        assert!(verify_webhook_signature(
+            b"s3cret",
+            1_740_000_000,
+            body,
+            &signature
+        ));
+        assert!(!verify_webhook_signature(
+            b"s3cret",
+            1_740_000_001,
+            body,
+            &signature
+        ));
+        assert!(!verify_webhook_signature(
+            b"other",
+            1_740_000_000,
+            body,
+            &signature
+        ));
+        assert!(!verify_webhook_signature(
+            b"s3cret",
+            1_740_000_000,
+            body,
+            "sha256=zz"
+        ));
+    }
+
+    #[test]
+    fn only_transient_webhook_errors_are_retried() {
+        assert!(is_retryable(&AuditSinkError::Rejected(503)));
+        assert!(is_retryable(&AuditSinkError::Rejected(429)));
+        assert!(!is_retryable(&AuditSinkError::Rejected(400)));
+        assert!(!is_retryable(&AuditSinkError::Rejected(401)));
+    }
+}
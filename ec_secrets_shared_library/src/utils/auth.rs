@@ -1,17 +1,145 @@
 use crate::{
-    models::{User, UserCredentials},
-    repositories::keys::KeyRepository,
+    models::{UserCredentials, UserDocument, UserStatus},
+    repositories::{
+        keys::KeyRepository, login_attempts::LoginAttemptRepository,
+        revocations::SUBJECT_REVOCATION_RETENTION, users::UserRepository,
+    },
+    utils::{
+        approval::ApprovalConfig,
+        break_glass::BreakGlassConfig,
+        ldap::{self, LdapAuthError, LdapConfig},
+        lockout::{Lockout, LockoutConfig},
+        mail::MailConfig,
+        mtls::MtlsConfig,
+        oidc::OidcConfig,
+        password_hash::{self, PasswordHashing},
+        password_policy::PasswordPolicy,
+        webauthn::WebAuthnConfig,
+    },
 };
-use base64::{Engine as _, engine::general_purpose::STANDARD};
-use bcrypt::{hash, verify, DEFAULT_COST};
+use base64::{
+    Engine as _,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
 use chrono::{Duration, Utc};
+use log::warn;
 use pasetors::{
-    claims::Claims,
+    Public,
+    claims::{Claims, ClaimsValidationRules},
+    errors::{ClaimValidationError, Error as PasetoError},
     keys::{AsymmetricPublicKey, AsymmetricSecretKey},
     public,
+    token::UntrustedToken,
     version4::V4,
 };
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use thiserror::Error;
+
+pub const DEFAULT_ISSUER: &str = "https://www.embraconnect.com";
+
+/// The longest lifetime, in seconds, a token is issued for. "Revoke all
+/// sessions" cutoffs are only kept this long, so no token may outlive them.
+pub const MAX_TOKEN_TTL: i64 = SUBJECT_REVOCATION_RETENTION;
+
+/*---------------------------------------------------------------
+Token settings: lifetimes (in seconds), the issuer stamped on every
+token and the audiences accepted when validating one. Tokens are
+issued for the first audience in the list.
+
+The server reads these from the `[default.auth]` table of its Rocket
+configuration; other callers fall back to the defaults below.
+----------------------------------------------------------------*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub access_token_ttl: i64,
+    pub refresh_token_ttl: i64,
+    pub issuer: String,
+    pub audiences: Vec<String>,
+    pub webauthn: WebAuthnConfig,
+    pub oidc: OidcConfig,
+    pub ldap: LdapConfig,
+    pub mtls: MtlsConfig,
+    pub lockout: LockoutConfig,
+    pub password_hashing: PasswordHashing,
+    pub password_policy: PasswordPolicy,
+    pub email_verification_ttl: i64,
+    pub password_reset_ttl: i64,
+    pub mail: MailConfig,
+    pub approvals: ApprovalConfig,
+    pub break_glass: BreakGlassConfig,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            access_token_ttl: Duration::hours(8).num_seconds(),
+            refresh_token_ttl: Duration::days(7).num_seconds(),
+            issuer: DEFAULT_ISSUER.to_string(),
+            audiences: vec![DEFAULT_ISSUER.to_string()],
+            webauthn: WebAuthnConfig::default(),
+            oidc: OidcConfig::default(),
+            ldap: LdapConfig::default(),
+            mtls: MtlsConfig::default(),
+            lockout: LockoutConfig::default(),
+            password_hashing: PasswordHashing::default(),
+            password_policy: PasswordPolicy::default(),
+            email_verification_ttl: Duration::days(1).num_seconds(),
+            password_reset_ttl: Duration::minutes(30).num_seconds(),
+            mail: MailConfig::default(),
+            approvals: ApprovalConfig::default(),
+            break_glass: BreakGlassConfig::default(),
+        }
+    }
+}
+
+impl AuthConfig {
+    /// The audience newly issued tokens are intended for.
+    pub fn audience(&self) -> &str {
+        self.audiences
+            .first()
+            .map_or(self.issuer.as_str(), String::as_str)
+    }
+
+    /// Checks the configured lifetimes: each must be positive, and access
+    /// tokens may not live longer than `MAX_TOKEN_TTL`.
+    pub fn validate_lifetimes(&self) -> Result<(), String> {
+        if !(1..=MAX_TOKEN_TTL).contains(&self.access_token_ttl) {
+            return Err(format!(
+                "access_token_ttl must be between 1 and {MAX_TOKEN_TTL} seconds"
+            ));
+        }
+        for (field, ttl) in [
+            ("refresh_token_ttl", self.refresh_token_ttl),
+            ("email_verification_ttl", self.email_verification_ttl),
+            ("password_reset_ttl", self.password_reset_ttl),
+        ] {
+            if ttl <= 0 {
+                return Err(format!("{field} must be positive"));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn access_token_lifetime(&self) -> Duration {
+        Duration::seconds(self.access_token_ttl)
+    }
+
+    pub fn refresh_token_lifetime(&self) -> Duration {
+        Duration::seconds(self.refresh_token_ttl)
+    }
+
+    pub fn email_verification_lifetime(&self) -> Duration {
+        Duration::seconds(self.email_verification_ttl)
+    }
+
+    pub fn password_reset_lifetime(&self) -> Duration {
+        Duration::seconds(self.password_reset_ttl)
+    }
+}
 
 pub async fn decode_keys(
     repo: &KeyRepository,
@@ -31,47 +159,608 @@ pub async fn decode_keys(
     Ok((private_key, public_key))
 }
 
+/*---------------------------------------------
+Verify a user's password without issuing a token.
+----------------------------------------------*/
+pub fn verify_credentials(
+    user: &UserDocument,
+    credentials: &UserCredentials,
+) -> Result<(), String> {
+    if !password_matches(&credentials.password, &user.password) {
+        return Err("Invalid credentials".into());
+    }
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+pub enum LoginError {
+    /// Wrong email or password, with any limits the failure tripped.
+    #[error("invalid credentials")]
+    InvalidCredentials(Vec<Lockout>),
+    #[error("too many failed attempts; retry in {0} seconds")]
+    Throttled(i64),
+    #[error("account is disabled")]
+    Disabled,
+    #[error("database error: {0}")]
+    Database(#[from] mongodb::error::Error),
+    #[error("directory error: {0}")]
+    Directory(LdapAuthError),
+    #[error("{0}")]
+    Internal(String),
+}
+
+/*---------------------------------------------------------------
+Authenticate a password login, throttled per account and per client
+address (see `LockoutConfig`). Blocked logins are refused before any
+password is checked, so guesses made while blocked reveal nothing.
+A failure that locks the account or blocks the address returns the
+`Lockout` with `InvalidCredentials`, for the caller to audit.
+----------------------------------------------------------------*/
+pub async fn authenticate_password(
+    repo: &UserRepository,
+    attempts: &LoginAttemptRepository,
+    credentials: &UserCredentials,
+    client_address: Option<IpAddr>,
+    config: &AuthConfig,
+) -> Result<UserDocument, LoginError> {
+    if let Some(blocked_until) = attempts
+        .blocked_until(&credentials.email, client_address.as_ref())
+        .await?
+    {
+        let retry_after = (blocked_until - Utc::now()).num_seconds().max(1);
+        return Err(LoginError::Throttled(retry_after));
+    }
+
+    match verify_password_login(repo, credentials, config).await {
+        Ok(user) => {
+            attempts.clear_account(&credentials.email).await?;
+            // Checked only after the password, so the status isn't revealed to guessers.
+            if user.status == UserStatus::Disabled {
+                return Err(LoginError::Disabled);
+            }
+            Ok(user)
+        }
+        Err(LoginError::InvalidCredentials(_)) => {
+            let limits = &config.lockout;
+            let mut lockouts = Vec::new();
+            let failures = attempts
+                .record_account_failure(&credentials.email, limits)
+                .await?;
+            if limits.locks_account(failures) {
+                lockouts.push(Lockout::Account {
+                    email: credentials.email.clone(),
+                    failures,
+                    seconds: limits.lockout_duration,
+                });
+            }
+            if let Some(address) = &client_address {
+                let failures = attempts.record_address_failure(address, limits).await?;
+                if failures == limits.ip_max_attempts {
+                    lockouts.push(Lockout::Address {
+                        address: *address,
+                        failures,
+                        seconds: limits.ip_window,
+                    });
+                }
+            }
+            Err(LoginError::InvalidCredentials(lockouts))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/*---------------------------------------------------------------
+Check a password login. The local password hash is checked first, so
+accounts created through /setup keep working when the directory is
+down; the LDAP directory is tried next when enabled.
+
+Directory users are created on their first login when the LDAP
+config allows it, and their roles follow their directory groups
+whenever `group_roles` is configured.
+----------------------------------------------------------------*/
+async fn verify_password_login(
+    repo: &UserRepository,
+    credentials: &UserCredentials,
+    config: &AuthConfig,
+) -> Result<UserDocument, LoginError> {
+    let local_user = repo.get_user_by_email(&credentials.email).await?;
+    if let Some(user) = local_user
+        && password_matches(&credentials.password, &user.password)
+    {
+        // Upgrade legacy bcrypt and outdated Argon2 hashes while the password is at hand.
+        if config.password_hashing.needs_rehash(&user.password) {
+            match hash_password(&credentials.password, &config.password_hashing) {
+                Ok(hash) => {
+                    if let Err(e) = repo
+                        .rehash_password(&user.email, &user.password, &hash)
+                        .await
+                    {
+                        warn!(
+                            "Failed to upgrade the password hash of {}: {}",
+                            user.email, e
+                        );
+                    }
+                }
+                Err(e) => warn!(
+                    "Failed to upgrade the password hash of {}: {}",
+                    user.email, e
+                ),
+            }
+        }
+        return Ok(user);
+    }
+
+    if !config.ldap.enabled {
+        return Err(LoginError::InvalidCredentials(Vec::new()));
+    }
+
+    let identity =
+        match ldap::authenticate(&config.ldap, &credentials.email, &credentials.password).await {
+            Ok(identity) => identity,
+            Err(
+                LdapAuthError::InvalidCredentials
+                | LdapAuthError::UserNotFound
+                | LdapAuthError::AmbiguousUser,
+            ) => return Err(LoginError::InvalidCredentials(Vec::new())),
+            Err(e) => return Err(LoginError::Directory(e)),
+        };
+
+    match repo.get_user_by_email(&identity.email).await? {
+        Some(mut user) => {
+            if !config.ldap.group_roles.is_empty() && user.roles != identity.roles {
+                repo.set_roles(&user.email, &identity.roles).await?;
+                user.roles = identity.roles;
+            }
+            Ok(user)
+        }
+        None if config.ldap.auto_provision => {
+            // Directory accounts get a random password nobody knows, so the
+            // directory stays the only way to log in with a password.
+            let password = hash_password(&generate_identifier(), &config.password_hashing)
+                .map_err(LoginError::Internal)?;
+            Ok(repo
+                .create_user(&identity.email, &password, &identity.roles)
+                .await?)
+        }
+        None => Err(LoginError::InvalidCredentials(Vec::new())),
+    }
+}
+
 /*---------------------------------------------
 Authorize the user via password verification.
 ----------------------------------------------*/
 
 pub async fn authorize_user(
-    user: &User,
+    users: &UserRepository,
+    attempts: &LoginAttemptRepository,
     credentials: &UserCredentials,
     repo: &KeyRepository,
+    config: &AuthConfig,
 ) -> Result<String, String> {
-    if !verify(&credentials.password, &user.password).map_err(|e| e.to_string())? {
-        return Err("Invalid credentials".into());
-    }
+    let user = authenticate_password(users, attempts, credentials, None, config)
+        .await
+        .map_err(|e| e.to_string())?;
+    issue_token(
+        &user.email,
+        &user.roles,
+        None,
+        config.access_token_lifetime(),
+        config,
+        repo,
+    )
+    .await
+}
+
+/*---------------------------------------------------------------
+Mint a signed token for an already authenticated subject.
+
+Machine identities pass the policies they are scoped to, which are
+carried in the `policies` claim and enforced by the vault routes.
+Every token carries a unique `jti` so it can be revoked individually.
+----------------------------------------------------------------*/
+pub async fn issue_token(
+    subject: &str,
+    roles: &[String],
+    policies: Option<&[String]>,
+    lifetime: Duration,
+    config: &AuthConfig,
+    repo: &KeyRepository,
+) -> Result<String, String> {
+    let claims = build_claims(
+        subject,
+        roles,
+        policies,
+        lifetime,
+        config,
+        &authentication_key(),
+    )?;
+    let (private_key, _public_key) = decode_keys(repo).await.map_err(|e| e.to_string())?;
+    let token = public::sign(&private_key, &claims, None, None).map_err(|e| e.to_string())?;
+    Ok(token)
+}
+
+pub fn build_claims(
+    subject: &str,
+    roles: &[String],
+    policies: Option<&[String]>,
+    lifetime: Duration,
+    config: &AuthConfig,
+    authentication_key: &str,
+) -> Result<Claims, String> {
     let mut claims = Claims::new().map_err(|e| e.to_string())?;
-    let ecs_authentication_key = std::env::var_os("ECS_AUTHENTICATION_KEY")
-        .expect("[ECS_AUTHENTICATION_KEY] must be set...")
-        .into_string()
-        .unwrap();
 
-    let expiration = Utc::now() + Duration::hours(8);
+    // Roles and configurations are checked against the cap when loaded; this
+    // also covers roles stored before it existed.
+    let lifetime = lifetime.min(Duration::seconds(MAX_TOKEN_TTL));
+    let expiration = Utc::now() + lifetime;
     let expiration = expiration.to_rfc3339();
 
-    let mut hasher = Sha256::new();
-    hasher.update(format!("{}{}", user.email, ecs_authentication_key)); // Unique to current system
-    let nonce = format!("{:x}", hasher.finalize());
-
+    claims.subject(subject).map_err(|e| e.to_string())?;
     claims
-        .subject(&credentials.email)
+        .token_identifier(&generate_identifier())
         .map_err(|e| e.to_string())?;
     claims.expiration(&expiration).map_err(|e| e.to_string())?;
+    claims.issuer(&config.issuer).map_err(|e| e.to_string())?;
     claims
-        .issuer("https://www.embraconnect.com")
+        .audience(config.audience())
         .map_err(|e| e.to_string())?;
     claims
-        .add_additional("nonce", nonce)
+        .add_additional("nonce", token_nonce(subject, authentication_key))
         .map_err(|e| e.to_string())?;
-    // claims.add_additional("aud", vec!["https://www.embraconnect.com"]).map_err(|e|e.to_string())?;
-    let (private_key, _public_key) = decode_keys(repo).await.map_err(|e| e.to_string())?;
-    let token = public::sign(&private_key, &claims, None, None).map_err(|e| e.to_string())?;
-    Ok(token)
+    if !roles.is_empty() {
+        claims
+            .add_additional("roles", roles.to_vec())
+            .map_err(|e| e.to_string())?;
+    }
+    if let Some(policies) = policies {
+        claims
+            .add_additional("policies", policies.to_vec())
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(claims)
 }
 
-pub fn hash_password(password: String) -> Result<String, String> {
-    hash(password, DEFAULT_COST).map_err(|e| e.to_string())
-}
\ No newline at end of file
+pub fn authentication_key() -> String {
+    std::env::var_os("ECS_AUTHENTICATION_KEY")
+        .expect("[ECS_AUTHENTICATION_KEY] must be set...")
+        .into_string()
+        .unwrap()
+}
+
+/// Binds a token to this deployment: only holders of the authentication key
+/// can produce the nonce expected for a subject.
+fn token_nonce(subject: &str, authentication_key: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}{}", subject, authentication_key)); // Unique to current system
+    format!("{:x}", hasher.finalize())
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum TokenError {
+    #[error("token is malformed")]
+    Malformed,
+    #[error("token signature is invalid")]
+    InvalidSignature,
+    #[error("token has expired")]
+    Expired,
+    #[error("token is not valid yet")]
+    NotYetValid,
+    #[error("token was issued by an untrusted issuer")]
+    InvalidIssuer,
+    #[error("token is not intended for this audience")]
+    InvalidAudience,
+    #[error("token nonce does not match this deployment")]
+    InvalidNonce,
+    #[error("token is missing the `{0}` claim")]
+    MissingClaim(&'static str),
+    #[error("token claims are invalid")]
+    InvalidClaims,
+}
+
+impl From<PasetoError> for TokenError {
+    fn from(error: PasetoError) -> Self {
+        match error {
+            PasetoError::TokenValidation => TokenError::InvalidSignature,
+            PasetoError::ClaimValidation(ClaimValidationError::Exp) => TokenError::Expired,
+            PasetoError::ClaimValidation(ClaimValidationError::Nbf | ClaimValidationError::Iat) => {
+                TokenError::NotYetValid
+            }
+            PasetoError::ClaimValidation(
+                ClaimValidationError::Iss | ClaimValidationError::NoIss,
+            ) => TokenError::InvalidIssuer,
+            PasetoError::ClaimValidation(ClaimValidationError::NoExp) => {
+                TokenError::MissingClaim("exp")
+            }
+            PasetoError::ClaimValidation(_) => TokenError::InvalidClaims,
+            _ => TokenError::Malformed,
+        }
+    }
+}
+
+/*---------------------------------------------------------------
+Verify a token's signature and every claim Locksmith relies on:
+issuer, audience, expiry, not-before, issued-at and the nonce that
+ties the token to this deployment's authentication key.
+----------------------------------------------------------------*/
+pub struct TokenValidator {
+    issuer: String,
+    audiences: Vec<String>,
+    authentication_key: String,
+}
+
+impl TokenValidator {
+    pub fn new(config: &AuthConfig, authentication_key: &str) -> Self {
+        Self {
+            issuer: config.issuer.clone(),
+            audiences: config.audiences.clone(),
+            authentication_key: authentication_key.to_string(),
+        }
+    }
+
+    pub fn from_env(config: &AuthConfig) -> Self {
+        Self::new(config, &authentication_key())
+    }
+
+    pub fn validate(
+        &self,
+        token: &str,
+        public_key: &AsymmetricPublicKey<V4>,
+    ) -> Result<Claims, TokenError> {
+        let untrusted_token =
+            UntrustedToken::<Public, V4>::try_from(token).map_err(|_| TokenError::Malformed)?;
+
+        let mut validation_rules = ClaimsValidationRules::new();
+        validation_rules.validate_issuer_with(&self.issuer);
+
+        let trusted_token =
+            public::verify(public_key, &untrusted_token, &validation_rules, None, None)?;
+        let claims = trusted_token
+            .payload_claims()
+            .ok_or(TokenError::Malformed)?;
+
+        let claim = |name: &'static str| {
+            claims
+                .get_claim(name)
+                .and_then(|value| value.as_str())
+                .ok_or(TokenError::MissingClaim(name))
+        };
+
+        let audience = claim("aud")?;
+        if !self.audiences.iter().any(|accepted| accepted == audience) {
+            return Err(TokenError::InvalidAudience);
+        }
+
+        if claim("nonce")? != token_nonce(claim("sub")?, &self.authentication_key) {
+            return Err(TokenError::InvalidNonce);
+        }
+
+        Ok(claims.clone())
+    }
+}
+
+pub fn hash_password(password: &str, hashing: &PasswordHashing) -> Result<String, String> {
+    hashing.hash(password)
+}
+
+/// Whether `password` matches a stored Argon2 or legacy bcrypt hash.
+pub fn password_matches(password: &str, hash: &str) -> bool {
+    password_hash::verify(password, hash)
+}
+
+/// Generates a random, URL-safe identifier with 256 bits of entropy.
+pub fn generate_identifier() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// SHA-256 digest used to store bearer secrets without keeping them in clear.
+pub fn hash_identifier(identifier: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(identifier.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use pasetors::keys::{AsymmetricKeyPair, Generate};
+
+    const AUTHENTICATION_KEY: &str = "test-authentication-key";
+
+    fn key_pair() -> AsymmetricKeyPair<V4> {
+        AsymmetricKeyPair::<V4>::generate().expect("Failed to generate key pair")
+    }
+
+    fn claims(config: &AuthConfig) -> Claims {
+        build_claims(
+            "user@example.com",
+            &[],
+            None,
+            Duration::minutes(5),
+            config,
+            AUTHENTICATION_KEY,
+        )
+        .expect("Failed to build claims")
+    }
+
+    fn sign(claims: &Claims, key_pair: &AsymmetricKeyPair<V4>) -> String {
+        public::sign(&key_pair.secret, claims, None, None).expect("Failed to sign token")
+    }
+
+    fn validate(token: &str, key_pair: &AsymmetricKeyPair<V4>) -> Result<Claims, TokenError> {
+        TokenValidator::new(&AuthConfig::default(), AUTHENTICATION_KEY)
+            .validate(token, &key_pair.public)
+    }
+
+    #[test]
+    fn valid_token() {
+        let key_pair = key_pair();
+        let token = sign(&claims(&AuthConfig::default()), &key_pair);
+        let claims = validate(&token, &key_pair).expect("Token should be valid");
+        assert_eq!(
+            claims.get_claim("sub").and_then(|sub| sub.as_str()),
+            Some("user@example.com")
+        );
+        assert!(claims.get_claim("jti").is_some());
+    }
+
+    #[test]
+    fn lifetimes_are_capped() {
+        let claims = build_claims(
+            "svc",
+            &[],
+            None,
+            Duration::days(365),
+            &AuthConfig::default(),
+            AUTHENTICATION_KEY,
+        )
+        .expect("Failed to build claims");
+        let expiration = claims
+            .get_claim("exp")
+            .and_then(|exp| exp.as_str())
+            .and_then(|exp| DateTime::parse_from_rfc3339(exp).ok())
+            .expect("Token should expire");
+        assert!(expiration <= Utc::now() + Duration::seconds(MAX_TOKEN_TTL));
+
+        let mut config = AuthConfig::default();
+        assert!(config.validate_lifetimes().is_ok());
+        config.access_token_ttl = MAX_TOKEN_TTL + 1;
+        assert!(config.validate_lifetimes().is_err());
+        config.access_token_ttl = 0;
+        assert!(config.validate_lifetimes().is_err());
+    }
+
+    #[test]
+    fn tampered_token() {
+        let key_pair = key_pair();
+        let token = sign(&claims(&AuthConfig::default()), &key_pair);
+
+        // Flip a character inside the signed payload.
+        let mut tampered: Vec<char> = token.chars().collect();
+        let index = "v4.public.".len() + 10;
+        tampered[index] = if tampered[index] == 'A' { 'B' } else { 'A' };
+        let tampered: String = tampered.into_iter().collect();
+
+        assert_eq!(
+            validate(&tampered, &key_pair).unwrap_err(),
+            TokenError::InvalidSignature
+        );
+        assert_eq!(
+            validate("not-a-token", &key_pair).unwrap_err(),
+            TokenError::Malformed
+        );
+    }
+
+    #[test]
+    fn foreign_signing_key() {
+        let token = sign(&claims(&AuthConfig::default()), &key_pair());
+        assert_eq!(
+            validate(&token, &key_pair()).unwrap_err(),
+            TokenError::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn expired_token() {
+        let key_pair = key_pair();
+        let mut claims = claims(&AuthConfig::default());
+        claims
+            .expiration(&(Utc::now() - Duration::minutes(1)).to_rfc3339())
+            .unwrap();
+        assert_eq!(
+            validate(&sign(&claims, &key_pair), &key_pair).unwrap_err(),
+            TokenError::Expired
+        );
+    }
+
+    #[test]
+    fn not_yet_valid_token() {
+        let key_pair = key_pair();
+        let mut claims = claims(&AuthConfig::default());
+        claims
+            .not_before(&(Utc::now() + Duration::minutes(1)).to_rfc3339())
+            .unwrap();
+        assert_eq!(
+            validate(&sign(&claims, &key_pair), &key_pair).unwrap_err(),
+            TokenError::NotYetValid
+        );
+    }
+
+    #[test]
+    fn foreign_issuer() {
+        let key_pair = key_pair();
+        let config = AuthConfig {
+            issuer: "https://attacker.example.com".to_string(),
+            ..AuthConfig::default()
+        };
+        assert_eq!(
+            validate(&sign(&claims(&config), &key_pair), &key_pair).unwrap_err(),
+            TokenError::InvalidIssuer
+        );
+    }
+
+    #[test]
+    fn foreign_audience() {
+        let key_pair = key_pair();
+        let config = AuthConfig {
+            audiences: vec!["https://other-service.example.com".to_string()],
+            ..AuthConfig::default()
+        };
+        let token = sign(&claims(&config), &key_pair);
+        assert_eq!(
+            validate(&token, &key_pair).unwrap_err(),
+            TokenError::InvalidAudience
+        );
+
+        // Accepted once the audience is part of the configured list.
+        let validator = TokenValidator::new(
+            &AuthConfig {
+                audiences: vec![
+                    DEFAULT_ISSUER.to_string(),
+                    "https://other-service.example.com".to_string(),
+                ],
+                ..AuthConfig::default()
+            },
+            AUTHENTICATION_KEY,
+        );
+        assert!(validator.validate(&token, &key_pair.public).is_ok());
+    }
+
+    #[test]
+    fn foreign_nonce() {
+        let key_pair = key_pair();
+        let claims = build_claims(
+            "user@example.com",
+            &[],
+            None,
+            Duration::minutes(5),
+            &AuthConfig::default(),
+            "another-deployment-key",
+        )
+        .unwrap();
+        assert_eq!(
+            validate(&sign(&claims, &key_pair), &key_pair).unwrap_err(),
+            TokenError::InvalidNonce
+        );
+    }
+
+    #[test]
+    fn missing_claims() {
+        let key_pair = key_pair();
+        let mut claims = Claims::new().unwrap();
+        claims.subject("user@example.com").unwrap();
+        claims.issuer(DEFAULT_ISSUER).unwrap();
+        assert_eq!(
+            validate(&sign(&claims, &key_pair), &key_pair).unwrap_err(),
+            TokenError::MissingClaim("aud")
+        );
+
+        claims.audience(DEFAULT_ISSUER).unwrap();
+        assert_eq!(
+            validate(&sign(&claims, &key_pair), &key_pair).unwrap_err(),
+            TokenError::MissingClaim("nonce")
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::models::BreakGlassSession;
+use crate::utils::policy::is_permitted;
+
+/*---------------------------------------------------------------
+Break-glass access for emergencies. A user holding the
+`breakglass` role opens a session on one of the configured
+`paths` (secret key patterns as in access policies) with a reason;
+until it expires or is ended they can read every secret under the
+path, whoever owns it and whatever its protection. Everything done
+during a session is audited as critical and announced to the
+approvers, the administrators and `notify`.
+
+No paths means break-glass access is off. Read from the
+`[default.auth.break_glass]` table of the server's Rocket
+configuration.
+----------------------------------------------------------------*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BreakGlassConfig {
+    pub paths: Vec<String>,
+    /// Seconds a session lasts unless a shorter one is asked for.
+    pub default_duration: i64,
+    /// Longest session, in seconds.
+    pub max_duration: i64,
+    pub min_reason_length: usize,
+    /// Extra addresses told about every session, e.g. the security team.
+    pub notify: Vec<String>,
+}
+
+impl Default for BreakGlassConfig {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            default_duration: Duration::minutes(30).num_seconds(),
+            max_duration: Duration::hours(2).num_seconds(),
+            min_reason_length: 20,
+            notify: Vec::new(),
+        }
+    }
+}
+
+impl BreakGlassConfig {
+    pub fn enabled(&self) -> bool {
+        !self.paths.is_empty()
+    }
+
+    /// Checks a request, returning the trimmed reason and the session length.
+    pub fn validate(
+        &self,
+        reason: &str,
+        path: &str,
+        duration: Option<i64>,
+    ) -> Result<(String, Duration), String> {
+        if !self.paths.iter().any(|allowed| allowed == path) {
+            return Err(format!(
+                "'{}' is not a break-glass path; use one of: {}",
+                path,
+                self.paths.join(", ")
+            ));
+        }
+        let reason = reason.trim();
+        if reason.chars().count() < self.min_reason_length {
+            return Err(format!(
+                "A reason of at least {} characters is required",
+                self.min_reason_length
+            ));
+        }
+        let seconds = duration.unwrap_or(self.default_duration);
+        if seconds <= 0 || seconds > self.max_duration {
+            return Err(format!(
+                "The duration must be between 1 and {} seconds",
+                self.max_duration
+            ));
+        }
+        Ok((reason.to_string(), Duration::seconds(seconds)))
+    }
+}
+
+/// Whether a session on `path` reaches the secret stored under `key`.
+pub fn covers(path: &str, key: &str) -> bool {
+    is_permitted(&[path.to_string()], key)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakGlassEvent {
+    Opened,
+    Ended,
+}
+
+#[derive(Debug, Clone)]
+pub struct BreakGlassNotice {
+    pub event: BreakGlassEvent,
+    pub session: BreakGlassSession,
+    pub recipients: Vec<String>,
+}
+
+impl BreakGlassNotice {
+    pub fn subject(&self) -> String {
+        match self.event {
+            BreakGlassEvent::Opened => format!(
+                "[BREAK-GLASS] {} unlocked {}",
+                self.session.user, self.session.path
+            ),
+            BreakGlassEvent::Ended => format!(
+                "[BREAK-GLASS] Session of {} on {} ended",
+                self.session.user, self.session.path
+            ),
+        }
+    }
+
+    pub fn body(&self, public_url: &str) -> String {
+        let session = &self.session;
+        let report = format!(
+            "{}/break-glass/{}/report",
+            public_url.trim_end_matches('/'),
+            session.id
+        );
+        match self.event {
+            BreakGlassEvent::Opened => format!(
+                "{} opened a break-glass session on {} until {}.\n\nReason: {}\n\nEvery secret read is audited. Review the session afterwards:\n\n{}",
+                session.user, session.path, session.expires_at, session.reason, report
+            ),
+            BreakGlassEvent::Ended => format!(
+                "The break-glass session of {} on {} was ended by {}.\n\nPost-incident report:\n\n{}",
+                session.user,
+                session.path,
+                session.ended_by.as_deref().unwrap_or("-"),
+                report
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> BreakGlassConfig {
+        BreakGlassConfig {
+            paths: vec!["prod/*".to_string()],
+            ..BreakGlassConfig::default()
+        }
+    }
+
+    #[test]
+    fn off_without_paths() {
+        assert!(!BreakGlassConfig::default().enabled());
+        assert!(
+            BreakGlassConfig::default()
+                .validate("Primary database is down", "prod/*", None)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn validates_requests() {
+        let config = config();
+        let reason = "  Primary database down, approvers unreachable ";
+
+        let (trimmed, duration) = config.validate(reason, "prod/*", None).unwrap();
+        assert_eq!(trimmed, "Primary database down, approvers unreachable");
+        assert_eq!(duration, Duration::minutes(30));
+        assert_eq!(
+            config.validate(reason, "prod/*", Some(600)).unwrap().1,
+            Duration::minutes(10)
+        );
+
+        assert!(config.validate(reason, "*", None).is_err());
+        assert!(config.validate("outage", "prod/*", None).is_err());
+        assert!(config.validate(reason, "prod/*", Some(0)).is_err());
+        assert!(config.validate(reason, "prod/*", Some(3 * 3600)).is_err());
+    }
+
+    #[test]
+    fn sessions_cover_their_path() {
+        assert!(covers("prod/*", "prod/db/password"));
+        assert!(!covers("prod/*", "staging/db/password"));
+        assert!(covers("prod/db_password", "prod/db_password"));
+    }
+}
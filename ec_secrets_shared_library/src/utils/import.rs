@@ -0,0 +1,428 @@
+use serde_json::Value;
+use std::{collections::HashSet, path::Path};
+use thiserror::Error;
+
+use crate::models::{ConflictStrategy, ImportAction, ImportFormat};
+use crate::utils::secret::SecretValue;
+
+/*---------------------------------------------------------------
+Bulk import. A dotenv file, a JSON or YAML document or a HashiCorp
+Vault KV export is parsed into keys and values before anything is
+stored. Nested objects become `parent/child` keys and arrays
+`parent/0`, `parent/1`, so imported keys line up with the path
+patterns of policies; numbers and booleans are stored as text.
+
+Dotenv values are taken literally: `${VAR}` is not expanded, so an
+import never picks up the environment of the process parsing it.
+----------------------------------------------------------------*/
+pub const MAX_IMPORT_ENTRIES: usize = 500;
+pub const MAX_KEY_LENGTH: usize = 256;
+
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("line {line}: {reason}")]
+    Dotenv { line: usize, reason: String },
+    #[error("invalid {format}: {reason}")]
+    Parse {
+        format: ImportFormat,
+        reason: String,
+    },
+    #[error("{0} has no value")]
+    Null(String),
+    #[error("invalid key {0:?}")]
+    Key(String),
+    #[error("duplicate key {0}")]
+    Duplicate(String),
+    #[error("nothing to import")]
+    Empty,
+    #[error("more than {MAX_IMPORT_ENTRIES} keys")]
+    TooMany,
+}
+
+/// A key and its value, as imported.
+pub type ImportEntry = (String, SecretValue);
+
+/// What importing a key the owner holds already does to its latest
+/// version. `unchanged` compares the stored value with the imported one and
+/// is only called for unprotected entries: a protected entry is skipped
+/// under every strategy, since a match would confirm a guessed value and a
+/// write would replace it, both without approval.
+pub fn resolve_existing<E>(
+    protected: bool,
+    conflict: ConflictStrategy,
+    unchanged: impl FnOnce() -> Result<bool, E>,
+) -> Result<ImportAction, E> {
+    if protected {
+        return Ok(ImportAction::Skip);
+    }
+    if unchanged()? {
+        return Ok(ImportAction::Unchanged);
+    }
+    Ok(match conflict {
+        ConflictStrategy::Skip => ImportAction::Skip,
+        ConflictStrategy::Overwrite => ImportAction::Overwrite,
+        ConflictStrategy::NewVersion => ImportAction::NewVersion,
+    })
+}
+
+/// Parses `content` into entries, with `prefix/` prepended to every key.
+/// Dotenv entries keep the order of the file.
+pub fn parse(
+    format: ImportFormat,
+    content: &str,
+    prefix: Option<&str>,
+) -> Result<Vec<ImportEntry>, ImportError> {
+    let parse_error = |reason: String| ImportError::Parse { format, reason };
+    let prefix = prefix.unwrap_or_default().trim_matches('/');
+
+    let mut entries = Vec::new();
+    match format {
+        ImportFormat::Dotenv => {
+            for (key, value) in dotenv(content)? {
+                entries.push((join(prefix, &key), value));
+            }
+        }
+        ImportFormat::Json => {
+            let document: Value =
+                serde_json::from_str(content).map_err(|e| parse_error(e.to_string()))?;
+            flatten_document(format, prefix, &document, &mut entries)?;
+        }
+        ImportFormat::Yaml => {
+            let document: Value =
+                serde_yaml::from_str(content).map_err(|e| parse_error(e.to_string()))?;
+            flatten_document(format, prefix, &document, &mut entries)?;
+        }
+        ImportFormat::Vault => {
+            let document: Value =
+                serde_json::from_str(content).map_err(|e| parse_error(e.to_string()))?;
+            let Value::Object(paths) = &document else {
+                return Err(parse_error("expected an object".to_string()));
+            };
+            if document.get("data").is_some() {
+                // A single `vault kv get -format=json` response.
+                flatten_document(format, prefix, kv_data(&document), &mut entries)?;
+            } else {
+                for (path, secret) in paths {
+                    let path = join(prefix, path.trim_matches('/'));
+                    flatten_document(format, &path, kv_data(secret), &mut entries)?;
+                }
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for (key, _) in &entries {
+        if !is_valid_key(key) {
+            return Err(ImportError::Key(key.clone()));
+        }
+        if !seen.insert(key.as_str()) {
+            return Err(ImportError::Duplicate(key.clone()));
+        }
+    }
+    match entries.len() {
+        0 => Err(ImportError::Empty),
+        n if n > MAX_IMPORT_ENTRIES => Err(ImportError::TooMany),
+        _ => Ok(entries),
+    }
+}
+
+/// The format a file name suggests. Vault exports are JSON files, so they
+/// are never guessed.
+pub fn detect_format(path: &Path) -> Option<ImportFormat> {
+    let name = path.file_name()?.to_str()?;
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => Some(ImportFormat::Json),
+        Some("yaml" | "yml") => Some(ImportFormat::Yaml),
+        Some("env") => Some(ImportFormat::Dotenv),
+        _ if name.starts_with(".env") => Some(ImportFormat::Dotenv),
+        _ => None,
+    }
+}
+
+fn is_valid_key(key: &str) -> bool {
+    key.len() <= MAX_KEY_LENGTH
+        && !key.chars().any(char::is_control)
+        && key.split('/').all(|segment| !segment.trim().is_empty())
+}
+
+fn join(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{prefix}/{key}")
+    }
+}
+
+/// The key/value map of a KV response: `data.data` in version 2, `data`
+/// in version 1; anything else is taken to be the map itself.
+fn kv_data(secret: &Value) -> &Value {
+    match secret.get("data") {
+        Some(data) if data.get("metadata").is_some() && data.get("data").is_some() => &data["data"],
+        Some(data) if data.is_object() => data,
+        _ => secret,
+    }
+}
+
+fn flatten_document(
+    format: ImportFormat,
+    prefix: &str,
+    document: &Value,
+    entries: &mut Vec<ImportEntry>,
+) -> Result<(), ImportError> {
+    if !document.is_object() {
+        return Err(ImportError::Parse {
+            format,
+            reason: "expected an object".to_string(),
+        });
+    }
+    flatten(prefix, document, entries)
+}
+
+fn flatten(key: &str, value: &Value, entries: &mut Vec<ImportEntry>) -> Result<(), ImportError> {
+    match value {
+        Value::Object(fields) => {
+            for (field, value) in fields {
+                flatten(&join(key, field), value, entries)?;
+            }
+        }
+        Value::Array(items) => {
+            for (index, value) in items.iter().enumerate() {
+                flatten(&join(key, &index.to_string()), value, entries)?;
+            }
+        }
+        Value::Null => return Err(ImportError::Null(key.to_string())),
+        Value::String(text) => entries.push((key.to_string(), text.as_str().into())),
+        scalar => entries.push((key.to_string(), scalar.to_string().into())),
+    }
+    Ok(())
+}
+
+/*---------------------------------------------------------------
+Dotenv: `KEY=value` lines, optionally preceded by `export`, with
+`#` comments. Single-quoted values are literal; double-quoted ones
+understand `\n`, `\t`, `\"` and `\\`; unquoted ones end at ` #`.
+----------------------------------------------------------------*/
+fn dotenv(content: &str) -> Result<Vec<ImportEntry>, ImportError> {
+    let mut entries = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let error = |reason: &str| ImportError::Dotenv {
+            line: index + 1,
+            reason: reason.to_string(),
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").map_or(line, str::trim_start);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected KEY=value"))?;
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        {
+            return Err(error("invalid key"));
+        }
+
+        let value = value.trim();
+        let (value, rest) = if let Some(quoted) = value.strip_prefix('\'') {
+            let (value, rest) = quoted
+                .split_once('\'')
+                .ok_or_else(|| error("unterminated quote"))?;
+            (value.to_string(), rest)
+        } else if let Some(quoted) = value.strip_prefix('"') {
+            double_quoted(quoted).ok_or_else(|| error("unterminated quote"))?
+        } else {
+            let value = value.split(" #").next().unwrap_or_default().trim_end();
+            (value.to_string(), "")
+        };
+        let rest = rest.trim_start();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(error("unexpected text after the value"));
+        }
+        entries.push((key.to_string(), value.into()));
+    }
+    Ok(entries)
+}
+
+/// The value up to the closing quote, unescaped, and what follows it.
+fn double_quoted(text: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[index + 1..])),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                escaped => value.push(escaped),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(entries: Vec<ImportEntry>) -> Vec<(String, String)> {
+        entries
+            .into_iter()
+            .map(|(key, value)| (key, value.as_str().to_string()))
+            .collect()
+    }
+
+    fn pair(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    fn never_compared() -> Result<bool, ()> {
+        panic!("a protected value was compared")
+    }
+
+    #[test]
+    fn protected_entries_are_skipped_without_comparing() {
+        // A dry run resolves the same way, so a matching guess is not reported as unchanged.
+        for conflict in [
+            ConflictStrategy::Skip,
+            ConflictStrategy::Overwrite,
+            ConflictStrategy::NewVersion,
+        ] {
+            assert_eq!(
+                resolve_existing(true, conflict, never_compared),
+                Ok(ImportAction::Skip)
+            );
+        }
+    }
+
+    #[test]
+    fn unprotected_entries_follow_the_strategy() {
+        let same = || Ok::<_, ()>(true);
+        let differs = || Ok::<_, ()>(false);
+        assert_eq!(
+            resolve_existing(false, ConflictStrategy::Overwrite, same),
+            Ok(ImportAction::Unchanged)
+        );
+        assert_eq!(
+            resolve_existing(false, ConflictStrategy::Skip, differs),
+            Ok(ImportAction::Skip)
+        );
+        assert_eq!(
+            resolve_existing(false, ConflictStrategy::Overwrite, differs),
+            Ok(ImportAction::Overwrite)
+        );
+        assert_eq!(
+            resolve_existing(false, ConflictStrategy::NewVersion, differs),
+            Ok(ImportAction::NewVersion)
+        );
+    }
+
+    #[test]
+    fn dotenv_files_are_read_literally() {
+        let content = r#"
+            # database
+            export DB_HOST=localhost # local only
+            DB_PASSWORD='p@ss #1 ${HOME}'
+            GREETING="line one\nline \"two\""
+            EMPTY=
+        "#;
+        let entries = parse(ImportFormat::Dotenv, content, Some("app/")).unwrap();
+        assert_eq!(
+            pairs(entries),
+            [
+                pair("app/DB_HOST", "localhost"),
+                pair("app/DB_PASSWORD", "p@ss #1 ${HOME}"),
+                pair("app/GREETING", "line one\nline \"two\""),
+                pair("app/EMPTY", ""),
+            ]
+        );
+
+        assert!(matches!(
+            parse(ImportFormat::Dotenv, "A=1\nB='open", None),
+            Err(ImportError::Dotenv { line: 2, .. })
+        ));
+        assert!(matches!(
+            parse(ImportFormat::Dotenv, "A=1\nA=2", None),
+            Err(ImportError::Duplicate(_))
+        ));
+    }
+
+    #[test]
+    fn documents_are_flattened_to_paths() {
+        let json =
+            r#"{ "db": { "user": "app", "port": 5432, "hosts": ["a", "b"] }, "debug": false }"#;
+        let yaml = "db:\n  user: app\n  port: 5432\n  hosts: [a, b]\ndebug: false\n";
+        for (format, content) in [(ImportFormat::Json, json), (ImportFormat::Yaml, yaml)] {
+            let mut entries = pairs(parse(format, content, None).unwrap());
+            entries.sort();
+            assert_eq!(
+                entries,
+                [
+                    pair("db/hosts/0", "a"),
+                    pair("db/hosts/1", "b"),
+                    pair("db/port", "5432"),
+                    pair("db/user", "app"),
+                    pair("debug", "false"),
+                ]
+            );
+        }
+
+        assert!(matches!(
+            parse(ImportFormat::Json, r#"{ "token": null }"#, None),
+            Err(ImportError::Null(_))
+        ));
+        assert!(matches!(
+            parse(ImportFormat::Yaml, "- a\n- b\n", None),
+            Err(ImportError::Parse { .. })
+        ));
+        assert!(matches!(
+            parse(ImportFormat::Json, "{}", None),
+            Err(ImportError::Empty)
+        ));
+    }
+
+    #[test]
+    fn vault_exports_are_unwrapped() {
+        let single = r#"{
+            "request_id": "1c2d",
+            "data": { "data": { "password": "hunter2" }, "metadata": { "version": 3 } }
+        }"#;
+        let entries = parse(ImportFormat::Vault, single, Some("db")).unwrap();
+        assert_eq!(pairs(entries), [pair("db/password", "hunter2")]);
+
+        let by_path = r#"{
+            "secret/app": { "data": { "data": { "token": "abc" }, "metadata": {} } },
+            "secret/legacy": { "data": { "key": "v1" } },
+            "secret/plain/": { "user": "svc" }
+        }"#;
+        let mut entries = pairs(parse(ImportFormat::Vault, by_path, None).unwrap());
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                pair("secret/app/token", "abc"),
+                pair("secret/legacy/key", "v1"),
+                pair("secret/plain/user", "svc"),
+            ]
+        );
+    }
+
+    #[test]
+    fn formats_are_detected_from_file_names() {
+        assert_eq!(detect_format(Path::new(".env")), Some(ImportFormat::Dotenv));
+        assert_eq!(
+            detect_format(Path::new("config/.env.production")),
+            Some(ImportFormat::Dotenv)
+        );
+        assert_eq!(
+            detect_format(Path::new("secrets.yml")),
+            Some(ImportFormat::Yaml)
+        );
+        assert_eq!(detect_format(Path::new("export.txt")), None);
+    }
+}
@@ -0,0 +1,701 @@
+use base64::{Engine, prelude::BASE64_STANDARD};
+use chacha20poly1305::{
+    AeadCore, ChaCha20Poly1305, KeyInit, Nonce,
+    aead::{Aead, OsRng},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::stream::StreamExt;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use mongodb::{
+    bson::{Bson, Document},
+    change_stream::event::{ChangeStreamEvent, OperationType, ResumeToken},
+    error::ErrorKind,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::models::{ChainBreak, ChainVerification};
+use crate::repositories::journal::JournalRepository;
+use crate::utils::audit::GENESIS_HASH;
+
+/*---------------------------------------------------------------
+The change journal. Every insert, update, replace and delete in
+the collections a snapshot holds is appended to a file as it
+happens, so a restored snapshot can be brought forward to any
+moment after it was taken.
+
+Each line is one entry: its sequence number, the time of the
+change, the change itself encrypted with ChaCha20-Poly1305, and an
+HMAC-SHA256 over all of that and the MAC of the entry before,
+starting from `GENESIS_HASH`. Entries are only ever appended, so
+an edited, dropped or reordered entry breaks the chain; entries cut
+off the end leave a shorter chain that still verifies, which only
+a head kept elsewhere shows. Both keys are derived from
+`ECS_JOURNAL_KEY`; the journal is read without unsealing, but
+documents are journaled as stored, so secret values stay encrypted
+under the master key of the deployment that wrote them.
+
+Read from the `[default.journal]` table of the server's Rocket
+configuration.
+----------------------------------------------------------------*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JournalConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    /// Seconds to wait before watching again after the change stream fails.
+    pub retry_delay: u64,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("journal/locksmith.journal"),
+            retry_delay: 5,
+        }
+    }
+}
+
+/// The key in `ECS_JOURNAL_KEY`, if set.
+pub fn journal_key() -> Option<Zeroizing<Vec<u8>>> {
+    std::env::var("ECS_JOURNAL_KEY")
+        .ok()
+        .filter(|key| !key.is_empty())
+        .map(|key| Zeroizing::new(key.into_bytes()))
+}
+
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error("ECS_JOURNAL_KEY is not set")]
+    MissingKey,
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to encode the change: {0}")]
+    Encode(String),
+    #[error("the change stream carried no document for the change to {0}")]
+    MissingDocument(String),
+    #[error("the journal is broken at entry {}: {}", .0.sequence, .0.reason)]
+    Broken(ChainBreak),
+}
+
+/// How a change left the document.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalOperation {
+    /// Inserted, updated or replaced; the record holds the whole document.
+    Upsert,
+    Delete,
+}
+
+/// One change, as encrypted into the journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalRecord {
+    pub collection: String,
+    pub operation: JournalOperation,
+    pub id: Bson,
+    /// The document after the change; `None` for deletes.
+    pub document: Option<Document>,
+    /// Where the change stream resumes after this change.
+    pub resume_token: Option<ResumeToken>,
+}
+
+impl JournalRecord {
+    /// The change an event made, and when. `None` for events that change
+    /// no single document. An insert, update or replace must carry the
+    /// document as the change left it; one that does not is an error
+    /// rather than a change to skip.
+    pub fn from_event(
+        event: ChangeStreamEvent<Document>,
+    ) -> Result<Option<(DateTime<Utc>, Self)>, JournalError> {
+        let Some(collection) = event.ns.and_then(|ns| ns.coll) else {
+            return Ok(None);
+        };
+        let Some(id) = event.document_key.and_then(|key| key.get("_id").cloned()) else {
+            return Ok(None);
+        };
+        let (operation, document) = match event.operation_type {
+            OperationType::Insert | OperationType::Update | OperationType::Replace => {
+                let Some(document) = event.full_document else {
+                    return Err(JournalError::MissingDocument(format!(
+                        "{} {}",
+                        collection, id
+                    )));
+                };
+                (JournalOperation::Upsert, Some(document))
+            }
+            OperationType::Delete => (JournalOperation::Delete, None),
+            _ => return Ok(None),
+        };
+        let timestamp = event
+            .wall_time
+            .map(|time| time.to_chrono())
+            .unwrap_or_else(Utc::now);
+        Ok(Some((
+            timestamp,
+            Self {
+                collection,
+                operation,
+                id,
+                document,
+                resume_token: Some(event.id),
+            },
+        )))
+    }
+}
+
+/// A change read back from the journal.
+#[derive(Debug, Clone)]
+pub struct JournalChange {
+    pub sequence: i64,
+    pub timestamp: DateTime<Utc>,
+    pub record: JournalRecord,
+}
+
+/// The changes in a journal, up to the first entry that fails to verify.
+#[derive(Debug)]
+pub struct JournalReading {
+    pub changes: Vec<JournalChange>,
+    /// MAC of the last good entry.
+    pub head_mac: String,
+    pub broken_at: Option<ChainBreak>,
+}
+
+impl JournalReading {
+    pub fn verification(&self) -> ChainVerification {
+        ChainVerification {
+            valid: self.broken_at.is_none(),
+            entries: self.changes.len() as u64,
+            head_sequence: self.changes.last().map_or(0, |change| change.sequence),
+            head_hash: self.head_mac.clone(),
+            broken_at: self.broken_at.clone(),
+        }
+    }
+
+    fn intact(self) -> Result<Self, JournalError> {
+        match self.broken_at {
+            Some(broken_at) => Err(JournalError::Broken(broken_at)),
+            None => Ok(self),
+        }
+    }
+}
+
+/// Reads and verifies the journal at `path`. A missing file is an empty
+/// journal.
+pub fn read(path: &Path, key: &[u8]) -> Result<JournalReading, JournalError> {
+    let keys = JournalKeys::derive(key);
+    let mut reading = JournalReading {
+        changes: Vec::new(),
+        head_mac: GENESIS_HASH.to_string(),
+        broken_at: None,
+    };
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(reading),
+        Err(e) => return Err(e.into()),
+    };
+
+    for line in BufReader::new(file).lines() {
+        let sequence = reading.changes.len() as i64 + 1;
+        match keys.check(&line?, sequence, &reading.head_mac) {
+            Ok((change, mac)) => {
+                reading.changes.push(change);
+                reading.head_mac = mac;
+            }
+            Err(reason) => {
+                reading.broken_at = Some(ChainBreak { sequence, reason });
+                break;
+            }
+        }
+    }
+    Ok(reading)
+}
+
+/*---------------------------------------------------------------
+Appending. The journal is verified when opened and refuses to
+extend a broken chain; move the file aside to start a new one.
+----------------------------------------------------------------*/
+pub struct Journal {
+    path: PathBuf,
+    keys: JournalKeys,
+    head_sequence: i64,
+    head_mac: String,
+    resume_token: Option<ResumeToken>,
+}
+
+impl Journal {
+    pub fn open(path: &Path, key: &[u8]) -> Result<Self, JournalError> {
+        let reading = read(path, key)?.intact()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            keys: JournalKeys::derive(key),
+            head_sequence: reading.changes.len() as i64,
+            resume_token: reading
+                .changes
+                .last()
+                .and_then(|change| change.record.resume_token.clone()),
+            head_mac: reading.head_mac,
+        })
+    }
+
+    pub fn head_sequence(&self) -> i64 {
+        self.head_sequence
+    }
+
+    /// Where the change stream left off when the last entry was written.
+    pub fn resume_token(&self) -> Option<ResumeToken> {
+        self.resume_token.clone()
+    }
+
+    /// Appends a change and flushes it to disk. A failed write is cut
+    /// off again, leaving the journal as it was.
+    pub fn append(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        record: &JournalRecord,
+    ) -> Result<(), JournalError> {
+        let sequence = self.head_sequence + 1;
+        let entry = self
+            .keys
+            .entry(sequence, timestamp, record, &self.head_mac)?;
+        let line = entry.line()?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let length = file.metadata()?.len();
+        if let Err(e) = file.write_all(&line).and_then(|()| file.sync_data()) {
+            let _ = file.set_len(length);
+            return Err(e.into());
+        }
+
+        self.head_sequence = sequence;
+        self.head_mac = entry.mac;
+        if record.resume_token.is_some() {
+            self.resume_token = record.resume_token.clone();
+        }
+        Ok(())
+    }
+}
+
+/// What `compact` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compaction {
+    pub before: usize,
+    pub after: usize,
+}
+
+/*---------------------------------------------------------------
+COMPACT keeps, of the changes made before `cutoff`, only the last
+one to each document, and every change from `cutoff` on. Deletes
+are kept, since the snapshot replayed onto may still hold the
+document. The entries are numbered and chained afresh into a new
+file that then replaces the journal, so stop the server while it
+runs.
+----------------------------------------------------------------*/
+pub fn compact(path: &Path, key: &[u8], cutoff: DateTime<Utc>) -> Result<Compaction, JournalError> {
+    let reading = read(path, key)?.intact()?;
+    let document = |change: &JournalChange| {
+        (
+            change.record.collection.clone(),
+            change.record.id.to_string(),
+        )
+    };
+
+    let mut latest = HashMap::new();
+    for (index, change) in reading.changes.iter().enumerate() {
+        if change.timestamp < cutoff {
+            latest.insert(document(change), index);
+        }
+    }
+    let kept: Vec<&JournalChange> = reading
+        .changes
+        .iter()
+        .enumerate()
+        .filter(|(index, change)| {
+            change.timestamp >= cutoff || latest.get(&document(change)) == Some(index)
+        })
+        .map(|(_, change)| change)
+        .collect();
+
+    let keys = JournalKeys::derive(key);
+    let mut temporary = OsString::from(path.as_os_str());
+    temporary.push(".compacting");
+    let temporary = PathBuf::from(temporary);
+
+    let mut file = File::create(&temporary)?;
+    let mut prev_mac = GENESIS_HASH.to_string();
+    for (sequence, change) in (1..).zip(&kept) {
+        let entry = keys.entry(sequence, change.timestamp, &change.record, &prev_mac)?;
+        file.write_all(&entry.line()?)?;
+        prev_mac = entry.mac;
+    }
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+
+    Ok(Compaction {
+        before: reading.changes.len(),
+        after: kept.len(),
+    })
+}
+
+/*---------------------------------------------------------------
+Following the database. Changes arrive through a change stream,
+which needs MongoDB to run as a replica set, and the stream
+resumes after the last journaled change when the server restarts.
+When MongoDB no longer has that far back, following starts over
+from now and the gap is logged; take a new snapshot then. A change
+that cannot be journaled stops the stream, which is watched again
+from the last change written, so no change is passed over.
+----------------------------------------------------------------*/
+/// Spawns the task that journals every change; call from within a Tokio
+/// runtime.
+pub fn follow(repository: Arc<JournalRepository>, mut journal: Journal, retry_delay: Duration) {
+    tokio::spawn(async move {
+        // Where to watch from: after the last journaled change, or where
+        // the first stream began while nothing is journaled yet.
+        let mut resume_after = journal.resume_token();
+        loop {
+            let resuming = resume_after.is_some();
+            let mut stream = match repository.watch(resume_after.clone()).await {
+                Ok(stream) => stream,
+                Err(e) if resuming && history_lost(&e) => {
+                    warn!(
+                        "Cannot resume the change journal after entry {}; changes since are missing from it: {}",
+                        journal.head_sequence(),
+                        e
+                    );
+                    journal.resume_token = None;
+                    resume_after = None;
+                    continue;
+                }
+                Err(e) => {
+                    error!("Failed to watch for changes to journal: {}", e);
+                    tokio::time::sleep(retry_delay).await;
+                    continue;
+                }
+            };
+            if resume_after.is_none() {
+                resume_after = stream.resume_token();
+            }
+            info!("Journaling changes after entry {}", journal.head_sequence());
+
+            while let Some(event) = stream.next().await {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        error!("The change stream failed: {}", e);
+                        break;
+                    }
+                };
+                let journaled = JournalRecord::from_event(event).and_then(|change| match change {
+                    Some((timestamp, record)) => journal.append(timestamp, &record),
+                    None => Ok(()),
+                });
+                if let Err(e) = journaled {
+                    error!(
+                        "Failed to journal a change; watching again after entry {}: {}",
+                        journal.head_sequence(),
+                        e
+                    );
+                    break;
+                }
+                if let Some(token) = journal.resume_token() {
+                    resume_after = Some(token);
+                }
+            }
+            tokio::time::sleep(retry_delay).await;
+        }
+    });
+}
+
+/// ChangeStreamHistoryLost and ChangeStreamFatalError.
+fn history_lost(error: &mongodb::error::Error) -> bool {
+    matches!(&*error.kind, ErrorKind::Command(e) if e.code == 286 || e.code == 280)
+}
+
+/*---------------------------------------------------------------
+Entry encoding.
+----------------------------------------------------------------*/
+#[derive(Serialize, Deserialize)]
+struct JournalEntry {
+    sequence: i64,
+    /// RFC 3339, at millisecond precision.
+    timestamp: String,
+    /// The record as BSON, encrypted; base64 of the nonce and ciphertext.
+    data: String,
+    prev_mac: String,
+    mac: String,
+}
+
+impl JournalEntry {
+    fn line(&self) -> Result<Vec<u8>, JournalError> {
+        let mut line = serde_json::to_vec(self).map_err(|e| JournalError::Encode(e.to_string()))?;
+        line.push(b'\n');
+        Ok(line)
+    }
+}
+
+struct JournalKeys {
+    cipher: Zeroizing<Vec<u8>>,
+    mac: Zeroizing<Vec<u8>>,
+}
+
+impl JournalKeys {
+    fn derive(key: &[u8]) -> Self {
+        let derive = |label: &[u8]| {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key)
+                .expect("HMAC accepts keys of any length");
+            mac.update(label);
+            Zeroizing::new(mac.finalize().into_bytes().to_vec())
+        };
+        Self {
+            cipher: derive(b"locksmith-journal:cipher"),
+            mac: derive(b"locksmith-journal:mac"),
+        }
+    }
+
+    /// Timestamps count at millisecond precision, as in the audit chain.
+    fn mac(&self, sequence: i64, timestamp: DateTime<Utc>, data: &str, prev_mac: &str) -> String {
+        let canonical = json!([sequence, timestamp.timestamp_millis(), data, prev_mac]);
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.mac)
+            .expect("HMAC accepts keys of any length");
+        mac.update(canonical.to_string().as_bytes());
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+
+    fn entry(
+        &self,
+        sequence: i64,
+        timestamp: DateTime<Utc>,
+        record: &JournalRecord,
+        prev_mac: &str,
+    ) -> Result<JournalEntry, JournalError> {
+        let plaintext = Zeroizing::new(
+            mongodb::bson::to_vec(record).map_err(|e| JournalError::Encode(e.to_string()))?,
+        );
+        let cipher = ChaCha20Poly1305::new_from_slice(&self.cipher).expect("32-byte key");
+        let nonce = ChaCha20Poly1305::generate_nonce(OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|e| JournalError::Encode(e.to_string()))?;
+        let data = BASE64_STANDARD.encode([nonce.as_slice(), &ciphertext].concat());
+
+        Ok(JournalEntry {
+            sequence,
+            timestamp: timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+            mac: self.mac(sequence, timestamp, &data, prev_mac),
+            data,
+            prev_mac: prev_mac.to_string(),
+        })
+    }
+
+    /// Verifies one line against the chain so far; returns the change and
+    /// the entry's MAC, or why it does not verify.
+    fn check(
+        &self,
+        line: &str,
+        sequence: i64,
+        prev_mac: &str,
+    ) -> Result<(JournalChange, String), String> {
+        let entry: JournalEntry =
+            serde_json::from_str(line).map_err(|e| format!("unreadable entry: {e}"))?;
+        if entry.sequence != sequence {
+            return Err(format!(
+                "expected entry {sequence}, found {}",
+                entry.sequence
+            ));
+        }
+        if entry.prev_mac != prev_mac {
+            return Err("does not link to the previous entry".to_string());
+        }
+        let timestamp = DateTime::parse_from_rfc3339(&entry.timestamp)
+            .map_err(|e| format!("unreadable timestamp: {e}"))?
+            .with_timezone(&Utc);
+        if self.mac(sequence, timestamp, &entry.data, prev_mac) != entry.mac {
+            return Err("MAC mismatch; the entry was modified or the key is wrong".to_string());
+        }
+
+        let data = BASE64_STANDARD
+            .decode(&entry.data)
+            .ok()
+            .filter(|data| data.len() > 12)
+            .ok_or("unreadable ciphertext")?;
+        let (nonce, ciphertext) = data.split_at(12);
+        let cipher = ChaCha20Poly1305::new_from_slice(&self.cipher).expect("32-byte key");
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(Nonce::from_slice(nonce), ciphertext)
+                .map_err(|_| "cannot be decrypted")?,
+        );
+        let record =
+            mongodb::bson::from_slice(&plaintext).map_err(|e| format!("unreadable change: {e}"))?;
+
+        Ok((
+            JournalChange {
+                sequence,
+                timestamp,
+                record,
+            },
+            entry.mac,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration as TimeDelta;
+    use mongodb::bson::doc;
+
+    const KEY: &[u8] = b"journal-test-key";
+
+    fn journal_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("locksmith-journal-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn upsert(id: i32, value: &str) -> JournalRecord {
+        JournalRecord {
+            collection: "vault".to_string(),
+            operation: JournalOperation::Upsert,
+            id: Bson::Int32(id),
+            document: Some(doc! { "_id": id, "value": value }),
+            resume_token: None,
+        }
+    }
+
+    #[test]
+    fn journals_verify_and_detect_tampering() {
+        let path = journal_path("tamper.journal");
+        let mut journal = Journal::open(&path, KEY).unwrap();
+        let now = Utc::now();
+        for (id, value) in [(1, "a"), (2, "b"), (1, "c")] {
+            journal.append(now, &upsert(id, value)).unwrap();
+        }
+
+        let reading = read(&path, KEY).unwrap();
+        assert!(reading.verification().valid);
+        assert_eq!(reading.verification().head_sequence, 3);
+        let last = reading.changes[2].record.document.as_ref().unwrap();
+        assert_eq!(last.get_str("value").unwrap(), "c");
+        assert!(!fs::read_to_string(&path).unwrap().contains("\"c\""));
+
+        let wrong_key = read(&path, b"another key").unwrap();
+        assert_eq!(wrong_key.broken_at.unwrap().sequence, 1);
+
+        let lines: Vec<String> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        let reading = read(&path, KEY).unwrap();
+        assert_eq!(reading.changes.len(), 1);
+        assert_eq!(reading.broken_at.unwrap().sequence, 2);
+        assert!(matches!(
+            Journal::open(&path, KEY),
+            Err(JournalError::Broken(_))
+        ));
+    }
+
+    #[test]
+    fn changes_without_their_document_are_not_skipped() {
+        let event = |operation: &str, full_document: Option<Document>| {
+            let mut event = doc! {
+                "_id": { "_data": "8263" },
+                "operationType": operation,
+                "ns": { "db": "locksmith", "coll": "vault" },
+                "documentKey": { "_id": 1 },
+            };
+            if let Some(document) = full_document {
+                event.insert("fullDocument", document);
+            }
+            mongodb::bson::from_document::<ChangeStreamEvent<Document>>(event).unwrap()
+        };
+
+        let (_, record) = JournalRecord::from_event(event("update", Some(doc! { "_id": 1 })))
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.operation, JournalOperation::Upsert);
+        assert!(matches!(
+            JournalRecord::from_event(event("update", None)),
+            Err(JournalError::MissingDocument(_))
+        ));
+        let (_, record) = JournalRecord::from_event(event("delete", None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.operation, JournalOperation::Delete);
+        assert!(
+            JournalRecord::from_event(event("drop", None))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn compaction_keeps_the_latest_change_before_the_cutoff() {
+        let path = journal_path("compact.journal");
+        let mut journal = Journal::open(&path, KEY).unwrap();
+        let start = Utc::now() - TimeDelta::hours(2);
+        let mut delete = upsert(2, "");
+        delete.operation = JournalOperation::Delete;
+        delete.document = None;
+        let changes = [
+            (start, upsert(1, "a")),
+            (start + TimeDelta::minutes(1), upsert(1, "b")),
+            (start + TimeDelta::minutes(2), upsert(2, "x")),
+            (start + TimeDelta::minutes(3), delete),
+            (start + TimeDelta::hours(1), upsert(1, "c")),
+            (start + TimeDelta::hours(1), upsert(1, "d")),
+        ];
+        for (timestamp, record) in &changes {
+            journal.append(*timestamp, record).unwrap();
+        }
+
+        let compaction = compact(&path, KEY, start + TimeDelta::minutes(30)).unwrap();
+        assert_eq!(
+            compaction,
+            Compaction {
+                before: 6,
+                after: 4
+            }
+        );
+
+        let reading = read(&path, KEY).unwrap();
+        assert!(reading.verification().valid);
+        let values: Vec<Option<&str>> = reading
+            .changes
+            .iter()
+            .map(|change| {
+                change
+                    .record
+                    .document
+                    .as_ref()
+                    .map(|document| document.get_str("value").unwrap())
+            })
+            .collect();
+        assert_eq!(values, [Some("b"), None, Some("c"), Some("d")]);
+        assert_eq!(reading.changes[3].sequence, 4);
+    }
+}
@@ -0,0 +1,326 @@
+use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::models::{AppRoleCredentials, TransitRequest, TransitResponse};
+use crate::utils::pkcs11::Pkcs11KeyManager;
+use crate::utils::secret::SecretValue;
+use crate::utils::vault::{decrypt, encrypt};
+
+/*---------------------------------------------------------------
+Auto-unseal. A key manager outside Locksmith wraps the master key
+at initialization; the wrapped copy is stored with the seal, and
+on boot the server asks the key manager to unwrap it instead of
+waiting for operators. The key manager never hands out its own
+key, so the database alone still reveals nothing.
+
+Two are built in: a PKCS#11 HSM (tested against SoftHSM), and
+"transit", another Locksmith instance that encrypts on our behalf
+without ever storing the data. Unseal shares keep working as a
+fallback when the key manager is unreachable.
+
+Read from the `[default.seal]` table of the server's Rocket
+configuration.
+----------------------------------------------------------------*/
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SealConfig {
+    /// `pkcs11` or `transit`; unset keeps unsealing manual.
+    pub auto_unseal: Option<KeyManagerKind>,
+    pub pkcs11: Pkcs11Config,
+    pub transit: TransitConfig,
+    /// Lock the master key into RAM so it never reaches swap; Linux
+    /// only, and subject to `RLIMIT_MEMLOCK`.
+    pub mlock: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyManagerKind {
+    Pkcs11,
+    Transit,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Pkcs11Config {
+    /// The vendor's PKCS#11 module.
+    pub library: PathBuf,
+    /// Label of the token holding the wrapping key.
+    pub token_label: String,
+    /// Label of the AES key that wraps the master key.
+    pub key_label: String,
+    /// User PIN of the token; may be supplied through the
+    /// `ECS_PKCS11_PIN` environment variable.
+    pub pin: Option<String>,
+}
+
+impl Default for Pkcs11Config {
+    fn default() -> Self {
+        Self {
+            library: PathBuf::from("/usr/lib/softhsm/libsofthsm2.so"),
+            token_label: "locksmith".to_string(),
+            key_label: "locksmith-master-key".to_string(),
+            pin: None,
+        }
+    }
+}
+
+impl Pkcs11Config {
+    pub(crate) fn pin(&self) -> Option<Zeroizing<String>> {
+        std::env::var("ECS_PKCS11_PIN")
+            .ok()
+            .or_else(|| self.pin.clone())
+            .filter(|pin| !pin.is_empty())
+            .map(Zeroizing::new)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransitConfig {
+    /// Base URL of the Locksmith instance doing the encryption.
+    pub url: String,
+    /// Name of the transit key on that instance.
+    pub key: String,
+    /// AppRole this server logs in with.
+    pub role_id: String,
+    /// Secret id of that AppRole; may be supplied through the
+    /// `ECS_TRANSIT_SECRET_ID` environment variable.
+    pub secret_id: Option<String>,
+    /// Seconds to wait for the transit server.
+    pub timeout: u64,
+}
+
+impl Default for TransitConfig {
+    fn default() -> Self {
+        Self {
+            url: "https://localhost:8089".to_string(),
+            key: "locksmith".to_string(),
+            role_id: String::new(),
+            secret_id: None,
+            timeout: 10,
+        }
+    }
+}
+
+impl TransitConfig {
+    fn secret_id(&self) -> Option<SecretValue> {
+        std::env::var("ECS_TRANSIT_SECRET_ID")
+            .ok()
+            .or_else(|| self.secret_id.clone())
+            .filter(|secret_id| !secret_id.is_empty())
+            .map(SecretValue::new)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum KmsError {
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    #[error("PKCS#11 error: {0}")]
+    Pkcs11(String),
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("transit server answered {0}")]
+    Rejected(u16),
+    #[error("malformed wrapped key")]
+    Malformed,
+}
+
+/// Wraps and unwraps the master key; implement it to plug in another
+/// key manager.
+#[async_trait]
+pub trait KeyManager: Send + Sync {
+    /// Recorded with the wrapped key, e.g. `pkcs11`.
+    fn name(&self) -> &'static str;
+    async fn wrap(&self, key: &[u8]) -> Result<Vec<u8>, KmsError>;
+    async fn unwrap(&self, wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>, KmsError>;
+}
+
+/// The key manager configured for auto-unseal, if any.
+pub fn key_manager(config: &SealConfig) -> Result<Option<Arc<dyn KeyManager>>, KmsError> {
+    Ok(match config.auto_unseal {
+        None => None,
+        Some(KeyManagerKind::Pkcs11) => Some(Arc::new(Pkcs11KeyManager::open(&config.pkcs11)?)),
+        Some(KeyManagerKind::Transit) => Some(Arc::new(TransitKeyManager::new(&config.transit)?)),
+    })
+}
+
+/*---------------------------------------------------------------
+Transit, client side: log in with an AppRole, then have the other
+instance encrypt or decrypt under its key.
+----------------------------------------------------------------*/
+pub struct TransitKeyManager {
+    client: Client,
+    url: String,
+    key: String,
+    role_id: String,
+    secret_id: SecretValue,
+}
+
+#[derive(Deserialize)]
+struct TransitLogin {
+    token: String,
+}
+
+impl TransitKeyManager {
+    pub fn new(config: &TransitConfig) -> Result<Self, KmsError> {
+        if !is_valid_transit_key(&config.key) {
+            return Err(KmsError::Config(format!(
+                "invalid transit key name: {}",
+                config.key
+            )));
+        }
+        let Some(secret_id) = config.secret_id() else {
+            return Err(KmsError::Config(
+                "transit needs a role_id and a secret_id".to_string(),
+            ));
+        };
+        if config.role_id.is_empty() {
+            return Err(KmsError::Config(
+                "transit needs a role_id and a secret_id".to_string(),
+            ));
+        }
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()?;
+        Ok(Self {
+            client,
+            url: config.url.trim_end_matches('/').to_string(),
+            key: config.key.clone(),
+            role_id: config.role_id.clone(),
+            secret_id,
+        })
+    }
+
+    async fn login(&self) -> Result<String, KmsError> {
+        let response = self
+            .client
+            .post(format!("{}/approle/login", self.url))
+            .json(&AppRoleCredentials {
+                role_id: self.role_id.clone(),
+                secret_id: self.secret_id.clone(),
+            })
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            return Err(KmsError::Rejected(response.status().as_u16()));
+        }
+        Ok(response.json::<TransitLogin>().await?.token)
+    }
+
+    async fn transit(&self, operation: &str, data: &[u8]) -> Result<Vec<u8>, KmsError> {
+        let token = Zeroizing::new(self.login().await?);
+        let response = self
+            .client
+            .post(format!("{}/transit/{}/{}", self.url, self.key, operation))
+            .bearer_auth(token.as_str())
+            .json(&TransitRequest {
+                data: STANDARD.encode(data),
+            })
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            return Err(KmsError::Rejected(response.status().as_u16()));
+        }
+        let response = response.json::<TransitResponse>().await?;
+        STANDARD
+            .decode(response.data)
+            .map_err(|_| KmsError::Malformed)
+    }
+}
+
+#[async_trait]
+impl KeyManager for TransitKeyManager {
+    fn name(&self) -> &'static str {
+        "transit"
+    }
+
+    async fn wrap(&self, key: &[u8]) -> Result<Vec<u8>, KmsError> {
+        self.transit("encrypt", key).await
+    }
+
+    async fn unwrap(&self, wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>, KmsError> {
+        self.transit("decrypt", wrapped).await.map(Zeroizing::new)
+    }
+}
+
+/*---------------------------------------------------------------
+Transit, server side. Each named key is derived from the master
+key, so nothing is stored and sealing this instance suspends its
+transit keys too.
+----------------------------------------------------------------*/
+pub fn is_valid_transit_key(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn transit_key(master_key: &[u8], name: &str) -> Zeroizing<Vec<u8>> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(master_key).expect("HMAC accepts keys of any length");
+    mac.update(b"locksmith-transit:");
+    mac.update(name.as_bytes());
+    Zeroizing::new(mac.finalize().into_bytes().to_vec())
+}
+
+pub fn transit_encrypt(master_key: &[u8], name: &str, data: &[u8]) -> Option<Vec<u8>> {
+    encrypt(data, &transit_key(master_key, name)).ok()
+}
+
+pub fn transit_decrypt(master_key: &[u8], name: &str, data: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+    decrypt(data, &transit_key(master_key, name)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transit_keys_are_separate() {
+        let master_key = b"transit-master-key";
+        let wrapped = transit_encrypt(master_key, "prod", b"payload").unwrap();
+        assert_eq!(
+            transit_decrypt(master_key, "prod", &wrapped)
+                .unwrap()
+                .as_slice(),
+            b"payload"
+        );
+        assert!(transit_decrypt(master_key, "staging", &wrapped).is_none());
+        assert!(transit_decrypt(b"another-master-key", "prod", &wrapped).is_none());
+
+        assert!(is_valid_transit_key("locksmith-prod_1"));
+        assert!(!is_valid_transit_key("../keys"));
+        assert!(!is_valid_transit_key(""));
+    }
+
+    #[test]
+    fn auto_unseal_is_optional() {
+        assert!(key_manager(&SealConfig::default()).unwrap().is_none());
+
+        let config = SealConfig {
+            auto_unseal: Some(KeyManagerKind::Transit),
+            ..SealConfig::default()
+        };
+        assert!(matches!(key_manager(&config), Err(KmsError::Config(_))));
+
+        let config = SealConfig {
+            auto_unseal: Some(KeyManagerKind::Pkcs11),
+            pkcs11: Pkcs11Config {
+                library: PathBuf::from("/nonexistent/libpkcs11.so"),
+                ..Pkcs11Config::default()
+            },
+            ..SealConfig::default()
+        };
+        assert!(matches!(key_manager(&config), Err(KmsError::Config(_))));
+    }
+}
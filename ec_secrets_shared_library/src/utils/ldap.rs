@@ -0,0 +1,397 @@
+use std::{collections::HashMap, time::Duration};
+
+use ldap3::{
+    Ldap, LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry, dn_escape, ldap_escape,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// LDAP result code returned for a wrong DN or password.
+const INVALID_CREDENTIALS: u32 = 49;
+
+/*---------------------------------------------------------------
+Password logins against an Active Directory or OpenLDAP directory.
+
+With `user_dn_template` set, users bind directly with the DN built
+from it (bind-as-user). Otherwise the service account `bind_dn`
+looks the user up under `user_base_dn` with `user_filter` and the
+user's DN is then bound with the password (search-then-bind).
+`{username}` in the template and filter is replaced, escaped, by
+the login name.
+
+Groups come from the `group_attribute` of the user entry (AD's
+`memberOf`) and, with `group_base_dn` set, from a search with
+`group_filter`, where `{dn}` is the user's DN. `group_roles` maps
+group names (the first RDN value, e.g. `admins`) or full DNs to
+Locksmith roles; when non-empty, a user's roles follow the
+directory on every login.
+
+`ldaps://` URLs use TLS from the start, `starttls` upgrades a
+plain `ldap://` connection. Certificates are checked against the
+system store; point `SSL_CERT_FILE` at a PEM bundle to trust a
+private CA. The service account password may be supplied through
+the `ECS_LDAP_BIND_PASSWORD` environment variable.
+
+Read from the `[default.auth.ldap]` table of the server's Rocket
+configuration.
+----------------------------------------------------------------*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LdapConfig {
+    pub enabled: bool,
+    pub url: String,
+    pub starttls: bool,
+    /// Only disable to test against a directory with a throwaway certificate.
+    pub tls_verify: bool,
+    /// Seconds to wait for the directory before giving up.
+    pub timeout: u64,
+    pub user_dn_template: String,
+    pub bind_dn: String,
+    pub bind_password: String,
+    pub user_base_dn: String,
+    pub user_filter: String,
+    pub email_attribute: String,
+    pub group_attribute: String,
+    pub group_base_dn: String,
+    pub group_filter: String,
+    pub group_roles: HashMap<String, Vec<String>>,
+    /// Create an account the first time a directory user logs in.
+    pub auto_provision: bool,
+}
+
+impl Default for LdapConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: "ldap://localhost:389".to_string(),
+            starttls: false,
+            tls_verify: true,
+            timeout: 10,
+            user_dn_template: String::new(),
+            bind_dn: String::new(),
+            bind_password: String::new(),
+            user_base_dn: String::new(),
+            user_filter: "(|(uid={username})(mail={username}))".to_string(),
+            email_attribute: "mail".to_string(),
+            group_attribute: "memberOf".to_string(),
+            group_base_dn: String::new(),
+            group_filter: "(|(member={dn})(uniqueMember={dn}))".to_string(),
+            group_roles: HashMap::new(),
+            auto_provision: true,
+        }
+    }
+}
+
+impl LdapConfig {
+    fn bind_password(&self) -> String {
+        std::env::var("ECS_LDAP_BIND_PASSWORD").unwrap_or_else(|_| self.bind_password.clone())
+    }
+
+    /// The DN a user binds with in bind-as-user mode.
+    pub fn user_dn(&self, username: &str) -> Option<String> {
+        (!self.user_dn_template.is_empty()).then(|| {
+            self.user_dn_template
+                .replace("{username}", &dn_escape(username))
+        })
+    }
+
+    /// The filter locating a user in search-then-bind mode.
+    pub fn user_search_filter(&self, username: &str) -> String {
+        self.user_filter
+            .replace("{username}", &ldap_escape(username))
+    }
+
+    /// The filter locating the groups a user is a member of.
+    pub fn group_search_filter(&self, user_dn: &str) -> String {
+        self.group_filter.replace("{dn}", &ldap_escape(user_dn))
+    }
+
+    /// Locksmith roles for a user's groups, sorted and deduplicated.
+    pub fn roles_for_groups(&self, groups: &[String]) -> Vec<String> {
+        let mut roles: Vec<String> = groups
+            .iter()
+            .flat_map(|group| {
+                self.group_roles
+                    .iter()
+                    .filter(move |(name, _)| {
+                        name.eq_ignore_ascii_case(group)
+                            || name.eq_ignore_ascii_case(group_name(group))
+                    })
+                    .flat_map(|(_, roles)| roles.iter().cloned())
+            })
+            .collect();
+        roles.sort();
+        roles.dedup();
+        roles
+    }
+}
+
+/// The value of a group DN's first RDN, e.g. `admins` for `cn=admins,ou=groups,...`.
+fn group_name(dn: &str) -> &str {
+    let rdn = dn.split(',').next().unwrap_or(dn);
+    rdn.split_once('=').map_or(rdn, |(_, value)| value).trim()
+}
+
+#[derive(Error, Debug)]
+pub enum LdapAuthError {
+    #[error("LDAP authentication is not enabled")]
+    Disabled,
+    #[error("invalid credentials")]
+    InvalidCredentials,
+    #[error("user was not found in the directory")]
+    UserNotFound,
+    #[error("login name matches more than one directory entry")]
+    AmbiguousUser,
+    #[error("directory entry has no `{0}` attribute")]
+    MissingAttribute(String),
+    #[error("directory error: {0}")]
+    Directory(#[from] LdapError),
+}
+
+/// A user authenticated by the directory, mapped onto Locksmith.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LdapIdentity {
+    pub dn: String,
+    pub email: String,
+    pub groups: Vec<String>,
+    pub roles: Vec<String>,
+}
+
+/*---------------------------------------------------------
+Authenticate `username` with `password` against the directory
+----------------------------------------------------------*/
+pub async fn authenticate(
+    config: &LdapConfig,
+    username: &str,
+    password: &str,
+) -> Result<LdapIdentity, LdapAuthError> {
+    if !config.enabled {
+        return Err(LdapAuthError::Disabled);
+    }
+    // An empty password is an unauthenticated bind, which most servers accept.
+    if username.is_empty() || password.is_empty() {
+        return Err(LdapAuthError::InvalidCredentials);
+    }
+
+    let timeout = Duration::from_secs(config.timeout);
+    let settings = LdapConnSettings::new()
+        .set_conn_timeout(timeout)
+        .set_starttls(config.starttls)
+        .set_no_tls_verify(!config.tls_verify);
+    let (connection, mut ldap) = LdapConnAsync::with_settings(settings, &config.url).await?;
+    ldap3::drive!(connection);
+
+    let result = authenticate_with(&mut ldap, config, username, password, timeout).await;
+    let _ = ldap.unbind().await;
+    result
+}
+
+async fn authenticate_with(
+    ldap: &mut Ldap,
+    config: &LdapConfig,
+    username: &str,
+    password: &str,
+    timeout: Duration,
+) -> Result<LdapIdentity, LdapAuthError> {
+    let attributes = vec![
+        config.email_attribute.as_str(),
+        config.group_attribute.as_str(),
+    ];
+
+    let user_dn = match config.user_dn(username) {
+        Some(user_dn) => user_dn,
+        None => {
+            ldap.with_timeout(timeout)
+                .simple_bind(&config.bind_dn, &config.bind_password())
+                .await?
+                .success()?;
+
+            let (entries, _) = ldap
+                .with_timeout(timeout)
+                .search(
+                    &config.user_base_dn,
+                    Scope::Subtree,
+                    &config.user_search_filter(username),
+                    attributes.clone(),
+                )
+                .await?
+                .success()?;
+            match entries.as_slice() {
+                [] => return Err(LdapAuthError::UserNotFound),
+                [entry] => SearchEntry::construct(entry.clone()).dn,
+                _ => return Err(LdapAuthError::AmbiguousUser),
+            }
+        }
+    };
+
+    let bind = ldap
+        .with_timeout(timeout)
+        .simple_bind(&user_dn, password)
+        .await?;
+    if bind.rc == INVALID_CREDENTIALS {
+        return Err(LdapAuthError::InvalidCredentials);
+    }
+    bind.success()?;
+
+    // Read the entry as the user, who can always see their own attributes.
+    let (entries, _) = ldap
+        .with_timeout(timeout)
+        .search(&user_dn, Scope::Base, "(objectClass=*)", attributes)
+        .await?
+        .success()?;
+    let entry = entries
+        .into_iter()
+        .next()
+        .map(SearchEntry::construct)
+        .ok_or(LdapAuthError::UserNotFound)?;
+
+    let email = attribute(&entry, &config.email_attribute)
+        .first()
+        .cloned()
+        .ok_or_else(|| LdapAuthError::MissingAttribute(config.email_attribute.clone()))?;
+    let mut groups = attribute(&entry, &config.group_attribute);
+
+    if !config.group_base_dn.is_empty() {
+        let (entries, _) = ldap
+            .with_timeout(timeout)
+            .search(
+                &config.group_base_dn,
+                Scope::Subtree,
+                &config.group_search_filter(&user_dn),
+                vec!["1.1"],
+            )
+            .await?
+            .success()?;
+        groups.extend(
+            entries
+                .into_iter()
+                .map(|entry| SearchEntry::construct(entry).dn),
+        );
+    }
+    groups.sort();
+    groups.dedup();
+
+    Ok(LdapIdentity {
+        roles: config.roles_for_groups(&groups),
+        dn: user_dn,
+        email,
+        groups,
+    })
+}
+
+/// Attribute names are case-insensitive in LDAP.
+fn attribute(entry: &SearchEntry, name: &str) -> Vec<String> {
+    entry
+        .attrs
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, values)| values.clone())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> LdapConfig {
+        LdapConfig {
+            enabled: true,
+            group_roles: HashMap::from([
+                ("admins".to_string(), vec!["admin".to_string()]),
+                (
+                    "cn=operators,ou=groups,dc=example,dc=org".to_string(),
+                    vec!["operator".to_string()],
+                ),
+            ]),
+            ..LdapConfig::default()
+        }
+    }
+
+    #[test]
+    fn escapes_login_names() {
+        let mut config = config();
+        assert_eq!(config.user_dn("jane"), None);
+        assert_eq!(
+            config.user_search_filter("*)(uid=*"),
+            "(|(uid=\\2a\\29\\28uid=\\2a)(mail=\\2a\\29\\28uid=\\2a))"
+        );
+
+        config.user_dn_template = "uid={username},ou=people,dc=example,dc=org".to_string();
+        assert_eq!(
+            config.user_dn("jane,ou=admins").as_deref(),
+            Some("uid=jane\\2cou\\3dadmins,ou=people,dc=example,dc=org")
+        );
+    }
+
+    #[test]
+    fn maps_groups_to_roles() {
+        let config = config();
+        let groups = vec![
+            "CN=Admins,OU=Groups,DC=example,DC=org".to_string(),
+            "cn=operators,ou=groups,dc=example,dc=org".to_string(),
+            "cn=staff,ou=groups,dc=example,dc=org".to_string(),
+        ];
+        assert_eq!(config.roles_for_groups(&groups), vec!["admin", "operator"]);
+        assert!(config.roles_for_groups(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_empty_passwords_without_contacting_the_directory() {
+        let config = LdapConfig {
+            url: "ldap://127.0.0.1:1".to_string(),
+            ..config()
+        };
+        assert!(matches!(
+            authenticate(&config, "jane", "").await,
+            Err(LdapAuthError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            authenticate(&LdapConfig::default(), "jane", "secret").await,
+            Err(LdapAuthError::Disabled)
+        ));
+    }
+
+    /*---------------------------------------------------------------
+    Runs against a disposable OpenLDAP server, e.g.
+
+        docker run --rm -p 389:389 -e LDAP_ORGANISATION=Example \
+            -e LDAP_DOMAIN=example.org -e LDAP_ADMIN_PASSWORD=admin \
+            osixia/openldap:1.5.0
+
+    seeded with `uid=jane,ou=people,dc=example,dc=org` (password
+    `jane-password`, mail `jane@example.org`) as a `member` of
+    `cn=admins,ou=groups,dc=example,dc=org`, then
+
+        ECS_TEST_LDAP_URL=ldap://localhost:389 cargo test -- --ignored
+    ----------------------------------------------------------------*/
+    #[tokio::test]
+    #[ignore = "needs a local OpenLDAP server"]
+    async fn authenticates_against_openldap() {
+        let url = std::env::var("ECS_TEST_LDAP_URL").expect("ECS_TEST_LDAP_URL must be set");
+        let search_then_bind = LdapConfig {
+            url,
+            bind_dn: "cn=admin,dc=example,dc=org".to_string(),
+            bind_password: "admin".to_string(),
+            user_base_dn: "ou=people,dc=example,dc=org".to_string(),
+            group_base_dn: "ou=groups,dc=example,dc=org".to_string(),
+            ..config()
+        };
+        let bind_as_user = LdapConfig {
+            user_dn_template: "uid={username},ou=people,dc=example,dc=org".to_string(),
+            ..search_then_bind.clone()
+        };
+
+        for config in [search_then_bind, bind_as_user] {
+            let identity = authenticate(&config, "jane", "jane-password")
+                .await
+                .unwrap();
+            assert_eq!(identity.email, "jane@example.org");
+            assert_eq!(identity.roles, vec!["admin"]);
+
+            assert!(matches!(
+                authenticate(&config, "jane", "wrong-password").await,
+                Err(LdapAuthError::InvalidCredentials)
+            ));
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/*---------------------------------------------------------------
+Limits on password guessing, applied to every password login.
+
+Each account gets an exponential backoff after a failed attempt
+(`base_delay`, doubled per failure up to `max_delay` seconds) and
+is locked for `lockout_duration` seconds once `max_attempts`
+consecutive attempts have failed. Counters are forgotten after
+`reset_after` seconds without a failure.
+
+Independently, a client address may fail `ip_max_attempts` times
+within `ip_window` seconds before it is blocked for the rest of
+that window, which stops one client guessing across many accounts.
+
+Read from the `[default.auth.lockout]` table of the server's
+Rocket configuration.
+----------------------------------------------------------------*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LockoutConfig {
+    pub max_attempts: i64,
+    pub base_delay: i64,
+    pub max_delay: i64,
+    pub lockout_duration: i64,
+    pub reset_after: i64,
+    pub ip_max_attempts: i64,
+    pub ip_window: i64,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: 1,
+            max_delay: 60,
+            lockout_duration: 900,
+            reset_after: 3600,
+            ip_max_attempts: 50,
+            ip_window: 300,
+        }
+    }
+}
+
+impl LockoutConfig {
+    /// How long an account must wait after its `failures`-th consecutive
+    /// failure before the next attempt is considered.
+    pub fn account_delay(&self, failures: i64) -> Duration {
+        if failures >= self.max_attempts {
+            return Duration::seconds(self.lockout_duration);
+        }
+        let exponent = failures.saturating_sub(1).clamp(0, 30) as u32;
+        let delay = self.base_delay.saturating_mul(2_i64.pow(exponent));
+        Duration::seconds(delay.min(self.max_delay))
+    }
+
+    /// Whether the `failures`-th consecutive failure locks the account.
+    pub fn locks_account(&self, failures: i64) -> bool {
+        failures == self.max_attempts
+    }
+
+    /// How long a client address is blocked after its `failures`-th failure
+    /// within the current window.
+    pub fn ip_delay(&self, failures: i64) -> Duration {
+        if failures >= self.ip_max_attempts {
+            Duration::seconds(self.ip_window)
+        } else {
+            Duration::zero()
+        }
+    }
+}
+
+/// A limit tripped by a failed login. Returned to the caller, which
+/// writes it to the audit log as a denied `auth.lockout` or
+/// `auth.throttle` event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lockout {
+    /// The account is locked for `seconds` after `failures` failed logins.
+    Account {
+        email: String,
+        failures: i64,
+        seconds: i64,
+    },
+    /// The client address is blocked for `seconds` after `failures` failed
+    /// logins within the window.
+    Address {
+        address: IpAddr,
+        failures: i64,
+        seconds: i64,
+    },
+}
+
+impl Lockout {
+    pub fn action(&self) -> &'static str {
+        match self {
+            Lockout::Account { .. } => "auth.lockout",
+            Lockout::Address { .. } => "auth.throttle",
+        }
+    }
+
+    /// The locked account or the blocked address.
+    pub fn target(&self) -> String {
+        match self {
+            Lockout::Account { email, .. } => email.clone(),
+            Lockout::Address { address, .. } => address.to_string(),
+        }
+    }
+
+    pub fn detail(&self) -> String {
+        let (Lockout::Account {
+            failures, seconds, ..
+        }
+        | Lockout::Address {
+            failures, seconds, ..
+        }) = self;
+        format!(
+            "blocked for {} seconds after {} failed logins",
+            seconds, failures
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accounts_back_off_then_lock() {
+        let config = LockoutConfig::default();
+        let delays: Vec<i64> = (1..=5)
+            .map(|failures| config.account_delay(failures).num_seconds())
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 900]);
+        assert!(config.locks_account(5));
+        assert!(!config.locks_account(6));
+
+        let capped = LockoutConfig {
+            max_attempts: 100,
+            ..config
+        };
+        assert_eq!(capped.account_delay(99).num_seconds(), 60);
+    }
+
+    #[test]
+    fn addresses_are_blocked_for_the_window() {
+        let config = LockoutConfig::default();
+        assert_eq!(config.ip_delay(49), Duration::zero());
+        assert_eq!(config.ip_delay(50), Duration::seconds(300));
+    }
+
+    #[test]
+    fn lockouts_describe_their_audit_events() {
+        let account = Lockout::Account {
+            email: "alice@example.com".to_string(),
+            failures: 5,
+            seconds: 900,
+        };
+        assert_eq!(account.action(), "auth.lockout");
+        assert_eq!(account.target(), "alice@example.com");
+        assert_eq!(
+            account.detail(),
+            "blocked for 900 seconds after 5 failed logins"
+        );
+
+        let address = Lockout::Address {
+            address: "192.0.2.7".parse().unwrap(),
+            failures: 50,
+            seconds: 300,
+        };
+        assert_eq!(address.action(), "auth.throttle");
+        assert_eq!(address.target(), "192.0.2.7");
+    }
+}
@@ -0,0 +1,313 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::{Mailbox, header::ContentType},
+    transport::smtp::authentication::Credentials,
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, sync::Arc};
+use thiserror::Error;
+
+use crate::utils::auth::generate_identifier;
+
+/*---------------------------------------------------------------
+Outgoing mail for email verification and password resets. The
+`log` transport writes messages to the server log and the `file`
+transport drops them into `directory`, both meant for local
+testing only since the mails carry live tokens. `smtp` delivers
+through a relay; its password may be supplied through the
+`ECS_SMTP_PASSWORD` environment variable.
+
+Links in the mails point at `public_url`.
+
+Read from the `[default.auth.mail]` table of the server's Rocket
+configuration.
+----------------------------------------------------------------*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MailConfig {
+    pub transport: MailTransportKind,
+    pub from: String,
+    pub public_url: String,
+    pub directory: PathBuf,
+    pub smtp: SmtpConfig,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            transport: MailTransportKind::Log,
+            from: "Locksmith <no-reply@localhost>".to_string(),
+            public_url: "http://localhost:8000".to_string(),
+            directory: PathBuf::from("mail"),
+            smtp: SmtpConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransportKind {
+    Log,
+    File,
+    Smtp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Seconds to wait for the relay.
+    pub timeout: u64,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: 587,
+            security: SmtpSecurity::Starttls,
+            username: None,
+            password: None,
+            timeout: 10,
+        }
+    }
+}
+
+impl SmtpConfig {
+    fn password(&self) -> Option<String> {
+        std::env::var("ECS_SMTP_PASSWORD")
+            .ok()
+            .or_else(|| self.password.clone())
+    }
+}
+
+/// `tls` connects over TLS from the start (usually port 465), `starttls`
+/// upgrades a plain connection and `none` is only fit for a local relay.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    Tls,
+    Starttls,
+    None,
+}
+
+#[derive(Error, Debug)]
+pub enum MailError {
+    #[error("invalid address: {0}")]
+    Address(#[from] lettre::address::AddressError),
+    #[error("failed to build message: {0}")]
+    Message(#[from] lettre::error::Error),
+    #[error("SMTP delivery failed: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("failed to write message: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers mail; implement it to plug in another provider.
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    async fn send(&self, message: &MailMessage) -> Result<(), MailError>;
+}
+
+/// Builds the transport selected by the configuration.
+pub fn transport(config: &MailConfig) -> Result<Arc<dyn MailTransport>, MailError> {
+    Ok(match config.transport {
+        MailTransportKind::Log => Arc::new(LogTransport),
+        MailTransportKind::File => Arc::new(FileTransport {
+            directory: config.directory.clone(),
+        }),
+        MailTransportKind::Smtp => Arc::new(SmtpTransport::new(config)?),
+    })
+}
+
+pub struct LogTransport;
+
+#[async_trait]
+impl MailTransport for LogTransport {
+    async fn send(&self, message: &MailMessage) -> Result<(), MailError> {
+        info!(
+            target: "mail",
+            "To: {}\nSubject: {}\n\n{}",
+            message.to,
+            message.subject,
+            message.body
+        );
+        Ok(())
+    }
+}
+
+pub struct FileTransport {
+    pub directory: PathBuf,
+}
+
+#[async_trait]
+impl MailTransport for FileTransport {
+    async fn send(&self, message: &MailMessage) -> Result<(), MailError> {
+        std::fs::create_dir_all(&self.directory)?;
+        let name = format!(
+            "{}-{}.txt",
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            &generate_identifier()[..8]
+        );
+        let contents = format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            message.to, message.subject, message.body
+        );
+        std::fs::write(self.directory.join(name), contents)?;
+        Ok(())
+    }
+}
+
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpTransport {
+    pub fn new(config: &MailConfig) -> Result<Self, MailError> {
+        let smtp = &config.smtp;
+        let mut builder = match smtp.security {
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)?,
+            SmtpSecurity::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)?
+            }
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host)
+            }
+        }
+        .port(smtp.port)
+        .timeout(Some(std::time::Duration::from_secs(smtp.timeout)));
+
+        if let (Some(username), Some(password)) = (&smtp.username, smtp.password()) {
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+
+        Ok(Self {
+            mailer: builder.build(),
+            from: config.from.parse()?,
+        })
+    }
+}
+
+#[async_trait]
+impl MailTransport for SmtpTransport {
+    async fn send(&self, message: &MailMessage) -> Result<(), MailError> {
+        let email = Message::builder()
+            .from(self.from.clone())
+            .to(message.to.parse()?)
+            .subject(&message.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(message.body.clone())?;
+        self.mailer.send(email).await?;
+        Ok(())
+    }
+}
+
+/*---------------------------------------------------------------
+Messages
+----------------------------------------------------------------*/
+fn validity(lifetime: chrono::Duration) -> String {
+    match lifetime.num_minutes().max(1) {
+        60 => "1 hour".to_string(),
+        minutes if minutes % 60 == 0 => format!("{} hours", minutes / 60),
+        minutes => format!("{} minutes", minutes),
+    }
+}
+
+pub fn verification_message(
+    config: &MailConfig,
+    email: &str,
+    token: &str,
+    lifetime: chrono::Duration,
+) -> MailMessage {
+    MailMessage {
+        to: email.to_string(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Confirm that {} belongs to you by opening this link within {}:\n\n{}/email/verify?token={}\n\nIf you did not create a Locksmith account, ignore this message.",
+            email,
+            validity(lifetime),
+            config.public_url.trim_end_matches('/'),
+            token
+        ),
+    }
+}
+
+pub fn password_reset_message(
+    config: &MailConfig,
+    email: &str,
+    token: &str,
+    lifetime: chrono::Duration,
+) -> MailMessage {
+    MailMessage {
+        to: email.to_string(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "A password reset was requested for {}. Choose a new password within {}:\n\n{}/password/reset?token={}\n\nResetting the password signs you out everywhere. If you did not ask for this, ignore this message; your password stays the same.",
+            email,
+            validity(lifetime),
+            config.public_url.trim_end_matches('/'),
+            token
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn file_transport_writes_messages() {
+        let directory = std::env::temp_dir().join(format!("ec_mail_{}", generate_identifier()));
+        let config = MailConfig {
+            transport: MailTransportKind::File,
+            public_url: "https://locksmith.example.com/".to_string(),
+            directory: directory.clone(),
+            ..MailConfig::default()
+        };
+        let message = password_reset_message(
+            &config,
+            "jane@example.com",
+            "abc123",
+            chrono::Duration::minutes(30),
+        );
+        assert!(
+            message
+                .body
+                .contains("https://locksmith.example.com/password/reset?token=abc123")
+        );
+
+        transport(&config).unwrap().send(&message).await.unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&directory).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let contents = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert!(contents.starts_with("To: jane@example.com\nSubject: Reset your password\n"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn smtp_transport_rejects_a_bad_sender() {
+        let config = MailConfig {
+            transport: MailTransportKind::Smtp,
+            from: "not an address".to_string(),
+            ..MailConfig::default()
+        };
+        assert!(matches!(transport(&config), Err(MailError::Address(_))));
+    }
+}
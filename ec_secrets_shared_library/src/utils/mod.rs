@@ -1,2 +1,24 @@
+pub mod approval;
+pub mod audit;
+pub mod audit_sinks;
 pub mod auth;
+pub mod break_glass;
+pub mod import;
+pub mod journal;
+pub mod kms;
+pub mod ldap;
+pub mod lockout;
+pub mod mail;
+pub mod mtls;
+pub mod oidc;
+pub mod password_hash;
+pub mod password_policy;
+pub mod pkcs11;
+pub mod policy;
+pub mod seal;
+pub mod secret;
+pub mod shamir;
+pub mod snapshot;
+pub mod totp;
 pub mod vault;
+pub mod webauthn;
@@ -0,0 +1,163 @@
+use chrono::{DateTime, Utc};
+use pasetors::claims::Claims;
+use serde::{Deserialize, Serialize};
+
+/*---------------------------------------------------------------
+Services that authenticate with a TLS client certificate instead
+of a bearer token. The certificate chain itself is verified by the
+server against the CA bundle in `[default.tls.mutual]`; here a
+verified certificate is mapped to a service identity.
+
+An identity matches when every selector it sets matches: the
+subject common name and/or one of the subject alternative names
+(DNS name, URI such as a SPIFFE id, or email). Like AppRole logins,
+service identities are restricted to their `policies`.
+
+Read from the `[default.auth.mtls]` table of the server's Rocket
+configuration.
+----------------------------------------------------------------*/
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MtlsConfig {
+    pub enabled: bool,
+    pub identities: Vec<CertificateIdentity>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct CertificateIdentity {
+    /// The subject the service acts as, e.g. `svc:billing`.
+    pub name: String,
+    pub common_name: Option<String>,
+    pub subject_alt_name: Option<String>,
+    pub policies: Vec<String>,
+}
+
+impl CertificateIdentity {
+    fn matches(&self, common_names: &[&str], alt_names: &[&str]) -> bool {
+        let selects = |selector: &Option<String>, names: &[&str]| {
+            selector
+                .as_ref()
+                .is_none_or(|selector| names.iter().any(|name| name.eq_ignore_ascii_case(selector)))
+        };
+        // An identity without selectors would match every certificate.
+        (self.common_name.is_some() || self.subject_alt_name.is_some())
+            && selects(&self.common_name, common_names)
+            && selects(&self.subject_alt_name, alt_names)
+    }
+}
+
+impl MtlsConfig {
+    /// The first identity matching a verified client certificate's names.
+    pub fn resolve(
+        &self,
+        common_names: &[&str],
+        alt_names: &[&str],
+    ) -> Option<&CertificateIdentity> {
+        if !self.enabled {
+            return None;
+        }
+        self.identities
+            .iter()
+            .find(|identity| identity.matches(common_names, alt_names))
+    }
+}
+
+/*---------------------------------------------------------------
+Claims standing in for a token when a service presents a client
+certificate. They are valid for the certificate's lifetime and are
+"issued" when the certificate was, so a subject-wide revocation of
+the service name cuts off certificates issued before it.
+----------------------------------------------------------------*/
+pub fn certificate_claims(
+    identity: &CertificateIdentity,
+    not_before: DateTime<Utc>,
+    not_after: DateTime<Utc>,
+) -> Result<Claims, String> {
+    let mut claims = Claims::new().map_err(|e| e.to_string())?;
+    claims.subject(&identity.name).map_err(|e| e.to_string())?;
+    claims
+        .issued_at(&not_before.to_rfc3339())
+        .map_err(|e| e.to_string())?;
+    claims
+        .expiration(&not_after.to_rfc3339())
+        .map_err(|e| e.to_string())?;
+    claims
+        .add_additional("policies", identity.policies.clone())
+        .map_err(|e| e.to_string())?;
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn config() -> MtlsConfig {
+        MtlsConfig {
+            enabled: true,
+            identities: vec![
+                CertificateIdentity {
+                    name: "svc:billing".to_string(),
+                    common_name: Some("billing".to_string()),
+                    subject_alt_name: Some("spiffe://example.org/billing".to_string()),
+                    policies: vec!["billing/*".to_string()],
+                },
+                CertificateIdentity {
+                    name: "svc:reports".to_string(),
+                    subject_alt_name: Some("reports.internal.example.org".to_string()),
+                    ..CertificateIdentity::default()
+                },
+                CertificateIdentity {
+                    name: "svc:anything".to_string(),
+                    ..CertificateIdentity::default()
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn resolves_identities_by_subject_and_alt_names() {
+        let config = config();
+        let name = |identity: Option<&CertificateIdentity>| identity.map(|i| i.name.clone());
+
+        assert_eq!(
+            name(config.resolve(&["billing"], &["spiffe://example.org/billing"])),
+            Some("svc:billing".to_string())
+        );
+        // Every selector must match.
+        assert_eq!(name(config.resolve(&["billing"], &[])), None);
+        assert_eq!(
+            name(config.resolve(&[], &["REPORTS.internal.example.org"])),
+            Some("svc:reports".to_string())
+        );
+        assert_eq!(name(config.resolve(&["unknown"], &["unknown"])), None);
+
+        let disabled = MtlsConfig {
+            enabled: false,
+            ..config
+        };
+        assert_eq!(
+            name(disabled.resolve(&[], &["reports.internal.example.org"])),
+            None
+        );
+    }
+
+    #[test]
+    fn claims_follow_the_certificate() {
+        let identity = &config().identities[0];
+        let not_before = Utc::now() - Duration::days(1);
+        let claims =
+            certificate_claims(identity, not_before, Utc::now() + Duration::days(30)).unwrap();
+
+        assert_eq!(claims.get_claim("sub").unwrap(), "svc:billing");
+        assert_eq!(
+            claims.get_claim("iat").unwrap().as_str().unwrap(),
+            not_before.to_rfc3339()
+        );
+        assert_eq!(
+            claims.get_claim("policies").unwrap(),
+            &serde_json::json!(["billing/*"])
+        );
+    }
+}
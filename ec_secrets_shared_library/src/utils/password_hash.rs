@@ -0,0 +1,114 @@
+use argon2::{Config, Variant, Version};
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use serde::{Deserialize, Serialize};
+
+/*---------------------------------------------------------------
+How passwords are hashed. New hashes are Argon2id PHC strings
+(`$argon2id$v=19$m=<KiB>,t=<passes>,p=<lanes>$<salt>$<hash>`) made
+with these parameters; the defaults follow the OWASP baseline.
+
+Legacy bcrypt hashes still verify. They, and Argon2 hashes made
+with other parameters, are replaced on the user's next successful
+login, so tuning the parameters migrates accounts without a reset.
+
+Read from the `[default.auth.password_hashing]` table of the
+server's Rocket configuration.
+----------------------------------------------------------------*/
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct PasswordHashing {
+    /// Memory per hash, in KiB.
+    pub memory_cost: u32,
+    pub time_cost: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PasswordHashing {
+    fn default() -> Self {
+        Self {
+            memory_cost: 19456,
+            time_cost: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl PasswordHashing {
+    fn config(&self) -> Config<'static> {
+        Config {
+            mem_cost: self.memory_cost,
+            time_cost: self.time_cost,
+            lanes: self.parallelism,
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            ..Config::default()
+        }
+    }
+
+    /// Hashes `password` into a PHC string with a fresh random salt.
+    pub fn hash(&self, password: &str) -> Result<String, String> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        argon2::hash_encoded(password.as_bytes(), &salt, &self.config()).map_err(|e| e.to_string())
+    }
+
+    /// Whether a stored hash should be replaced by one with these parameters.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let expected = format!(
+            "m={},t={},p={}",
+            self.memory_cost, self.time_cost, self.parallelism
+        );
+        let fields: Vec<&str> = hash.split('$').collect();
+        !matches!(
+            fields.as_slice(),
+            ["", "argon2id", "v=19", params, _, _] if *params == expected
+        )
+    }
+}
+
+/// Checks `password` against an Argon2 or legacy bcrypt hash; anything
+/// malformed never matches.
+pub fn verify(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        argon2::verify_encoded(hash, password.as_bytes()).unwrap_or(false)
+    } else {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small parameters keep the tests fast in debug builds.
+    fn hashing() -> PasswordHashing {
+        PasswordHashing {
+            memory_cost: 1024,
+            time_cost: 1,
+            parallelism: 1,
+        }
+    }
+
+    #[test]
+    fn hashes_with_argon2id() {
+        let hashing = hashing();
+        let hash = hashing.hash("correct horse").unwrap();
+
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(verify("correct horse", &hash));
+        assert!(!verify("wrong horse", &hash));
+        assert_ne!(hash, hashing.hash("correct horse").unwrap());
+        assert!(!hashing.needs_rehash(&hash));
+        assert!(PasswordHashing::default().needs_rehash(&hash));
+    }
+
+    #[test]
+    fn verifies_and_upgrades_legacy_bcrypt_hashes() {
+        let legacy = bcrypt::hash("correct horse", 4).unwrap();
+
+        assert!(verify("correct horse", &legacy));
+        assert!(!verify("wrong horse", &legacy));
+        assert!(hashing().needs_rehash(&legacy));
+        assert!(!verify("correct horse", "not a hash"));
+    }
+}
@@ -0,0 +1,518 @@
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::path::PathBuf;
+use thiserror::Error;
+
+use crate::utils::auth::password_matches;
+
+/*---------------------------------------------------------------
+Rules a new password must satisfy when an account is created or
+its password is changed: length, character classes, an estimated
+strength score from 0 (trivial) to 4 (very hard to guess) and no
+reuse of the last `history` passwords.
+
+`breached_passwords` points at a local copy of the Pwned Passwords
+range files (one `<PREFIX>.txt` file of `SUFFIX:COUNT` lines per
+five-character SHA-1 prefix, as written by the official downloader).
+Only the file for the password's prefix is ever read.
+
+Read from the `[default.auth.password_policy]` table of the server's
+Rocket configuration.
+----------------------------------------------------------------*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    pub min_strength: u8,
+    pub history: usize,
+    pub breached_passwords: Option<PathBuf>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 12,
+            max_length: 128,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: false,
+            min_strength: 3,
+            history: 5,
+            breached_passwords: None,
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PolicyViolation {
+    #[error("must be at least {0} characters long")]
+    TooShort(usize),
+    #[error("must be at most {0} characters long")]
+    TooLong(usize),
+    #[error("must contain a lowercase letter")]
+    MissingLowercase,
+    #[error("must contain an uppercase letter")]
+    MissingUppercase,
+    #[error("must contain a digit")]
+    MissingDigit,
+    #[error("must contain a symbol")]
+    MissingSymbol,
+    #[error("is too easy to guess (strength {score} of 4, {required} required)")]
+    TooWeak { score: u8, required: u8 },
+    #[error("was used recently")]
+    Reused,
+    #[error("appears in a known data breach")]
+    Breached,
+}
+
+impl PasswordPolicy {
+    /// Every rule `password` breaks, apart from reuse. `user_inputs` (such as
+    /// the email address) make a password weaker when it contains them.
+    pub fn check(
+        &self,
+        password: &str,
+        user_inputs: &[&str],
+    ) -> std::io::Result<Vec<PolicyViolation>> {
+        let mut violations = Vec::new();
+        let length = password.chars().count();
+        if length < self.min_length {
+            violations.push(PolicyViolation::TooShort(self.min_length));
+        }
+        if length > self.max_length {
+            violations.push(PolicyViolation::TooLong(self.max_length));
+        }
+
+        let classes = [
+            (
+                self.require_lowercase,
+                CharClass::Lower,
+                PolicyViolation::MissingLowercase,
+            ),
+            (
+                self.require_uppercase,
+                CharClass::Upper,
+                PolicyViolation::MissingUppercase,
+            ),
+            (
+                self.require_digit,
+                CharClass::Digit,
+                PolicyViolation::MissingDigit,
+            ),
+            (
+                self.require_symbol,
+                CharClass::Symbol,
+                PolicyViolation::MissingSymbol,
+            ),
+        ];
+        for (required, class, violation) in classes {
+            if required && !password.chars().any(|c| CharClass::of(c) == class) {
+                violations.push(violation);
+            }
+        }
+
+        let score = strength(password, user_inputs);
+        if score < self.min_strength {
+            violations.push(PolicyViolation::TooWeak {
+                score,
+                required: self.min_strength,
+            });
+        }
+
+        if self.is_breached(password)? {
+            violations.push(PolicyViolation::Breached);
+        }
+        Ok(violations)
+    }
+
+    /// Whether `password` matches the current hash or one of the previous
+    /// hashes (most recent first) still covered by `history`.
+    pub fn reuses(&self, password: &str, current: &str, previous: &[String]) -> bool {
+        std::iter::once(current)
+            .chain(previous.iter().map(String::as_str))
+            .take(self.history)
+            .any(|hash| password_matches(password, hash))
+    }
+
+    /// Looks the password's SHA-1 up in the local breach corpus, if any.
+    pub fn is_breached(&self, password: &str) -> std::io::Result<bool> {
+        let Some(directory) = &self.breached_passwords else {
+            return Ok(false);
+        };
+
+        let digest: String = Sha1::digest(password.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let (prefix, suffix) = digest.split_at(5);
+
+        let range = match std::fs::read_to_string(directory.join(format!("{}.txt", prefix))) {
+            Ok(range) => range,
+            // A partial corpus simply has nothing on this prefix.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+
+        Ok(range.lines().any(|line| {
+            let (hash, count) = line.trim().split_once(':').unwrap_or((line.trim(), "1"));
+            // Padded ranges list made-up suffixes with a count of zero.
+            hash.eq_ignore_ascii_case(suffix) && count.trim() != "0"
+        }))
+    }
+}
+
+/// A loose syntax check: one `@`, a non-empty local part and a dotted domain.
+pub fn is_valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && email.len() <= 254
+        && !domain.contains('@')
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        && domain.split('.').count() >= 2
+        && domain.split('.').all(|label| !label.is_empty())
+}
+
+/*---------------------------------------------------------------
+Strength estimation in the spirit of zxcvbn: the password is split
+greedily into guessable patterns (common passwords, the user's own
+details, repeats, sequences, keyboard runs and years) and the
+remaining characters, each priced in guesses. The total, in powers
+of ten, maps onto the same 0-4 scale zxcvbn uses.
+----------------------------------------------------------------*/
+const COMMON_PASSWORDS: &[&str] = &[
+    "password",
+    "passwort",
+    "qwerty",
+    "letmein",
+    "welcome",
+    "admin",
+    "administrator",
+    "login",
+    "monkey",
+    "dragon",
+    "master",
+    "sunshine",
+    "princess",
+    "football",
+    "baseball",
+    "soccer",
+    "hockey",
+    "iloveyou",
+    "trustno",
+    "shadow",
+    "superman",
+    "batman",
+    "michael",
+    "jennifer",
+    "jordan",
+    "secret",
+    "summer",
+    "winter",
+    "spring",
+    "autumn",
+    "hello",
+    "freedom",
+    "whatever",
+    "starwars",
+    "computer",
+    "changeme",
+    "default",
+    "access",
+    "killer",
+    "charlie",
+    "pepper",
+    "cheese",
+    "flower",
+    "orange",
+    "banana",
+    "purple",
+    "ginger",
+    "hunter",
+    "ranger",
+    "buster",
+    "thomas",
+    "robert",
+    "matrix",
+    "internet",
+    "service",
+    "abc",
+    "love",
+    "god",
+    "sex",
+    "money",
+    "secure",
+    "security",
+    "private",
+    "root",
+    "toor",
+    "guest",
+    "user",
+    "test",
+    "temp",
+    "vault",
+    "secrets",
+    "locksmith",
+    "company",
+    "office",
+    "january",
+    "february",
+    "march",
+    "april",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+    "monday",
+    "friday",
+    "weekend",
+];
+
+const KEYBOARD_ROWS: &[&str] = &[
+    "qwertyuiop",
+    "asdfghjkl",
+    "zxcvbnm",
+    "qwertzuiop",
+    "azertyuiop",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Lower,
+    Upper,
+    Digit,
+    Symbol,
+    Other,
+}
+
+impl CharClass {
+    fn of(c: char) -> Self {
+        match c {
+            'a'..='z' => Self::Lower,
+            'A'..='Z' => Self::Upper,
+            '0'..='9' => Self::Digit,
+            c if c.is_ascii_punctuation() || c == ' ' => Self::Symbol,
+            _ => Self::Other,
+        }
+    }
+
+    fn size(self) -> f64 {
+        match self {
+            Self::Lower | Self::Upper => 26.0,
+            Self::Digit => 10.0,
+            Self::Symbol => 33.0,
+            Self::Other => 100.0,
+        }
+    }
+}
+
+fn unleet(c: char) -> char {
+    match c {
+        '@' | '4' => 'a',
+        '3' => 'e',
+        '1' | '!' => 'i',
+        '0' => 'o',
+        '$' | '5' => 's',
+        '7' => 't',
+        c => c,
+    }
+}
+
+/// Estimated strength of `password` from 0 (trivial) to 4 (very hard to guess).
+pub fn strength(password: &str, user_inputs: &[&str]) -> u8 {
+    let lower: Vec<char> = password.to_lowercase().chars().collect();
+    let unleeted: Vec<char> = lower.iter().copied().map(unleet).collect();
+
+    let mut classes: Vec<CharClass> = lower.iter().map(|&c| CharClass::of(c)).collect();
+    if password.chars().any(char::is_uppercase) {
+        classes.push(CharClass::Upper);
+    }
+    classes.sort_by_key(|class| *class as u8);
+    classes.dedup();
+    let cardinality: f64 = classes.iter().map(|class| class.size()).sum();
+
+    let user_words: Vec<String> = user_inputs
+        .iter()
+        .flat_map(|input| input.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| word.chars().count() >= 3)
+        .map(str::to_lowercase)
+        .collect();
+
+    let mut guesses_log10 = 0.0;
+    let mut i = 0;
+    while i < lower.len() {
+        let candidates = [
+            dictionary_match(&unleeted[i..], &user_words),
+            repeat_match(&lower[i..], cardinality),
+            sequence_match(&lower[i..]),
+            keyboard_match(&lower[i..]),
+            year_match(&lower[i..]),
+        ];
+        match candidates
+            .into_iter()
+            .flatten()
+            .max_by_key(|(length, _)| *length)
+        {
+            Some((length, cost)) => {
+                guesses_log10 += cost;
+                i += length;
+            }
+            None => {
+                guesses_log10 += cardinality.log10();
+                i += 1;
+            }
+        }
+    }
+
+    match guesses_log10 {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    }
+}
+
+/// Each matcher returns the length of the pattern at the start of `chars`
+/// and its cost in log10 guesses.
+fn dictionary_match(chars: &[char], user_words: &[String]) -> Option<(usize, f64)> {
+    let starts_with = |word: &str| {
+        word.chars().count() <= chars.len() && word.chars().zip(chars).all(|(a, &b)| a == b)
+    };
+    let user = user_words
+        .iter()
+        .filter(|word| starts_with(word))
+        .map(|word| (word.chars().count(), 1.0));
+    let common = COMMON_PASSWORDS
+        .iter()
+        .filter(|word| starts_with(word))
+        .map(|word| (word.len(), (COMMON_PASSWORDS.len() as f64).log10() + 1.0));
+    user.chain(common).max_by_key(|(length, _)| *length)
+}
+
+fn repeat_match(chars: &[char], cardinality: f64) -> Option<(usize, f64)> {
+    let length = chars.iter().take_while(|&&c| c == chars[0]).count();
+    (length >= 3).then(|| (length, (cardinality * length as f64).log10()))
+}
+
+fn sequence_match(chars: &[char]) -> Option<(usize, f64)> {
+    let step = |a: char, b: char| b as i64 - a as i64;
+    let direction = step(*chars.first()?, *chars.get(1)?);
+    if direction.abs() != 1 || !chars[0].is_ascii_alphanumeric() {
+        return None;
+    }
+    let length = 1 + chars
+        .windows(2)
+        .take_while(|pair| step(pair[0], pair[1]) == direction && pair[1].is_ascii_alphanumeric())
+        .count();
+    (length >= 3).then(|| (length, (2.0 * 36.0 * length as f64).log10()))
+}
+
+fn keyboard_match(chars: &[char]) -> Option<(usize, f64)> {
+    KEYBOARD_ROWS
+        .iter()
+        .filter_map(|row| {
+            let row: Vec<char> = row.chars().collect();
+            let start = row.iter().position(|&c| c == chars[0])?;
+            let length = row[start..]
+                .iter()
+                .zip(chars)
+                .take_while(|(a, b)| a == b)
+                .count();
+            (length >= 4).then(|| (length, (2.0 * 40.0 * length as f64).log10()))
+        })
+        .max_by_key(|(length, _)| *length)
+}
+
+fn year_match(chars: &[char]) -> Option<(usize, f64)> {
+    let year: String = chars.iter().take(4).collect();
+    let is_year = year.len() == 4
+        && year.chars().all(|c| c.is_ascii_digit())
+        && (year.starts_with("19") || year.starts_with("20"));
+    is_year.then_some((4, 200f64.log10()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_guessable_passwords_low() {
+        assert_eq!(strength("password", &[]), 0);
+        assert_eq!(strength("P@ssw0rd", &[]), 0);
+        assert_eq!(strength("qwertyuiop", &[]), 0);
+        assert!(strength("Password123!", &[]) < 3);
+        assert!(strength("JaneDoe1234!", &["jane.doe@example.com"]) < 3);
+        assert_eq!(strength("correct-horse-battery-staple", &[]), 4);
+        assert_eq!(strength("vT8#qLz2mW!r", &[]), 4);
+    }
+
+    #[test]
+    fn reports_every_violation() {
+        let policy = PasswordPolicy::default();
+        let violations = policy.check("password", &[]).unwrap();
+        assert_eq!(
+            violations,
+            vec![
+                PolicyViolation::TooShort(12),
+                PolicyViolation::MissingUppercase,
+                PolicyViolation::MissingDigit,
+                PolicyViolation::TooWeak {
+                    score: 0,
+                    required: 3
+                },
+            ]
+        );
+        assert!(policy.check("Vault-Orbit-7-Maple", &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn finds_breached_passwords_in_range_files() {
+        let directory = std::env::temp_dir().join(format!(
+            "ec_pwned_{}",
+            crate::utils::auth::generate_identifier()
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        // SHA-1("password") = 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+        std::fs::write(
+            directory.join("5BAA6.txt"),
+            "003D68EB55068C33ACE09247EE4C639306B:0\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:10434004\r\n",
+        )
+        .unwrap();
+
+        let policy = PasswordPolicy {
+            breached_passwords: Some(directory.clone()),
+            ..PasswordPolicy::default()
+        };
+        assert!(policy.is_breached("password").unwrap());
+        assert!(!policy.is_breached("Vault-Orbit-7-Maple").unwrap());
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn validates_email_syntax() {
+        assert!(is_valid_email("jane.doe+vault@example.com"));
+        for email in [
+            "",
+            "jane",
+            "@example.com",
+            "jane@",
+            "jane@localhost",
+            "jane doe@example.com",
+            "a@b@c.com",
+            "jane@example..com",
+        ] {
+            assert!(!is_valid_email(email), "{email}");
+        }
+    }
+}
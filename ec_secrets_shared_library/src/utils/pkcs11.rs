@@ -0,0 +1,524 @@
+use async_trait::async_trait;
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use libloading::Library;
+use std::{ffi::c_void, os::raw::c_ulong, ptr, sync::Arc};
+use zeroize::Zeroizing;
+
+use crate::utils::kms::{KeyManager, KmsError, Pkcs11Config};
+
+/*---------------------------------------------------------------
+Wrapping with an AES key held by a PKCS#11 token. The key never
+leaves the HSM: the master key is sent in to be encrypted with
+AES-GCM and comes back the same way on boot.
+
+Only the handful of Cryptoki calls this needs are bound, straight
+from the vendor's module. The layouts follow the PKCS#11 v2.40
+headers as laid out on Unix platforms.
+----------------------------------------------------------------*/
+type Ulong = c_ulong;
+
+const CKR_OK: Ulong = 0x000;
+const CKR_USER_ALREADY_LOGGED_IN: Ulong = 0x100;
+const CKR_BUFFER_TOO_SMALL: Ulong = 0x150;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: Ulong = 0x191;
+const CKF_OS_LOCKING_OK: Ulong = 0x2;
+const CKF_SERIAL_SESSION: Ulong = 0x4;
+const CKU_USER: Ulong = 1;
+const CKA_CLASS: Ulong = 0x000;
+const CKA_LABEL: Ulong = 0x003;
+const CKO_SECRET_KEY: Ulong = 0x004;
+const CKM_AES_GCM: Ulong = 0x1087;
+
+const IV_LENGTH: usize = 12;
+const TAG_BITS: Ulong = 128;
+const AAD: &[u8] = b"locksmith-master-key";
+
+#[repr(C)]
+struct Version {
+    major: u8,
+    minor: u8,
+}
+
+#[repr(C)]
+struct InitializeArgs {
+    create_mutex: *mut c_void,
+    destroy_mutex: *mut c_void,
+    lock_mutex: *mut c_void,
+    unlock_mutex: *mut c_void,
+    flags: Ulong,
+    reserved: *mut c_void,
+}
+
+#[repr(C)]
+struct TokenInfo {
+    label: [u8; 32],
+    manufacturer_id: [u8; 32],
+    model: [u8; 16],
+    serial_number: [u8; 16],
+    flags: Ulong,
+    /// Session counts, PIN lengths and memory sizes.
+    counters: [Ulong; 10],
+    hardware_version: Version,
+    firmware_version: Version,
+    utc_time: [u8; 16],
+}
+
+#[repr(C)]
+struct Attribute {
+    kind: Ulong,
+    value: *const c_void,
+    value_len: Ulong,
+}
+
+#[repr(C)]
+struct Mechanism {
+    mechanism: Ulong,
+    parameter: *const c_void,
+    parameter_len: Ulong,
+}
+
+#[repr(C)]
+struct GcmParams {
+    iv: *const u8,
+    iv_len: Ulong,
+    iv_bits: Ulong,
+    aad: *const u8,
+    aad_len: Ulong,
+    tag_bits: Ulong,
+}
+
+type GetFunctionList = unsafe extern "C" fn(*mut *const FunctionList) -> Ulong;
+type Unused = Option<unsafe extern "C" fn()>;
+type CryptInit = unsafe extern "C" fn(Ulong, *const Mechanism, Ulong) -> Ulong;
+type Crypt = unsafe extern "C" fn(Ulong, *const u8, Ulong, *mut u8, *mut Ulong) -> Ulong;
+
+/// The start of `CK_FUNCTION_LIST`, up to `C_Decrypt`; later entries are
+/// never read.
+#[repr(C)]
+struct FunctionList {
+    version: Version,
+    initialize: Option<unsafe extern "C" fn(*const InitializeArgs) -> Ulong>,
+    finalize: Unused,
+    get_info: Unused,
+    get_function_list: Unused,
+    get_slot_list: Option<unsafe extern "C" fn(u8, *mut Ulong, *mut Ulong) -> Ulong>,
+    get_slot_info: Unused,
+    get_token_info: Option<unsafe extern "C" fn(Ulong, *mut TokenInfo) -> Ulong>,
+    get_mechanism_list: Unused,
+    get_mechanism_info: Unused,
+    init_token: Unused,
+    init_pin: Unused,
+    set_pin: Unused,
+    open_session:
+        Option<unsafe extern "C" fn(Ulong, Ulong, *mut c_void, *mut c_void, *mut Ulong) -> Ulong>,
+    close_session: Option<unsafe extern "C" fn(Ulong) -> Ulong>,
+    close_all_sessions: Unused,
+    get_session_info: Unused,
+    get_operation_state: Unused,
+    set_operation_state: Unused,
+    login: Option<unsafe extern "C" fn(Ulong, Ulong, *const u8, Ulong) -> Ulong>,
+    logout: Unused,
+    create_object: Unused,
+    copy_object: Unused,
+    destroy_object: Unused,
+    get_object_size: Unused,
+    get_attribute_value: Unused,
+    set_attribute_value: Unused,
+    find_objects_init: Option<unsafe extern "C" fn(Ulong, *const Attribute, Ulong) -> Ulong>,
+    find_objects: Option<unsafe extern "C" fn(Ulong, *mut Ulong, Ulong, *mut Ulong) -> Ulong>,
+    find_objects_final: Option<unsafe extern "C" fn(Ulong) -> Ulong>,
+    encrypt_init: Option<CryptInit>,
+    encrypt: Option<Crypt>,
+    encrypt_update: Unused,
+    encrypt_final: Unused,
+    decrypt_init: Option<CryptInit>,
+    decrypt: Option<Crypt>,
+}
+
+fn check(rv: Ulong, call: &str) -> Result<(), KmsError> {
+    if rv == CKR_OK {
+        Ok(())
+    } else {
+        Err(KmsError::Pkcs11(format!("{call} failed with CKR 0x{rv:x}")))
+    }
+}
+
+fn function<T>(function: Option<T>, name: &str) -> Result<T, KmsError> {
+    function.ok_or_else(|| KmsError::Pkcs11(format!("the module does not provide {name}")))
+}
+
+/// A loaded and initialized module.
+struct Module {
+    functions: *const FunctionList,
+    // Keeps `functions` valid; never unloaded.
+    _library: Library,
+}
+
+// The module is initialized with OS locking, which makes Cryptoki calls
+// safe from any thread.
+unsafe impl Send for Module {}
+unsafe impl Sync for Module {}
+
+impl Module {
+    fn load(config: &Pkcs11Config) -> Result<Self, KmsError> {
+        let load_error = |error: libloading::Error| {
+            KmsError::Config(format!("cannot load {}: {error}", config.library.display()))
+        };
+        // SAFETY: loading runs the module's initializers; the library is
+        // the operator's configured PKCS#11 module.
+        let library = unsafe { Library::new(&config.library) }.map_err(load_error)?;
+        let mut functions: *const FunctionList = ptr::null();
+        // SAFETY: `C_GetFunctionList` has this signature in every module.
+        unsafe {
+            let get_function_list = library
+                .get::<GetFunctionList>(b"C_GetFunctionList\0")
+                .map_err(load_error)?;
+            check(get_function_list(&mut functions), "C_GetFunctionList")?;
+        }
+        if functions.is_null() {
+            return Err(KmsError::Pkcs11(
+                "C_GetFunctionList returned no functions".to_string(),
+            ));
+        }
+
+        let module = Self {
+            functions,
+            _library: library,
+        };
+        let args = InitializeArgs {
+            create_mutex: ptr::null_mut(),
+            destroy_mutex: ptr::null_mut(),
+            lock_mutex: ptr::null_mut(),
+            unlock_mutex: ptr::null_mut(),
+            flags: CKF_OS_LOCKING_OK,
+            reserved: ptr::null_mut(),
+        };
+        let initialize = function(module.functions().initialize, "C_Initialize")?;
+        // SAFETY: `args` outlives the call.
+        match unsafe { initialize(&args) } {
+            CKR_CRYPTOKI_ALREADY_INITIALIZED => {}
+            rv => check(rv, "C_Initialize")?,
+        }
+        Ok(module)
+    }
+
+    fn functions(&self) -> &FunctionList {
+        // SAFETY: checked non-null on load, and valid while the library is.
+        unsafe { &*self.functions }
+    }
+
+    fn find_slot(&self, token_label: &str) -> Result<Ulong, KmsError> {
+        let get_slot_list = function(self.functions().get_slot_list, "C_GetSlotList")?;
+        let get_token_info = function(self.functions().get_token_info, "C_GetTokenInfo")?;
+
+        let mut count: Ulong = 0;
+        // SAFETY: a null list asks for the count only.
+        check(
+            unsafe { get_slot_list(1, ptr::null_mut(), &mut count) },
+            "C_GetSlotList",
+        )?;
+        let mut slots = vec![0; count as usize];
+        // SAFETY: `slots` has room for `count` entries.
+        check(
+            unsafe { get_slot_list(1, slots.as_mut_ptr(), &mut count) },
+            "C_GetSlotList",
+        )?;
+        slots.truncate(count as usize);
+
+        for slot in slots {
+            // SAFETY: TokenInfo is plain data, valid when zeroed.
+            let mut info: TokenInfo = unsafe { std::mem::zeroed() };
+            // SAFETY: `info` is a writable CK_TOKEN_INFO.
+            if unsafe { get_token_info(slot, &mut info) } != CKR_OK {
+                continue;
+            }
+            // Labels are padded with blanks.
+            if String::from_utf8_lossy(&info.label).trim_end() == token_label {
+                return Ok(slot);
+            }
+        }
+        Err(KmsError::Config(format!("no token labelled {token_label}")))
+    }
+}
+
+/// An open, logged-in session, closed on drop.
+struct Session<'a> {
+    module: &'a Module,
+    handle: Ulong,
+}
+
+impl<'a> Session<'a> {
+    fn open(module: &'a Module, slot: Ulong, pin: &str) -> Result<Self, KmsError> {
+        let open_session = function(module.functions().open_session, "C_OpenSession")?;
+        let login = function(module.functions().login, "C_Login")?;
+
+        let mut handle: Ulong = 0;
+        // SAFETY: no notification callback; `handle` is writable.
+        check(
+            unsafe {
+                open_session(
+                    slot,
+                    CKF_SERIAL_SESSION,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    &mut handle,
+                )
+            },
+            "C_OpenSession",
+        )?;
+        let session = Self { module, handle };
+
+        // SAFETY: the PIN buffer outlives the call.
+        match unsafe { login(handle, CKU_USER, pin.as_ptr(), pin.len() as Ulong) } {
+            CKR_USER_ALREADY_LOGGED_IN => {}
+            rv => check(rv, "C_Login")?,
+        }
+        Ok(session)
+    }
+
+    fn find_key(&self, key_label: &str) -> Result<Ulong, KmsError> {
+        let functions = self.module.functions();
+        let find_objects_init = function(functions.find_objects_init, "C_FindObjectsInit")?;
+        let find_objects = function(functions.find_objects, "C_FindObjects")?;
+        let find_objects_final = function(functions.find_objects_final, "C_FindObjectsFinal")?;
+
+        let class = CKO_SECRET_KEY;
+        let template = [
+            Attribute {
+                kind: CKA_CLASS,
+                value: (&class as *const Ulong).cast(),
+                value_len: size_of::<Ulong>() as Ulong,
+            },
+            Attribute {
+                kind: CKA_LABEL,
+                value: key_label.as_ptr().cast(),
+                value_len: key_label.len() as Ulong,
+            },
+        ];
+        let mut key: Ulong = 0;
+        let mut found: Ulong = 0;
+        // SAFETY: the template and outputs outlive the calls.
+        unsafe {
+            check(
+                find_objects_init(self.handle, template.as_ptr(), template.len() as Ulong),
+                "C_FindObjectsInit",
+            )?;
+            let rv = find_objects(self.handle, &mut key, 1, &mut found);
+            find_objects_final(self.handle);
+            check(rv, "C_FindObjects")?;
+        }
+        if found == 0 {
+            return Err(KmsError::Config(format!(
+                "no secret key labelled {key_label}"
+            )));
+        }
+        Ok(key)
+    }
+
+    /// Runs a single-part `C_Encrypt` or `C_Decrypt` with AES-GCM.
+    fn crypt(
+        &self,
+        key: Ulong,
+        iv: &[u8],
+        input: &[u8],
+        init: (Option<CryptInit>, &str),
+        run: (Option<Crypt>, &str),
+    ) -> Result<Zeroizing<Vec<u8>>, KmsError> {
+        let init_call = function(init.0, init.1)?;
+        let call = function(run.0, run.1)?;
+        let params = GcmParams {
+            iv: iv.as_ptr(),
+            iv_len: iv.len() as Ulong,
+            iv_bits: (iv.len() * 8) as Ulong,
+            aad: AAD.as_ptr(),
+            aad_len: AAD.len() as Ulong,
+            tag_bits: TAG_BITS,
+        };
+        let mechanism = Mechanism {
+            mechanism: CKM_AES_GCM,
+            parameter: (&params as *const GcmParams).cast(),
+            parameter_len: size_of::<GcmParams>() as Ulong,
+        };
+
+        // SAFETY: every pointer handed over outlives the calls, and the
+        // output buffer is as large as the module asked for.
+        unsafe {
+            check(init_call(self.handle, &mechanism, key), init.1)?;
+            let mut length: Ulong = 0;
+            let rv = call(
+                self.handle,
+                input.as_ptr(),
+                input.len() as Ulong,
+                ptr::null_mut(),
+                &mut length,
+            );
+            check(rv, run.1)?;
+            let mut output = Zeroizing::new(vec![0u8; length as usize]);
+            let rv = call(
+                self.handle,
+                input.as_ptr(),
+                input.len() as Ulong,
+                output.as_mut_ptr(),
+                &mut length,
+            );
+            if rv == CKR_BUFFER_TOO_SMALL {
+                return Err(KmsError::Pkcs11(format!("{} output did not fit", run.1)));
+            }
+            check(rv, run.1)?;
+            output.truncate(length as usize);
+            Ok(output)
+        }
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        if let Some(close_session) = self.module.functions().close_session {
+            // SAFETY: the handle came from C_OpenSession.
+            unsafe {
+                close_session(self.handle);
+            }
+        }
+    }
+}
+
+pub struct Pkcs11KeyManager {
+    inner: Arc<Pkcs11Inner>,
+}
+
+struct Pkcs11Inner {
+    module: Module,
+    slot: Ulong,
+    key_label: String,
+    pin: Zeroizing<String>,
+}
+
+impl Pkcs11Inner {
+    fn wrap(&self, key: &[u8]) -> Result<Vec<u8>, KmsError> {
+        let session = Session::open(&self.module, self.slot, &self.pin)?;
+        let wrapping_key = session.find_key(&self.key_label)?;
+        let mut iv = [0u8; IV_LENGTH];
+        OsRng.fill_bytes(&mut iv);
+        let functions = self.module.functions();
+        let ciphertext = session.crypt(
+            wrapping_key,
+            &iv,
+            key,
+            (functions.encrypt_init, "C_EncryptInit"),
+            (functions.encrypt, "C_Encrypt"),
+        )?;
+        Ok([iv.as_slice(), ciphertext.as_slice()].concat())
+    }
+
+    fn unwrap(&self, wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>, KmsError> {
+        if wrapped.len() <= IV_LENGTH {
+            return Err(KmsError::Malformed);
+        }
+        let (iv, ciphertext) = wrapped.split_at(IV_LENGTH);
+        let session = Session::open(&self.module, self.slot, &self.pin)?;
+        let wrapping_key = session.find_key(&self.key_label)?;
+        let functions = self.module.functions();
+        session.crypt(
+            wrapping_key,
+            iv,
+            ciphertext,
+            (functions.decrypt_init, "C_DecryptInit"),
+            (functions.decrypt, "C_Decrypt"),
+        )
+    }
+}
+
+impl Pkcs11KeyManager {
+    /// Loads the module and finds the token; the key is looked up on use.
+    pub fn open(config: &Pkcs11Config) -> Result<Self, KmsError> {
+        let module = Module::load(config)?;
+        let slot = module.find_slot(&config.token_label)?;
+        let Some(pin) = config.pin() else {
+            return Err(KmsError::Config(
+                "the PKCS#11 token needs a PIN".to_string(),
+            ));
+        };
+        Ok(Self {
+            inner: Arc::new(Pkcs11Inner {
+                module,
+                slot,
+                key_label: config.key_label.clone(),
+                pin,
+            }),
+        })
+    }
+
+    /// Cryptoki calls block, so they run off the async runtime.
+    async fn run<T: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&Pkcs11Inner) -> Result<T, KmsError> + Send + 'static,
+    ) -> Result<T, KmsError> {
+        let inner = Arc::clone(&self.inner);
+        tokio::task::spawn_blocking(move || operation(&inner))
+            .await
+            .map_err(|error| KmsError::Pkcs11(error.to_string()))?
+    }
+}
+
+#[async_trait]
+impl KeyManager for Pkcs11KeyManager {
+    fn name(&self) -> &'static str {
+        "pkcs11"
+    }
+
+    async fn wrap(&self, key: &[u8]) -> Result<Vec<u8>, KmsError> {
+        let key = Zeroizing::new(key.to_vec());
+        self.run(move |inner| inner.wrap(&key)).await
+    }
+
+    async fn unwrap(&self, wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>, KmsError> {
+        let wrapped = wrapped.to_vec();
+        self.run(move |inner| inner.unwrap(&wrapped)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*---------------------------------------------------------------
+    Runs against SoftHSM, e.g.
+
+        softhsm2-util --init-token --free --label locksmith \
+            --pin 1234 --so-pin 5678
+        pkcs11-tool --module /usr/lib/softhsm/libsofthsm2.so \
+            --token-label locksmith --login --pin 1234 \
+            --keygen --key-type AES:32 --label locksmith-master-key
+
+    then
+
+        ECS_PKCS11_PIN=1234 cargo test -- --ignored
+    ----------------------------------------------------------------*/
+    #[tokio::test]
+    #[ignore = "needs SoftHSM with an initialized token"]
+    async fn wraps_with_softhsm() {
+        let manager = Pkcs11KeyManager::open(&Pkcs11Config::default()).unwrap();
+        let key = b"0123456789abcdef0123456789abcdef";
+
+        let wrapped = manager.wrap(key).await.unwrap();
+        assert_ne!(&wrapped[IV_LENGTH..], key.as_slice());
+        assert_eq!(manager.unwrap(&wrapped).await.unwrap().as_slice(), key);
+
+        let mut tampered = wrapped.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(manager.unwrap(&tampered).await.is_err());
+    }
+
+    #[test]
+    fn layouts_match_the_unix_headers() {
+        let word = size_of::<Ulong>();
+        assert_eq!(size_of::<Attribute>(), 3 * word);
+        assert_eq!(size_of::<GcmParams>(), 6 * word);
+        assert_eq!(
+            size_of::<TokenInfo>(),
+            (96 + 11 * word + 4 + 16).next_multiple_of(word)
+        );
+        // The version, padded to a pointer, then the 35 bound entries.
+        assert_eq!(size_of::<FunctionList>(), 36 * size_of::<usize>());
+    }
+}
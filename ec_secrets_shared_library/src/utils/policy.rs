@@ -0,0 +1,126 @@
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+/*---------------------------------------------------------------------------
+    Access policies attached to machine identities.
+
+    A policy is a secret key pattern where `*` matches any run of
+    characters, e.g. `db*` grants access to `db/password` and
+    `db/replica/password`.
+---------------------------------------------------------------------------*/
+
+pub const MAX_POLICY_LENGTH: usize = 256;
+
+/// Returns `true` when `key` matches at least one of the given policies.
+pub fn is_permitted(policies: &[String], key: &str) -> bool {
+    policies.iter().any(|policy| glob_matches(policy, key))
+}
+
+/// Checks that a policy is a usable pattern: not blank, without surrounding
+/// whitespace or control characters, and no longer than a key may be.
+pub fn validate_policy(policy: &str) -> Result<(), String> {
+    if policy.trim().is_empty() {
+        return Err("Policies may not be empty".to_string());
+    }
+    if policy.trim() != policy || policy.chars().any(char::is_control) {
+        return Err(format!("Invalid policy: {policy:?}"));
+    }
+    if policy.len() > MAX_POLICY_LENGTH {
+        return Err(format!(
+            "Policies may not exceed {MAX_POLICY_LENGTH} characters"
+        ));
+    }
+    Ok(())
+}
+
+fn glob_matches(pattern: &str, key: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = key.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard in the pattern: it must match exactly.
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+/// Parses a CIDR block, accepting bare addresses as single-host networks.
+pub fn parse_cidr(cidr: &str) -> Result<IpNet, String> {
+    let cidr = cidr.trim();
+    if let Ok(network) = cidr.parse::<IpNet>() {
+        return Ok(network);
+    }
+    cidr.parse::<IpAddr>()
+        .map(IpNet::from)
+        .map_err(|_| format!("Invalid CIDR block: {cidr}"))
+}
+
+/// Returns `true` when no CIDR restriction applies or `ip` falls inside one
+/// of the listed blocks.
+pub fn ip_allowed(cidr_list: &[String], ip: Option<IpAddr>) -> bool {
+    if cidr_list.is_empty() {
+        return true;
+    }
+
+    let Some(ip) = ip else {
+        return false;
+    };
+
+    cidr_list
+        .iter()
+        .filter_map(|cidr| parse_cidr(cidr).ok())
+        .any(|network| network.contains(&ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies() {
+        let policies = vec!["db/*".to_string(), "api_key".to_string()];
+        assert!(is_permitted(&policies, "db/password"));
+        assert!(is_permitted(&policies, "db/replica/password"));
+        assert!(is_permitted(&policies, "api_key"));
+        assert!(!is_permitted(&policies, "api_key_2"));
+        assert!(!is_permitted(&policies, "cache/password"));
+        assert!(is_permitted(&["*".to_string()], "anything"));
+        assert!(is_permitted(&["*/password".to_string()], "db/password"));
+        assert!(!is_permitted(&["db/*/password".to_string()], "db/user"));
+        assert!(!is_permitted(&[], "db/password"));
+    }
+
+    #[test]
+    fn policy_validation() {
+        assert!(validate_policy("db/*").is_ok());
+        assert!(validate_policy("").is_err());
+        assert!(validate_policy("   ").is_err());
+        assert!(validate_policy(" db/*").is_err());
+        assert!(validate_policy("db/\n*").is_err());
+        assert!(validate_policy(&"a".repeat(MAX_POLICY_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn cidr_restrictions() {
+        let cidr_list = vec!["10.0.0.0/8".to_string(), "192.168.1.7".to_string()];
+        assert!(ip_allowed(&cidr_list, "10.1.2.3".parse().ok()));
+        assert!(ip_allowed(&cidr_list, "192.168.1.7".parse().ok()));
+        assert!(!ip_allowed(&cidr_list, "192.168.1.8".parse().ok()));
+        assert!(!ip_allowed(&cidr_list, None));
+        assert!(ip_allowed(&[], None));
+        assert!(parse_cidr("not-a-network").is_err());
+    }
+}
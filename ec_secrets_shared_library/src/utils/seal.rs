@@ -0,0 +1,460 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use chrono::Utc;
+use log::warn;
+use std::{
+    io,
+    sync::{PoisonError, RwLock},
+};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::models::{SealDocument, SealStatus, WrappedKey};
+use crate::utils::kms::{KeyManager, KmsError};
+use crate::utils::secret::{KeyMaterial, SecretValue};
+use crate::utils::shamir::{self, ShamirError};
+use crate::utils::vault::{EncryptError, decrypt, encrypt};
+
+/*---------------------------------------------------------------
+Sealing. The master key that encrypts secrets and TOTP seeds only
+lives in memory: at initialization it is split into Shamir shares
+handed to operators, and after every start (or `seal`) a threshold
+of them must be submitted before anything can be decrypted.
+
+Deployments that never initialized sealing keep reading the key
+from `ECS_ENCRYPTION_KEY`; initializing splits that same key, so
+existing secrets stay readable once the variable is removed.
+----------------------------------------------------------------*/
+pub const SEAL_ID: &str = "seal";
+/// Length of a generated master key, in bytes.
+pub const MASTER_KEY_LENGTH: usize = 32;
+const SEAL_CHECK: &[u8] = b"locksmith-seal-check";
+
+#[derive(Debug, Error)]
+pub enum SealError {
+    #[error("Locksmith is sealed")]
+    Sealed,
+    #[error("Locksmith is already initialized")]
+    AlreadyInitialized,
+    #[error("Locksmith is not initialized")]
+    NotInitialized,
+    #[error("invalid unseal share: {0}")]
+    InvalidShare(String),
+    #[error("the submitted shares do not rebuild the master key; start over")]
+    WrongShares,
+    #[error("{0}")]
+    Shamir(#[from] ShamirError),
+    #[error("the unwrapped key does not match the seal")]
+    WrongKey,
+    #[error("failed to encrypt the seal verifier: {0}")]
+    Encrypt(#[from] EncryptError),
+    #[error("key manager: {0}")]
+    Kms(#[from] KmsError),
+}
+
+/// The master key, when unsealed, and the shares submitted so far.
+#[derive(Default)]
+pub struct Keyring {
+    state: RwLock<KeyringState>,
+}
+
+#[derive(Default)]
+struct KeyringState {
+    key: Option<KeyMaterial>,
+    pending: Vec<Zeroizing<Vec<u8>>>,
+    /// Lock the key into RAM whenever it is installed.
+    lock_memory: bool,
+}
+
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keyring")
+            .field("sealed", &self.is_sealed())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Keyring {
+    pub fn sealed() -> Self {
+        Self::default()
+    }
+
+    pub fn unsealed(key: Vec<u8>) -> Self {
+        let keyring = Self::default();
+        keyring.install(Zeroizing::new(key));
+        keyring
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        self.state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .key
+            .is_none()
+    }
+
+    /// Runs `f` with the master key, without copying it out.
+    pub fn with_key<T>(&self, f: impl FnOnce(&[u8]) -> T) -> Result<T, SealError> {
+        let state = self.state.read().unwrap_or_else(PoisonError::into_inner);
+        state
+            .key
+            .as_ref()
+            .map(|key| f(key))
+            .ok_or(SealError::Sealed)
+    }
+
+    /// Puts the master key in place, unsealing.
+    pub fn install(&self, key: Zeroizing<Vec<u8>>) {
+        let mut state = self.state.write().unwrap_or_else(PoisonError::into_inner);
+        state.install(key);
+    }
+
+    /// Locks the master key into RAM from now on, including the key in use.
+    pub fn lock_memory(&self) -> io::Result<()> {
+        let mut state = self.state.write().unwrap_or_else(PoisonError::into_inner);
+        state.lock_memory = true;
+        match state.key.as_mut() {
+            Some(key) => key.lock(),
+            None => Ok(()),
+        }
+    }
+
+    /// Wipes the master key and any submitted shares from memory.
+    pub fn seal(&self) {
+        let mut state = self.state.write().unwrap_or_else(PoisonError::into_inner);
+        state.key = None;
+        state.pending.clear();
+    }
+
+    /// Discards the shares submitted so far.
+    pub fn reset(&self) {
+        self.state
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pending
+            .clear();
+    }
+
+    pub fn status(&self, seal: Option<&SealDocument>) -> SealStatus {
+        let state = self.state.read().unwrap_or_else(PoisonError::into_inner);
+        SealStatus {
+            initialized: seal.is_some(),
+            sealed: state.key.is_none(),
+            shares: seal.map(|seal| seal.shares),
+            threshold: seal.map(|seal| seal.threshold),
+            progress: state.pending.len(),
+            auto_unseal: seal
+                .and_then(|seal| seal.wrapped_key.as_ref())
+                .map(|wrapped_key| wrapped_key.manager.clone()),
+        }
+    }
+
+    /*---------------------------------------------------------------
+    Take one share. Once `threshold` shares are in, the key is rebuilt
+    and checked against the verifier; wrong shares are all discarded.
+    Returns whether the keyring is now unsealed.
+    ----------------------------------------------------------------*/
+    pub fn submit(&self, seal: &SealDocument, share: &str) -> Result<bool, SealError> {
+        let share = Zeroizing::new(
+            STANDARD
+                .decode(share.trim())
+                .map_err(|_| SealError::InvalidShare("not base64".to_string()))?,
+        );
+        if share.len() < 2 || share[0] == 0 {
+            return Err(SealError::InvalidShare("malformed".to_string()));
+        }
+
+        let mut state = self.state.write().unwrap_or_else(PoisonError::into_inner);
+        if state.key.is_some() {
+            return Ok(true);
+        }
+        if let Some(first) = state.pending.first()
+            && first.len() != share.len()
+        {
+            return Err(SealError::InvalidShare(
+                "it does not belong to the same key as the shares already submitted".to_string(),
+            ));
+        }
+        if state.pending.iter().any(|pending| pending[0] == share[0]) {
+            return Err(SealError::InvalidShare(
+                "this share was already submitted".to_string(),
+            ));
+        }
+        state.pending.push(share);
+        if state.pending.len() < seal.threshold as usize {
+            return Ok(false);
+        }
+
+        let rebuilt = shamir::combine(&state.pending);
+        state.pending.clear();
+        let key = rebuilt?;
+        if !verifies(seal, &key) {
+            return Err(SealError::WrongShares);
+        }
+        state.install(key);
+        Ok(true)
+    }
+}
+
+impl KeyringState {
+    fn install(&mut self, key: Zeroizing<Vec<u8>>) {
+        let mut key = KeyMaterial::new(key);
+        if self.lock_memory
+            && let Err(error) = key.lock()
+        {
+            warn!("Cannot lock the master key into memory: {}", error);
+        }
+        self.key = Some(key);
+        self.pending.clear();
+    }
+}
+
+/// A split prepared by `Keyring::initialize`, applied by `complete`.
+pub struct Initialization {
+    pub seal: SealDocument,
+    pub shares: Vec<SecretValue>,
+    key: Zeroizing<Vec<u8>>,
+}
+
+impl Keyring {
+    /*---------------------------------------------------------------
+    Split the key in use or, when sealed, a fresh one. Nothing changes
+    until `complete` is called, once the seal document is stored.
+    ----------------------------------------------------------------*/
+    pub fn initialize(&self, shares: u8, threshold: u8) -> Result<Initialization, SealError> {
+        let key = self
+            .with_key(|key| Zeroizing::new(key.to_vec()))
+            .unwrap_or_else(|_| generate_master_key());
+        let (seal, shares) = split_master_key(&key, shares, threshold)?;
+        Ok(Initialization { seal, shares, key })
+    }
+
+    /// Unseals with the key of a stored initialization.
+    pub fn complete(&self, initialization: Initialization) {
+        self.install(initialization.key);
+    }
+}
+
+impl Keyring {
+    /*---------------------------------------------------------------
+    Unseal with the key wrapped by `manager`. `false` when the seal
+    holds no key wrapped by that manager, e.g. right after switching
+    to auto-unseal; unseal with shares once and wrap it then.
+    ----------------------------------------------------------------*/
+    pub async fn auto_unseal(
+        &self,
+        seal: &SealDocument,
+        manager: &dyn KeyManager,
+    ) -> Result<bool, SealError> {
+        let Some(wrapped_key) = seal
+            .wrapped_key
+            .as_ref()
+            .filter(|wrapped_key| wrapped_key.manager == manager.name())
+        else {
+            return Ok(false);
+        };
+        let ciphertext = STANDARD
+            .decode(&wrapped_key.ciphertext)
+            .map_err(|_| KmsError::Malformed)?;
+        let key = manager.unwrap(&ciphertext).await?;
+        if !verifies(seal, &key) {
+            return Err(SealError::WrongKey);
+        }
+        self.install(key);
+        Ok(true)
+    }
+
+    /// Wraps the key in use with `manager`, for storage with the seal.
+    pub async fn wrap(&self, manager: &dyn KeyManager) -> Result<WrappedKey, SealError> {
+        let key = self.with_key(|key| Zeroizing::new(key.to_vec()))?;
+        wrap_key(manager, &key).await
+    }
+}
+
+impl Initialization {
+    /// Also wraps the key with `manager`, so the server unseals itself.
+    pub async fn wrap(&mut self, manager: &dyn KeyManager) -> Result<(), SealError> {
+        self.seal.wrapped_key = Some(wrap_key(manager, &self.key).await?);
+        Ok(())
+    }
+}
+
+async fn wrap_key(manager: &dyn KeyManager, key: &[u8]) -> Result<WrappedKey, SealError> {
+    let ciphertext = manager.wrap(key).await?;
+    Ok(WrappedKey {
+        manager: manager.name().to_string(),
+        ciphertext: STANDARD.encode(ciphertext),
+    })
+}
+
+/// A fresh random master key.
+pub fn generate_master_key() -> Zeroizing<Vec<u8>> {
+    let mut key = Zeroizing::new(vec![0u8; MASTER_KEY_LENGTH]);
+    OsRng.fill_bytes(&mut key);
+    key
+}
+
+/// Splits `key` into base64 shares and describes the split for storage.
+pub fn split_master_key(
+    key: &[u8],
+    shares: u8,
+    threshold: u8,
+) -> Result<(SealDocument, Vec<SecretValue>), SealError> {
+    let split = shamir::split(key, shares, threshold)?;
+    let encoded = split
+        .into_iter()
+        .map(|share| {
+            let share = Zeroizing::new(share);
+            SecretValue::new(STANDARD.encode(&*share))
+        })
+        .collect();
+    let verifier = STANDARD.encode(encrypt(SEAL_CHECK, key)?);
+
+    Ok((
+        SealDocument {
+            id: SEAL_ID.to_string(),
+            shares,
+            threshold,
+            verifier,
+            initialized_at: Utc::now(),
+            wrapped_key: None,
+        },
+        encoded,
+    ))
+}
+
+fn verifies(seal: &SealDocument, key: &[u8]) -> bool {
+    STANDARD
+        .decode(&seal.verifier)
+        .ok()
+        .and_then(|verifier| decrypt(&verifier, key).ok())
+        .is_some_and(|check| check.as_slice() == SEAL_CHECK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unseals_with_threshold_shares() {
+        let key = generate_master_key();
+        let (seal, shares) = split_master_key(&key, 5, 3).unwrap();
+        let keyring = Keyring::sealed();
+        assert!(matches!(keyring.with_key(|_| ()), Err(SealError::Sealed)));
+
+        assert!(!keyring.submit(&seal, &shares[4]).unwrap());
+        assert!(matches!(
+            keyring.submit(&seal, &shares[4]),
+            Err(SealError::InvalidShare(_))
+        ));
+        assert!(!keyring.submit(&seal, &shares[1]).unwrap());
+        assert_eq!(keyring.status(Some(&seal)).progress, 2);
+        assert!(keyring.submit(&seal, &shares[2]).unwrap());
+
+        assert!(!keyring.is_sealed());
+        assert_eq!(
+            keyring.with_key(|unsealed| unsealed.to_vec()).unwrap(),
+            key.to_vec()
+        );
+
+        keyring.seal();
+        assert!(keyring.is_sealed());
+        assert_eq!(keyring.status(Some(&seal)).progress, 0);
+    }
+
+    #[test]
+    fn wrong_shares_start_over() {
+        let (seal, shares) = split_master_key(&generate_master_key(), 3, 2).unwrap();
+        let (_, other_shares) = split_master_key(&generate_master_key(), 3, 2).unwrap();
+        let keyring = Keyring::sealed();
+
+        keyring.submit(&seal, &shares[0]).unwrap();
+        assert!(matches!(
+            keyring.submit(&seal, &other_shares[1]),
+            Err(SealError::WrongShares)
+        ));
+        assert!(keyring.is_sealed());
+        assert_eq!(keyring.status(Some(&seal)).progress, 0);
+
+        assert!(matches!(
+            keyring.submit(&seal, "not a share!"),
+            Err(SealError::InvalidShare(_))
+        ));
+    }
+
+    #[test]
+    fn existing_keys_can_be_split() {
+        let legacy = Keyring::unsealed(b"legacy-environment-key".to_vec());
+        let Initialization { seal, shares, .. } = legacy.initialize(2, 2).unwrap();
+        let keyring = Keyring::sealed();
+        keyring.submit(&seal, &shares[1]).unwrap();
+        keyring.submit(&seal, &shares[0]).unwrap();
+        assert_eq!(
+            keyring.with_key(|key| key.to_vec()).unwrap(),
+            b"legacy-environment-key"
+        );
+        assert_eq!(format!("{:?}", keyring), "Keyring { sealed: false, .. }");
+    }
+
+    /// Stands in for an HSM by XOR-ing with a fixed pad.
+    struct PadKeyManager(u8);
+
+    #[async_trait::async_trait]
+    impl KeyManager for PadKeyManager {
+        fn name(&self) -> &'static str {
+            "pad"
+        }
+
+        async fn wrap(&self, key: &[u8]) -> Result<Vec<u8>, KmsError> {
+            Ok(key.iter().map(|byte| byte ^ self.0).collect())
+        }
+
+        async fn unwrap(&self, wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>, KmsError> {
+            Ok(Zeroizing::new(self.wrap(wrapped).await?))
+        }
+    }
+
+    #[tokio::test]
+    async fn auto_unseals_with_the_wrapped_key() {
+        let mut initialization = Keyring::sealed().initialize(3, 2).unwrap();
+        initialization.wrap(&PadKeyManager(0x5a)).await.unwrap();
+        let seal = initialization.seal.clone();
+        assert_eq!(
+            Keyring::sealed().status(Some(&seal)).auto_unseal.as_deref(),
+            Some("pad")
+        );
+
+        let keyring = Keyring::sealed();
+        assert!(matches!(
+            keyring.auto_unseal(&seal, &PadKeyManager(0x33)).await,
+            Err(SealError::WrongKey)
+        ));
+        assert!(keyring.is_sealed());
+        assert!(
+            keyring
+                .auto_unseal(&seal, &PadKeyManager(0x5a))
+                .await
+                .unwrap()
+        );
+        assert!(!keyring.is_sealed());
+
+        // A seal wrapped by another manager waits for shares.
+        let unwrapped = SealDocument {
+            wrapped_key: None,
+            ..seal.clone()
+        };
+        let keyring = Keyring::sealed();
+        assert!(
+            !keyring
+                .auto_unseal(&unwrapped, &PadKeyManager(0x5a))
+                .await
+                .unwrap()
+        );
+        keyring.submit(&seal, &initialization.shares[0]).unwrap();
+        keyring.submit(&seal, &initialization.shares[2]).unwrap();
+        assert_eq!(
+            keyring.wrap(&PadKeyManager(0x5a)).await.unwrap(),
+            seal.wrapped_key.unwrap()
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use schemars::{JsonSchema, r#gen::SchemaGenerator, schema::Schema};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, io, ops::Deref};
+use zeroize::{Zeroize, Zeroizing};
+
+/*---------------------------------------------------------------
+Secret material in memory. Plaintext secret values, passwords and
+credentials are held in `SecretValue`s, which are wiped when they
+are dropped and never show up in `Debug` output or logs; buffers
+of key material use `Zeroizing` directly.
+
+On Linux, key material can also be locked into RAM (see
+`lock_memory`) so it is never written to swap.
+----------------------------------------------------------------*/
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SecretValue(Zeroizing<String>);
+
+impl SecretValue {
+    pub fn new(value: String) -> Self {
+        Self(Zeroizing::new(value))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for SecretValue {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for SecretValue {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl From<String> for SecretValue {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&str> for SecretValue {
+    fn from(value: &str) -> Self {
+        Self::new(value.to_string())
+    }
+}
+
+impl fmt::Debug for SecretValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl Serialize for SecretValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::new)
+    }
+}
+
+impl JsonSchema for SecretValue {
+    fn schema_name() -> String {
+        String::schema_name()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        String::json_schema(generator)
+    }
+}
+
+/// Key material, wiped on drop and optionally locked into RAM.
+pub struct KeyMaterial {
+    bytes: Zeroizing<Vec<u8>>,
+    locked: bool,
+}
+
+impl KeyMaterial {
+    pub fn new(bytes: Zeroizing<Vec<u8>>) -> Self {
+        Self {
+            bytes,
+            locked: false,
+        }
+    }
+
+    /// Locks the bytes into RAM, keeping them out of swap.
+    pub fn lock(&mut self) -> io::Result<()> {
+        if !self.locked && !self.bytes.is_empty() {
+            lock(&self.bytes)?;
+            self.locked = true;
+        }
+        Ok(())
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl Deref for KeyMaterial {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl fmt::Debug for KeyMaterial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeyMaterial([REDACTED])")
+    }
+}
+
+impl Drop for KeyMaterial {
+    fn drop(&mut self) {
+        // Wipe before unlocking, so the bytes cannot reach swap in between.
+        let (address, length) = (self.bytes.as_ptr(), self.bytes.len());
+        self.bytes.zeroize();
+        if self.locked {
+            // SAFETY: the allocation is still alive; only its length was reset.
+            unlock(unsafe { std::slice::from_raw_parts(address, length) });
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn lock(bytes: &[u8]) -> io::Result<()> {
+    // SAFETY: the range is a live allocation.
+    if unsafe { libc::mlock(bytes.as_ptr().cast(), bytes.len()) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn unlock(bytes: &[u8]) {
+    // SAFETY: the range was locked by `lock`.
+    unsafe {
+        libc::munlock(bytes.as_ptr().cast(), bytes.len());
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lock(_bytes: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "memory locking is only supported on Linux",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unlock(_bytes: &[u8]) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_values_are_redacted() {
+        let value = SecretValue::from("hunter2");
+        assert_eq!(format!("{value:?}"), "[REDACTED]");
+        assert_eq!(&*value, "hunter2");
+        assert_eq!(serde_json::to_string(&value).unwrap(), "\"hunter2\"");
+        assert_eq!(
+            serde_json::from_str::<SecretValue>("\"hunter2\"").unwrap(),
+            value
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn key_material_can_be_locked() {
+        let mut key = KeyMaterial::new(Zeroizing::new(vec![7u8; 32]));
+        assert_eq!(format!("{key:?}"), "KeyMaterial([REDACTED])");
+        // A low RLIMIT_MEMLOCK may refuse; locking is best effort.
+        if key.lock().is_ok() {
+            assert!(key.is_locked());
+        }
+        assert_eq!(&key[..], &[7u8; 32]);
+    }
+}
@@ -0,0 +1,163 @@
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+/*---------------------------------------------------------------
+Shamir's secret sharing over GF(2^8), byte by byte. A share is
+its x coordinate (1..=255) followed by one y value per secret
+byte; any `threshold` distinct shares rebuild the secret and
+fewer reveal nothing about it.
+
+Field arithmetic avoids secret-dependent branches and tables.
+----------------------------------------------------------------*/
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ShamirError {
+    #[error("the threshold must be between 1 and the number of shares (at most 255)")]
+    Parameters,
+    #[error("shares must be non-empty and of equal length")]
+    Malformed,
+    #[error("every share must have a different, non-zero x coordinate")]
+    Duplicate,
+}
+
+/// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x + 1.
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    product
+}
+
+/// The multiplicative inverse, as a^254; zero maps to zero.
+fn inv(a: u8) -> u8 {
+    let mut result = 1;
+    let mut base = a;
+    let mut exponent: u8 = 254;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul(result, base);
+        }
+        base = mul(base, base);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Splits `secret` into `shares` shares, any `threshold` of which
+/// rebuild it.
+pub fn split(secret: &[u8], shares: u8, threshold: u8) -> Result<Vec<Vec<u8>>, ShamirError> {
+    if secret.is_empty() {
+        return Err(ShamirError::Malformed);
+    }
+    if threshold == 0 || threshold > shares {
+        return Err(ShamirError::Parameters);
+    }
+
+    // One polynomial per secret byte; the constant term is the byte.
+    let mut coefficients = Zeroizing::new(vec![0u8; secret.len() * threshold as usize]);
+    OsRng.fill_bytes(&mut coefficients);
+    for (polynomial, byte) in coefficients
+        .chunks_mut(threshold as usize)
+        .zip(secret.iter())
+    {
+        polynomial[0] = *byte;
+    }
+
+    Ok((1..=shares)
+        .map(|x| {
+            let mut share = Vec::with_capacity(secret.len() + 1);
+            share.push(x);
+            for polynomial in coefficients.chunks(threshold as usize) {
+                share.push(
+                    polynomial
+                        .iter()
+                        .rev()
+                        .fold(0, |acc, coefficient| mul(acc, x) ^ coefficient),
+                );
+            }
+            share
+        })
+        .collect())
+}
+
+/// Rebuilds the secret from shares by Lagrange interpolation at zero.
+/// Enough distinct shares of the same split always rebuild it; too few,
+/// or shares of different splits, silently give a different value, so
+/// callers check the result.
+pub fn combine<T: AsRef<[u8]>>(shares: &[T]) -> Result<Zeroizing<Vec<u8>>, ShamirError> {
+    let Some(length) = shares.first().map(|share| share.as_ref().len()) else {
+        return Err(ShamirError::Malformed);
+    };
+    if length < 2 || shares.iter().any(|share| share.as_ref().len() != length) {
+        return Err(ShamirError::Malformed);
+    }
+    let x = |share: &T| share.as_ref()[0];
+    for (i, share) in shares.iter().enumerate() {
+        if x(share) == 0 || shares[..i].iter().any(|other| x(other) == x(share)) {
+            return Err(ShamirError::Duplicate);
+        }
+    }
+
+    let mut secret = Zeroizing::new(vec![0u8; length - 1]);
+    for share in shares {
+        let basis = shares
+            .iter()
+            .filter(|other| x(other) != x(share))
+            .fold(1, |acc, other| {
+                mul(acc, mul(x(other), inv(x(other) ^ x(share))))
+            });
+        for (byte, y) in secret.iter_mut().zip(&share.as_ref()[1..]) {
+            *byte ^= mul(basis, *y);
+        }
+    }
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_inverses() {
+        assert_eq!(mul(0x53, 0xca), 0x01);
+        for a in 1..=255u8 {
+            assert_eq!(mul(a, inv(a)), 1);
+        }
+    }
+
+    #[test]
+    fn any_threshold_of_shares_rebuilds_the_secret() {
+        let secret = b"0123456789abcdef0123456789abcdef";
+        let shares = split(secret, 5, 3).unwrap();
+        assert_eq!(shares.len(), 5);
+        assert!(shares.iter().all(|share| share.len() == secret.len() + 1));
+
+        for subset in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let picked: Vec<Vec<u8>> = subset.iter().map(|&i| shares[i].clone()).collect();
+            assert_eq!(combine(&picked).unwrap().as_slice(), secret);
+        }
+        assert_eq!(combine(&shares).unwrap().as_slice(), secret);
+        assert_ne!(combine(&shares[..2]).unwrap().as_slice(), secret);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert_eq!(split(b"key", 3, 4), Err(ShamirError::Parameters));
+        assert_eq!(split(b"key", 3, 0), Err(ShamirError::Parameters));
+        assert_eq!(split(b"", 3, 2), Err(ShamirError::Malformed));
+
+        let shares = split(b"key", 3, 2).unwrap();
+        assert_eq!(
+            combine(&[shares[0].clone(), shares[0].clone()]),
+            Err(ShamirError::Duplicate)
+        );
+        assert_eq!(
+            combine(&[shares[0].clone(), shares[1][..2].to_vec()]),
+            Err(ShamirError::Malformed)
+        );
+    }
+}
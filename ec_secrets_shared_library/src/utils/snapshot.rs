@@ -0,0 +1,327 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+use std::{collections::HashSet, fmt, hash::Hash};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::models::{
+    AppRoleDocument, KeyPairDocument, SnapshotCounts, SnapshotManifest, TotpEnrollmentDocument,
+    UserDocument, VaultDocument,
+};
+use crate::utils::vault::{
+    DecryptDirectoryError, EncryptDirectoryError, decrypt_archive, encrypt_archive,
+};
+
+/*---------------------------------------------------------------
+Snapshots. Users, secrets, TOTP enrollments, the token signing key
+and AppRoles are written into one tar archive, encrypted with a
+passphrase by the same AEAD as the rest of the vault. Values held
+under the master key are stored in plaintext inside the archive,
+so a snapshot restores into a deployment with another master key;
+the passphrase is all that protects them.
+
+Every collection is a BSON file. `manifest.json` records how many
+documents each holds and its SHA-256 digest, which are checked on
+top of the authentication tag before anything is restored.
+----------------------------------------------------------------*/
+pub const SNAPSHOT_FORMAT: u32 = 1;
+pub const MIN_PASSPHRASE_LENGTH: usize = 12;
+
+const MANIFEST: &str = "manifest.json";
+const USERS: &str = "users.bson";
+const SECRETS: &str = "vault.bson";
+const TOTP: &str = "totp.bson";
+const KEYS: &str = "keys.bson";
+const APP_ROLES: &str = "app_roles.bson";
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("the passphrase must be at least {MIN_PASSPHRASE_LENGTH} characters long")]
+    WeakPassphrase,
+    #[error("failed to encode the snapshot: {0}")]
+    Encode(String),
+    #[error("failed to encrypt the snapshot: {0}")]
+    Encrypt(#[from] EncryptDirectoryError),
+    #[error("wrong passphrase, or the snapshot is corrupted")]
+    Decrypt,
+    #[error("snapshot format {0} is not supported")]
+    Format(u32),
+    #[error("the snapshot has no {0}")]
+    Missing(&'static str),
+    #[error("{0} does not match its checksum")]
+    Checksum(&'static str),
+    #[error("invalid snapshot: {0}")]
+    Invalid(String),
+}
+
+/// The contents of a snapshot. Secret values and TOTP secrets are in
+/// plaintext.
+#[derive(Clone, Default)]
+pub struct Snapshot {
+    pub users: Vec<UserDocument>,
+    pub secrets: Vec<VaultDocument>,
+    pub totp: Vec<TotpEnrollmentDocument>,
+    pub keys: Vec<KeyPairDocument>,
+    pub app_roles: Vec<AppRoleDocument>,
+}
+
+impl fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("counts", &self.counts())
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Documents<T> {
+    documents: T,
+}
+
+impl Snapshot {
+    pub fn counts(&self) -> SnapshotCounts {
+        SnapshotCounts {
+            users: self.users.len(),
+            secrets: self.secrets.len(),
+            totp: self.totp.len(),
+            keys: self.keys.len(),
+            app_roles: self.app_roles.len(),
+        }
+    }
+
+    /// Archives the snapshot and encrypts it with `passphrase`.
+    pub fn pack(
+        &self,
+        created_by: &str,
+        passphrase: &str,
+    ) -> Result<(SnapshotManifest, Vec<u8>), SnapshotError> {
+        if passphrase.chars().count() < MIN_PASSPHRASE_LENGTH {
+            return Err(SnapshotError::WeakPassphrase);
+        }
+
+        let files = [
+            (USERS, encode(&self.users)?),
+            (SECRETS, encode(&self.secrets)?),
+            (TOTP, encode(&self.totp)?),
+            (KEYS, encode(&self.keys)?),
+            (APP_ROLES, encode(&self.app_roles)?),
+        ];
+        let manifest = SnapshotManifest {
+            format: SNAPSHOT_FORMAT,
+            created_at: Utc::now().to_rfc3339(),
+            created_by: created_by.to_string(),
+            counts: self.counts(),
+            checksums: files
+                .iter()
+                .map(|(name, data)| (name.to_string(), checksum(data)))
+                .collect(),
+        };
+        let encoded_manifest = serde_json::to_vec_pretty(&manifest)
+            .map_err(|e| SnapshotError::Encode(e.to_string()))?;
+
+        let mut entries: Vec<(&str, &[u8])> = vec![(MANIFEST, &encoded_manifest)];
+        entries.extend(files.iter().map(|(name, data)| (*name, data.as_slice())));
+        let archive = encrypt_archive(&entries, passphrase.as_bytes())?;
+        Ok((manifest, archive))
+    }
+
+    /// Decrypts a snapshot made by `pack` and checks it against its
+    /// manifest before returning it.
+    pub fn unpack(
+        data: &[u8],
+        passphrase: &str,
+    ) -> Result<(SnapshotManifest, Snapshot), SnapshotError> {
+        let files = decrypt_archive(data, passphrase.as_bytes()).map_err(|error| match error {
+            DecryptDirectoryError::Archive(error) => SnapshotError::Invalid(error.to_string()),
+            _ => SnapshotError::Decrypt,
+        })?;
+        let file = |name: &'static str| {
+            files
+                .iter()
+                .find(|(file, _)| file == name)
+                .map(|(_, data)| data.as_slice())
+                .ok_or(SnapshotError::Missing(name))
+        };
+
+        let manifest: SnapshotManifest = serde_json::from_slice(file(MANIFEST)?)
+            .map_err(|e| SnapshotError::Invalid(format!("unreadable manifest: {e}")))?;
+        if manifest.format != SNAPSHOT_FORMAT {
+            return Err(SnapshotError::Format(manifest.format));
+        }
+        let verified = |name: &'static str| {
+            let data = file(name)?;
+            if manifest.checksums.get(name) != Some(&checksum(data)) {
+                return Err(SnapshotError::Checksum(name));
+            }
+            Ok(data)
+        };
+
+        let snapshot = Snapshot {
+            users: decode(USERS, verified(USERS)?)?,
+            secrets: decode(SECRETS, verified(SECRETS)?)?,
+            totp: decode(TOTP, verified(TOTP)?)?,
+            keys: decode(KEYS, verified(KEYS)?)?,
+            app_roles: decode(APP_ROLES, verified(APP_ROLES)?)?,
+        };
+        if snapshot.counts() != manifest.counts {
+            return Err(SnapshotError::Invalid(
+                "document counts do not match the manifest".to_string(),
+            ));
+        }
+        snapshot.validate()?;
+        Ok((manifest, snapshot))
+    }
+
+    fn validate(&self) -> Result<(), SnapshotError> {
+        unique(self.users.iter().map(|user| user.id), "user ids")?;
+        unique(self.users.iter().map(|user| &user.email), "user emails")?;
+        unique(self.secrets.iter().map(|secret| secret.id), "secret ids")?;
+        unique(
+            self.totp.iter().map(|enrollment| &enrollment.subject),
+            "TOTP enrollments",
+        )?;
+        unique(self.app_roles.iter().map(|role| role.id), "AppRole ids")?;
+        unique(self.app_roles.iter().map(|role| &role.role_id), "role ids")?;
+        if self.keys.len() > 1 {
+            return Err(SnapshotError::Invalid(
+                "more than one signing key".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn encode<T: Serialize>(documents: &[T]) -> Result<Zeroizing<Vec<u8>>, SnapshotError> {
+    bson::to_vec(&Documents { documents })
+        .map(Zeroizing::new)
+        .map_err(|e| SnapshotError::Encode(e.to_string()))
+}
+
+fn decode<T: DeserializeOwned>(name: &str, data: &[u8]) -> Result<Vec<T>, SnapshotError> {
+    bson::from_slice::<Documents<Vec<T>>>(data)
+        .map(|file| file.documents)
+        .map_err(|e| SnapshotError::Invalid(format!("unreadable {name}: {e}")))
+}
+
+fn checksum(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn unique<T: Eq + Hash>(values: impl Iterator<Item = T>, what: &str) -> Result<(), SnapshotError> {
+    let mut seen = HashSet::new();
+    for value in values {
+        if !seen.insert(value) {
+            return Err(SnapshotError::Invalid(format!("duplicate {what}")));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UserStatus;
+    use mongodb::bson::oid::ObjectId;
+
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    fn user(email: &str) -> UserDocument {
+        UserDocument {
+            id: ObjectId::new(),
+            email: email.to_string(),
+            password: "$argon2id$hash".to_string(),
+            roles: vec![],
+            webauthn_credentials: vec![],
+            password_history: vec![],
+            email_verified: true,
+            status: UserStatus::Active,
+            created_at: Utc::now(),
+            last_login: None,
+        }
+    }
+
+    fn secret(key: &str, value: &str) -> VaultDocument {
+        VaultDocument {
+            id: ObjectId::new(),
+            key: key.to_string(),
+            value: value.into(),
+            created_by: "user@example.com".to_string(),
+            owner_id: String::new(),
+            created_at: Utc::now(),
+            archived_at: None,
+            protection: None,
+            version: 1,
+        }
+    }
+
+    #[test]
+    fn snapshots_round_trip() {
+        let snapshot = Snapshot {
+            users: vec![user("user@example.com")],
+            secrets: vec![secret("db-password", "hunter2"), secret("api-key", "abc")],
+            keys: vec![KeyPairDocument {
+                private_key: "private".to_string(),
+                public_key: "public".to_string(),
+                created_at: Utc::now(),
+            }],
+            ..Snapshot::default()
+        };
+        let (manifest, archive) = snapshot.pack("admin@example.com", PASSPHRASE).unwrap();
+        assert_eq!(manifest.counts.secrets, 2);
+        assert_eq!(manifest.checksums.len(), 5);
+
+        let (restored_manifest, restored) = Snapshot::unpack(&archive, PASSPHRASE).unwrap();
+        assert_eq!(restored_manifest, manifest);
+        assert_eq!(restored.users[0].email, "user@example.com");
+        assert_eq!(&*restored.secrets[0].value, "hunter2");
+        assert_eq!(restored.keys[0].private_key, "private");
+
+        assert!(matches!(
+            Snapshot::unpack(&archive, "another passphrase"),
+            Err(SnapshotError::Decrypt)
+        ));
+        assert!(matches!(
+            snapshot.pack("admin@example.com", "short"),
+            Err(SnapshotError::WeakPassphrase)
+        ));
+    }
+
+    #[test]
+    fn snapshots_are_checked_against_their_manifest() {
+        let snapshot = Snapshot {
+            users: vec![user("user@example.com")],
+            ..Snapshot::default()
+        };
+        let (manifest, _) = snapshot.pack("admin@example.com", PASSPHRASE).unwrap();
+        let encoded_manifest = serde_json::to_vec(&manifest).unwrap();
+        let users = encode(&[user("user@example.com"), user("other@example.com")]).unwrap();
+        let empty = encode::<VaultDocument>(&[]).unwrap();
+        let archive = encrypt_archive(
+            &[
+                (MANIFEST, &encoded_manifest),
+                (USERS, &users),
+                (SECRETS, &empty),
+                (TOTP, &empty),
+                (KEYS, &empty),
+                (APP_ROLES, &empty),
+            ],
+            PASSPHRASE.as_bytes(),
+        )
+        .unwrap();
+        assert!(matches!(
+            Snapshot::unpack(&archive, PASSPHRASE),
+            Err(SnapshotError::Checksum(USERS))
+        ));
+
+        let duplicated = Snapshot {
+            users: vec![user("user@example.com"), user("user@example.com")],
+            ..Snapshot::default()
+        };
+        let (_, archive) = duplicated.pack("admin@example.com", PASSPHRASE).unwrap();
+        assert!(matches!(
+            Snapshot::unpack(&archive, PASSPHRASE),
+            Err(SnapshotError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,166 @@
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+/// Issuer label shown by authenticator apps next to the account name.
+pub const TOTP_ISSUER: &str = "Locksmith";
+
+/// Number of single-use recovery codes handed out on enrollment.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP: u64 = 30;
+/// Codes from one step before or after the current one are still accepted
+/// to tolerate clock drift between the server and the authenticator.
+const TOTP_SKEW: u64 = 1;
+
+/// 32 symbols without look-alikes (`i`, `l`, `o`, `1`), so every byte maps without bias.
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz023456789";
+
+/*---------------------------------------------------------------------------
+    Time-based one-time passwords (RFC 6238) used as a second login factor.
+
+    Secrets are 160-bit random values exchanged with authenticator apps in
+    base32. A verified code reports the time step it matched so callers can
+    refuse to accept the same code twice.
+---------------------------------------------------------------------------*/
+
+/// Generates a new base32 encoded TOTP secret.
+pub fn generate_totp_secret() -> String {
+    let mut secret = [0u8; 20];
+    OsRng.fill_bytes(&mut secret);
+    Secret::Raw(secret.to_vec()).to_encoded().to_string()
+}
+
+fn totp(secret: &str, account: &str) -> Result<TOTP, String> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| format!("{:?}", e))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW as u8,
+        TOTP_STEP,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        account.to_string(),
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// The `otpauth://` URI authenticator apps import, usually as a QR code.
+pub fn provisioning_uri(secret: &str, account: &str) -> Result<String, String> {
+    Ok(totp(secret, account)?.get_url())
+}
+
+/// Generates the code for `secret` at the given unix time.
+pub fn totp_code(secret: &str, time: u64) -> Result<String, String> {
+    Ok(totp(secret, "")?.generate(time))
+}
+
+/// Checks `code` against `secret` at the given unix time. Returns the time
+/// step the code belongs to, or `None` when it does not match or its step is
+/// not later than `last_used_step`.
+pub fn verify_totp(
+    secret: &str,
+    code: &str,
+    time: u64,
+    last_used_step: Option<u64>,
+) -> Option<u64> {
+    let totp = totp(secret, "").ok()?;
+    let code = code.trim();
+    let current_step = time / TOTP_STEP;
+
+    (current_step.saturating_sub(TOTP_SKEW)..=current_step + TOTP_SKEW)
+        .filter(|step| last_used_step.is_none_or(|last_used_step| *step > last_used_step))
+        .find(|step| constant_time_eq(totp.generate(step * TOTP_STEP).as_bytes(), code.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Generates single-use recovery codes in the form `xxxxx-xxxxx`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 10];
+            OsRng.fill_bytes(&mut bytes);
+            let code: String = bytes
+                .iter()
+                .map(|byte| {
+                    RECOVERY_CODE_ALPHABET[*byte as usize % RECOVERY_CODE_ALPHABET.len()] as char
+                })
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are compared case-insensitively and without separators.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn accepts_current_and_adjacent_codes() {
+        let secret = generate_totp_secret();
+        let step = NOW / TOTP_STEP;
+
+        let code = totp_code(&secret, NOW).unwrap();
+        assert_eq!(code.len(), TOTP_DIGITS);
+        assert_eq!(verify_totp(&secret, &code, NOW, None), Some(step));
+
+        let previous = totp_code(&secret, NOW - TOTP_STEP).unwrap();
+        assert_eq!(verify_totp(&secret, &previous, NOW, None), Some(step - 1));
+
+        let stale = totp_code(&secret, NOW - 3 * TOTP_STEP).unwrap();
+        assert_eq!(verify_totp(&secret, &stale, NOW, None), None);
+        assert_eq!(verify_totp(&secret, "not-a-code", NOW, None), None);
+    }
+
+    #[test]
+    fn rejects_replayed_codes() {
+        let secret = generate_totp_secret();
+        let code = totp_code(&secret, NOW).unwrap();
+        let step = verify_totp(&secret, &code, NOW, None).unwrap();
+
+        assert_eq!(verify_totp(&secret, &code, NOW, Some(step)), None);
+    }
+
+    #[test]
+    fn rejects_codes_of_other_secrets() {
+        let code = totp_code("JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP", NOW).unwrap();
+        assert_eq!(
+            verify_totp("KRUGS4ZANFZSAYJAORSXG5BAONSWG4TF", &code, NOW, None),
+            None
+        );
+    }
+
+    #[test]
+    fn provisioning_uri_identifies_the_account() {
+        let secret = generate_totp_secret();
+        let uri = provisioning_uri(&secret, "user@example.com").unwrap();
+        assert!(uri.starts_with("otpauth://totp/Locksmith:user%40example.com?"));
+        assert!(uri.contains(&format!("secret={secret}")));
+    }
+
+    #[test]
+    fn recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes.iter().all(|code| code.len() == 11));
+        assert_eq!(
+            normalize_recovery_code(" ABCDE-fghij "),
+            normalize_recovery_code("abcdefghij")
+        );
+    }
+}
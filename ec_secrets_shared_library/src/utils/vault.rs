@@ -1,4 +1,8 @@
-use std::{fs, io, path::Path};
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+};
 
 use argon2::Config;
 use chacha20poly1305::{
@@ -7,8 +11,9 @@ use chacha20poly1305::{
 };
 use log::{info, trace};
 use serde_derive::{Deserialize, Serialize};
-use tar::{Archive, Builder};
+use tar::{Archive, Builder, Header};
 use thiserror::Error;
+use zeroize::Zeroizing;
 
 #[derive(Serialize, Deserialize)]
 struct PrecryptorFile {
@@ -30,7 +35,7 @@ pub enum EncryptError {
 /// # Examples
 ///
 /// ```no_run
-/// use ec_vault::encrypt;
+/// use ec_secrets_shared_library::utils::vault::encrypt;
 ///
 /// let encrypted_data = encrypt(b"example text", b"encryption key").expect("Failed to encrypt");
 /// // and now you can write it to a file:
@@ -47,8 +52,9 @@ pub fn encrypt(data: &[u8], encryption_key: &[u8]) -> Result<Vec<u8>, EncryptErr
     };
 
     trace!("Generating key");
-    let encryption_key =
-        argon2::hash_raw(encryption_key, &salt, &config).map_err(EncryptError::Hashing)?;
+    let encryption_key = Zeroizing::new(
+        argon2::hash_raw(encryption_key, &salt, &config).map_err(EncryptError::Hashing)?,
+    );
     let key = GenericArray::from_slice(&encryption_key);
     let cipher = ChaCha20Poly1305::new(key);
 
@@ -84,6 +90,7 @@ pub enum DecryptError {
 /// # Examples
 ///
 /// ```no_run
+/// use ec_secrets_shared_library::utils::vault::{decrypt, encrypt};
 ///
 /// let encrypted_data = encrypt(b"example text", b"encryption key").expect("Failed to encrypt");
 ///
@@ -95,7 +102,8 @@ pub enum DecryptError {
 /// // fs::write("text.txt", data).expect("Failed to write to file");
 /// ```
 ///
-pub fn decrypt(data: &[u8], encryption_key: &[u8]) -> Result<Vec<u8>, DecryptError> {
+/// The plaintext is wiped when the returned buffer is dropped.
+pub fn decrypt(data: &[u8], encryption_key: &[u8]) -> Result<Zeroizing<Vec<u8>>, DecryptError> {
     trace!("Decoding");
     let decoded: PrecryptorFile = bincode::deserialize(data).map_err(DecryptError::Deserialize)?;
 
@@ -105,8 +113,9 @@ pub fn decrypt(data: &[u8], encryption_key: &[u8]) -> Result<Vec<u8>, DecryptErr
     };
 
     trace!("Generating key");
-    let encryption_key =
-        argon2::hash_raw(encryption_key, &decoded.salt, &config).map_err(DecryptError::Hashing)?;
+    let encryption_key = Zeroizing::new(
+        argon2::hash_raw(encryption_key, &decoded.salt, &config).map_err(DecryptError::Hashing)?,
+    );
 
     let key = GenericArray::from_slice(&encryption_key);
     let cipher = ChaCha20Poly1305::new(key);
@@ -116,7 +125,7 @@ pub fn decrypt(data: &[u8], encryption_key: &[u8]) -> Result<Vec<u8>, DecryptErr
     let text = cipher
         .decrypt(nonce, decoded.data.as_ref())
         .map_err(DecryptError::Cipher)?;
-    Ok(text)
+    Ok(Zeroizing::new(text))
 }
 #[derive(Error, Debug)]
 pub enum FsEncryptError {
@@ -130,7 +139,7 @@ pub enum FsEncryptError {
 /// # Examples
 ///
 /// ```no_run
-/// use ec_vault::encrypt_file;
+/// use ec_secrets_shared_library::utils::vault::encrypt_file;
 /// use std::path::Path;
 ///
 /// encrypt_file(Path::new("example.txt"), Path::new("encrypted_example.txt"), b"encryption key").expect("Failed to encrypt the file");
@@ -155,7 +164,7 @@ pub fn encrypt_file(
 /// # Examples
 ///
 /// ```no_run
-/// use ec_vault::decrypt_file;
+/// use ec_secrets_shared_library::utils::vault::decrypt_file;
 /// use std::path::Path;
 ///
 /// decrypt_file(Path::new("encrypted_example.txt"), Path::new("example.txt"), b"encryption key").expect("Failed to decrypt the file");
@@ -199,7 +208,7 @@ pub enum EncryptDirectoryError {
 /// # Examples
 ///
 /// ```no_run
-/// use ec_vault::encrypt_directory;
+/// use ec_secrets_shared_library::utils::vault::encrypt_directory;
 /// use std::path::Path;
 ///
 /// encrypt_directory(Path::new("example"), Path::new("example.dir"), b"encryption key").expect("Failed to encrypt directory");
@@ -246,7 +255,7 @@ pub enum DecryptDirectoryError {
 /// # Examples
 ///
 /// ```no_run
-/// use ec_vault::decrypt_directory;
+/// use ec_secrets_shared_library::utils::vault::decrypt_directory;
 /// use std::path::Path;
 ///
 /// decrypt_directory(Path::new("example.dir"), Path::new("example"), b"encryption key").expect("Failed to decrypt directory");
@@ -272,6 +281,82 @@ pub fn decrypt_directory(
     Ok(())
 }
 
+/// Archives files held in memory and encrypts the archive, like
+/// `encrypt_directory` without going through the file system
+///
+/// # Examples
+///
+/// ```no_run
+/// use ec_secrets_shared_library::utils::vault::encrypt_archive;
+///
+/// let files: [(&str, &[u8]); 1] = [("example.txt", b"example text")];
+/// let encrypted_archive = encrypt_archive(&files, b"encryption key").expect("Failed to encrypt archive");
+/// ```
+///
+pub fn encrypt_archive(
+    files: &[(&str, &[u8])],
+    encryption_key: &[u8],
+) -> Result<Vec<u8>, EncryptDirectoryError> {
+    let mut archive_output = Zeroizing::new(Vec::new());
+    let mut archive = Builder::new(&mut *archive_output);
+
+    trace!("Adding files to archive");
+    for (name, data) in files {
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o600);
+        header.set_cksum();
+        archive
+            .append_data(&mut header, name, *data)
+            .map_err(EncryptDirectoryError::Archive)?;
+    }
+
+    let data = archive
+        .into_inner()
+        .map_err(EncryptDirectoryError::Archive)?;
+    encrypt(data, encryption_key).map_err(EncryptDirectoryError::Encrypt)
+}
+
+/// A file read from an archive: its path and contents
+pub type ArchiveFile = (String, Zeroizing<Vec<u8>>);
+
+/// Decrypts an archive made by `encrypt_archive` and returns its files
+///
+/// # Examples
+///
+/// ```no_run
+/// use ec_secrets_shared_library::utils::vault::{decrypt_archive, encrypt_archive};
+///
+/// let files: [(&str, &[u8]); 1] = [("example.txt", b"example text")];
+/// let encrypted_archive = encrypt_archive(&files, b"encryption key").expect("Failed to encrypt archive");
+/// let files = decrypt_archive(&encrypted_archive, b"encryption key").expect("Failed to decrypt archive");
+/// ```
+///
+pub fn decrypt_archive(
+    data: &[u8],
+    encryption_key: &[u8],
+) -> Result<Vec<ArchiveFile>, DecryptDirectoryError> {
+    let data = decrypt(data, encryption_key).map_err(DecryptDirectoryError::Decrypt)?;
+    let mut archive: Archive<&[u8]> = Archive::new(data.as_ref());
+
+    trace!("Reading files from archive");
+    let mut files = Vec::new();
+    for entry in archive.entries().map_err(DecryptDirectoryError::Archive)? {
+        let mut entry = entry.map_err(DecryptDirectoryError::Archive)?;
+        let name = entry
+            .path()
+            .map_err(DecryptDirectoryError::Archive)?
+            .to_string_lossy()
+            .into_owned();
+        let mut contents = Zeroizing::new(Vec::new());
+        entry
+            .read_to_end(&mut contents)
+            .map_err(DecryptDirectoryError::Archive)?;
+        files.push((name, contents));
+    }
+    Ok(files)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -280,7 +365,7 @@ mod tests {
     fn data() {
         let encrypted_data = encrypt(b"test", b"test").expect("Failed to encrypt");
         let data = decrypt(&encrypted_data, b"test").expect("Failed to decrypt");
-        assert_eq!(data, b"test");
+        assert_eq!(data.as_slice(), b"test");
     }
 
     #[test]
@@ -307,4 +392,20 @@ mod tests {
         fs::remove_file("test.dir").expect("Failed to remove file");
         fs::remove_dir_all("test").expect("Failed to remove test directory");
     }
+
+    #[test]
+    fn archive() {
+        let files: [(&str, &[u8]); 2] = [("a.txt", b"first"), ("b/c.txt", b"second")];
+        let encrypted_archive =
+            encrypt_archive(&files, b"test").expect("Failed to encrypt archive");
+        assert!(decrypt_archive(&encrypted_archive, b"wrong").is_err());
+
+        let files =
+            decrypt_archive(&encrypted_archive, b"test").expect("Failed to decrypt archive");
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, "a.txt");
+        assert_eq!(files[0].1.as_slice(), b"first");
+        assert_eq!(files[1].0, "b/c.txt");
+        assert_eq!(files[1].1.as_slice(), b"second");
+    }
 }
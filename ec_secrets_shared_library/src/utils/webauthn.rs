@@ -0,0 +1,489 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use ciborium::Value;
+use p256::ecdsa::{Signature, VerifyingKey, signature::Verifier};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::models::{WebAuthnAssertion, WebAuthnRegistration};
+
+/// COSE identifier of ECDSA with P-256 and SHA-256, the only algorithm accepted.
+pub const COSE_ALG_ES256: i64 = -7;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/*---------------------------------------------------------------
+Relying party settings for passkey ceremonies. `rp_id` is the
+domain credentials are scoped to and `origins` the exact origins
+the console is served from.
+
+Read from the `[default.auth.webauthn]` table of the server's
+Rocket configuration.
+----------------------------------------------------------------*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebAuthnConfig {
+    pub rp_id: String,
+    pub rp_name: String,
+    pub origins: Vec<String>,
+    /// Seconds a registration or login ceremony may take.
+    pub challenge_ttl: i64,
+    /// Require the authenticator to verify the user (PIN or biometrics).
+    pub require_user_verification: bool,
+}
+
+impl Default for WebAuthnConfig {
+    fn default() -> Self {
+        Self {
+            rp_id: "localhost".to_string(),
+            rp_name: "Locksmith".to_string(),
+            origins: vec!["https://localhost:8089".to_string()],
+            challenge_ttl: 300,
+            require_user_verification: true,
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum WebAuthnError {
+    #[error("malformed {0}")]
+    Malformed(&'static str),
+    #[error("unexpected ceremony type")]
+    CeremonyType,
+    #[error("challenge does not match")]
+    Challenge,
+    #[error("origin is not allowed")]
+    Origin,
+    #[error("relying party id does not match")]
+    RelyingParty,
+    #[error("user presence was not asserted")]
+    UserPresence,
+    #[error("user verification was not performed")]
+    UserVerification,
+    #[error("unsupported credential algorithm")]
+    UnsupportedAlgorithm,
+    #[error("signature is invalid")]
+    InvalidSignature,
+    #[error("signature counter did not increase; the authenticator may be cloned")]
+    CounterRegression,
+}
+
+/// A credential accepted by a registration ceremony. Identifiers and keys
+/// are base64url encoded; the key is an uncompressed SEC1 P-256 point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisteredCredential {
+    pub credential_id: String,
+    pub public_key: String,
+    pub sign_count: u32,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony: String,
+    challenge: String,
+    origin: String,
+}
+
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    attested_credential_data: &'a [u8],
+}
+
+fn decode(field: &'static str, value: &str) -> Result<Vec<u8>, WebAuthnError> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| WebAuthnError::Malformed(field))
+}
+
+fn parse_client_data(client_data_json: &[u8]) -> Result<ClientData, WebAuthnError> {
+    serde_json::from_slice(client_data_json).map_err(|_| WebAuthnError::Malformed("client data"))
+}
+
+/// The challenge a browser signed, used to look up the pending ceremony.
+pub fn client_data_challenge(client_data_json: &str) -> Result<String, WebAuthnError> {
+    let client_data = parse_client_data(&decode("client data", client_data_json)?)?;
+    Ok(client_data.challenge)
+}
+
+fn verify_client_data(
+    config: &WebAuthnConfig,
+    client_data_json: &[u8],
+    ceremony: &str,
+    expected_challenge: &str,
+) -> Result<(), WebAuthnError> {
+    let client_data = parse_client_data(client_data_json)?;
+    if client_data.ceremony != ceremony {
+        return Err(WebAuthnError::CeremonyType);
+    }
+    if client_data.challenge.trim_end_matches('=') != expected_challenge {
+        return Err(WebAuthnError::Challenge);
+    }
+    if !config.origins.contains(&client_data.origin) {
+        return Err(WebAuthnError::Origin);
+    }
+    Ok(())
+}
+
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData<'_>, WebAuthnError> {
+    if data.len() < 37 {
+        return Err(WebAuthnError::Malformed("authenticator data"));
+    }
+    Ok(AuthenticatorData {
+        rp_id_hash: &data[..32],
+        flags: data[32],
+        sign_count: u32::from_be_bytes([data[33], data[34], data[35], data[36]]),
+        attested_credential_data: &data[37..],
+    })
+}
+
+fn check_authenticator_data(
+    config: &WebAuthnConfig,
+    authenticator_data: &AuthenticatorData,
+) -> Result<(), WebAuthnError> {
+    if authenticator_data.rp_id_hash != Sha256::digest(config.rp_id.as_bytes()).as_slice() {
+        return Err(WebAuthnError::RelyingParty);
+    }
+    if authenticator_data.flags & FLAG_USER_PRESENT == 0 {
+        return Err(WebAuthnError::UserPresence);
+    }
+    if config.require_user_verification && authenticator_data.flags & FLAG_USER_VERIFIED == 0 {
+        return Err(WebAuthnError::UserVerification);
+    }
+    Ok(())
+}
+
+fn cose_integer(value: &Value) -> Option<i128> {
+    value.as_integer().map(i128::from)
+}
+
+/// Converts an EC2 COSE key into a SEC1 encoded P-256 public key.
+fn parse_cose_key(cose_key: &[u8]) -> Result<Vec<u8>, WebAuthnError> {
+    let value: Value = ciborium::de::from_reader(cose_key)
+        .map_err(|_| WebAuthnError::Malformed("credential public key"))?;
+    let entries = value
+        .as_map()
+        .ok_or(WebAuthnError::Malformed("credential public key"))?;
+    let entry = |label: i128| {
+        entries
+            .iter()
+            .find(|(key, _)| cose_integer(key) == Some(label))
+            .map(|(_, value)| value)
+    };
+
+    let key_type = entry(1).and_then(cose_integer);
+    let algorithm = entry(3).and_then(cose_integer);
+    let curve = entry(-1).and_then(cose_integer);
+    if key_type != Some(2) || algorithm != Some(COSE_ALG_ES256.into()) || curve != Some(1) {
+        return Err(WebAuthnError::UnsupportedAlgorithm);
+    }
+
+    let coordinate = |label: i128| {
+        entry(label)
+            .and_then(Value::as_bytes)
+            .filter(|bytes| bytes.len() == 32)
+            .ok_or(WebAuthnError::Malformed("credential public key"))
+    };
+    let mut public_key = vec![0x04];
+    public_key.extend_from_slice(coordinate(-2)?);
+    public_key.extend_from_slice(coordinate(-3)?);
+
+    VerifyingKey::from_sec1_bytes(&public_key)
+        .map_err(|_| WebAuthnError::Malformed("credential public key"))?;
+    Ok(public_key)
+}
+
+/*---------------------------------------------------------------
+Verify the response to a registration ceremony and extract the new
+credential. Attestation statements are not evaluated: ceremonies ask
+for `none` attestation, so only the credential itself is trusted.
+----------------------------------------------------------------*/
+pub fn verify_registration(
+    config: &WebAuthnConfig,
+    expected_challenge: &str,
+    response: &WebAuthnRegistration,
+) -> Result<RegisteredCredential, WebAuthnError> {
+    let client_data_json = decode("client data", &response.client_data_json)?;
+    verify_client_data(
+        config,
+        &client_data_json,
+        "webauthn.create",
+        expected_challenge,
+    )?;
+
+    let attestation_object = decode("attestation object", &response.attestation_object)?;
+    let attestation: Value = ciborium::de::from_reader(attestation_object.as_slice())
+        .map_err(|_| WebAuthnError::Malformed("attestation object"))?;
+    let authenticator_data = attestation
+        .as_map()
+        .and_then(|entries| {
+            entries
+                .iter()
+                .find(|(key, _)| key.as_text() == Some("authData"))
+        })
+        .and_then(|(_, value)| value.as_bytes())
+        .ok_or(WebAuthnError::Malformed("attestation object"))?;
+
+    let authenticator_data = parse_authenticator_data(authenticator_data)?;
+    check_authenticator_data(config, &authenticator_data)?;
+    if authenticator_data.flags & FLAG_ATTESTED_CREDENTIAL_DATA == 0 {
+        return Err(WebAuthnError::Malformed("attested credential data"));
+    }
+
+    // AAGUID (16 bytes), credential id length (2 bytes), credential id, COSE key.
+    let attested = authenticator_data.attested_credential_data;
+    if attested.len() < 18 {
+        return Err(WebAuthnError::Malformed("attested credential data"));
+    }
+    let credential_id_length = u16::from_be_bytes([attested[16], attested[17]]) as usize;
+    let Some(credential_id) = attested.get(18..18 + credential_id_length) else {
+        return Err(WebAuthnError::Malformed("attested credential data"));
+    };
+    if credential_id != decode("credential id", &response.id)? {
+        return Err(WebAuthnError::Malformed("credential id"));
+    }
+
+    let public_key = parse_cose_key(&attested[18 + credential_id_length..])?;
+
+    Ok(RegisteredCredential {
+        credential_id: URL_SAFE_NO_PAD.encode(credential_id),
+        public_key: URL_SAFE_NO_PAD.encode(public_key),
+        sign_count: authenticator_data.sign_count,
+    })
+}
+
+/*---------------------------------------------------------------
+Verify the response to an authentication ceremony against a stored
+credential. Returns the authenticator's new signature counter.
+----------------------------------------------------------------*/
+pub fn verify_assertion(
+    config: &WebAuthnConfig,
+    expected_challenge: &str,
+    public_key: &str,
+    stored_sign_count: u32,
+    response: &WebAuthnAssertion,
+) -> Result<u32, WebAuthnError> {
+    let client_data_json = decode("client data", &response.client_data_json)?;
+    verify_client_data(
+        config,
+        &client_data_json,
+        "webauthn.get",
+        expected_challenge,
+    )?;
+
+    let raw_authenticator_data = decode("authenticator data", &response.authenticator_data)?;
+    let authenticator_data = parse_authenticator_data(&raw_authenticator_data)?;
+    check_authenticator_data(config, &authenticator_data)?;
+
+    let verifying_key = VerifyingKey::from_sec1_bytes(&decode("public key", public_key)?)
+        .map_err(|_| WebAuthnError::Malformed("public key"))?;
+    let signature = Signature::from_der(&decode("signature", &response.signature)?)
+        .map_err(|_| WebAuthnError::InvalidSignature)?;
+
+    let mut signed_data = raw_authenticator_data.clone();
+    signed_data.extend_from_slice(&Sha256::digest(&client_data_json));
+    verifying_key
+        .verify(&signed_data, &signature)
+        .map_err(|_| WebAuthnError::InvalidSignature)?;
+
+    // Authenticators without a counter always report zero.
+    let sign_count = authenticator_data.sign_count;
+    if (sign_count != 0 || stored_sign_count != 0) && sign_count <= stored_sign_count {
+        return Err(WebAuthnError::CounterRegression);
+    }
+    Ok(sign_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chacha20poly1305::aead::OsRng;
+    use p256::ecdsa::{SigningKey, signature::Signer};
+
+    const CHALLENGE: &str = "registration-or-login-challenge";
+    const CREDENTIAL_ID: &[u8] = b"credential-id";
+
+    fn config() -> WebAuthnConfig {
+        WebAuthnConfig::default()
+    }
+
+    fn client_data(ceremony: &str, challenge: &str, origin: &str) -> String {
+        let client_data = serde_json::json!({
+            "type": ceremony,
+            "challenge": challenge,
+            "origin": origin,
+        });
+        URL_SAFE_NO_PAD.encode(client_data.to_string())
+    }
+
+    fn authenticator_data(rp_id: &str, flags: u8, sign_count: u32) -> Vec<u8> {
+        let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&sign_count.to_be_bytes());
+        data
+    }
+
+    fn cose_key(signing_key: &SigningKey) -> Vec<u8> {
+        let point = signing_key.verifying_key().to_encoded_point(false);
+        let key = Value::Map(vec![
+            (Value::from(1), Value::from(2)),
+            (Value::from(3), Value::from(COSE_ALG_ES256)),
+            (Value::from(-1), Value::from(1)),
+            (Value::from(-2), Value::Bytes(point.x().unwrap().to_vec())),
+            (Value::from(-3), Value::Bytes(point.y().unwrap().to_vec())),
+        ]);
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&key, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn registration(signing_key: &SigningKey, origin: &str) -> WebAuthnRegistration {
+        let mut auth_data = authenticator_data(
+            "localhost",
+            FLAG_USER_PRESENT | FLAG_USER_VERIFIED | FLAG_ATTESTED_CREDENTIAL_DATA,
+            0,
+        );
+        auth_data.extend_from_slice(&[0u8; 16]);
+        auth_data.extend_from_slice(&(CREDENTIAL_ID.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(CREDENTIAL_ID);
+        auth_data.extend_from_slice(&cose_key(signing_key));
+
+        let attestation = Value::Map(vec![
+            (Value::from("fmt"), Value::from("none")),
+            (Value::from("attStmt"), Value::Map(vec![])),
+            (Value::from("authData"), Value::Bytes(auth_data)),
+        ]);
+        let mut attestation_object = Vec::new();
+        ciborium::ser::into_writer(&attestation, &mut attestation_object).unwrap();
+
+        WebAuthnRegistration {
+            id: URL_SAFE_NO_PAD.encode(CREDENTIAL_ID),
+            client_data_json: client_data("webauthn.create", CHALLENGE, origin),
+            attestation_object: URL_SAFE_NO_PAD.encode(attestation_object),
+            name: None,
+        }
+    }
+
+    fn assertion(signing_key: &SigningKey, flags: u8, sign_count: u32) -> WebAuthnAssertion {
+        let auth_data = authenticator_data("localhost", flags, sign_count);
+        let client_data_json = client_data("webauthn.get", CHALLENGE, "https://localhost:8089");
+
+        let mut signed_data = auth_data.clone();
+        signed_data.extend_from_slice(&Sha256::digest(
+            URL_SAFE_NO_PAD.decode(&client_data_json).unwrap(),
+        ));
+        let signature: Signature = signing_key.sign(&signed_data);
+
+        WebAuthnAssertion {
+            id: URL_SAFE_NO_PAD.encode(CREDENTIAL_ID),
+            client_data_json,
+            authenticator_data: URL_SAFE_NO_PAD.encode(auth_data),
+            signature: URL_SAFE_NO_PAD.encode(signature.to_der()),
+            user_handle: None,
+        }
+    }
+
+    fn registered(signing_key: &SigningKey) -> RegisteredCredential {
+        verify_registration(
+            &config(),
+            CHALLENGE,
+            &registration(signing_key, "https://localhost:8089"),
+        )
+        .expect("registration should succeed")
+    }
+
+    #[test]
+    fn registers_credentials() {
+        let signing_key = SigningKey::random(&mut OsRng);
+        let credential = registered(&signing_key);
+
+        assert_eq!(
+            credential.credential_id,
+            URL_SAFE_NO_PAD.encode(CREDENTIAL_ID)
+        );
+        assert_eq!(
+            credential.public_key,
+            URL_SAFE_NO_PAD.encode(signing_key.verifying_key().to_encoded_point(false))
+        );
+    }
+
+    #[test]
+    fn rejects_foreign_registrations() {
+        let signing_key = SigningKey::random(&mut OsRng);
+        let response = registration(&signing_key, "https://localhost:8089");
+
+        assert_eq!(
+            verify_registration(&config(), "another-challenge", &response),
+            Err(WebAuthnError::Challenge)
+        );
+        assert_eq!(
+            verify_registration(
+                &config(),
+                CHALLENGE,
+                &registration(&signing_key, "https://phishing.example")
+            ),
+            Err(WebAuthnError::Origin)
+        );
+
+        let foreign_rp = WebAuthnConfig {
+            rp_id: "example.com".to_string(),
+            ..config()
+        };
+        assert_eq!(
+            verify_registration(&foreign_rp, CHALLENGE, &response),
+            Err(WebAuthnError::RelyingParty)
+        );
+    }
+
+    #[test]
+    fn verifies_assertions() {
+        let signing_key = SigningKey::random(&mut OsRng);
+        let credential = registered(&signing_key);
+        let response = assertion(&signing_key, FLAG_USER_PRESENT | FLAG_USER_VERIFIED, 5);
+
+        assert_eq!(
+            verify_assertion(&config(), CHALLENGE, &credential.public_key, 4, &response),
+            Ok(5)
+        );
+        assert_eq!(
+            verify_assertion(&config(), CHALLENGE, &credential.public_key, 5, &response),
+            Err(WebAuthnError::CounterRegression)
+        );
+    }
+
+    #[test]
+    fn rejects_forged_assertions() {
+        let signing_key = SigningKey::random(&mut OsRng);
+        let credential = registered(&signing_key);
+
+        let forged = assertion(
+            &SigningKey::random(&mut OsRng),
+            FLAG_USER_PRESENT | FLAG_USER_VERIFIED,
+            1,
+        );
+        assert_eq!(
+            verify_assertion(&config(), CHALLENGE, &credential.public_key, 0, &forged),
+            Err(WebAuthnError::InvalidSignature)
+        );
+
+        let unverified = assertion(&signing_key, FLAG_USER_PRESENT, 1);
+        assert_eq!(
+            verify_assertion(&config(), CHALLENGE, &credential.public_key, 0, &unverified),
+            Err(WebAuthnError::UserVerification)
+        );
+
+        let response = assertion(&signing_key, FLAG_USER_PRESENT | FLAG_USER_VERIFIED, 1);
+        assert_eq!(
+            client_data_challenge(&response.client_data_json),
+            Ok(CHALLENGE.to_string())
+        );
+        assert_eq!(
+            verify_assertion(&config(), "stale", &credential.public_key, 0, &response),
+            Err(WebAuthnError::Challenge)
+        );
+    }
+}